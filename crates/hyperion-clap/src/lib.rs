@@ -1,13 +1,17 @@
 use std::iter::zip;
 
-use clap::{Arg as ClapArg, Parser, ValueEnum, ValueHint, error::ErrorKind};
+use clap::{
+    Arg as ClapArg, Command as ClapCommand, Parser, ValueEnum, ValueHint, error::ErrorKind,
+};
 use flecs_ecs::{
     core::{Entity, EntityView, EntityViewGet, World, WorldGet, WorldProvider},
     prelude::{Component, Module},
 };
 use hyperion::{
     net::{Compose, ConnectionId, DataBundle, agnostic},
-    simulation::{IgnMap, command::get_root_command_entity, handlers::PacketSwitchQuery},
+    simulation::{
+        IgnMap, Pitch, Position, Yaw, command::get_root_command_entity, handlers::PacketSwitchQuery,
+    },
     storage::CommandCompletionRequest,
 };
 pub use hyperion_clap_macros::CommandPermission;
@@ -16,12 +20,13 @@ use hyperion_command::{CommandHandler, CommandRegistry};
 use hyperion_permission::Group;
 use valence_protocol::{
     VarInt,
-    packets::{
-        play,
-        play::{command_suggestions_s2c::CommandSuggestionsMatch, command_tree_s2c::StringArg},
-    },
+    packets::{play, play::command_suggestions_s2c::CommandSuggestionsMatch},
 };
 
+pub mod parser;
+
+pub use parser::{BlockPos, Origin, Vec3Pos};
+
 pub trait MinecraftCommand: Parser + CommandPermission {
     fn execute(self, system: EntityView<'_>, caller: Entity);
 
@@ -40,30 +45,29 @@ pub trait MinecraftCommand: Parser + CommandPermission {
         };
 
         let node_to_register =
-            hyperion::simulation::command::Command::literal(name, has_permissions);
+            hyperion::simulation::command::Command::literal(name, has_permissions)
+                .executable(is_executable(&cmd));
 
-        let mut on = world
+        let on = world
             .entity()
             .set(node_to_register)
             .child_of_id(get_root_command_entity());
 
-        for arg in cmd.get_arguments() {
-            use valence_protocol::packets::play::command_tree_s2c::Parser as ValenceParser;
-            let name = arg.get_value_names().unwrap().first().unwrap();
-            let name = name.to_ascii_lowercase();
-            let node_to_register = hyperion::simulation::command::Command::argument(
-                name,
-                ValenceParser::String(StringArg::SingleWord),
-            );
-
-            on = world.entity().set(node_to_register).child_of_id(on);
-        }
+        register_nodes(world, on.id(), &cmd);
 
         let on_execute = |input: &str, system: EntityView<'_>, caller: Entity| {
-            let input = input.split_whitespace();
             let world = system.world();
 
-            match Self::try_parse_from(input) {
+            let origin = caller
+                .entity_view(world)
+                .try_get::<(&Position, &Yaw, &Pitch)>(|(position, yaw, pitch)| Origin {
+                    position: **position,
+                    yaw: **yaw,
+                    pitch: **pitch,
+                })
+                .unwrap_or_default();
+
+            match parser::parse::<Self>(input, &origin) {
                 Ok(elem) => {
                     if world.get::<&Compose>(|compose| {
                         caller.entity_view(world).get::<(&ConnectionId, &Group)>(
@@ -243,6 +247,8 @@ pub trait MinecraftCommand: Parser + CommandPermission {
 
 pub enum Arg {
     Player,
+    /// The rest of the command, spaces included.
+    Greedy,
 }
 
 // Custom trait for Minecraft-specific argument behavior
@@ -256,8 +262,67 @@ impl MinecraftArg for ClapArg {
     fn minecraft(self, arg: Arg) -> Self {
         match arg {
             Arg::Player => self.value_hint(ValueHint::Username),
+            Arg::Greedy => self.value_hint(ValueHint::Other),
+        }
+    }
+}
+
+/// Whether `command` can run without any more arguments.
+fn is_executable(command: &ClapCommand) -> bool {
+    let subcommand_required = command.has_subcommands() && command.is_subcommand_required_set();
+    !subcommand_required && command.get_positionals().all(|arg| !arg.is_required_set())
+}
+
+/// Adds the subcommands and positional arguments of `command` below `parent` in the command tree.
+fn register_nodes(world: &World, parent: Entity, command: &ClapCommand) {
+    let positionals: Vec<_> = command.get_positionals().collect();
+    register_positionals(world, parent, &positionals);
+
+    for subcommand in command.get_subcommands() {
+        let node =
+            hyperion::simulation::command::Command::literal(subcommand.get_name(), |_, _| true)
+                .executable(is_executable(subcommand));
+
+        let on = world.entity().set(node).child_of_id(parent);
+        register_nodes(world, on.id(), subcommand);
+    }
+}
+
+fn register_positionals(world: &World, parent: Entity, positionals: &[&ClapArg]) {
+    use hyperion::simulation::command::Command;
+
+    let Some((arg, rest)) = positionals.split_first() else {
+        return;
+    };
+
+    // the command can run after this argument if the ones after it are optional
+    let executable = rest.iter().all(|arg| !arg.is_required_set());
+
+    if parser::is_literal(arg) {
+        for value in arg.get_possible_values() {
+            if value.is_hide_set() {
+                continue;
+            }
+
+            let node = Command::literal(value.get_name(), |_, _| true).executable(executable);
+            let on = world.entity().set(node).child_of_id(parent);
+            register_positionals(world, on.id(), rest);
         }
+
+        return;
     }
+
+    let name = arg
+        .get_value_names()
+        .and_then(|names| names.first())
+        .map_or_else(
+            || arg.get_id().to_string(),
+            |name| name.to_ascii_lowercase(),
+        );
+
+    let node = Command::argument(name, parser::parser(arg)).executable(executable);
+    let on = world.entity().set(node).child_of_id(parent);
+    register_positionals(world, on.id(), rest);
 }
 
 pub trait CommandPermission {
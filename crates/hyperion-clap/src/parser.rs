@@ -0,0 +1,388 @@
+//! Maps clap arguments to the Brigadier parsers the client validates and highlights them with,
+//! and splits commands into arguments the way those parsers read them.
+
+use std::{any::TypeId, fmt, str::FromStr};
+
+use clap::{Arg as ClapArg, Command as ClapCommand, CommandFactory, FromArgMatches, ValueHint};
+use hyperion::glam::{IVec3, Vec3};
+use valence_protocol::packets::play::command_tree_s2c::{Parser, StringArg};
+
+/// A block position argument, written as three integers such as `10 64 -3`. Coordinates may also
+/// be relative to the [`Origin`] of the command, as in `~ ~1 ~`, or local, as in `^ ^ ^2`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct BlockPos(pub IVec3);
+
+/// A position argument, written as three numbers such as `10.5 64 -3.25`. Like [`BlockPos`], it
+/// may be relative or local.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Vec3Pos(pub Vec3);
+
+/// Where a command is run from, which relative (`~`) and local (`^`) coordinates are measured
+/// from.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Origin {
+    pub position: Vec3,
+    /// In degrees, as in [`hyperion::simulation::Yaw`].
+    pub yaw: f32,
+    /// In degrees, as in [`hyperion::simulation::Pitch`].
+    pub pitch: f32,
+}
+
+impl Origin {
+    /// The offset of `local`, which is `[left, up, forwards]` as seen from the origin's rotation.
+    fn local(&self, local: Vec3) -> Vec3 {
+        let yaw = (self.yaw + 90.0).to_radians();
+        let pitch = (-self.pitch).to_radians();
+        let up_pitch = (90.0 - self.pitch).to_radians();
+
+        let forwards = Vec3::new(
+            yaw.cos() * pitch.cos(),
+            pitch.sin(),
+            yaw.sin() * pitch.cos(),
+        );
+        let up = Vec3::new(
+            yaw.cos() * up_pitch.cos(),
+            up_pitch.sin(),
+            yaw.sin() * up_pitch.cos(),
+        );
+        let left = -forwards.cross(up);
+
+        left * local.x + up * local.y + forwards * local.z
+    }
+}
+
+/// Why a position argument could not be parsed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PositionError(String);
+
+impl fmt::Display for PositionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "expected three coordinates, found `{}`", self.0)
+    }
+}
+
+impl std::error::Error for PositionError {}
+
+fn coordinates<T: FromStr>(s: &str) -> Result<[T; 3], PositionError> {
+    let error = || PositionError(s.to_owned());
+
+    let mut coordinates = s.split_whitespace().map(str::parse);
+
+    let mut next = || coordinates.next().and_then(Result::ok).ok_or_else(error);
+    let result = [next()?, next()?, next()?];
+
+    if coordinates.next().is_some() {
+        return Err(error());
+    }
+
+    Ok(result)
+}
+
+/// The offset after `~` or `^`, where nothing means 0.
+fn offset(s: &str) -> Option<f32> {
+    if s.is_empty() {
+        return Some(0.0);
+    }
+
+    s.parse().ok().filter(|offset: &f32| offset.is_finite())
+}
+
+/// Replaces relative and local coordinates with the absolute coordinates they are from `origin`,
+/// rounded down to a block if `block` is set. Coordinates which cannot be read are left as they
+/// are for [`FromStr`] to reject, as are local coordinates mixed with other coordinates.
+fn resolve(coordinates: [&str; 3], origin: &Origin, block: bool) -> [String; 3] {
+    let to_string = |coordinate: f32| {
+        if block {
+            coordinate.floor().to_string()
+        } else {
+            coordinate.to_string()
+        }
+    };
+
+    let local = coordinates.map(|coordinate| coordinate.strip_prefix('^').and_then(offset));
+
+    if let [Some(left), Some(up), Some(forwards)] = local {
+        let position = origin.position + origin.local(Vec3::new(left, up, forwards));
+        return position.to_array().map(to_string);
+    }
+
+    let position = origin.position.to_array();
+
+    std::array::from_fn(|axis| {
+        let coordinate = coordinates[axis];
+
+        match coordinate.strip_prefix('~').and_then(offset) {
+            Some(offset) => to_string(position[axis] + offset),
+            None => coordinate.to_owned(),
+        }
+    })
+}
+
+impl FromStr for BlockPos {
+    type Err = PositionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        coordinates(s).map(|coordinates| Self(IVec3::from_array(coordinates)))
+    }
+}
+
+impl FromStr for Vec3Pos {
+    type Err = PositionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        coordinates(s).map(|coordinates| Self(Vec3::from_array(coordinates)))
+    }
+}
+
+fn is<T: 'static>(arg: &ClapArg) -> bool {
+    arg.get_value_parser().type_id() == TypeId::of::<T>()
+}
+
+fn integer(min: i64, max: i64) -> Parser {
+    match (i32::try_from(min), i32::try_from(max)) {
+        (Ok(min), Ok(max)) => Parser::Integer {
+            min: (min != i32::MIN).then_some(min),
+            max: (max != i32::MAX).then_some(max),
+        },
+        _ => Parser::Long {
+            min: (min != i64::MIN).then_some(min),
+            max: (max != i64::MAX).then_some(max),
+        },
+    }
+}
+
+/// The parser the client uses for `arg`. Arguments with possible values, such as
+/// [`clap::ValueEnum`]s, are sent as literals instead; see [`is_literal`].
+///
+/// Integers are limited to the range of their type. Narrower ranges, such as
+/// `value_parser!(u8).range(1..=10)`, cannot be read back from clap, so the client accepts any
+/// `u8` and clap rejects values outside the range when the command runs.
+#[must_use]
+pub fn parser(arg: &ClapArg) -> Parser {
+    if is::<bool>(arg) {
+        return Parser::Bool;
+    }
+
+    if is::<i8>(arg) {
+        return integer(i8::MIN.into(), i8::MAX.into());
+    }
+
+    if is::<u8>(arg) {
+        return integer(u8::MIN.into(), u8::MAX.into());
+    }
+
+    if is::<i16>(arg) {
+        return integer(i16::MIN.into(), i16::MAX.into());
+    }
+
+    if is::<u16>(arg) {
+        return integer(u16::MIN.into(), u16::MAX.into());
+    }
+
+    if is::<i32>(arg) {
+        return integer(i32::MIN.into(), i32::MAX.into());
+    }
+
+    if is::<u32>(arg) {
+        return integer(u32::MIN.into(), u32::MAX.into());
+    }
+
+    if is::<i64>(arg) {
+        return integer(i64::MIN, i64::MAX);
+    }
+
+    if is::<u64>(arg) {
+        return integer(0, i64::MAX);
+    }
+
+    if is::<f32>(arg) {
+        return Parser::Float {
+            min: None,
+            max: None,
+        };
+    }
+
+    if is::<f64>(arg) {
+        return Parser::Double {
+            min: None,
+            max: None,
+        };
+    }
+
+    if is::<BlockPos>(arg) {
+        return Parser::BlockPos;
+    }
+
+    if is::<Vec3Pos>(arg) {
+        return Parser::Vec3;
+    }
+
+    match arg.get_value_hint() {
+        ValueHint::Username => Parser::GameProfile,
+        ValueHint::Other => Parser::String(StringArg::GreedyPhrase),
+        _ => Parser::String(StringArg::QuotablePhrase),
+    }
+}
+
+/// Whether every value of `arg` is known, so that it is sent to the client as one literal per
+/// value.
+#[must_use]
+pub fn is_literal(arg: &ClapArg) -> bool {
+    !is::<bool>(arg) && !arg.get_possible_values().is_empty()
+}
+
+/// Lets the arguments of `command` and its subcommands start with `-` where the client's parsers
+/// allow it: numbers may be negative and positions may start with a negative coordinate. Without
+/// this, clap reads such arguments as unknown flags.
+#[must_use]
+pub fn allow_negative_values(command: ClapCommand) -> ClapCommand {
+    let subcommands: Vec<_> = command
+        .get_subcommands()
+        .map(|subcommand| subcommand.get_name().to_owned())
+        .collect();
+
+    let mut command = command.mut_args(|arg| match parser(&arg) {
+        Parser::Integer { .. }
+        | Parser::Long { .. }
+        | Parser::Float { .. }
+        | Parser::Double { .. } => arg.allow_negative_numbers(true),
+        Parser::BlockPos | Parser::Vec3 => arg.allow_hyphen_values(true),
+        _ => arg,
+    });
+
+    for name in subcommands {
+        command = command.mut_subcommand(name, allow_negative_values);
+    }
+
+    command
+}
+
+/// Parses `input`, a command without the leading `/` run from `origin`, into `T` with the
+/// arguments split by [`tokenize`].
+pub fn parse<T: clap::Parser>(input: &str, origin: &Origin) -> Result<T, clap::Error> {
+    let command = allow_negative_values(T::command());
+    let tokens = tokenize(input, &command, origin);
+
+    let mut matches = command.try_get_matches_from(tokens)?;
+    T::from_arg_matches_mut(&mut matches).map_err(|e| e.format(&mut T::command()))
+}
+
+/// Reads a command the way the client's parsers do.
+struct Reader<'a> {
+    remaining: &'a str,
+}
+
+impl<'a> Reader<'a> {
+    fn skip_whitespace(&mut self) {
+        self.remaining = self.remaining.trim_start_matches(' ');
+    }
+
+    fn word(&mut self) -> &'a str {
+        self.skip_whitespace();
+
+        let end = self.remaining.find(' ').unwrap_or(self.remaining.len());
+        let (word, remaining) = self.remaining.split_at(end);
+        self.remaining = remaining;
+        word
+    }
+
+    fn peek_word(&self) -> &'a str {
+        let remaining = self.remaining.trim_start_matches(' ');
+        let end = remaining.find(' ').unwrap_or(remaining.len());
+        remaining.split_at(end).0
+    }
+
+    /// A single word, or a string in `"` or `'` quotes in which `\` escapes the quote and itself.
+    fn quotable(&mut self) -> String {
+        self.skip_whitespace();
+
+        let remaining = self.remaining;
+        let mut chars = remaining.char_indices();
+
+        let Some((_, quote @ ('"' | '\''))) = chars.next() else {
+            return self.word().to_owned();
+        };
+
+        let mut result = String::new();
+        let mut escaped = false;
+
+        for (i, c) in chars {
+            if escaped {
+                result.push(c);
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == quote {
+                self.remaining = remaining.split_at(i + c.len_utf8()).1;
+                return result;
+            } else {
+                result.push(c);
+            }
+        }
+
+        // an unterminated quote takes the rest of the command
+        self.remaining = "";
+        result
+    }
+
+    fn rest(&mut self) -> &'a str {
+        self.skip_whitespace();
+        std::mem::take(&mut self.remaining)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.remaining.trim_start_matches(' ').is_empty()
+    }
+}
+
+/// Splits `input`, a command without the leading `/`, into the arguments of `command`. Quoted
+/// strings, greedy strings and positions each become one argument, unlike with
+/// [`str::split_whitespace`]. Relative and local coordinates of positions are made absolute by
+/// measuring them from `origin`.
+#[must_use]
+pub fn tokenize(input: &str, command: &ClapCommand, origin: &Origin) -> Vec<String> {
+    let mut reader = Reader { remaining: input };
+
+    // the name of the command
+    let mut tokens = vec![reader.word().to_owned()];
+
+    let mut command = command;
+    let mut positional = 0;
+
+    while !reader.is_empty() {
+        let word = reader.peek_word();
+
+        if let Some(subcommand) = command.find_subcommand(word) {
+            tokens.push(reader.word().to_owned());
+            command = subcommand;
+            positional = 0;
+            continue;
+        }
+
+        // negative numbers are arguments rather than flags
+        if word.starts_with('-') && word.parse::<f64>().is_err() {
+            tokens.push(reader.word().to_owned());
+            continue;
+        }
+
+        let Some(arg) = command.get_positionals().nth(positional) else {
+            tokens.push(reader.quotable());
+            continue;
+        };
+
+        positional += 1;
+
+        let token = match parser(arg) {
+            Parser::String(StringArg::GreedyPhrase) => reader.rest().to_owned(),
+            position @ (Parser::BlockPos | Parser::Vec3) => {
+                let coordinates = [reader.word(), reader.word(), reader.word()];
+                resolve(coordinates, origin, position == Parser::BlockPos).join(" ")
+            }
+            _ => reader.quotable(),
+        };
+
+        tokens.push(token);
+    }
+
+    tokens
+}
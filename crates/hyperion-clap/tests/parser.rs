@@ -0,0 +1,177 @@
+use clap::{CommandFactory, Parser, Subcommand};
+use hyperion::glam::{IVec3, Vec3};
+use hyperion_clap::{
+    Arg, BlockPos, MinecraftArg, Origin, Vec3Pos,
+    parser::{is_literal, parse, parser, tokenize},
+};
+use valence_protocol::packets::play::command_tree_s2c::{Parser as Brigadier, StringArg};
+
+#[derive(Parser, Debug)]
+#[command(name = "tp")]
+struct Teleport {
+    position: BlockPos,
+    reason: Option<String>,
+}
+
+#[derive(Parser, Debug)]
+#[command(name = "summon")]
+struct Summon {
+    position: Vec3Pos,
+}
+
+#[derive(Parser, Debug)]
+#[command(name = "xp")]
+struct Xp {
+    amount: u16,
+}
+
+#[derive(Parser, Debug)]
+#[command(name = "speed")]
+struct Speed {
+    amount: i32,
+    multiplier: Option<f32>,
+}
+
+#[derive(Parser, Debug)]
+#[command(name = "say")]
+struct Say {
+    #[arg(value_hint = clap::ValueHint::Other)]
+    message: String,
+}
+
+#[derive(Parser, Debug)]
+#[command(name = "gamemode")]
+struct Gamemode {
+    #[arg(value_enum)]
+    mode: hyperion_clap::GameMode,
+}
+
+#[derive(Parser, Debug)]
+#[command(name = "perms")]
+struct Perms {
+    #[command(subcommand)]
+    command: PermsCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum PermsCommand {
+    Get { player: String },
+}
+
+fn positional(command: &clap::Command, index: usize) -> clap::Arg {
+    command.get_positionals().nth(index).unwrap().clone()
+}
+
+#[test]
+fn test_parsers() {
+    let xp = Xp::command();
+    assert_eq!(parser(&positional(&xp, 0)), Brigadier::Integer {
+        min: Some(0),
+        max: Some(65535),
+    });
+
+    let tp = Teleport::command();
+    assert_eq!(parser(&positional(&tp, 0)), Brigadier::BlockPos);
+    assert_eq!(
+        parser(&positional(&tp, 1)),
+        Brigadier::String(StringArg::QuotablePhrase)
+    );
+
+    let say = Say::command();
+    assert_eq!(
+        parser(&positional(&say, 0)),
+        Brigadier::String(StringArg::GreedyPhrase)
+    );
+
+    let player = clap::Arg::new("player").minecraft(Arg::Player);
+    assert_eq!(parser(&player), Brigadier::GameProfile);
+
+    let gamemode = Gamemode::command();
+    assert!(is_literal(&positional(&gamemode, 0)));
+    assert!(!is_literal(&positional(&xp, 0)));
+}
+
+#[test]
+fn test_tokenize() {
+    let tp = Teleport::command();
+    let tokens = tokenize(r#"tp 10 -64 3 "it's \"fine\"""#, &tp, &Origin::default());
+    assert_eq!(tokens, ["tp", "10 -64 3", r#"it's "fine""#]);
+
+    let teleport = parse::<Teleport>(r#"tp 10 -64 3 "it's \"fine\"""#, &Origin::default()).unwrap();
+    assert_eq!(teleport.position, BlockPos(IVec3::new(10, -64, 3)));
+    assert_eq!(teleport.reason.as_deref(), Some(r#"it's "fine""#));
+
+    let say = Say::command();
+    let tokens = tokenize("say hello  there 'world'", &say, &Origin::default());
+    assert_eq!(tokens, ["say", "hello  there 'world'"]);
+
+    let perms = Perms::command();
+    let tokens = tokenize("perms get 'Some Player'", &perms, &Origin::default());
+    assert_eq!(tokens, ["perms", "get", "Some Player"]);
+}
+
+#[test]
+fn test_negative_values() {
+    let teleport = parse::<Teleport>("tp -10 64 -3", &Origin::default()).unwrap();
+    assert_eq!(teleport.position, BlockPos(IVec3::new(-10, 64, -3)));
+    assert_eq!(teleport.reason, None);
+
+    let speed = parse::<Speed>("speed -1 -0.5", &Origin::default()).unwrap();
+    assert_eq!(speed.amount, -1);
+    assert!(
+        speed
+            .multiplier
+            .is_some_and(|multiplier| (multiplier + 0.5).abs() < f32::EPSILON)
+    );
+
+    // still a flag where no number is expected
+    assert!(parse::<Speed>("speed -x", &Origin::default()).is_err());
+}
+
+#[test]
+fn test_relative_coordinates() {
+    let origin = Origin {
+        position: Vec3::new(10.5, 64.0, -3.5),
+        ..Origin::default()
+    };
+
+    let teleport = parse::<Teleport>("tp ~ ~1 ~-1", &origin).unwrap();
+    assert_eq!(teleport.position, BlockPos(IVec3::new(10, 65, -5)));
+
+    // relative and absolute coordinates may be mixed
+    let teleport = parse::<Teleport>("tp 0 ~ ~0.5 reason", &origin).unwrap();
+    assert_eq!(teleport.position, BlockPos(IVec3::new(0, 64, -3)));
+    assert_eq!(teleport.reason.as_deref(), Some("reason"));
+
+    let summon = parse::<Summon>("summon ~ ~ ~-1.25", &origin).unwrap();
+    assert_eq!(summon.position, Vec3Pos(Vec3::new(10.5, 64.0, -4.75)));
+
+    assert!(parse::<Teleport>("tp ~x ~ ~", &origin).is_err());
+}
+
+#[test]
+fn test_local_coordinates() {
+    // facing south, towards +z, so left is +x
+    let origin = Origin {
+        position: Vec3::new(0.5, 64.0, 0.5),
+        yaw: 0.0,
+        pitch: 0.0,
+    };
+
+    let teleport = parse::<Teleport>("tp ^ ^ ^2", &origin).unwrap();
+    assert_eq!(teleport.position, BlockPos(IVec3::new(0, 64, 2)));
+
+    let teleport = parse::<Teleport>("tp ^3 ^1 ^", &origin).unwrap();
+    assert_eq!(teleport.position, BlockPos(IVec3::new(3, 65, 0)));
+
+    // facing east, towards +x
+    let origin = Origin {
+        yaw: -90.0,
+        ..origin
+    };
+    let summon = parse::<Summon>("summon ^ ^ ^4", &origin).unwrap();
+    assert!((summon.position.0 - Vec3::new(4.5, 64.0, 0.5)).length() < 1e-4);
+
+    // local coordinates cannot be mixed with other coordinates
+    assert!(parse::<Teleport>("tp ^ ~ ^", &origin).is_err());
+}
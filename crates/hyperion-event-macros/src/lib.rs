@@ -113,7 +113,7 @@ impl EventType {
     }
 
     /// Generates the necessary trait implementations for the event type
-    fn generate_impls(&self) -> proc_macro2::TokenStream {
+    fn generate_impls(&self, index: usize) -> proc_macro2::TokenStream {
         let path = &self.path;
         let ident = &self.ident;
         let field_name = self.ident.to_string().to_case(Case::Snake);
@@ -122,6 +122,7 @@ impl EventType {
         quote! {
             impl Event for #path::#ident {
                 fn input(elem: Self, events: &Events, world: &World) {
+                    events.pushed[#index].fetch_add(1, ::std::sync::atomic::Ordering::Relaxed);
                     unsafe {
                         (*events.#field_ident.0).push(elem, world);
                     }
@@ -144,26 +145,52 @@ impl EventsInput {
             format_ident!("{field_name}")
         });
 
+        let names = self
+            .events
+            .iter()
+            .map(|event| event.ident.to_string().to_case(Case::Snake));
+
+        let len = self.events.len();
+
         let initializers = self.events.iter().map(EventType::generate_initializer);
 
         // Generate all trait implementations
-        let impls = self.events.iter().map(EventType::generate_impls);
+        let impls = self
+            .events
+            .iter()
+            .enumerate()
+            .map(|(index, event)| event.generate_impls(index));
 
         // Generate the Events struct
         let events_struct = quote! {
             #[derive(Component)]
             pub struct Events {
                 #(#fields)*
+                /// How many events of each type were pushed since [`Events::take_pushed`] was
+                /// last called.
+                pushed: [::std::sync::atomic::AtomicUsize; #len],
             }
 
             impl Events {
+                /// The name of each event type, in the order of [`Events::take_pushed`].
+                pub const NAMES: [&'static str; #len] = [#(#names),*];
+
                 #[must_use]
                 pub fn initialize(world: &World) -> Self {
                     Self {
                         #(#initializers)*
+                        pushed: [const { ::std::sync::atomic::AtomicUsize::new(0) }; #len],
                     }
                 }
 
+                /// Returns how many events of each type were pushed since this was last called.
+                #[must_use]
+                pub fn take_pushed(&self) -> [usize; #len] {
+                    self.pushed
+                        .each_ref()
+                        .map(|pushed| pushed.swap(0, ::std::sync::atomic::Ordering::Relaxed))
+                }
+
                 pub fn clear(&mut self) {
                     #(
                         let ptr = self.#field_idents.0;
@@ -5,9 +5,10 @@ use flecs_ecs::{
     prelude::{Module, flecs},
 };
 use hyperion::{
+    ingress::PendingRemove,
     net::{Compose, ConnectionId},
     simulation::{Player, Uuid, command::get_command_packet},
-    storage::LocalDb,
+    storage::{AccessList, Ban, LocalDb},
 };
 use num_derive::{FromPrimitive, ToPrimitive};
 
@@ -37,6 +38,9 @@ pub enum Group {
     Admin,
 }
 
+/// The ban reason for players put in [`Group::Banned`].
+const BANNED_REASON: &str = "You have been banned";
+
 // todo:
 
 impl Module for PermissionModule {
@@ -44,22 +48,67 @@ impl Module for PermissionModule {
         world.component::<Group>();
         world.component::<storage::PermissionStorage>();
 
-        world.get::<&LocalDb>(|db| {
-            let storage = storage::PermissionStorage::new(db).unwrap();
-            world.set(storage);
+        let storage = world.get::<&LocalDb>(storage::PermissionStorage::new);
+
+        let storage = match storage {
+            Ok(storage) => storage,
+            Err(e) => {
+                tracing::error!("failed to open the permission storage: {e}");
+                return;
+            }
+        };
+
+        world.set(storage);
+
+        // the ban list decides who is banned: players are kept out by it during login, and a
+        // player who logs in while still in the banned group was unbanned or their ban expired
+        observer!(
+            world,
+            flecs::OnSet,
+            &Uuid,
+            &storage::PermissionStorage($),
+            &AccessList($),
+        )
+        .with::<Player>()
+        .each_entity(|entity, (uuid, permissions, access)| {
+            let mut group = permissions.get(**uuid);
+
+            if group == Group::Banned && matches!(access.active_ban(**uuid), Ok(None)) {
+                group = Group::Normal;
+            }
+
+            entity.set(group);
         });
 
-        observer!(world, flecs::OnSet, &Uuid, &storage::PermissionStorage($))
+        observer!(world, flecs::OnRemove, &Uuid, &Group, &storage::PermissionStorage($))
             .with::<Player>()
-            .each_entity(|entity, (uuid, permissions)| {
-                let group = permissions.get(**uuid);
-                entity.set(group);
+            .each(|(uuid, group, permissions)| {
+                if let Err(e) = permissions.set(**uuid, *group) {
+                    tracing::error!("failed to save the group of {uuid}: {e}");
+                }
             });
 
-        observer!(world, flecs::OnRemove, &Uuid, &Group, &storage::PermissionStorage($))
+        observer!(world, flecs::OnSet, &Uuid, &Group, &AccessList($))
             .with::<Player>()
-            .each(|(uuid, group, permissions)| {
-                permissions.set(**uuid, *group).unwrap();
+            .each_entity(|entity, (uuid, group, access)| {
+                if *group != Group::Banned {
+                    return;
+                }
+
+                // keep the reason and expiry of an existing ban
+                let banned = match access.active_ban(**uuid) {
+                    Ok(ban) => ban.is_some(),
+                    Err(e) => {
+                        tracing::error!("failed to look up the ban of {uuid}: {e}");
+                        false
+                    }
+                };
+
+                if !banned && let Err(e) = access.ban(**uuid, &Ban::permanent(BANNED_REASON)) {
+                    tracing::error!("failed to ban {uuid}: {e}");
+                }
+
+                entity.set(PendingRemove::new(BANNED_REASON));
             });
 
         observer!(world, flecs::OnSet, &Group).each_iter(|it, row, _group| {
@@ -0,0 +1,129 @@
+//! Every message on a link is sent as a frame: its length as a big endian `u64`, the archived
+//! message, and then zeroes up to a multiple of [`FRAME_ALIGN`]. The padding keeps each message
+//! at the alignment it was archived with, so the receiver only has to read it into an
+//! [`AlignedVec`].
+
+use rkyv::{Portable, api::high::HighValidator, bytecheck::CheckBytes, rancor, util::AlignedVec};
+
+use crate::ServerToProxyMessage;
+
+/// The size of the length at the start of each frame.
+pub const LEN_PREFIX: usize = size_of::<u64>();
+
+/// Every frame is padded to a multiple of this, which is the largest alignment of an archived
+/// message.
+pub const FRAME_ALIGN: usize = 8;
+
+/// Frames longer than this are rejected, as a length this large almost certainly means the
+/// stream is out of sync.
+pub const MAX_FRAME_LEN: usize = 1 << 28;
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum FrameError {
+    #[error("frame of {0} bytes is longer than the maximum of {MAX_FRAME_LEN} bytes")]
+    TooLong(u64),
+}
+
+#[must_use]
+pub fn encode_len(len: usize) -> [u8; LEN_PREFIX] {
+    u64::try_from(len).unwrap().to_be_bytes()
+}
+
+/// The length of the message in a frame starting with `prefix`.
+pub fn decode_len(prefix: [u8; LEN_PREFIX]) -> Result<usize, FrameError> {
+    let len = u64::from_be_bytes(prefix);
+
+    usize::try_from(len)
+        .ok()
+        .filter(|&len| len <= MAX_FRAME_LEN)
+        .ok_or(FrameError::TooLong(len))
+}
+
+/// The number of zeroes after a message of `len` bytes.
+#[must_use]
+pub const fn padding(len: usize) -> usize {
+    len.next_multiple_of(FRAME_ALIGN) - len
+}
+
+/// Appends `message` as a frame to `buffer`, which must only contain whole frames.
+pub fn encode_frame(message: &ServerToProxyMessage<'_>, buffer: &mut AlignedVec) {
+    debug_assert_eq!(buffer.len() % FRAME_ALIGN, 0);
+
+    let start = buffer.len();
+    buffer.extend_from_slice(&[0; LEN_PREFIX]);
+
+    rkyv::api::high::to_bytes_in::<_, rancor::Error>(message, &mut *buffer).unwrap();
+
+    let len = buffer.len() - start - LEN_PREFIX;
+    buffer[start..start + LEN_PREFIX].copy_from_slice(&encode_len(len));
+    buffer.resize(buffer.len() + padding(len), 0);
+}
+
+/// Reads the archived message in `bytes`, which must start at an address aligned to
+/// [`FRAME_ALIGN`].
+///
+/// With the `validate` feature, a corrupted message is an error. Without it, the message is
+/// trusted, which is only sound because both ends of a link exchange a
+/// [`crate::Handshake`] before sending any frame.
+pub fn access<T>(bytes: &[u8]) -> Result<&T, rancor::Error>
+where
+    T: Portable + for<'a> CheckBytes<HighValidator<'a, rancor::Error>>,
+{
+    #[cfg(feature = "validate")]
+    {
+        rkyv::access::<T, rancor::Error>(bytes)
+    }
+
+    #[cfg(not(feature = "validate"))]
+    {
+        Ok(unsafe { rkyv::access_unchecked::<T>(bytes) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ArchivedServerToProxyMessage, Priority, Unicast};
+
+    #[test]
+    fn test_frames_stay_aligned() {
+        let mut buffer = AlignedVec::<16>::new();
+
+        for data in [&b"a"[..], b"abc", b"abcdefghi"] {
+            let message = ServerToProxyMessage::Unicast(Unicast {
+                data,
+                stream: 1,
+                order: 0,
+                priority: Priority::High,
+            });
+
+            encode_frame(&message, &mut buffer);
+            assert_eq!(buffer.len() % FRAME_ALIGN, 0);
+        }
+
+        let mut remaining = &buffer[..];
+
+        for data in [&b"a"[..], b"abc", b"abcdefghi"] {
+            let (prefix, rest) = remaining.split_at(LEN_PREFIX);
+            let len = decode_len(prefix.try_into().unwrap()).unwrap();
+
+            let message = access::<ArchivedServerToProxyMessage<'_>>(&rest[..len]).unwrap();
+
+            let ArchivedServerToProxyMessage::Unicast(unicast) = message else {
+                panic!("expected a unicast");
+            };
+
+            assert_eq!(&*unicast.data, data);
+
+            remaining = &rest[len + padding(len)..];
+        }
+
+        assert!(remaining.is_empty());
+    }
+
+    #[test]
+    fn test_len_too_long() {
+        let prefix = u64::MAX.to_be_bytes();
+        assert_eq!(decode_len(prefix), Err(FrameError::TooLong(u64::MAX)));
+    }
+}
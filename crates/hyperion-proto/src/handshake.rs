@@ -0,0 +1,153 @@
+use std::fmt;
+
+/// The first bytes both ends of a link send, so that anything other than a hyperion proxy or
+/// server is rejected straight away.
+pub const MAGIC: [u8; 4] = *b"HYPX";
+
+/// Bumped whenever a message or the framing changes in a way older builds cannot read.
+pub const PROTOCOL_VERSION: u16 = 8;
+
+/// Optional capabilities of one end of a link.
+#[derive(Copy, Clone, PartialEq, Eq, Default)]
+pub struct Features(u32);
+
+impl Features {
+    pub const NONE: Self = Self(0);
+    /// Stream ids carry the id of the proxy which owns them. See [`crate::stream_id`].
+    pub const PROXY_IDS: Self = Self(1 << 0);
+    /// Features the other end of a link must have.
+    pub const REQUIRED: Self = Self::PROXY_IDS;
+    /// Received frames are validated before they are read. See [`crate::access`].
+    pub const VALIDATE: Self = Self(1 << 1);
+
+    /// The features of this build.
+    #[must_use]
+    pub const fn local() -> Self {
+        if cfg!(feature = "validate") {
+            Self(Self::PROXY_IDS.0 | Self::VALIDATE.0)
+        } else {
+            Self::PROXY_IDS
+        }
+    }
+
+    #[must_use]
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl fmt::Debug for Features {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Features({:#x})", self.0)
+    }
+}
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum HandshakeError {
+    #[error("the other end is not a hyperion server or proxy (got {0:02x?} instead of the magic)")]
+    BadMagic([u8; 4]),
+    #[error(
+        "protocol version mismatch: this build speaks version {local} but the other end speaks \
+         version {remote}; make sure the server and proxy are built from the same revision"
+    )]
+    VersionMismatch { local: u16, remote: u16 },
+    #[error("the other end is missing required features {0:?}")]
+    MissingFeatures(Features),
+}
+
+/// What each end of a link sends before any frame: [`MAGIC`], then the protocol version and
+/// the feature bits, both big endian.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Handshake {
+    pub version: u16,
+    pub features: Features,
+}
+
+impl Handshake {
+    /// The encoded length of a handshake.
+    pub const LEN: usize = 10;
+
+    /// The handshake of this build.
+    #[must_use]
+    pub const fn local() -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            features: Features::local(),
+        }
+    }
+
+    #[must_use]
+    pub const fn encode(self) -> [u8; Self::LEN] {
+        let [m0, m1, m2, m3] = MAGIC;
+        let [v0, v1] = self.version.to_be_bytes();
+        let [f0, f1, f2, f3] = self.features.0.to_be_bytes();
+
+        [m0, m1, m2, m3, v0, v1, f0, f1, f2, f3]
+    }
+
+    /// Decodes the handshake of the other end and checks that this build can talk to it.
+    pub fn decode(bytes: [u8; Self::LEN]) -> Result<Self, HandshakeError> {
+        let [m0, m1, m2, m3, v0, v1, f0, f1, f2, f3] = bytes;
+
+        let magic = [m0, m1, m2, m3];
+        if magic != MAGIC {
+            return Err(HandshakeError::BadMagic(magic));
+        }
+
+        let version = u16::from_be_bytes([v0, v1]);
+        if version != PROTOCOL_VERSION {
+            return Err(HandshakeError::VersionMismatch {
+                local: PROTOCOL_VERSION,
+                remote: version,
+            });
+        }
+
+        let features = Features(u32::from_be_bytes([f0, f1, f2, f3]));
+        if !features.contains(Features::REQUIRED) {
+            return Err(HandshakeError::MissingFeatures(Features(
+                Features::REQUIRED.0 & !features.0,
+            )));
+        }
+
+        Ok(Self { version, features })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let handshake = Handshake::local();
+        assert_eq!(Handshake::decode(handshake.encode()), Ok(handshake));
+    }
+
+    #[test]
+    fn test_version_mismatch() {
+        let handshake = Handshake {
+            version: PROTOCOL_VERSION + 1,
+            features: Features::local(),
+        };
+
+        assert_eq!(
+            Handshake::decode(handshake.encode()),
+            Err(HandshakeError::VersionMismatch {
+                local: PROTOCOL_VERSION,
+                remote: PROTOCOL_VERSION + 1
+            })
+        );
+    }
+
+    #[test]
+    fn test_bad_magic() {
+        // what an older server sends first: the length of a frame
+        let mut bytes = [0; Handshake::LEN];
+        bytes[7] = 16;
+
+        assert_eq!(
+            Handshake::decode(bytes),
+            Err(HandshakeError::BadMagic([0; 4]))
+        );
+    }
+}
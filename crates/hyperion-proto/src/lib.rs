@@ -5,10 +5,14 @@
     hidden_glob_reexports
 )]
 
+mod frame;
+mod handshake;
 mod proxy_to_server;
 mod server_to_proxy;
 mod shared;
 
+pub use frame::*;
+pub use handshake::*;
 pub use proxy_to_server::*;
 pub use server_to_proxy::*;
 pub use shared::*;
@@ -1,3 +1,5 @@
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+
 use rkyv::{Archive, Deserialize, Serialize, with::InlineAsBox};
 
 #[derive(Archive, Deserialize, Serialize, Clone, PartialEq, Debug)]
@@ -8,9 +10,70 @@ pub struct PlayerPackets<'a> {
     pub data: &'a [u8],
 }
 
+/// The first message sent to the server after the [`crate::Handshake`]. The server answers with
+/// [`crate::AssignProxyId`].
+#[derive(Archive, Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
+pub struct RequestProxyId {
+    /// The id this proxy had before it reconnected, or `0` if it has not been assigned one yet.
+    /// The server assigns it again if it is free, so that held players keep their stream ids.
+    pub previous: u16,
+}
+
+/// Sent periodically to measure the latency of the link. The server answers with
+/// [`crate::Pong`].
 #[derive(Archive, Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
-pub struct PlayerConnect {
+pub struct Ping {
+    /// Echoed back by the server. Only meaningful to the proxy.
+    pub nonce: u64,
+}
+
+#[derive(Archive, Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
+pub struct PlayerConnect<'a> {
     pub stream: u64,
+    /// Set if the player was already playing before the proxy reconnected to the server.
+    pub rejoin: Option<Rejoin<'a>>,
+    /// The address of the client, or `None` if it did not connect over IP.
+    pub addr: Option<ClientAddr>,
+}
+
+/// The address of a client. If the proxy is behind a load balancer which speaks the PROXY
+/// protocol, this is the address the load balancer reported.
+#[derive(Archive, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ClientAddr {
+    /// IPv4 addresses are mapped to IPv6.
+    pub ip: [u8; 16],
+    pub port: u16,
+}
+
+impl From<SocketAddr> for ClientAddr {
+    fn from(addr: SocketAddr) -> Self {
+        let ip = match addr.ip() {
+            IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+            IpAddr::V6(ip) => ip,
+        };
+
+        Self {
+            ip: ip.octets(),
+            port: addr.port(),
+        }
+    }
+}
+
+impl From<ClientAddr> for SocketAddr {
+    fn from(addr: ClientAddr) -> Self {
+        let ip = Ipv6Addr::from(addr.ip).to_canonical();
+        Self::new(ip, addr.port)
+    }
+}
+
+/// Who a rejoining player is, as last told to the proxy with [`crate::SetRejoin`]. The client is
+/// still in the play state with compression enabled.
+#[derive(Archive, Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
+pub struct Rejoin<'a> {
+    pub uuid: [u8; 16],
+
+    #[rkyv(with = InlineAsBox)]
+    pub username: &'a str,
 }
 
 #[derive(Archive, Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
@@ -31,7 +94,22 @@ pub enum PlayerDisconnectReason<'a> {
 
 #[derive(Archive, Deserialize, Serialize, Clone, PartialEq, Debug)]
 pub enum ProxyToServerMessage<'a> {
-    PlayerConnect(PlayerConnect),
+    RequestProxyId(RequestProxyId),
+    PlayerConnect(PlayerConnect<'a>),
     PlayerDisconnect(PlayerDisconnect<'a>),
     PlayerPackets(PlayerPackets<'a>),
+    Ping(Ping),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_addr_round_trip() {
+        for addr in ["127.0.0.1:25565", "[2001:db8::1]:443"] {
+            let addr: SocketAddr = addr.parse().unwrap();
+            assert_eq!(SocketAddr::from(ClientAddr::from(addr)), addr);
+        }
+    }
 }
@@ -1,12 +1,25 @@
 use rkyv::{Archive, Deserialize, Serialize, with::InlineAsBox};
 
-use crate::ChunkPosition;
+use crate::{ChunkPosition, Priority};
+
+/// The answer to [`crate::RequestProxyId`] and the first message sent to a proxy. The proxy must
+/// build all of its stream ids with [`crate::stream_id`] from this id. Ids start at `1`, so no
+/// stream id is `0`.
+#[derive(Archive, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[rkyv(derive(Debug))]
+pub struct AssignProxyId {
+    pub proxy_id: u16,
+}
 
 #[derive(Archive, Deserialize, Serialize, Clone, PartialEq)]
 #[rkyv(derive(Debug))]
 pub struct UpdatePlayerChunkPositions {
     pub stream: Vec<u64>,
     pub positions: Vec<ChunkPosition>,
+    /// The radius in chunks around each position that local broadcasts reach.
+    pub view_distances: Vec<i16>,
+    /// The world each player is in. Local broadcasts only reach players in the same world.
+    pub worlds: Vec<u64>,
 }
 
 #[derive(Archive, Deserialize, Serialize, Clone, Copy, PartialEq)]
@@ -15,6 +28,36 @@ pub struct SetReceiveBroadcasts {
     pub stream: u64,
 }
 
+/// Encrypts everything written to the player from this message onwards and decrypts everything the
+/// player sends after their encryption response with AES/CFB8, using `key` as both the key and
+/// the IV.
+#[derive(Archive, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[rkyv(derive(Debug))]
+pub struct SetEncryption {
+    pub stream: u64,
+    pub key: [u8; 16],
+}
+
+/// Lets the proxy hold the player while it reconnects to the server, and then resume the stream
+/// with [`crate::PlayerConnect::rejoin`]. Sent once the player is in the play state.
+#[derive(Archive, Deserialize, Serialize, Clone, Copy, PartialEq)]
+pub struct SetRejoin<'a> {
+    pub stream: u64,
+    pub uuid: [u8; 16],
+
+    #[rkyv(with = InlineAsBox)]
+    pub username: &'a str,
+}
+
+/// Packets the proxy sends to the players it holds while it reconnects to the server, such as a
+/// title saying that the server is restarting. They are encoded for the play state with
+/// compression enabled.
+#[derive(Archive, Deserialize, Serialize, Clone, PartialEq)]
+pub struct SetLimbo<'a> {
+    #[rkyv(with = InlineAsBox)]
+    pub data: &'a [u8],
+}
+
 #[derive(Archive, Deserialize, Serialize, Clone, PartialEq)]
 pub struct BroadcastGlobal<'a> {
     pub exclude: u64,
@@ -27,8 +70,14 @@ pub struct BroadcastGlobal<'a> {
 #[derive(Archive, Deserialize, Serialize, Clone, PartialEq)]
 pub struct BroadcastLocal<'a> {
     pub center: ChunkPosition,
+    /// The world [`Self::center`] is in. `0` is the main world.
+    pub world: u64,
     pub exclude: u64,
+    /// More players who do not receive this, such as players who hide chat.
+    #[rkyv(with = InlineAsBox)]
+    pub exclude_more: &'a [u64],
     pub order: u32,
+    pub priority: Priority,
 
     #[rkyv(with = InlineAsBox)]
     pub data: &'a [u8],
@@ -38,21 +87,87 @@ pub struct BroadcastLocal<'a> {
 pub struct Unicast<'a> {
     pub stream: u64,
     pub order: u32,
+    pub priority: Priority,
+
+    #[rkyv(with = InlineAsBox)]
+    pub data: &'a [u8],
+}
+
+/// Stores the encoded packets of a chunk in the chunk cache of the proxy, so that the server can
+/// send them with [`UnicastCachedChunk`] afterwards. `hash` is a hash of `data`.
+#[derive(Archive, Deserialize, Serialize, Clone, PartialEq)]
+pub struct CacheChunk<'a> {
+    pub hash: u64,
 
     #[rkyv(with = InlineAsBox)]
     pub data: &'a [u8],
 }
 
+/// Like [`Unicast`] with the data of a cached chunk, which has [`Priority::Bulk`]. The
+/// [`CacheChunk`] of `hash` may arrive later in the same tick, so this is only resolved on
+/// [`Flush`].
+#[derive(Archive, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[rkyv(derive(Debug))]
+pub struct UnicastCachedChunk {
+    pub stream: u64,
+    pub order: u32,
+    pub hash: u64,
+    /// The world the chunk is in, see [`DiscardDelayed`].
+    pub world: u64,
+    pub position: ChunkPosition,
+}
+
+/// Drops the [`Priority::Bulk`] messages the proxy has not written to `stream` yet because a
+/// packet sent after this makes them outdated.
+///
+/// With a `position`, only the data of that chunk of `world` is dropped, which is sent before the
+/// chunk is unloaded. Without one, every delayed message and the chunks of `world` are dropped,
+/// which is sent before the player leaves `world`.
+#[derive(Archive, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[rkyv(derive(Debug))]
+pub struct DiscardDelayed {
+    pub stream: u64,
+    pub world: u64,
+    pub position: Option<ChunkPosition>,
+}
+
+/// Removes a chunk from the chunk cache because the chunk changed or the cache is full. The
+/// server keeps track of what the proxy caches, so this is the only way chunks are removed. The
+/// proxy removes it on the
+/// next [`Flush`], after sending the [`UnicastCachedChunk`]s of the tick, unless a [`CacheChunk`]
+/// of the same tick caches it again.
+#[derive(Archive, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[rkyv(derive(Debug))]
+pub struct EvictCachedChunk {
+    pub hash: u64,
+}
+
 #[derive(Archive, Deserialize, Serialize, Clone, Copy, PartialEq)]
 #[rkyv(derive(Debug))]
 pub struct Flush;
 
+/// The answer to [`crate::Ping`], sent as soon as the ping is read.
+#[derive(Archive, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[rkyv(derive(Debug))]
+pub struct Pong {
+    pub nonce: u64,
+}
+
 #[derive(Archive, Deserialize, Serialize, Clone, PartialEq)]
 pub enum ServerToProxyMessage<'a> {
+    AssignProxyId(AssignProxyId),
     UpdatePlayerChunkPositions(UpdatePlayerChunkPositions),
     BroadcastGlobal(BroadcastGlobal<'a>),
     BroadcastLocal(BroadcastLocal<'a>),
     Unicast(Unicast<'a>),
     SetReceiveBroadcasts(SetReceiveBroadcasts),
+    SetEncryption(SetEncryption),
+    SetRejoin(SetRejoin<'a>),
+    SetLimbo(SetLimbo<'a>),
     Flush(Flush),
+    Pong(Pong),
+    CacheChunk(CacheChunk<'a>),
+    UnicastCachedChunk(UnicastCachedChunk),
+    EvictCachedChunk(EvictCachedChunk),
+    DiscardDelayed(DiscardDelayed),
 }
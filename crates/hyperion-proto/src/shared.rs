@@ -23,3 +23,68 @@ impl From<I16Vec2> for ChunkPosition {
         }
     }
 }
+
+/// How the proxy treats packets while a player's queue is backed up.
+#[derive(
+    Archive,
+    Deserialize,
+    Serialize,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    Debug,
+    Default
+)]
+#[rkyv(derive(Debug))]
+pub enum Priority {
+    /// Packets the player must get, such as velocity, damage and teleports. The player is
+    /// disconnected if they cannot keep up with them.
+    #[default]
+    High,
+    /// Bulk packets such as chunks, which are delayed until the player catches up.
+    Bulk,
+    /// Packets such as animations, particles and sounds, which are dropped instead.
+    Cosmetic,
+}
+
+/// How far the id of the proxy a stream belongs to is shifted within the stream id. Each proxy
+/// numbers its streams on its own, so the proxy id keeps stream ids unique across proxies.
+pub const PROXY_ID_SHIFT: u32 = 48;
+
+/// The id of the stream `local_id` of the proxy `proxy_id`.
+#[must_use]
+pub fn stream_id(proxy_id: u16, local_id: u64) -> u64 {
+    debug_assert!(local_id < 1 << PROXY_ID_SHIFT);
+    (u64::from(proxy_id) << PROXY_ID_SHIFT) | local_id
+}
+
+/// The id of the proxy which owns `stream`.
+#[must_use]
+pub fn proxy_id(stream: u64) -> u16 {
+    u16::try_from(stream >> PROXY_ID_SHIFT).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stream_id_round_trip() {
+        for proxy in [1, 2, 0x1234, u16::MAX] {
+            for local in [0, 1, (1 << PROXY_ID_SHIFT) - 1] {
+                let stream = stream_id(proxy, local);
+
+                assert_eq!(proxy_id(stream), proxy);
+                assert_eq!(stream & ((1 << PROXY_ID_SHIFT) - 1), local);
+            }
+        }
+    }
+
+    #[test]
+    fn test_stream_ids_of_proxies_differ() {
+        assert_ne!(stream_id(1, 5), stream_id(2, 5));
+        assert_ne!(stream_id(1, 0), 0);
+    }
+}
@@ -1,12 +1,17 @@
-use std::{ops::Range, sync::Arc};
+use std::{collections::HashMap, ops::Range, sync::Arc, time::Instant};
 
 use bvh::{Bvh, Data, Point};
+use bytes::Bytes;
 use glam::I16Vec2;
-use hyperion_proto::{ArchivedServerToProxyMessage, BroadcastGlobal};
+use hyperion_proto::{ArchivedServerToProxyMessage, BroadcastGlobal, ChunkPosition, Priority};
 use more_asserts::debug_assert_le;
 use rustc_hash::FxBuildHasher;
+use tracing::warn;
 
-use crate::egress::{BroadcastLocalInstruction, Egress};
+use crate::{
+    egress::{BroadcastLocalInstruction, Egress},
+    metrics::{Kind, METRICS},
+};
 
 /// Represents a node in the exclusion list, containing information about the previous node
 /// and the range of the exclusion.
@@ -159,6 +164,9 @@ struct LocalBroadcastData {
     range_start: usize,
     range_end: usize,
     player_id_to_exclude: u64,
+    /// The range of [`LocalBroadcasts::excluded`] with the other players to exclude.
+    excluded_start: usize,
+    excluded_end: usize,
 }
 
 impl LocalBroadcastData {
@@ -183,13 +191,23 @@ impl Data for LocalBroadcastData {
     }
 }
 
+/// The local broadcasts of one world since the last flush.
+#[derive(Default)]
+struct LocalBroadcasts {
+    raw_data: Vec<u8>,
+    buffer: Vec<LocalBroadcastData>,
+    /// The players excluded with `exclude_more`, which each broadcast refers to a range of.
+    excluded: Vec<u64>,
+}
+
 /// Buffers egress operations for optimized processing.
 pub struct BufferedEgress {
     /// Buffer for required broadcast data.
     global_broadcast_buffer: Vec<u8>,
 
-    raw_local_broadcast_data: Vec<u8>,
-    local_broadcast_buffer: Vec<LocalBroadcastData>,
+    /// Local broadcasts by world and priority. Entries are kept across flushes to reuse their
+    /// buffers.
+    local_broadcasts: HashMap<(u64, Priority), LocalBroadcasts, FxBuildHasher>,
 
     /// Manages player-specific exclusions.
     exclusion_manager: ExclusionsManager,
@@ -198,6 +216,91 @@ pub struct BufferedEgress {
     /// Tracks the current broadcast order.
     current_broadcast_order: Option<u32>,
     local_flush_counter: u32,
+
+    chunks: ChunkCache,
+}
+
+/// A cached chunk requested with [`hyperion_proto::UnicastCachedChunk`].
+struct PendingChunk {
+    stream: u64,
+    order: u32,
+    hash: u64,
+    world: u64,
+    position: ChunkPosition,
+}
+
+/// The packets of chunks by their hash, which the server sends once and then refers to, so that
+/// it does not send the same chunk for every player who walks into it.
+/// The server bounds its size by evicting chunks.
+#[derive(Default)]
+struct ChunkCache {
+    chunks: HashMap<u64, Bytes, FxBuildHasher>,
+    /// Cached chunks to send to players on the next flush.
+    pending: Vec<PendingChunk>,
+    /// Chunks evicted since the last flush. They are only removed once the pending chunks are
+    /// sent, as the server may request a chunk and then change it in the same tick.
+    evicted: Vec<u64>,
+}
+
+impl ChunkCache {
+    fn insert(&mut self, hash: u64, data: Bytes) {
+        // the server caches a chunk again after evicting it if it changed back
+        self.evicted.retain(|&evicted| evicted != hash);
+
+        METRICS.chunk_cache_resized(data.len() as i64);
+
+        if let Some(previous) = self.chunks.insert(hash, data) {
+            METRICS.chunk_cache_resized(-(previous.len() as i64));
+        }
+    }
+
+    fn request(&mut self, chunk: PendingChunk) {
+        self.pending.push(chunk);
+    }
+
+    fn evict(&mut self, hash: u64) {
+        self.evicted.push(hash);
+    }
+
+    /// Forgets the pending chunks of `stream` in `world`, or only the one at `position`.
+    fn discard(&mut self, stream: u64, world: u64, position: Option<ChunkPosition>) {
+        self.pending.retain(|chunk| {
+            chunk.stream != stream
+                || chunk.world != world
+                || position.is_some_and(|position| chunk.position != position)
+        });
+    }
+
+    /// Passes the chunks requested since the last flush to `send`, then applies the evictions.
+    /// This waits for the flush because the server may send a chunk to cache after the first
+    /// request for it in the same tick.
+    fn flush(&mut self, mut send: impl FnMut(PendingChunk, Bytes)) {
+        for chunk in self.pending.drain(..) {
+            let Some(data) = self.chunks.get(&chunk.hash) else {
+                warn!(
+                    "the server sent chunk {:#x} to stream {:#x} without caching it",
+                    chunk.hash, chunk.stream
+                );
+                continue;
+            };
+
+            send(chunk, data.clone());
+        }
+
+        for hash in self.evicted.drain(..) {
+            if let Some(evicted) = self.chunks.remove(&hash) {
+                METRICS.chunk_cache_resized(-(evicted.len() as i64));
+            }
+        }
+    }
+}
+
+/// The chunk cache only lasts as long as the connection to the server.
+impl Drop for ChunkCache {
+    fn drop(&mut self) {
+        let size: usize = self.chunks.values().map(Bytes::len).sum();
+        METRICS.chunk_cache_resized(-(size as i64));
+    }
 }
 
 impl BufferedEgress {
@@ -206,12 +309,12 @@ impl BufferedEgress {
     pub fn new(egress: Egress) -> Self {
         Self {
             global_broadcast_buffer: Vec::new(),
-            raw_local_broadcast_data: vec![],
-            local_broadcast_buffer: Vec::default(),
+            local_broadcasts: HashMap::default(),
             exclusion_manager: ExclusionsManager::default(),
             egress,
             current_broadcast_order: None,
             local_flush_counter: 0,
+            chunks: ChunkCache::default(),
         }
     }
 
@@ -219,6 +322,9 @@ impl BufferedEgress {
     // #[instrument(skip_all)]
     pub fn handle_packet(&mut self, message: &ArchivedServerToProxyMessage<'_>) {
         match message {
+            ArchivedServerToProxyMessage::AssignProxyId(_) => {
+                warn!("the server assigned a proxy id more than once");
+            }
             ArchivedServerToProxyMessage::UpdatePlayerChunkPositions(packet) => {
                 self.egress.handle_update_player_chunk_positions(packet);
             }
@@ -234,6 +340,8 @@ impl BufferedEgress {
 
                 self.current_broadcast_order = Some(packet_order);
 
+                METRICS.server_bytes(Kind::GlobalBroadcast, packet.data.len());
+
                 let current_len = self.global_broadcast_buffer.len();
                 self.global_broadcast_buffer.extend_from_slice(&packet.data);
 
@@ -253,77 +361,173 @@ impl BufferedEgress {
                 let Ok(center_x) = rkyv::deserialize::<i16, !>(&packet.center.x);
                 let Ok(center_z) = rkyv::deserialize::<i16, !>(&packet.center.z);
                 let Ok(player_id_to_exclude) = rkyv::deserialize::<u64, !>(&packet.exclude);
+                let Ok(world) = rkyv::deserialize::<u64, !>(&packet.world);
+                let Ok(priority) = rkyv::deserialize::<Priority, !>(&packet.priority);
 
                 let position = I16Vec2::new(center_x, center_z);
 
-                let before_len = self.raw_local_broadcast_data.len();
-                self.raw_local_broadcast_data
-                    .extend_from_slice(&packet.data);
-                let after_len = self.raw_local_broadcast_data.len();
+                METRICS.server_bytes(Kind::LocalBroadcast, packet.data.len());
+
+                let broadcasts = self.local_broadcasts.entry((world, priority)).or_default();
+
+                let before_len = broadcasts.raw_data.len();
+                broadcasts.raw_data.extend_from_slice(&packet.data);
+                let after_len = broadcasts.raw_data.len();
 
-                self.local_broadcast_buffer.push(LocalBroadcastData {
+                let excluded_start = broadcasts.excluded.len();
+                broadcasts
+                    .excluded
+                    .extend(packet.exclude_more.iter().map(|id| {
+                        let Ok(id) = rkyv::deserialize::<u64, !>(id);
+                        id
+                    }));
+                let excluded_end = broadcasts.excluded.len();
+
+                broadcasts.buffer.push(LocalBroadcastData {
                     // todo: checked
                     position,
                     range_start: before_len,
                     range_end: after_len,
                     player_id_to_exclude,
+                    excluded_start,
+                    excluded_end,
                 });
             }
             ArchivedServerToProxyMessage::Unicast(unicast) => {
+                METRICS.server_bytes(Kind::Unicast, unicast.data.len());
                 self.egress.handle_unicast(unicast);
             }
             ArchivedServerToProxyMessage::SetReceiveBroadcasts(pkt) => {
                 self.egress.handle_set_receive_broadcasts(pkt);
             }
+            ArchivedServerToProxyMessage::SetEncryption(pkt) => {
+                self.egress.handle_set_encryption(pkt);
+            }
+            ArchivedServerToProxyMessage::SetRejoin(pkt) => {
+                self.egress.handle_set_rejoin(pkt);
+            }
+            ArchivedServerToProxyMessage::SetLimbo(pkt) => {
+                self.egress.handle_set_limbo(pkt);
+            }
             ArchivedServerToProxyMessage::Flush(_) => {
                 if let Some(order) = self.current_broadcast_order.take() {
                     self.flush_broadcast(order);
                 }
 
+                self.flush_cached_chunks();
+
                 self.egress.handle_flush();
                 self.local_flush_counter = 0;
 
-                if self.local_broadcast_buffer.is_empty() {
-                    return;
-                }
+                for (&(world, priority), broadcasts) in &mut self.local_broadcasts {
+                    if broadcasts.buffer.is_empty() {
+                        continue;
+                    }
 
-                let bvh = Bvh::build(
-                    &mut self.local_broadcast_buffer,
-                    &self.raw_local_broadcast_data,
-                );
+                    Self::flush_local_broadcasts(self.egress, world, priority, broadcasts);
+                }
+            }
+            ArchivedServerToProxyMessage::Pong(pong) => {
+                let Ok(nonce) = rkyv::deserialize::<u64, !>(&pong.nonce);
+                METRICS.pong(nonce);
+            }
+            ArchivedServerToProxyMessage::CacheChunk(pkt) => {
+                let Ok(hash) = rkyv::deserialize::<u64, !>(&pkt.hash);
 
-                let mut exclusions = ExclusionsManager::default();
-                let mut idx_on = 0;
+                METRICS.server_bytes(Kind::CachedChunk, pkt.data.len());
 
-                for packet in &self.local_broadcast_buffer {
-                    // todo: is there a more idiomatic way to do this?
-                    let packet_len = packet.len();
-                    let range = idx_on..idx_on + packet_len;
+                self.chunks.insert(hash, Bytes::copy_from_slice(&pkt.data));
+            }
+            ArchivedServerToProxyMessage::UnicastCachedChunk(pkt) => {
+                let Ok(stream) = rkyv::deserialize::<u64, !>(&pkt.stream);
+                let Ok(order) = rkyv::deserialize::<u32, !>(&pkt.order);
+                let Ok(hash) = rkyv::deserialize::<u64, !>(&pkt.hash);
+                let Ok(world) = rkyv::deserialize::<u64, !>(&pkt.world);
+                let Ok(position) = rkyv::deserialize::<ChunkPosition, !>(&pkt.position);
+
+                self.chunks.request(PendingChunk {
+                    stream,
+                    order,
+                    hash,
+                    world,
+                    position,
+                });
+            }
+            ArchivedServerToProxyMessage::EvictCachedChunk(pkt) => {
+                let Ok(hash) = rkyv::deserialize::<u64, !>(&pkt.hash);
+                self.chunks.evict(hash);
+            }
+            ArchivedServerToProxyMessage::DiscardDelayed(pkt) => {
+                let Ok(stream) = rkyv::deserialize::<u64, !>(&pkt.stream);
+                let Ok(world) = rkyv::deserialize::<u64, !>(&pkt.world);
+                let Ok(position) = rkyv::deserialize::<Option<ChunkPosition>, !>(&pkt.position);
 
-                    if packet.player_id_to_exclude != 0 {
-                        exclusions.append_exclusion(packet.player_id_to_exclude, range);
-                    }
+                // chunks requested earlier in this tick are not delayed yet
+                self.chunks.discard(stream, world, position);
 
-                    idx_on += packet_len;
-                }
+                self.egress.discard_delayed(stream, world, position);
+            }
+        }
+    }
 
-                self.local_broadcast_buffer.clear();
-                self.raw_local_broadcast_data.clear();
+    /// Sends the cached chunks requested since the last flush.
+    fn flush_cached_chunks(&mut self) {
+        let egress = self.egress;
 
-                let egress = self.egress;
-                tokio::spawn(async move {
-                    let bvh = bvh.into_bytes();
+        self.chunks.flush(|chunk, data| {
+            METRICS.cached_chunk_sent(data.len());
+            egress.unicast_chunk(chunk.stream, chunk.order, data, chunk.world, chunk.position);
+        });
+    }
 
-                    let instruction = BroadcastLocalInstruction {
-                        order: 0,
-                        bvh: Arc::new(bvh),
-                        exclusions: Arc::new(exclusions),
-                    };
+    /// Sends the local broadcasts of one world and priority to the players in that world and
+    /// clears them.
+    fn flush_local_broadcasts(
+        egress: Egress,
+        world: u64,
+        priority: Priority,
+        broadcasts: &mut LocalBroadcasts,
+    ) {
+        let start = Instant::now();
+        let bvh = Bvh::build(&mut broadcasts.buffer, &broadcasts.raw_data);
+        METRICS.bvh_build(start.elapsed());
+
+        let mut exclusions = ExclusionsManager::default();
+        let mut idx_on = 0;
+
+        for packet in &broadcasts.buffer {
+            // todo: is there a more idiomatic way to do this?
+            let packet_len = packet.len();
+            let range = idx_on..idx_on + packet_len;
+
+            if packet.player_id_to_exclude != 0 {
+                exclusions.append_exclusion(packet.player_id_to_exclude, range.clone());
+            }
 
-                    egress.handle_broadcast_local(instruction);
-                });
+            for &player in &broadcasts.excluded[packet.excluded_start..packet.excluded_end] {
+                exclusions.append_exclusion(player, range.clone());
             }
+
+            idx_on += packet_len;
         }
+
+        broadcasts.buffer.clear();
+        broadcasts.raw_data.clear();
+        broadcasts.excluded.clear();
+
+        tokio::spawn(async move {
+            let bvh = bvh.into_bytes();
+
+            let instruction = BroadcastLocalInstruction {
+                order: 0,
+                bvh: Arc::new(bvh),
+                exclusions: Arc::new(exclusions),
+                world,
+                priority,
+            };
+
+            egress.handle_broadcast_local(instruction);
+        });
     }
 
     /// Flushes the current broadcast buffer.
@@ -341,3 +545,87 @@ impl BufferedEgress {
         self.global_broadcast_buffer.clear();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use hyperion_proto::ChunkPosition;
+
+    use super::{ChunkCache, PendingChunk};
+
+    const HASH: u64 = 42;
+
+    fn request(stream: u64) -> PendingChunk {
+        PendingChunk {
+            stream,
+            order: 0,
+            hash: HASH,
+            world: 0,
+            position: ChunkPosition { x: 1, z: 2 },
+        }
+    }
+
+    fn flush(cache: &mut ChunkCache) -> Vec<(u64, Bytes)> {
+        let mut sent = Vec::new();
+        cache.flush(|chunk, data| sent.push((chunk.stream, data)));
+        sent
+    }
+
+    #[test]
+    fn test_chunk_sent_and_changed_in_one_tick() {
+        let mut cache = ChunkCache::default();
+        cache.insert(HASH, Bytes::from_static(b"chunk"));
+        assert!(flush(&mut cache).is_empty());
+
+        // the server requests the chunk, then evicts it as it changed later in the tick
+        cache.request(request(1));
+        cache.evict(HASH);
+
+        assert_eq!(flush(&mut cache), [(1, Bytes::from_static(b"chunk"))]);
+
+        cache.request(request(2));
+        assert!(flush(&mut cache).is_empty());
+    }
+
+    #[test]
+    fn test_chunk_cached_again_after_eviction() {
+        let mut cache = ChunkCache::default();
+        cache.insert(HASH, Bytes::from_static(b"chunk"));
+
+        cache.evict(HASH);
+        cache.insert(HASH, Bytes::from_static(b"chunk"));
+        cache.request(request(1));
+        assert_eq!(flush(&mut cache), [(1, Bytes::from_static(b"chunk"))]);
+
+        cache.request(request(2));
+        assert_eq!(flush(&mut cache), [(2, Bytes::from_static(b"chunk"))]);
+    }
+
+    #[test]
+    fn test_discard_pending_chunks() {
+        let mut cache = ChunkCache::default();
+        cache.insert(HASH, Bytes::from_static(b"chunk"));
+
+        cache.request(request(1));
+        cache.request(request(2));
+        cache.discard(1, 0, Some(ChunkPosition { x: 1, z: 2 }));
+        cache.discard(2, 0, Some(ChunkPosition { x: 0, z: 0 }));
+
+        assert_eq!(flush(&mut cache), [(2, Bytes::from_static(b"chunk"))]);
+    }
+
+    #[test]
+    fn test_eviction_frees_the_chunk() {
+        let mut cache = ChunkCache::default();
+        cache.insert(HASH, Bytes::from_static(b"chunk"));
+        cache.insert(HASH + 1, Bytes::from_static(b"other chunk"));
+
+        // the server evicts chunks when its record of the cache is full
+        cache.evict(HASH);
+        assert!(flush(&mut cache).is_empty());
+
+        assert!(!cache.chunks.contains_key(&HASH));
+        assert!(cache.chunks.contains_key(&(HASH + 1)));
+        assert!(cache.evicted.is_empty());
+    }
+}
@@ -1,10 +1,22 @@
-use std::sync::{Arc, atomic, atomic::AtomicBool};
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex, OnceLock, atomic, atomic::AtomicBool},
+};
 
 use anyhow::bail;
 use bytes::Bytes;
+use hyperion_proto::{ChunkPosition, Priority};
 use slotmap::{KeyData, new_key_type};
 
-use crate::cache::ExclusionsManager;
+use crate::{MAX_PLAYER_PENDING_MESSAGES, cache::ExclusionsManager, metrics::METRICS};
+
+/// A player with this many messages queued is backed up: packets without [`Priority::High`] are
+/// then delayed or dropped instead of queued.
+const BACKED_UP_MESSAGES: usize = MAX_PLAYER_PENDING_MESSAGES / 2;
+
+/// How many [`Priority::Bulk`] messages a backed up player may have delayed before they are
+/// disconnected.
+const MAX_DELAYED_MESSAGES: usize = MAX_PLAYER_PENDING_MESSAGES;
 
 new_key_type! {
     pub struct PlayerId;
@@ -17,6 +29,7 @@ impl From<u64> for PlayerId {
     }
 }
 
+#[derive(Debug)]
 pub struct OrderedBytes {
     /// The order number for this packet. Packets can be received in any order,
     /// but will be reordered before being written to ensure monotonically increasing order.
@@ -47,6 +60,17 @@ impl OrderedBytes {
         exclusions: None,
     };
 
+    /// Tells the player's writer to encrypt everything after this with the key in `data`.
+    #[must_use]
+    pub fn enable_encryption(key: [u8; 16]) -> Self {
+        Self {
+            order: u32::MAX - 2,
+            offset: 0,
+            data: Bytes::copy_from_slice(&key),
+            exclusions: None,
+        }
+    }
+
     pub const fn is_flush(&self) -> bool {
         self.order == u32::MAX
     }
@@ -55,6 +79,10 @@ impl OrderedBytes {
         self.order == u32::MAX - 1
     }
 
+    pub const fn is_enable_encryption(&self) -> bool {
+        self.order == u32::MAX - 2
+    }
+
     pub const fn no_order(data: Bytes) -> Self {
         Self {
             order: 0,
@@ -97,10 +125,41 @@ impl Ord for OrderedBytes {
     }
 }
 
+/// What the server tells the proxy about a player which the player's reader needs.
+#[derive(Debug, Default)]
+pub struct PlayerSession {
+    /// Set once the server enabled encryption. Everything the player sends afterwards is
+    /// decrypted with it.
+    pub decryption_key: OnceLock<[u8; 16]>,
+    /// Set once the player is in the play state, after which they are held while the proxy
+    /// reconnects to the server.
+    pub rejoin: OnceLock<Rejoin>,
+}
+
+/// See [`hyperion_proto::Rejoin`].
+#[derive(Debug)]
+pub struct Rejoin {
+    pub uuid: [u8; 16],
+    pub username: Box<str>,
+}
+
+/// A [`Priority::Bulk`] message held back while the player is backed up.
+#[derive(Debug)]
+struct Delayed {
+    ordered_bytes: OrderedBytes,
+    /// The world and position of the chunk this is the data of, if it is a cached chunk.
+    chunk: Option<(u64, ChunkPosition)>,
+}
+
 #[derive(Debug)]
 pub struct PlayerHandle {
     writer: kanal::AsyncSender<OrderedBytes>,
 
+    /// [`Priority::Bulk`] messages held back while the player is backed up, oldest first.
+    delayed: Mutex<VecDeque<Delayed>>,
+
+    pub session: Arc<PlayerSession>,
+
     /// Whether the player is allowed to send broadcasts.
     ///
     /// This exists because the player is not automatically in the play state,
@@ -112,9 +171,14 @@ pub struct PlayerHandle {
 
 impl PlayerHandle {
     #[must_use]
-    pub const fn new(writer: kanal::AsyncSender<OrderedBytes>) -> Self {
+    pub const fn new(
+        writer: kanal::AsyncSender<OrderedBytes>,
+        session: Arc<PlayerSession>,
+    ) -> Self {
         Self {
             writer,
+            delayed: Mutex::new(VecDeque::new()),
+            session,
             can_receive_broadcasts: AtomicBool::new(false),
         }
     }
@@ -133,12 +197,122 @@ impl PlayerHandle {
         self.can_receive_broadcasts.load(atomic::Ordering::Relaxed)
     }
 
+    /// Whether the player's queue is filling up faster than their connection drains it.
+    fn is_backed_up(&self) -> bool {
+        self.writer.len() >= BACKED_UP_MESSAGES
+    }
+
+    /// Like [`Self::send`], but while the player is backed up, [`Priority::Bulk`] messages are
+    /// delayed until the player catches up and [`Priority::Cosmetic`] messages are dropped.
+    pub fn send_with_priority(
+        &self,
+        ordered_bytes: OrderedBytes,
+        priority: Priority,
+    ) -> anyhow::Result<()> {
+        match priority {
+            Priority::High => self.send(ordered_bytes),
+            Priority::Bulk => self.send_bulk(ordered_bytes, None),
+            Priority::Cosmetic => {
+                if self.is_backed_up() {
+                    METRICS.dropped();
+                    return Ok(());
+                }
+
+                self.send(ordered_bytes)
+            }
+        }
+    }
+
+    /// Sends the data of the chunk at `position` of `world` with [`Priority::Bulk`]. Unlike other
+    /// messages, it can be dropped with [`Self::discard_delayed`] while it is delayed.
+    pub fn send_chunk(
+        &self,
+        ordered_bytes: OrderedBytes,
+        world: u64,
+        position: ChunkPosition,
+    ) -> anyhow::Result<()> {
+        self.send_bulk(ordered_bytes, Some((world, position)))
+    }
+
+    fn send_bulk(
+        &self,
+        ordered_bytes: OrderedBytes,
+        chunk: Option<(u64, ChunkPosition)>,
+    ) -> anyhow::Result<()> {
+        let mut delayed = self.delayed.lock().unwrap();
+
+        // bulk messages stay in order
+        if delayed.is_empty() && !self.is_backed_up() {
+            drop(delayed);
+            return self.send(ordered_bytes);
+        }
+
+        if delayed.len() >= MAX_DELAYED_MESSAGES {
+            drop(delayed);
+            METRICS.could_not_keep_up();
+            self.shutdown();
+            bail!("failed to send packet to player, too many packets are delayed");
+        }
+
+        METRICS.delayed();
+        delayed.push_back(Delayed {
+            ordered_bytes,
+            chunk,
+        });
+        Ok(())
+    }
+
+    /// Drops delayed messages which the packets sent next make outdated; see
+    /// [`hyperion_proto::DiscardDelayed`]. With a `position`, only the delayed data of that chunk
+    /// of `world` is dropped. Without one, every delayed message is dropped.
+    pub fn discard_delayed(&self, world: u64, position: Option<ChunkPosition>) {
+        let mut delayed = self.delayed.lock().unwrap();
+
+        let Some(position) = position else {
+            delayed.clear();
+            return;
+        };
+
+        delayed.retain(|delayed| delayed.chunk != Some((world, position)));
+    }
+
+    /// Ends the packets of this tick, which the player's writer then writes. Delayed messages
+    /// are sent first, as far as the player caught up.
+    pub fn flush(&self) -> anyhow::Result<()> {
+        {
+            let mut delayed = self.delayed.lock().unwrap();
+
+            while !self.is_backed_up() {
+                let Some(Delayed {
+                    mut ordered_bytes, ..
+                }) = delayed.pop_front()
+                else {
+                    break;
+                };
+
+                // the order is from the tick the message was delayed in, but the message comes
+                // before everything of this tick. The writer sorts stably, so delayed messages
+                // keep the order they were delayed in.
+                ordered_bytes.order = 0;
+
+                self.send(ordered_bytes)?;
+            }
+        }
+
+        self.send(OrderedBytes::FLUSH)
+    }
+
     pub fn send(&self, ordered_bytes: OrderedBytes) -> anyhow::Result<()> {
         match self.writer.try_send(ordered_bytes) {
             Ok(true) => Ok(()),
 
             Ok(false) => {
                 let is_full = self.writer.is_full();
+
+                if is_full {
+                    METRICS.could_not_keep_up();
+                }
+
                 self.shutdown();
                 bail!("failed to send packet to player, channel is full: {is_full}");
             }
@@ -149,3 +323,165 @@ impl PlayerHandle {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use bytes::Bytes;
+    use hyperion_proto::{ChunkPosition, Priority};
+
+    use super::{BACKED_UP_MESSAGES, OrderedBytes, PlayerHandle, PlayerSession};
+    use crate::MAX_PLAYER_PENDING_MESSAGES;
+
+    fn player() -> (PlayerHandle, kanal::AsyncReceiver<OrderedBytes>) {
+        let (tx, rx) = kanal::bounded_async(MAX_PLAYER_PENDING_MESSAGES);
+        let player = PlayerHandle::new(tx, Arc::new(PlayerSession::default()));
+        (player, rx)
+    }
+
+    fn message(order: u32, data: &'static [u8]) -> OrderedBytes {
+        OrderedBytes {
+            order,
+            data: Bytes::from_static(data),
+            ..OrderedBytes::DEFAULT
+        }
+    }
+
+    /// Fills the player's queue until they are backed up.
+    fn back_up(player: &PlayerHandle) {
+        for _ in 0..BACKED_UP_MESSAGES {
+            player.send(message(0, b"filler")).unwrap();
+        }
+    }
+
+    /// Everything the player's writer would read, after `skip` messages.
+    fn written(rx: &kanal::AsyncReceiver<OrderedBytes>, skip: usize) -> Vec<(u32, Bytes)> {
+        std::iter::from_fn(|| rx.try_recv().unwrap())
+            .skip(skip)
+            .map(|message| (message.order, message.data))
+            .collect()
+    }
+
+    #[test]
+    fn test_bulk_is_delayed_while_backed_up() {
+        let (player, rx) = player();
+        back_up(&player);
+
+        player
+            .send_with_priority(message(5, b"chunk"), Priority::Bulk)
+            .unwrap();
+        player
+            .send_with_priority(message(9, b"velocity"), Priority::High)
+            .unwrap();
+        player
+            .send_with_priority(message(3, b"sound"), Priority::Cosmetic)
+            .unwrap();
+
+        assert_eq!(written(&rx, BACKED_UP_MESSAGES), [(
+            9,
+            Bytes::from_static(b"velocity")
+        )]);
+
+        // the player caught up, so the delayed chunk is written before the next tick
+        player.flush().unwrap();
+
+        let written = written(&rx, 0);
+        assert_eq!(written[0], (0, Bytes::from_static(b"chunk")));
+        assert_eq!(written[1].0, OrderedBytes::FLUSH.order);
+    }
+
+    #[test]
+    fn test_delayed_keep_their_order() {
+        let (player, rx) = player();
+        back_up(&player);
+
+        // orders from a previous tick say nothing about the order in a later one
+        player
+            .send_with_priority(message(7, b"first"), Priority::Bulk)
+            .unwrap();
+        player
+            .send_with_priority(message(3, b"second"), Priority::Bulk)
+            .unwrap();
+
+        assert!(written(&rx, BACKED_UP_MESSAGES).is_empty());
+
+        // bulk messages are queued behind delayed ones even after the player caught up
+        player
+            .send_with_priority(message(1, b"third"), Priority::Bulk)
+            .unwrap();
+        player.flush().unwrap();
+
+        let written: Vec<_> = written(&rx, 0).into_iter().take(3).collect();
+        assert_eq!(written, [
+            (0, Bytes::from_static(b"first")),
+            (0, Bytes::from_static(b"second")),
+            (0, Bytes::from_static(b"third")),
+        ]);
+    }
+
+    #[test]
+    fn test_unload_discards_delayed_chunk() {
+        let (player, rx) = player();
+        back_up(&player);
+
+        let unloaded = ChunkPosition::new(1, 2);
+        let kept = ChunkPosition::new(1, 3);
+
+        player
+            .send_chunk(message(1, b"unloaded"), 0, unloaded)
+            .unwrap();
+        player
+            .send_chunk(message(2, b"other world"), 7, unloaded)
+            .unwrap();
+        player.send_chunk(message(3, b"kept"), 0, kept).unwrap();
+
+        player.discard_delayed(0, Some(unloaded));
+        player
+            .send_with_priority(message(4, b"unload"), Priority::High)
+            .unwrap();
+
+        assert_eq!(written(&rx, BACKED_UP_MESSAGES), [(
+            4,
+            Bytes::from_static(b"unload")
+        )]);
+
+        player.flush().unwrap();
+
+        let written: Vec<_> = written(&rx, 0).into_iter().map(|(_, data)| data).collect();
+        assert_eq!(written[..2], [
+            Bytes::from_static(b"other world"),
+            Bytes::from_static(b"kept")
+        ]);
+        assert_eq!(written.len(), 3);
+    }
+
+    #[test]
+    fn test_world_change_discards_everything_delayed() {
+        let (player, rx) = player();
+        back_up(&player);
+
+        player
+            .send_chunk(message(1, b"chunk"), 0, ChunkPosition::new(0, 0))
+            .unwrap();
+        player
+            .send_with_priority(message(2, b"block change"), Priority::Bulk)
+            .unwrap();
+
+        player.discard_delayed(0, None);
+        player
+            .send_with_priority(message(3, b"respawn"), Priority::High)
+            .unwrap();
+
+        assert_eq!(written(&rx, BACKED_UP_MESSAGES), [(
+            3,
+            Bytes::from_static(b"respawn")
+        )]);
+
+        player.flush().unwrap();
+
+        let written = written(&rx, 0);
+        assert_eq!(written.len(), 1);
+        assert_eq!(written[0].0, OrderedBytes::FLUSH.order);
+    }
+}
@@ -4,15 +4,16 @@ use bvh::{Aabb, Bvh};
 use bytes::Bytes;
 use glam::I16Vec2;
 use hyperion_proto::{
-    ArchivedSetReceiveBroadcasts, ArchivedUnicast, ArchivedUpdatePlayerChunkPositions,
-    ChunkPosition,
+    ArchivedSetEncryption, ArchivedSetLimbo, ArchivedSetReceiveBroadcasts, ArchivedSetRejoin,
+    ArchivedUnicast, ArchivedUpdatePlayerChunkPositions, ChunkPosition, Priority,
 };
 use rustc_hash::FxBuildHasher;
 use tracing::{Instrument, debug, error, info_span, instrument, warn};
 
 use crate::{
     cache::ExclusionsManager,
-    data::{OrderedBytes, PlayerHandle},
+    data::{OrderedBytes, PlayerHandle, Rejoin},
+    link::ServerLink,
 };
 
 #[derive(Copy, Clone)]
@@ -21,24 +22,41 @@ pub struct Egress {
     player_registry: &'static papaya::HashMap<u64, PlayerHandle, FxBuildHasher>,
 
     // todo: remove positions when player leaves
-    positions: &'static papaya::HashMap<u64, ChunkPosition, FxBuildHasher>,
+    positions: &'static papaya::HashMap<u64, PlayerView, FxBuildHasher>,
+
+    link: &'static ServerLink,
+}
+
+/// Where a player is and how far around them local broadcasts reach.
+#[derive(Copy, Clone, Debug)]
+pub struct PlayerView {
+    pub position: ChunkPosition,
+    /// The player's view distance in chunks.
+    pub radius: i16,
+    /// The world the player is in. `0` is the main world.
+    pub world: u64,
 }
 
 pub struct BroadcastLocalInstruction {
     pub order: u32,
     pub bvh: Arc<Bvh<Bytes>>,
     pub exclusions: Arc<ExclusionsManager>,
+    /// Only players in this world receive the broadcasts.
+    pub world: u64,
+    pub priority: Priority,
 }
 
 impl Egress {
     #[must_use]
     pub const fn new(
         player_registry: &'static papaya::HashMap<u64, PlayerHandle, FxBuildHasher>,
-        positions: &'static papaya::HashMap<u64, ChunkPosition, FxBuildHasher>,
+        positions: &'static papaya::HashMap<u64, PlayerView, FxBuildHasher>,
+        link: &'static ServerLink,
     ) -> Self {
         Self {
             player_registry,
             positions,
+            link,
         }
     }
 
@@ -48,8 +66,17 @@ impl Egress {
         pkt: &ArchivedUpdatePlayerChunkPositions,
     ) {
         let positions = self.positions.pin();
-        for (stream, position) in pkt.stream.iter().zip(pkt.positions.iter()) {
+        let views = pkt
+            .stream
+            .iter()
+            .zip(pkt.positions.iter())
+            .zip(pkt.view_distances.iter())
+            .zip(pkt.worlds.iter());
+
+        for (((stream, position), radius), world) in views {
             let Ok(stream) = rkyv::deserialize::<u64, !>(stream);
+            let Ok(radius) = rkyv::deserialize::<i16, !>(radius);
+            let Ok(world) = rkyv::deserialize::<u64, !>(world);
 
             // todo: can I just grab the whole thing as Infallible?
             let Ok(position_x) = rkyv::deserialize::<_, !>(&position.x);
@@ -60,7 +87,11 @@ impl Egress {
                 z: position_z,
             };
 
-            positions.insert(stream, position);
+            positions.insert(stream, PlayerView {
+                position,
+                radius,
+                world,
+            });
         }
     }
 
@@ -108,7 +139,7 @@ impl Egress {
         tokio::spawn(
             async move {
                 for (id, player) in &players {
-                    if let Err(e) = player.send(OrderedBytes::FLUSH) {
+                    if let Err(e) = player.flush() {
                         warn!("Failed to send data to player: {:?}", e);
                         if let Some(result) = players.remove(id) {
                             result.shutdown();
@@ -125,17 +156,21 @@ impl Egress {
         let order = instruction.order;
         let bvh = instruction.bvh;
         let exclusions = instruction.exclusions;
+        let world = instruction.world;
+        let priority = instruction.priority;
 
         let positions = self.positions.pin_owned();
         // we are spawning because it is rather intensive to call get_in_slices on a bvh
         // #[allow(clippy::significant_drop_tightening)]
         tokio::spawn(
             async move {
-                const RADIUS: i16 = 16;
-
                 let players = self.player_registry.pin();
 
-                for (id, &position) in &positions {
+                for (id, view) in &positions {
+                    if view.world != world {
+                        continue;
+                    }
+
                     let Some(player) = players.get(id) else {
                         // expected to still happen infrequently
                         debug!("Player not found for id {id:?}");
@@ -146,9 +181,9 @@ impl Egress {
                         continue;
                     }
 
-                    let position = I16Vec2::new(position.x, position.z);
-                    let min = position - I16Vec2::splat(RADIUS);
-                    let max = position + I16Vec2::splat(RADIUS);
+                    let position = I16Vec2::new(view.position.x, view.position.z);
+                    let min = position - I16Vec2::splat(view.radius);
+                    let max = position + I16Vec2::splat(view.radius);
 
                     let aabb = Aabb::new(min, max);
 
@@ -169,7 +204,7 @@ impl Egress {
                             exclusions: Some(exclusions.clone()),
                         };
 
-                        if let Err(e) = player.send(to_send) {
+                        if let Err(e) = player.send_with_priority(to_send, priority) {
                             warn!("Failed to send data to player: {:?}", e);
                             if let Some(result) = players.remove(id) {
                                 result.shutdown();
@@ -189,15 +224,51 @@ impl Egress {
         let data = bytes::Bytes::from(data);
 
         let Ok(order) = rkyv::deserialize::<u32, !>(&pkt.order);
+        let Ok(id) = rkyv::deserialize::<u64, !>(&pkt.stream);
+        let Ok(priority) = rkyv::deserialize::<Priority, !>(&pkt.priority);
 
+        self.unicast(id, order, data, priority);
+    }
+
+    /// Sends `data` to the player `id`, ordered by `order` among the other data of this tick.
+    pub fn unicast(&self, id: u64, order: u32, data: Bytes, priority: Priority) {
         let ordered = OrderedBytes {
             order,
             data,
             ..OrderedBytes::DEFAULT
         };
 
-        let Ok(id) = rkyv::deserialize::<u64, !>(&pkt.stream);
+        self.send_to(id, |player| player.send_with_priority(ordered, priority));
+    }
+
+    /// Like [`Self::unicast`] with the data of the chunk at `position` of `world`.
+    pub fn unicast_chunk(
+        &self,
+        id: u64,
+        order: u32,
+        data: Bytes,
+        world: u64,
+        position: ChunkPosition,
+    ) {
+        let ordered = OrderedBytes {
+            order,
+            data,
+            ..OrderedBytes::DEFAULT
+        };
+
+        self.send_to(id, |player| player.send_chunk(ordered, world, position));
+    }
 
+    /// See [`PlayerHandle::discard_delayed`].
+    pub fn discard_delayed(&self, id: u64, world: u64, position: Option<ChunkPosition>) {
+        let players = self.player_registry.pin();
+
+        if let Some(player) = players.get(&id) {
+            player.discard_delayed(world, position);
+        }
+    }
+
+    fn send_to(&self, id: u64, send: impl FnOnce(&PlayerHandle) -> anyhow::Result<()>) {
         let players = self.player_registry.pin();
 
         let Some(player) = players.get(&id) else {
@@ -207,7 +278,7 @@ impl Egress {
         };
 
         // todo: handle error; kick player if cannot send (buffer full)
-        if let Err(e) = player.send(ordered) {
+        if let Err(e) = send(player) {
             warn!("Failed to send data to player: {:?}", e);
             if let Some(result) = players.remove(&id) {
                 result.shutdown();
@@ -230,4 +301,54 @@ impl Egress {
 
         player.enable_receive_broadcasts();
     }
+
+    #[instrument(skip_all)]
+    pub fn handle_set_encryption(&self, pkt: &ArchivedSetEncryption) {
+        let players = self.player_registry.pin();
+        let Ok(stream) = rkyv::deserialize::<u64, !>(&pkt.stream);
+        let Ok(key) = rkyv::deserialize::<[u8; 16], !>(&pkt.key);
+
+        let Some(player) = players.get(&stream) else {
+            error!("Player not found for stream {stream:?}");
+            return;
+        };
+
+        if player.session.decryption_key.set(key).is_err() {
+            warn!("encryption was already enabled for stream {stream:?}");
+        }
+
+        // this goes through the same channel as unicasts, so every packet the server sent before
+        // enabling encryption is written in plaintext and every packet after it is encrypted
+        if let Err(e) = player.send(OrderedBytes::enable_encryption(key)) {
+            warn!("Failed to send data to player: {:?}", e);
+            if let Some(result) = players.remove(&stream) {
+                result.shutdown();
+            }
+        }
+    }
+
+    #[instrument(skip_all)]
+    pub fn handle_set_rejoin(&self, pkt: &ArchivedSetRejoin<'_>) {
+        let players = self.player_registry.pin();
+        let Ok(stream) = rkyv::deserialize::<u64, !>(&pkt.stream);
+        let Ok(uuid) = rkyv::deserialize::<[u8; 16], !>(&pkt.uuid);
+
+        let Some(player) = players.get(&stream) else {
+            error!("Player not found for stream {stream:?}");
+            return;
+        };
+
+        let rejoin = Rejoin {
+            uuid,
+            username: Box::from(&*pkt.username),
+        };
+
+        if player.session.rejoin.set(rejoin).is_err() {
+            warn!("rejoin was already set for stream {stream:?}");
+        }
+    }
+
+    pub fn handle_set_limbo(&self, pkt: &ArchivedSetLimbo<'_>) {
+        self.link.set_limbo(Bytes::copy_from_slice(&pkt.data));
+    }
 }
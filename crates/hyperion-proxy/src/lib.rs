@@ -16,27 +16,40 @@
     clippy::future_not_send
 )]
 
-use std::fmt::Debug;
+use std::{fmt::Debug, sync::Arc, time::Duration};
 
 use anyhow::Context;
 use colored::Colorize;
-use hyperion_proto::{ArchivedServerToProxyMessage, ChunkPosition};
+use hyperion_proto::{
+    ArchivedServerToProxyMessage, Handshake, HandshakeError, LEN_PREFIX, Ping,
+    ProxyToServerMessage, RequestProxyId,
+};
+use rkyv::util::AlignedVec;
 use rustc_hash::FxBuildHasher;
 use tokio::{
-    io::{AsyncReadExt, BufReader},
+    io::{AsyncReadExt, AsyncWriteExt, BufReader},
     net::{TcpStream, ToSocketAddrs},
 };
 use tokio_util::net::Listener;
 use tracing::{Instrument, debug, error, info, info_span, instrument, trace, warn};
 
 use crate::{
-    cache::BufferedEgress, data::PlayerHandle, egress::Egress, player::initiate_player_connection,
-    server_sender::launch_server_writer,
+    cache::BufferedEgress,
+    data::{PlayerHandle, PlayerSession},
+    egress::{Egress, PlayerView},
+    limits::{ConnectionsPerIp, Limits, PeerAddr},
+    link::ServerLink,
+    metrics::METRICS,
+    player::initiate_player_connection,
+    server_sender::{ServerSender, launch_server_writer},
 };
 
 /// 4 KiB
 const DEFAULT_BUFFER_SIZE: usize = 4 * 1024;
 
+/// How often the latency of the server link is measured.
+const PING_INTERVAL: Duration = Duration::from_secs(1);
+
 /// Maximum number of pending messages in a player's communication channel.
 /// If this limit is exceeded, the player will be disconnected to prevent
 /// memory exhaustion from slow or unresponsive clients.
@@ -45,7 +58,11 @@ const MAX_PLAYER_PENDING_MESSAGES: usize = 1_024;
 pub mod cache;
 pub mod data;
 pub mod egress;
+pub mod limits;
+pub mod link;
+pub mod metrics;
 pub mod player;
+pub mod proxy_protocol;
 pub mod server_sender;
 pub mod util;
 
@@ -62,17 +79,60 @@ async fn connect(addr: impl ToSocketAddrs + Debug + Clone) -> TcpStream {
 
 #[derive(Debug, PartialEq)]
 enum ShutdownType {
+    /// The connection to the server was lost. Players in the play state are held until the proxy
+    /// reconnects.
     Reconnect,
     Full,
 }
 
+/// Everything which outlives a single connection to the server, so that players can be held while
+/// the proxy reconnects.
+struct ProxyState {
+    player_registry: &'static papaya::HashMap<u64, PlayerHandle, FxBuildHasher>,
+    player_positions: &'static papaya::HashMap<u64, PlayerView, FxBuildHasher>,
+    link: &'static ServerLink,
+    /// The id the server assigned to this proxy, or `0` before the first connection.
+    proxy_id: u16,
+    /// The local id of the next player. 0 is reserved for "None" value.
+    next_local_id: u64,
+    limits: Limits,
+    connections_per_ip: ConnectionsPerIp,
+}
+
+impl ProxyState {
+    fn new(limits: Limits) -> Self {
+        let player_registry = papaya::HashMap::default();
+        let player_registry: &'static papaya::HashMap<u64, PlayerHandle, FxBuildHasher> =
+            Box::leak(Box::new(player_registry));
+
+        let player_positions = papaya::HashMap::default();
+        let player_positions: &'static papaya::HashMap<u64, PlayerView, FxBuildHasher> =
+            Box::leak(Box::new(player_positions));
+
+        let link: &'static ServerLink = Box::leak(Box::default());
+
+        Self {
+            player_registry,
+            player_positions,
+            link,
+            proxy_id: 0,
+            next_local_id: 1,
+            limits,
+            connections_per_ip: ConnectionsPerIp::new(limits.max_connections_per_ip),
+        }
+    }
+}
+
 #[tracing::instrument(level = "trace", skip_all)]
 pub async fn run_proxy(
     mut listener: impl HyperionListener,
     server_addr: impl ToSocketAddrs + Debug + Clone,
+    limits: Limits,
 ) -> anyhow::Result<()> {
     let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(None);
 
+    let mut state = ProxyState::new(limits);
+
     #[cfg(unix)]
     let mut sigterm = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
         .context("failed to register SIGTERM handler")?;
@@ -110,7 +170,7 @@ pub async fn run_proxy(
                 warn!("Received shutdown signal, exiting proxy loop");
                 break Ok(());
             }
-            () = async {
+            result = async {
 
                 // clear shutdown channel
                 shutdown_tx.send(None).unwrap();
@@ -121,12 +181,17 @@ pub async fn run_proxy(
                 let server_socket = connect(server_addr.clone()).await;
                 server_socket.set_nodelay(true).unwrap();
 
-                if let Err(e) = connect_to_server_and_run_proxy(&mut listener, server_socket, shutdown_rx.clone(), shutdown_tx.clone()).await {
-                    error!("Error connecting to server: {e:?}");
+                connect_to_server_and_run_proxy(&mut listener, server_socket, &mut state, shutdown_rx.clone(), shutdown_tx.clone()).await
+            } => {
+                match result {
+                    Ok(()) => {}
+                    // reconnecting will not help if the server was built from another revision
+                    Err(e) if e.is::<HandshakeError>() => {
+                        break Err(e.context("Server and proxy cannot talk to each other"));
+                    }
+                    Err(e) => error!("Error connecting to server: {e:?}"),
                 }
-
-
-            } => {}
+            }
         }
     }
 }
@@ -134,28 +199,77 @@ pub async fn run_proxy(
 #[tracing::instrument(level = "trace", skip_all)]
 async fn connect_to_server_and_run_proxy(
     listener: &mut impl HyperionListener,
-    server_socket: TcpStream,
+    mut server_socket: TcpStream,
+    state: &mut ProxyState,
     shutdown_rx: tokio::sync::watch::Receiver<Option<ShutdownType>>,
     shutdown_tx: tokio::sync::watch::Sender<Option<ShutdownType>>,
 ) -> anyhow::Result<()> {
-    info!("🔗 Connected to server, accepting connections");
+    server_socket
+        .write_all(&Handshake::local().encode())
+        .await
+        .context("Failed to send handshake")?;
+
+    let mut handshake = [0; Handshake::LEN];
+    server_socket
+        .read_exact(&mut handshake)
+        .await
+        .context("Failed to read handshake")?;
+
+    let handshake = Handshake::decode(handshake)?;
+
+    info!(
+        "🔗 Connected to server with {:?}, accepting connections",
+        handshake.features
+    );
+
     let (server_read, server_write) = server_socket.into_split();
     let server_sender = launch_server_writer(server_write);
 
-    let player_registry = papaya::HashMap::default();
-    let player_registry: &'static papaya::HashMap<u64, PlayerHandle, FxBuildHasher> =
-        Box::leak(Box::new(player_registry));
-
-    let player_positions = papaya::HashMap::default();
-    let player_positions: &'static papaya::HashMap<u64, ChunkPosition, FxBuildHasher> =
-        Box::leak(Box::new(player_positions));
+    let ProxyState {
+        player_registry,
+        player_positions,
+        link,
+        ..
+    } = *state;
 
-    let egress = Egress::new(player_registry, player_positions);
+    let egress = Egress::new(player_registry, player_positions, link);
 
     let egress = BufferedEgress::new(egress);
 
     let mut handler = IngressHandler::new(BufReader::new(server_read), egress);
 
+    let request = rkyv::to_bytes::<rkyv::rancor::Error>(&ProxyToServerMessage::RequestProxyId(
+        RequestProxyId {
+            previous: state.proxy_id,
+        },
+    ))?;
+
+    server_sender
+        .send(request)
+        .await
+        .context("Failed to request a proxy id")?;
+
+    let proxy_id = handler.read_proxy_id().await?;
+    info!("🆔 Assigned proxy id {proxy_id}");
+
+    if state.proxy_id != 0 && state.proxy_id != proxy_id {
+        warn!(
+            "Disconnecting held players because the proxy id changed from {}",
+            state.proxy_id
+        );
+
+        for player in player_registry.pin().values() {
+            player.shutdown();
+        }
+    }
+
+    state.proxy_id = proxy_id;
+
+    tokio::spawn(ping_server(server_sender.clone(), shutdown_rx.clone()));
+
+    // held players rejoin now
+    link.connect(server_sender);
+
     tokio::spawn({
         let mut shutdown_rx = shutdown_rx.clone();
 
@@ -179,33 +293,52 @@ async fn connect_to_server_and_run_proxy(
                 }
                 }
 
-                debug!("Sending shutdown to all players");
+                debug!("Holding players while reconnecting");
 
+                link.disconnect();
                 shutdown_tx.send(Some(ShutdownType::Reconnect)).unwrap();
             }
                 .instrument(info_span!("server_reader_loop"))
     });
 
-    // 0 is reserved for "None" value
-    let mut player_id_on = 1;
-
     loop {
         let mut shutdown_rx = shutdown_rx.clone();
-        let socket = tokio::select! {
+        let (socket, addr) = tokio::select! {
             _ = shutdown_rx.wait_for(Option::is_some) => {
                 return Ok(())
             }
             Ok((socket, addr)) = listener.accept() => {
                 info!("New client connection from {addr:?}");
-                socket
+                (socket, addr)
             }
         };
 
+        let remote_addr = addr.peer_addr();
+
+        // local clients, like benchmark bots, are not limited
+        let ip = remote_addr.map(|addr| addr.ip());
+        let ip_guard = match ip.filter(|ip| !ip.is_loopback()) {
+            Some(ip) => {
+                let Some(guard) = state.connections_per_ip.try_acquire(ip) else {
+                    warn!("Refusing connection from {addr:?} because it has too many open");
+                    METRICS.connection_rejected();
+                    continue;
+                };
+                Some(guard)
+            }
+            None => None,
+        };
+
+        let player_id_on = hyperion_proto::stream_id(proxy_id, state.next_local_id);
+        state.next_local_id += 1;
+
         let registry = player_registry.pin();
 
         // todo: re-add bounding but issues if have MASSIVE number of packets
         let (tx, rx) = kanal::bounded_async(MAX_PLAYER_PENDING_MESSAGES);
-        registry.insert(player_id_on, PlayerHandle::new(tx));
+        let session = Arc::new(PlayerSession::default());
+        registry.insert(player_id_on, PlayerHandle::new(tx, session.clone()));
+        METRICS.player_connected();
 
         // todo: some SlotMap like thing
         debug!("got player with id {player_id_on:?}");
@@ -215,18 +348,45 @@ async fn connect_to_server_and_run_proxy(
             shutdown_rx.clone(),
             player_id_on,
             rx,
-            server_sender.clone(),
+            session,
+            link,
             player_registry,
             player_positions,
+            remote_addr,
+            state.limits.for_client(ip_guard),
         );
+    }
+}
+
+/// Pings the server until the connection is lost so that [`METRICS`] knows the link latency.
+async fn ping_server(
+    server_sender: ServerSender,
+    mut shutdown_rx: tokio::sync::watch::Receiver<Option<ShutdownType>>,
+) {
+    let mut interval = tokio::time::interval(PING_INTERVAL);
+
+    loop {
+        tokio::select! {
+            _ = shutdown_rx.wait_for(Option::is_some) => return,
+            _ = interval.tick() => {}
+        }
+
+        let ping = ProxyToServerMessage::Ping(Ping {
+            nonce: metrics::ping_nonce(),
+        });
+
+        let ping = rkyv::to_bytes::<rkyv::rancor::Error>(&ping).unwrap();
 
-        player_id_on += 1;
+        if server_sender.send(ping).await.is_err() {
+            return;
+        }
     }
 }
 
 struct IngressHandler {
     server_read: BufReader<tokio::net::tcp::OwnedReadHalf>,
-    buffer: Vec<u8>,
+    /// Aligned so that messages can be read in place.
+    buffer: AlignedVec,
     egress: BufferedEgress,
 }
 
@@ -244,52 +404,93 @@ impl IngressHandler {
         Self {
             server_read,
             egress,
-            buffer: Vec::with_capacity(DEFAULT_BUFFER_SIZE),
+            buffer: AlignedVec::with_capacity(DEFAULT_BUFFER_SIZE),
         }
     }
 
     // #[instrument(level = "info", skip_all, name = "ServerReader::next")]
     pub async fn handle_next(&mut self) -> anyhow::Result<()> {
         let len = self.read_len().await?;
-        let len = usize::try_from(len).context("Failed to convert len to usize")?;
-
-        debug_assert!(len <= 1_000_000);
 
         trace!("Received packet of length {len}");
 
         self.handle_next_server_packet(len).await
     }
 
+    /// Reads the [`hyperion_proto::AssignProxyId`] the server sends first.
+    async fn read_proxy_id(&mut self) -> anyhow::Result<u16> {
+        let len = self.read_len().await?;
+
+        self.read_message(len).await?;
+
+        #[expect(
+            clippy::indexing_slicing,
+            reason = "read_message makes the buffer at least {len} long"
+        )]
+        let slice = &self.buffer[..len];
+
+        let result = hyperion_proto::access::<ArchivedServerToProxyMessage<'_>>(slice)
+            .context("the server sent an invalid frame")?;
+
+        let ArchivedServerToProxyMessage::AssignProxyId(assign) = result else {
+            anyhow::bail!("the server did not assign a proxy id");
+        };
+
+        let Ok(proxy_id) = rkyv::deserialize::<u16, !>(&assign.proxy_id);
+
+        Ok(proxy_id)
+    }
+
     #[instrument(level = "trace")]
-    async fn read_len(&mut self) -> anyhow::Result<u64> {
+    async fn read_len(&mut self) -> anyhow::Result<usize> {
+        let mut prefix = [0; LEN_PREFIX];
+
         self.server_read
-            .read_u64()
+            .read_exact(&mut prefix)
             .await
-            .context("Failed to read int")
+            .context("Failed to read frame length")?;
+
+        Ok(hyperion_proto::decode_len(prefix)?)
     }
 
     #[instrument(level = "trace")]
     async fn handle_next_server_packet(&mut self, len: usize) -> anyhow::Result<()> {
-        // [A]
-        if self.buffer.len() < len {
-            self.buffer.resize(len, 0);
-        }
+        self.read_message(len).await?;
 
         #[expect(
             clippy::indexing_slicing,
-            reason = "we already verified in [A] that length of buffer is at least {len}"
+            reason = "read_message makes the buffer at least {len} long"
         )]
-        let slice = &mut self.buffer[..len];
-        self.server_read.read_exact(slice).await?;
+        let slice = &self.buffer[..len];
 
-        let result = unsafe { rkyv::access_unchecked::<ArchivedServerToProxyMessage<'_>>(slice) };
+        let result = hyperion_proto::access::<ArchivedServerToProxyMessage<'_>>(slice)
+            .context("the server sent an invalid frame")?;
 
         self.egress.handle_packet(result);
 
         Ok(())
     }
+
+    /// Reads the next message of `len` bytes and its padding into the start of the buffer.
+    async fn read_message(&mut self, len: usize) -> anyhow::Result<()> {
+        let padded_len = len + hyperion_proto::padding(len);
+
+        // [A]
+        if self.buffer.len() < padded_len {
+            self.buffer.resize(padded_len, 0);
+        }
+
+        #[expect(
+            clippy::indexing_slicing,
+            reason = "we already verified in [A] that length of buffer is at least {padded_len}"
+        )]
+        let slice = &mut self.buffer[..padded_len];
+        self.server_read.read_exact(slice).await?;
+
+        Ok(())
+    }
 }
 
-trait HyperionListener: Listener<Io: Send, Addr: Debug> + 'static {}
+trait HyperionListener: Listener<Io: Send, Addr: Debug + PeerAddr> + 'static {}
 
-impl<L: Listener<Io: Send, Addr: Debug> + 'static> HyperionListener for L {}
+impl<L: Listener<Io: Send, Addr: Debug + PeerAddr> + 'static> HyperionListener for L {}
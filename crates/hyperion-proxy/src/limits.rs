@@ -0,0 +1,254 @@
+//! Limits on what a single client may do, so that one bad client cannot stall the server.
+
+use std::{
+    collections::hash_map::Entry,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use rustc_hash::FxHashMap;
+
+/// Configurable limits of the proxy.
+#[derive(Copy, Clone, Debug)]
+pub struct Limits {
+    /// How many connections a single IP address may have open at once.
+    pub max_connections_per_ip: usize,
+    /// How long a client may take to reach the play state before they are disconnected.
+    pub login_timeout: Duration,
+    /// How many bytes a client may send each second on average.
+    pub inbound_bytes_per_second: u32,
+    /// How many bytes a client may send at once.
+    pub inbound_burst_bytes: u32,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_connections_per_ip: 16,
+            login_timeout: Duration::from_secs(30),
+            inbound_bytes_per_second: 64 * 1024,
+            inbound_burst_bytes: 256 * 1024,
+        }
+    }
+}
+
+impl Limits {
+    /// The limits of a newly connected client.
+    #[must_use]
+    pub fn for_client(&self, ip_guard: Option<IpGuard>) -> ClientLimits {
+        ClientLimits {
+            ip_guard,
+            login_timeout: self.login_timeout,
+            inbound: TokenBucket::new(
+                f64::from(self.inbound_bytes_per_second),
+                f64::from(self.inbound_burst_bytes),
+            ),
+        }
+    }
+}
+
+/// The limits of a single client.
+#[derive(Debug)]
+pub struct ClientLimits {
+    /// Counts the client towards the connections of its IP address until it disconnects.
+    pub ip_guard: Option<IpGuard>,
+    pub login_timeout: Duration,
+    pub inbound: TokenBucket,
+}
+
+/// The address of a client, if it connected over IP.
+pub trait PeerAddr {
+    fn peer_addr(&self) -> Option<SocketAddr>;
+}
+
+impl PeerAddr for SocketAddr {
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        Some(*self)
+    }
+}
+
+/// Clients connecting over a Unix socket are local, so they are not limited.
+#[cfg(unix)]
+impl PeerAddr for tokio::net::unix::SocketAddr {
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        None
+    }
+}
+
+/// Counts the open connections of every IP address.
+#[derive(Debug)]
+pub struct ConnectionsPerIp {
+    max: usize,
+    counts: Arc<Mutex<FxHashMap<IpAddr, usize>>>,
+}
+
+impl ConnectionsPerIp {
+    #[must_use]
+    pub fn new(max: usize) -> Self {
+        Self {
+            max,
+            counts: Arc::default(),
+        }
+    }
+
+    /// Counts a new connection from `ip`, or returns `None` if `ip` already has the maximum
+    /// number of connections. The connection is counted until the guard is dropped.
+    pub fn try_acquire(&self, ip: IpAddr) -> Option<IpGuard> {
+        let mut counts = self.counts.lock().unwrap();
+        let count = counts.get(&ip).copied().unwrap_or(0);
+
+        if count >= self.max {
+            return None;
+        }
+
+        counts.insert(ip, count + 1);
+
+        Some(IpGuard {
+            ip,
+            counts: self.counts.clone(),
+        })
+    }
+}
+
+/// A connection counted by [`ConnectionsPerIp`].
+#[derive(Debug)]
+pub struct IpGuard {
+    ip: IpAddr,
+    counts: Arc<Mutex<FxHashMap<IpAddr, usize>>>,
+}
+
+impl Drop for IpGuard {
+    fn drop(&mut self) {
+        let mut counts = self.counts.lock().unwrap();
+
+        if let Entry::Occupied(mut entry) = counts.entry(self.ip) {
+            *entry.get_mut() -= 1;
+
+            if *entry.get() == 0 {
+                entry.remove();
+            }
+        }
+    }
+}
+
+/// Refills at a constant rate up to a maximum, and is drained by what a client sends.
+#[derive(Debug)]
+pub struct TokenBucket {
+    per_second: f64,
+    capacity: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    /// A full bucket.
+    #[must_use]
+    pub fn new(per_second: f64, capacity: f64) -> Self {
+        Self {
+            per_second,
+            capacity,
+            tokens: capacity,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Takes `amount` tokens. Returns `false` if there are not enough, in which case none are
+    /// taken.
+    pub fn try_take(&mut self, amount: usize) -> bool {
+        self.try_take_at(amount, Instant::now())
+    }
+
+    fn try_take_at(&mut self, amount: usize, now: Instant) -> bool {
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.last_refill = now;
+
+        self.tokens = elapsed
+            .mul_add(self.per_second, self.tokens)
+            .min(self.capacity);
+
+        let amount = amount as f64;
+
+        if self.tokens < amount {
+            return false;
+        }
+
+        self.tokens -= amount;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        time::Duration,
+    };
+
+    use super::{ConnectionsPerIp, TokenBucket};
+
+    const IP: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+    const OTHER_IP: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2));
+
+    #[test]
+    fn test_token_bucket_starts_full() {
+        let mut bucket = TokenBucket::new(10.0, 100.0);
+        let start = bucket.last_refill;
+
+        assert!(bucket.try_take_at(100, start));
+        assert!(!bucket.try_take_at(1, start));
+    }
+
+    #[test]
+    fn test_token_bucket_rejection_takes_nothing() {
+        let mut bucket = TokenBucket::new(10.0, 100.0);
+        let start = bucket.last_refill;
+
+        assert!(!bucket.try_take_at(101, start));
+        assert!(bucket.try_take_at(100, start));
+    }
+
+    #[test]
+    fn test_token_bucket_refills() {
+        let mut bucket = TokenBucket::new(10.0, 100.0);
+        let start = bucket.last_refill;
+
+        assert!(bucket.try_take_at(100, start));
+
+        let later = start + Duration::from_secs(2);
+        assert!(!bucket.try_take_at(21, later));
+        assert!(bucket.try_take_at(20, later));
+        assert!(!bucket.try_take_at(1, later));
+    }
+
+    #[test]
+    fn test_token_bucket_refills_up_to_capacity() {
+        let mut bucket = TokenBucket::new(10.0, 100.0);
+        let start = bucket.last_refill;
+
+        assert!(bucket.try_take_at(50, start));
+
+        let later = start + Duration::from_secs(60);
+        assert!(!bucket.try_take_at(101, later));
+        assert!(bucket.try_take_at(100, later));
+    }
+
+    #[test]
+    fn test_connections_per_ip() {
+        let connections = ConnectionsPerIp::new(2);
+
+        let first = connections.try_acquire(IP).unwrap();
+        let second = connections.try_acquire(IP).unwrap();
+        assert!(connections.try_acquire(IP).is_none());
+
+        // other addresses are counted separately
+        let other = connections.try_acquire(OTHER_IP).unwrap();
+
+        drop(first);
+        let third = connections.try_acquire(IP).unwrap();
+        assert!(connections.try_acquire(IP).is_none());
+
+        drop((second, third, other));
+        assert!(connections.counts.lock().unwrap().is_empty());
+    }
+}
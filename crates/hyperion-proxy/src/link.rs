@@ -0,0 +1,61 @@
+//! The connection to the game server as seen by players, which outlives each TCP connection so
+//! that players can be held while the proxy reconnects.
+
+use std::sync::RwLock;
+
+use bytes::Bytes;
+use tokio::sync::watch;
+
+use crate::{metrics::METRICS, server_sender::ServerSender};
+
+#[derive(Debug)]
+pub struct ServerLink {
+    /// The sender of the current connection, or `None` while reconnecting.
+    sender: watch::Sender<Option<ServerSender>>,
+    /// What to send to held players. See [`hyperion_proto::SetLimbo`].
+    limbo: RwLock<Bytes>,
+}
+
+impl Default for ServerLink {
+    fn default() -> Self {
+        Self {
+            sender: watch::Sender::new(None),
+            limbo: RwLock::default(),
+        }
+    }
+}
+
+impl ServerLink {
+    /// Makes `sender` the current connection. Held players rejoin through it.
+    pub fn connect(&self, sender: ServerSender) {
+        METRICS.server_connected();
+        self.sender.send_replace(Some(sender));
+    }
+
+    /// Marks the connection as lost, which makes players who can rejoin wait in limbo.
+    pub fn disconnect(&self) {
+        METRICS.server_disconnected();
+        self.sender.send_replace(None);
+    }
+
+    /// Watches the current connection.
+    #[must_use]
+    pub fn subscribe(&self) -> watch::Receiver<Option<ServerSender>> {
+        self.sender.subscribe()
+    }
+
+    /// The sender of the current connection, or `None` while reconnecting.
+    #[must_use]
+    pub fn sender(&self) -> Option<ServerSender> {
+        self.sender.borrow().clone()
+    }
+
+    pub fn set_limbo(&self, data: Bytes) {
+        *self.limbo.write().unwrap() = data;
+    }
+
+    #[must_use]
+    pub fn limbo(&self) -> Bytes {
+        self.limbo.read().unwrap().clone()
+    }
+}
@@ -1,7 +1,9 @@
-use std::{fmt::Debug, net::SocketAddr, path::PathBuf};
+use std::{fmt::Debug, net::SocketAddr, path::PathBuf, time::Duration};
 
 use clap::Parser;
-use hyperion_proxy::run_proxy;
+use hyperion_proxy::{
+    limits::Limits, metrics::METRICS, proxy_protocol::ProxyProtocolListener, run_proxy,
+};
 use serde::Deserialize;
 use tokio::net::TcpListener;
 #[cfg(unix)]
@@ -26,6 +28,50 @@ struct Params {
     #[clap(short, long, default_value = "127.0.0.1:35565")]
     #[serde(default = "default_server")]
     server: String,
+
+    /// The address to serve Prometheus metrics on at `/metrics`, like "127.0.0.1:9100". Metrics
+    /// are not served if this is not set.
+    #[clap(long)]
+    #[serde(default)]
+    metrics_addr: Option<String>,
+
+    /// Expect every TCP connection to start with a PROXY protocol header, as sent by load
+    /// balancers like HAProxy, and use the client address in it. Only enable this behind such a
+    /// load balancer, or clients can claim any address
+    #[clap(long)]
+    #[serde(default)]
+    proxy_protocol: bool,
+
+    /// How many connections a single IP address may have open at once
+    #[clap(long, default_value_t = Limits::default().max_connections_per_ip)]
+    #[serde(default = "default_max_connections_per_ip")]
+    max_connections_per_ip: usize,
+
+    /// How many seconds a client may take to log in before they are disconnected
+    #[clap(long, default_value_t = Limits::default().login_timeout.as_secs())]
+    #[serde(default = "default_login_timeout_secs")]
+    login_timeout_secs: u64,
+
+    /// How many bytes a client may send each second on average before they are disconnected
+    #[clap(long, default_value_t = Limits::default().inbound_bytes_per_second)]
+    #[serde(default = "default_inbound_bytes_per_second")]
+    inbound_bytes_per_second: u32,
+
+    /// How many bytes a client may send at once before they are disconnected
+    #[clap(long, default_value_t = Limits::default().inbound_burst_bytes)]
+    #[serde(default = "default_inbound_burst_bytes")]
+    inbound_burst_bytes: u32,
+}
+
+impl Params {
+    const fn limits(&self) -> Limits {
+        Limits {
+            max_connections_per_ip: self.max_connections_per_ip,
+            login_timeout: Duration::from_secs(self.login_timeout_secs),
+            inbound_bytes_per_second: self.inbound_bytes_per_second,
+            inbound_burst_bytes: self.inbound_burst_bytes,
+        }
+    }
 }
 
 fn default_proxy_addr() -> String {
@@ -36,6 +82,22 @@ fn default_server() -> String {
     "127.0.0.1:35565".to_string()
 }
 
+fn default_max_connections_per_ip() -> usize {
+    Limits::default().max_connections_per_ip
+}
+
+fn default_login_timeout_secs() -> u64 {
+    Limits::default().login_timeout.as_secs()
+}
+
+fn default_inbound_bytes_per_second() -> u32 {
+    Limits::default().inbound_bytes_per_second
+}
+
+fn default_inbound_burst_bytes() -> u32 {
+    Limits::default().inbound_burst_bytes
+}
+
 #[derive(Debug)]
 enum ProxyAddress {
     Tcp(SocketAddr),
@@ -110,6 +172,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     };
 
     let proxy_addr = ProxyAddress::parse(&params.proxy_addr)?;
+    let limits = params.limits();
+    let proxy_protocol = params.proxy_protocol;
 
     let server_addr: SocketAddr = tokio::net::lookup_host(&params.server)
         .await?
@@ -124,19 +188,36 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let server_help = "~ The event server internal address".dimmed();
     info!("👾 Internal server address: tcp://{server_addr} {server_help}");
 
+    if let Some(metrics_addr) = params.metrics_addr {
+        tokio::spawn(async move {
+            if let Err(e) =
+                hyperion_utils::serve_prometheus(metrics_addr, || METRICS.render()).await
+            {
+                error!("Metrics listener failed: {e:?}");
+            }
+        });
+    }
+
     let handle = tokio::spawn(async move {
         match &proxy_addr {
             ProxyAddress::Tcp(addr) => {
                 let listener = TcpListener::bind(addr).await.unwrap();
                 let socket = NoDelayTcpListener { listener };
-                run_proxy(socket, server_addr).await.unwrap();
+
+                if proxy_protocol {
+                    info!("Reading client addresses from PROXY protocol headers");
+                    let socket = ProxyProtocolListener::new(socket);
+                    run_proxy(socket, server_addr, limits).await.unwrap();
+                } else {
+                    run_proxy(socket, server_addr, limits).await.unwrap();
+                }
             }
             #[cfg(unix)]
             ProxyAddress::Unix(path) => {
                 // remove file if already exists
                 let _unused = tokio::fs::remove_file(path).await;
                 let listener = UnixListener::bind(path).unwrap();
-                run_proxy(listener, server_addr).await.unwrap();
+                run_proxy(listener, server_addr, limits).await.unwrap();
             }
         }
     });
@@ -0,0 +1,438 @@
+//! Counters and histograms describing how the proxy keeps up, served over HTTP in the Prometheus
+//! text format.
+
+use std::{
+    fmt::Write as _,
+    sync::{
+        LazyLock,
+        atomic::{AtomicI64, AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use crate::MAX_PLAYER_PENDING_MESSAGES;
+
+/// Every metric of the proxy.
+pub static METRICS: Metrics = Metrics::new();
+
+/// What bytes received from the server were sent as.
+#[derive(Copy, Clone, Debug)]
+pub enum Kind {
+    GlobalBroadcast,
+    LocalBroadcast,
+    Unicast,
+    /// Chunks the server sent once to cache.
+    CachedChunk,
+}
+
+impl Kind {
+    const ALL: [Self; 4] = [
+        Self::GlobalBroadcast,
+        Self::LocalBroadcast,
+        Self::Unicast,
+        Self::CachedChunk,
+    ];
+
+    const fn label(self) -> &'static str {
+        match self {
+            Self::GlobalBroadcast => "global_broadcast",
+            Self::LocalBroadcast => "local_broadcast",
+            Self::Unicast => "unicast",
+            Self::CachedChunk => "cached_chunk",
+        }
+    }
+}
+
+pub struct Metrics {
+    players: AtomicI64,
+    server_connected: AtomicI64,
+    server_reconnects: AtomicU64,
+    could_not_keep_up: AtomicU64,
+    delayed: AtomicU64,
+    dropped: AtomicU64,
+    connections_rejected: AtomicU64,
+    limited: AtomicU64,
+    global_broadcast_bytes: AtomicU64,
+    local_broadcast_bytes: AtomicU64,
+    unicast_bytes: AtomicU64,
+    cached_chunk_bytes: AtomicU64,
+    chunk_cache_size_bytes: AtomicI64,
+    cached_chunk_sent_bytes: AtomicU64,
+    /// Sampled each time a player's queue is flushed.
+    player_queue_depth: Histogram<9>,
+    bvh_build_seconds: Histogram<10>,
+    server_link_latency_seconds: Histogram<10>,
+}
+
+impl Metrics {
+    const fn new() -> Self {
+        Self {
+            players: AtomicI64::new(0),
+            server_connected: AtomicI64::new(0),
+            server_reconnects: AtomicU64::new(0),
+            could_not_keep_up: AtomicU64::new(0),
+            delayed: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            connections_rejected: AtomicU64::new(0),
+            limited: AtomicU64::new(0),
+            global_broadcast_bytes: AtomicU64::new(0),
+            local_broadcast_bytes: AtomicU64::new(0),
+            unicast_bytes: AtomicU64::new(0),
+            cached_chunk_bytes: AtomicU64::new(0),
+            chunk_cache_size_bytes: AtomicI64::new(0),
+            cached_chunk_sent_bytes: AtomicU64::new(0),
+            player_queue_depth: Histogram::new([
+                0.0, 1.0, 4.0, 16.0, 64.0, 128.0, 256.0, 512.0, 1024.0,
+            ]),
+            bvh_build_seconds: Histogram::new([
+                0.000_01, 0.000_05, 0.000_1, 0.000_5, 0.001, 0.002_5, 0.005, 0.01, 0.025, 0.05,
+            ]),
+            server_link_latency_seconds: Histogram::new([
+                0.000_1, 0.000_5, 0.001, 0.002_5, 0.005, 0.01, 0.025, 0.05, 0.1, 0.5,
+            ]),
+        }
+    }
+
+    pub fn player_connected(&self) {
+        self.players.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn player_disconnected(&self) {
+        self.players.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn server_connected(&self) {
+        if self.server_connected.swap(1, Ordering::Relaxed) == 0 {
+            self.server_reconnects.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn server_disconnected(&self) {
+        self.server_connected.store(0, Ordering::Relaxed);
+    }
+
+    /// A player was disconnected because their queue was full.
+    pub fn could_not_keep_up(&self) {
+        self.could_not_keep_up.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A [`hyperion_proto::Priority::Bulk`] message was delayed because a player was backed up.
+    pub fn delayed(&self) {
+        self.delayed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A [`hyperion_proto::Priority::Cosmetic`] message was dropped because a player was backed
+    /// up.
+    pub fn dropped(&self) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A connection was refused because its IP address had too many open.
+    pub fn connection_rejected(&self) {
+        self.connections_rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A player was disconnected because they broke one of their [`crate::limits::Limits`].
+    pub fn limited(&self) {
+        self.limited.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn server_bytes(&self, kind: Kind, len: usize) {
+        self.bytes_of(kind).fetch_add(len as u64, Ordering::Relaxed);
+    }
+
+    const fn bytes_of(&self, kind: Kind) -> &AtomicU64 {
+        match kind {
+            Kind::GlobalBroadcast => &self.global_broadcast_bytes,
+            Kind::LocalBroadcast => &self.local_broadcast_bytes,
+            Kind::Unicast => &self.unicast_bytes,
+            Kind::CachedChunk => &self.cached_chunk_bytes,
+        }
+    }
+
+    /// The chunk cache grew by `delta` bytes, or shrank if it is negative.
+    pub fn chunk_cache_resized(&self, delta: i64) {
+        self.chunk_cache_size_bytes
+            .fetch_add(delta, Ordering::Relaxed);
+    }
+
+    /// A player was sent `len` bytes from the chunk cache.
+    pub fn cached_chunk_sent(&self, len: usize) {
+        self.cached_chunk_sent_bytes
+            .fetch_add(len as u64, Ordering::Relaxed);
+    }
+
+    pub fn player_queue_depth(&self, depth: usize) {
+        self.player_queue_depth.observe(depth as f64);
+    }
+
+    pub fn bvh_build(&self, duration: Duration) {
+        self.bvh_build_seconds.observe(duration.as_secs_f64());
+    }
+
+    /// Records the latency of the server link from the nonce of a [`hyperion_proto::Pong`].
+    pub fn pong(&self, nonce: u64) {
+        let Some(latency) = ping_nonce().checked_sub(nonce) else {
+            return;
+        };
+
+        let latency = Duration::from_nanos(latency);
+        self.server_link_latency_seconds
+            .observe(latency.as_secs_f64());
+    }
+
+    /// Renders every metric in the Prometheus text format.
+    #[must_use]
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        gauge(
+            &mut out,
+            "hyperion_proxy_players",
+            "Players connected to the proxy, including held ones.",
+            self.players.load(Ordering::Relaxed),
+        );
+
+        gauge(
+            &mut out,
+            "hyperion_proxy_server_connected",
+            "Whether the proxy is connected to the server.",
+            self.server_connected.load(Ordering::Relaxed),
+        );
+
+        counter(
+            &mut out,
+            "hyperion_proxy_server_connections_total",
+            "Connections made to the server.",
+            self.server_reconnects.load(Ordering::Relaxed),
+        );
+
+        counter(
+            &mut out,
+            "hyperion_proxy_could_not_keep_up_total",
+            "Players disconnected because their queue was full.",
+            self.could_not_keep_up.load(Ordering::Relaxed),
+        );
+
+        counter(
+            &mut out,
+            "hyperion_proxy_delayed_total",
+            "Bulk messages delayed because a player was backed up.",
+            self.delayed.load(Ordering::Relaxed),
+        );
+
+        counter(
+            &mut out,
+            "hyperion_proxy_dropped_total",
+            "Cosmetic messages dropped because a player was backed up.",
+            self.dropped.load(Ordering::Relaxed),
+        );
+
+        counter(
+            &mut out,
+            "hyperion_proxy_connections_rejected_total",
+            "Connections refused because their IP address had too many open.",
+            self.connections_rejected.load(Ordering::Relaxed),
+        );
+
+        counter(
+            &mut out,
+            "hyperion_proxy_limited_total",
+            "Players disconnected because they broke a limit.",
+            self.limited.load(Ordering::Relaxed),
+        );
+
+        let name = "hyperion_proxy_server_bytes_total";
+        writeln!(out, "# HELP {name} Bytes received from the server by kind.").unwrap();
+        writeln!(out, "# TYPE {name} counter").unwrap();
+        for kind in Kind::ALL {
+            let value = self.bytes_of(kind).load(Ordering::Relaxed);
+            writeln!(out, "{name}{{kind=\"{}\"}} {value}", kind.label()).unwrap();
+        }
+
+        gauge(
+            &mut out,
+            "hyperion_proxy_chunk_cache_bytes",
+            "Bytes of chunks in the chunk cache.",
+            self.chunk_cache_size_bytes.load(Ordering::Relaxed),
+        );
+
+        counter(
+            &mut out,
+            "hyperion_proxy_cached_chunk_sent_bytes_total",
+            "Bytes sent to players from the chunk cache instead of by the server.",
+            self.cached_chunk_sent_bytes.load(Ordering::Relaxed),
+        );
+
+        gauge(
+            &mut out,
+            "hyperion_proxy_player_queue_capacity",
+            "Messages a player's queue holds before they are disconnected.",
+            MAX_PLAYER_PENDING_MESSAGES as i64,
+        );
+
+        self.player_queue_depth.render(
+            &mut out,
+            "hyperion_proxy_player_queue_depth",
+            "Messages in a player's queue when it is flushed.",
+        );
+
+        self.bvh_build_seconds.render(
+            &mut out,
+            "hyperion_proxy_bvh_build_seconds",
+            "Time to build the BVH of the local broadcasts of one world.",
+        );
+
+        self.server_link_latency_seconds.render(
+            &mut out,
+            "hyperion_proxy_server_link_latency_seconds",
+            "Round trip time of a ping to the server.",
+        );
+
+        out
+    }
+}
+
+/// What ping nonces are measured from.
+static EPOCH: LazyLock<Instant> = LazyLock::new(Instant::now);
+
+/// The nonce of a [`hyperion_proto::Ping`] sent now.
+pub fn ping_nonce() -> u64 {
+    u64::try_from(EPOCH.elapsed().as_nanos()).unwrap_or(u64::MAX)
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: i64) {
+    writeln!(out, "# HELP {name} {help}").unwrap();
+    writeln!(out, "# TYPE {name} gauge").unwrap();
+    writeln!(out, "{name} {value}").unwrap();
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: u64) {
+    writeln!(out, "# HELP {name} {help}").unwrap();
+    writeln!(out, "# TYPE {name} counter").unwrap();
+    writeln!(out, "{name} {value}").unwrap();
+}
+
+/// A histogram with `N` fixed upper bounds plus `+Inf`.
+struct Histogram<const N: usize> {
+    bounds: [f64; N],
+    /// Observations in each bucket, not yet cumulative.
+    buckets: [AtomicU64; N],
+    /// Observations above every bound.
+    overflow: AtomicU64,
+    /// The bits of the `f64` sum of all observations.
+    sum: AtomicU64,
+}
+
+impl<const N: usize> Histogram<N> {
+    const fn new(bounds: [f64; N]) -> Self {
+        Self {
+            bounds,
+            buckets: [const { AtomicU64::new(0) }; N],
+            overflow: AtomicU64::new(0),
+            sum: AtomicU64::new(0),
+        }
+    }
+
+    fn observe(&self, value: f64) {
+        let bucket = self
+            .bounds
+            .iter()
+            .position(|&bound| value <= bound)
+            .and_then(|bucket| self.buckets.get(bucket))
+            .unwrap_or(&self.overflow);
+
+        bucket.fetch_add(1, Ordering::Relaxed);
+
+        // there is no atomic float addition
+        let _ = self
+            .sum
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |sum| {
+                Some((f64::from_bits(sum) + value).to_bits())
+            });
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        writeln!(out, "# HELP {name} {help}").unwrap();
+        writeln!(out, "# TYPE {name} histogram").unwrap();
+
+        let mut count = 0;
+
+        for (bound, bucket) in self.bounds.iter().zip(&self.buckets) {
+            count += bucket.load(Ordering::Relaxed);
+            writeln!(out, "{name}_bucket{{le=\"{bound}\"}} {count}").unwrap();
+        }
+
+        count += self.overflow.load(Ordering::Relaxed);
+        writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {count}").unwrap();
+
+        let sum = f64::from_bits(self.sum.load(Ordering::Relaxed));
+        writeln!(out, "{name}_sum {sum}").unwrap();
+        writeln!(out, "{name}_count {count}").unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram_buckets_are_cumulative() {
+        let histogram = Histogram::new([1.0, 10.0]);
+        for value in [0.5, 1.0, 5.0, 100.0] {
+            histogram.observe(value);
+        }
+
+        let mut out = String::new();
+        histogram.render(&mut out, "test", "A test.");
+
+        assert_eq!(
+            out,
+            "# HELP test A test.\n# TYPE test histogram\ntest_bucket{le=\"1\"} \
+             2\ntest_bucket{le=\"10\"} 3\ntest_bucket{le=\"+Inf\"} 4\ntest_sum 106.5\ntest_count \
+             4\n"
+        );
+    }
+
+    #[test]
+    fn test_empty_histogram() {
+        let histogram = Histogram::new([1.0]);
+
+        let mut out = String::new();
+        histogram.render(&mut out, "test", "A test.");
+
+        assert!(out.contains("test_bucket{le=\"+Inf\"} 0\n"));
+        assert!(out.contains("test_sum 0\n"));
+        assert!(out.contains("test_count 0\n"));
+    }
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::new();
+        metrics.player_connected();
+        metrics.player_connected();
+        metrics.player_disconnected();
+        metrics.server_connected();
+        metrics.server_disconnected();
+        metrics.server_connected();
+        metrics.server_bytes(Kind::Unicast, 12);
+        metrics.chunk_cache_resized(-3);
+
+        let out = metrics.render();
+
+        assert!(out.contains(
+            "# HELP hyperion_proxy_players Players connected to the proxy, including held \
+             ones.\n# TYPE hyperion_proxy_players gauge\nhyperion_proxy_players 1\n"
+        ));
+        assert!(out.contains("hyperion_proxy_server_connections_total 2\n"));
+        assert!(out.contains("hyperion_proxy_server_bytes_total{kind=\"unicast\"} 12\n"));
+        assert!(out.contains("hyperion_proxy_server_bytes_total{kind=\"cached_chunk\"} 0\n"));
+        assert!(out.contains("hyperion_proxy_chunk_cache_bytes -3\n"));
+
+        // every sample is a name, optionally with labels, and a number
+        for line in out.lines().filter(|line| !line.starts_with('#')) {
+            let (_, value) = line.rsplit_once(' ').unwrap();
+            assert!(value.parse::<f64>().is_ok(), "{line}");
+        }
+    }
+}
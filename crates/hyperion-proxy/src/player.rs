@@ -1,15 +1,23 @@
 //! Player connection handling and packet processing.
 
-use std::io::IoSlice;
+use std::{
+    io::IoSlice,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
+use aes::cipher::{
+    BlockDecryptMut, BlockEncryptMut, BlockSizeUser, KeyIvInit, generic_array::GenericArray,
+};
+use anyhow::{anyhow, ensure};
 use hyperion_proto::{
-    ChunkPosition, PlayerConnect, PlayerDisconnect, PlayerDisconnectReason, PlayerPackets,
-    ProxyToServerMessage,
+    PlayerConnect, PlayerDisconnect, PlayerDisconnectReason, PlayerPackets, ProxyToServerMessage,
 };
-use rkyv::ser::allocator::Arena;
+use rkyv::{ser::allocator::Arena, util::AlignedVec};
 use rustc_hash::FxBuildHasher;
 use tokio::{
-    io::{AsyncReadExt, AsyncWrite},
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
     task::JoinHandle,
 };
 use tracing::{info, info_span, instrument, warn};
@@ -17,30 +25,54 @@ use tracing::{info, info_span, instrument, warn};
 use crate::{
     ShutdownType,
     cache::ExclusionsManager,
-    data::{OrderedBytes, PlayerHandle},
-    server_sender::ServerSender,
+    data::{OrderedBytes, PlayerHandle, PlayerSession, Rejoin},
+    egress::PlayerView,
+    limits::{ClientLimits, TokenBucket},
+    link::ServerLink,
+    metrics::METRICS,
     util::AsyncWriteVectoredExt,
 };
 
 /// Default buffer size for reading player packets, set to 8 KiB.
 const DEFAULT_READ_BUFFER_SIZE: usize = 8 * 1024;
 
+/// How often the limbo packets are sent to held players. This also keeps their client from
+/// timing out.
+const LIMBO_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How long a player is held while reconnecting to the server before they are disconnected.
+const REJOIN_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Packets are prefixed by their length, which is at most 3 bytes long.
+const MAX_PACKET_LEN_SIZE: usize = 3;
+
+/// The cipher used for online-mode connections.
+type Cipher = cfb8::Encryptor<aes::Aes128>;
+
+/// The cipher used to decrypt what online-mode clients send.
+type Decipher = cfb8::Decryptor<aes::Aes128>;
+
 /// Initiates a player connection handler, managing both incoming and outgoing packet streams.
 ///
 /// This function sets up two asynchronous tasks:
 /// 1. A reader task that processes incoming packets from the player.
 /// 2. A writer task that sends outgoing packets to the player.
 ///
-/// It also handles player disconnection and shutdown scenarios.
+/// It also handles player disconnection and shutdown scenarios. The player is disconnected if
+/// they break any of their `limits`.
+#[expect(clippy::too_many_arguments, reason = "todo; refactor")]
 #[instrument(skip_all, fields(player_id = player_id))]
 pub fn initiate_player_connection(
     socket: impl tokio::io::AsyncRead + AsyncWrite + Send + 'static,
     mut shutdown_signal: tokio::sync::watch::Receiver<Option<ShutdownType>>,
     player_id: u64,
     incoming_packet_receiver: kanal::AsyncReceiver<OrderedBytes>,
-    server_sender: ServerSender,
+    session: Arc<PlayerSession>,
+    link: &'static ServerLink,
     player_registry: &'static papaya::HashMap<u64, PlayerHandle, FxBuildHasher>,
-    player_positions: &'static papaya::HashMap<u64, ChunkPosition, FxBuildHasher>,
+    player_positions: &'static papaya::HashMap<u64, PlayerView, FxBuildHasher>,
+    remote_addr: Option<SocketAddr>,
+    limits: ClientLimits,
 ) -> JoinHandle<()> {
     let span = info_span!("player_connection", player_id);
     let _enter = span.enter();
@@ -48,67 +80,26 @@ pub fn initiate_player_connection(
     info!("Initiating player connection");
     let (socket_reader, socket_writer) = tokio::io::split(socket);
 
-    let mut socket_reader = Box::pin(socket_reader);
+    let socket_reader = Box::pin(socket_reader);
     let socket_writer = Box::pin(socket_writer);
 
-    // Task for handling incoming packets (player -> proxy)
-    let mut packet_reader_task = tokio::spawn({
-        let server_sender = server_sender.clone();
-        async move {
-            let mut read_buffer = Vec::new();
-            let player_stream_id = player_id;
-
-            let connect = rkyv::to_bytes::<rkyv::rancor::Error>(
-                &ProxyToServerMessage::PlayerConnect(PlayerConnect {
-                    stream: player_stream_id,
-                }),
-            )
-            .unwrap();
-
-            if let Err(e) = server_sender.send(connect).await {
-                warn!("failed to send player connect to server: {e}");
-                return;
-            }
-
-            let mut arena = Arena::new();
-
-            loop {
-                // Ensure the buffer has enough capacity
-                read_buffer.reserve(DEFAULT_READ_BUFFER_SIZE);
-
-                let bytes_read = match socket_reader.read_buf(&mut read_buffer).await {
-                    Ok(n) => n,
-                    Err(e) => {
-                        warn!("Error reading from player: {e:?}");
-                        return;
-                    }
-                };
-
-                if bytes_read == 0 {
-                    warn!("End of stream reached for player");
-                    return;
-                }
-
-                let player_packets = ProxyToServerMessage::PlayerPackets(PlayerPackets {
-                    stream: player_id,
-                    data: &read_buffer,
-                });
+    let ClientLimits {
+        ip_guard,
+        login_timeout,
+        inbound,
+    } = limits;
 
-                let aligned_vec = rkyv::api::high::to_bytes_with_alloc::<_, rkyv::rancor::Error>(
-                    &player_packets,
-                    arena.acquire(),
-                )
-                .unwrap();
-
-                read_buffer.clear();
-
-                if let Err(e) = server_sender.send(aligned_vec).await {
-                    warn!("Error forwarding player packets to server: {e:?}");
-                    return;
-                }
-            }
-        }
-    });
+    // Task for handling incoming packets (player -> proxy)
+    let mut packet_reader_task = tokio::spawn(forward_player_packets(
+        socket_reader,
+        player_id,
+        session,
+        link,
+        player_registry,
+        remote_addr,
+        login_timeout,
+        inbound,
+    ));
 
     // Task for handling outgoing packets (proxy -> player)
     let mut packet_writer_task = tokio::spawn(async move {
@@ -119,7 +110,20 @@ pub fn initiate_player_connection(
                 return;
             }
 
-            if outgoing_packet.is_flush() {
+            if outgoing_packet.is_enable_encryption() {
+                // everything queued so far was sent before encryption was enabled
+                if let Err(e) = packet_writer.flush_pending_packets().await {
+                    warn!("Error flushing packets to player: {e:?}");
+                    return;
+                }
+
+                if let Err(e) = packet_writer.enable_encryption(&outgoing_packet.data) {
+                    warn!("Error enabling encryption for player: {e:?}");
+                    return;
+                }
+            } else if outgoing_packet.is_flush() {
+                METRICS.player_queue_depth(incoming_packet_receiver.len());
+
                 let time_start = std::time::Instant::now();
                 if let Err(e) = packet_writer.flush_pending_packets().await {
                     warn!("Error flushing packets to player: {e:?}");
@@ -136,13 +140,17 @@ pub fn initiate_player_connection(
     });
 
     tokio::task::spawn(async move {
+        // players are kept while reconnecting to the server
         let shutdown_received = async move {
-            shutdown_signal.wait_for(Option::is_some).await.unwrap();
+            shutdown_signal
+                .wait_for(|signal| *signal == Some(ShutdownType::Full))
+                .await
+                .unwrap();
         };
 
         tokio::select! {
             () = shutdown_received => {
-                info!("Shutting down player connection due to server shutdown");
+                info!("Shutting down player connection due to proxy shutdown");
                 packet_reader_task.abort();
                 packet_writer_task.abort();
             },
@@ -150,31 +158,14 @@ pub fn initiate_player_connection(
                 info!("Player disconnected because writer task finished: {player_id:?}");
                 packet_reader_task.abort();
 
-                let disconnect = rkyv::to_bytes::<rkyv::rancor::Error>(
-                    &ProxyToServerMessage::PlayerDisconnect(PlayerDisconnect {
-                        stream: player_id,
-                        reason: PlayerDisconnectReason::LostConnection,
-                    }),
-                ).unwrap();
-
-                if let Err(e) = server_sender.send(disconnect).await {
-                    warn!("failed to send player disconnect to server: {e}");
-                }
+                send_disconnect(link, player_id, PlayerDisconnectReason::LostConnection).await;
             },
-            _ = &mut packet_reader_task => {
+            result = &mut packet_reader_task => {
                 info!("Player disconnected because reader task finished: {player_id:?}");
                 packet_writer_task.abort();
 
-
-                let disconnect = rkyv::to_bytes::<rkyv::rancor::Error>(
-                    &ProxyToServerMessage::PlayerDisconnect(PlayerDisconnect {
-                        stream: player_id,
-                        reason: PlayerDisconnectReason::LostConnection,
-                    })).unwrap();
-
-                if let Err(e) = server_sender.send(disconnect).await {
-                    warn!("failed to send player disconnect to server: {e}");
-                }
+                let reason = result.unwrap_or(PlayerDisconnectReason::LostConnection);
+                send_disconnect(link, player_id, reason).await;
 
                 let map_ref = player_registry.pin();
                 map_ref.remove(&player_id);
@@ -184,15 +175,289 @@ pub fn initiate_player_connection(
 
             }
         }
+
+        METRICS.player_disconnected();
+        drop(ip_guard);
     })
 }
 
+/// Tells the server that the player left, unless the proxy is reconnecting to it.
+async fn send_disconnect(
+    link: &ServerLink,
+    player_id: u64,
+    reason: PlayerDisconnectReason<'static>,
+) {
+    let Some(server_sender) = link.sender() else {
+        return;
+    };
+
+    let disconnect = rkyv::to_bytes::<rkyv::rancor::Error>(
+        &ProxyToServerMessage::PlayerDisconnect(PlayerDisconnect {
+            stream: player_id,
+            reason,
+        }),
+    )
+    .unwrap();
+
+    if let Err(e) = server_sender.send(disconnect).await {
+        warn!("failed to send player disconnect to server: {e}");
+    }
+}
+
+fn connect_message(
+    player_id: u64,
+    rejoin: Option<&Rejoin>,
+    remote_addr: Option<SocketAddr>,
+) -> AlignedVec {
+    let rejoin = rejoin.map(|rejoin| hyperion_proto::Rejoin {
+        uuid: rejoin.uuid,
+        username: &rejoin.username,
+    });
+
+    rkyv::to_bytes::<rkyv::rancor::Error>(&ProxyToServerMessage::PlayerConnect(PlayerConnect {
+        stream: player_id,
+        rejoin,
+        addr: remote_addr.map(hyperion_proto::ClientAddr::from),
+    }))
+    .unwrap()
+}
+
+/// Forwards whole packets from the player to the server until the player disconnects, and returns
+/// why they did.
+///
+/// When the connection to the server is lost, players in the play state are held: they are sent
+/// the limbo packets, everything they send is dropped, and once the proxy is connected again they
+/// rejoin the server. Everyone else is disconnected.
+///
+/// Players who are not in the play state after `login_timeout` or who send more than `inbound`
+/// allows are disconnected.
+#[expect(clippy::too_many_arguments, reason = "todo; refactor")]
+async fn forward_player_packets(
+    mut socket_reader: impl AsyncRead + Unpin,
+    player_id: u64,
+    session: Arc<PlayerSession>,
+    link: &'static ServerLink,
+    player_registry: &'static papaya::HashMap<u64, PlayerHandle, FxBuildHasher>,
+    remote_addr: Option<SocketAddr>,
+    login_timeout: Duration,
+    mut inbound: TokenBucket,
+) -> PlayerDisconnectReason<'static> {
+    const LOST_CONNECTION: PlayerDisconnectReason<'static> = PlayerDisconnectReason::LostConnection;
+
+    let mut link_rx = link.subscribe();
+
+    let Some(server_sender) = link_rx.borrow_and_update().clone() else {
+        warn!("Lost the server before the player connected");
+        return LOST_CONNECTION;
+    };
+
+    if let Err(e) = server_sender
+        .send(connect_message(player_id, None, remote_addr))
+        .await
+    {
+        warn!("failed to send player connect to server: {e}");
+        return LOST_CONNECTION;
+    }
+
+    let login_deadline = tokio::time::sleep(login_timeout);
+    tokio::pin!(login_deadline);
+
+    let mut server_sender = Some(server_sender);
+
+    let mut read_buffer = Vec::new();
+    let mut decipher = None;
+    let mut arena = Arena::new();
+
+    let mut limbo = tokio::time::interval(LIMBO_INTERVAL);
+    let mut held_since = Instant::now();
+
+    loop {
+        // Ensure the buffer has enough capacity
+        read_buffer.reserve(DEFAULT_READ_BUFFER_SIZE);
+
+        // everything before this has already been decrypted
+        let read_start = read_buffer.len();
+
+        tokio::select! {
+            () = &mut login_deadline, if session.rejoin.get().is_none() => {
+                warn!("Disconnecting player who did not log in within {login_timeout:?}");
+                METRICS.limited();
+                return PlayerDisconnectReason::Other("did not log in in time");
+            }
+            result = link_rx.changed() => {
+                if result.is_err() {
+                    return LOST_CONNECTION;
+                }
+
+                let Some(rejoin) = session.rejoin.get() else {
+                    info!("Disconnecting player who is not playing yet because the server was lost");
+                    return LOST_CONNECTION;
+                };
+
+                let sender = link_rx.borrow_and_update().clone();
+
+                if let Some(sender) = &sender {
+                    info!("Rejoining player");
+
+                    if let Err(e) = sender.send(connect_message(player_id, Some(rejoin), remote_addr)).await {
+                        warn!("failed to send player rejoin to server: {e}");
+                        return LOST_CONNECTION;
+                    }
+                } else {
+                    info!("Holding player while reconnecting to the server");
+                    held_since = Instant::now();
+                    limbo.reset_immediately();
+                }
+
+                server_sender = sender;
+            }
+            _ = limbo.tick(), if server_sender.is_none() => {
+                if held_since.elapsed() > REJOIN_TIMEOUT {
+                    warn!("Disconnecting held player because the server did not come back");
+                    return LOST_CONNECTION;
+                }
+
+                if !send_limbo(link, player_registry, player_id) {
+                    return LOST_CONNECTION;
+                }
+            }
+            result = socket_reader.read_buf(&mut read_buffer) => {
+                let read = match result {
+                    Ok(0) => {
+                        warn!("End of stream reached for player");
+                        return LOST_CONNECTION;
+                    }
+                    Ok(read) => read,
+                    Err(e) => {
+                        warn!("Error reading from player: {e:?}");
+                        return LOST_CONNECTION;
+                    }
+                };
+
+                if !inbound.try_take(read) {
+                    warn!("Disconnecting player who sent more than their inbound limit");
+                    METRICS.limited();
+                    return PlayerDisconnectReason::Other("sent too much data");
+                }
+
+                if decipher.is_none()
+                    && let Some(key) = session.decryption_key.get()
+                {
+                    decipher = Some(Decipher::new_from_slices(key, key).unwrap());
+                }
+
+                if let Some(decipher) = &mut decipher {
+                    for chunk in read_buffer[read_start..].chunks_mut(Decipher::block_size()) {
+                        decipher.decrypt_block_mut(GenericArray::from_mut_slice(chunk));
+                    }
+                }
+
+                let complete_len = match complete_packets_len(&read_buffer) {
+                    Ok(len) => len,
+                    Err(e) => {
+                        warn!("Invalid packet from player: {e:?}");
+                        return PlayerDisconnectReason::Other("sent an invalid packet");
+                    }
+                };
+
+                if complete_len == 0 {
+                    continue;
+                }
+
+                // packets sent while the player is held are dropped
+                if let Some(server_sender) = &server_sender {
+                    let player_packets = ProxyToServerMessage::PlayerPackets(PlayerPackets {
+                        stream: player_id,
+                        data: &read_buffer[..complete_len],
+                    });
+
+                    let aligned_vec = rkyv::api::high::to_bytes_with_alloc::<_, rkyv::rancor::Error>(
+                        &player_packets,
+                        arena.acquire(),
+                    )
+                    .unwrap();
+
+                    if let Err(e) = server_sender.send(aligned_vec).await {
+                        warn!("Error forwarding player packets to server: {e:?}");
+                        return LOST_CONNECTION;
+                    }
+                }
+
+                read_buffer.drain(..complete_len);
+            }
+        }
+    }
+}
+
+/// Sends the limbo packets to a held player. Returns `false` if the player is gone.
+fn send_limbo(
+    link: &ServerLink,
+    player_registry: &papaya::HashMap<u64, PlayerHandle, FxBuildHasher>,
+    player_id: u64,
+) -> bool {
+    let limbo = link.limbo();
+
+    if limbo.is_empty() {
+        return true;
+    }
+
+    let players = player_registry.pin();
+
+    let Some(player) = players.get(&player_id) else {
+        return false;
+    };
+
+    player.send(OrderedBytes::no_order(limbo)).is_ok() && player.send(OrderedBytes::FLUSH).is_ok()
+}
+
+/// The length of the whole packets at the start of `data`, so that a new server never receives
+/// half a packet.
+fn complete_packets_len(data: &[u8]) -> anyhow::Result<usize> {
+    let mut len = 0;
+
+    loop {
+        let remaining = &data[len..];
+
+        let Some((packet_len, packet_len_size)) = read_packet_len(remaining)? else {
+            return Ok(len);
+        };
+
+        let Some(packet) = remaining.get(packet_len_size..packet_len_size + packet_len) else {
+            return Ok(len);
+        };
+
+        len += packet_len_size + packet.len();
+    }
+}
+
+/// Reads the VarInt length at the start of a packet. Returns the length and the size of the
+/// VarInt, or `None` if `data` ends before the VarInt does.
+fn read_packet_len(data: &[u8]) -> anyhow::Result<Option<(usize, usize)>> {
+    let mut value = 0;
+
+    for (i, &byte) in data.iter().enumerate() {
+        ensure!(i < MAX_PACKET_LEN_SIZE, "packet length is too long");
+
+        value |= usize::from(byte & 0x7F) << (i * 7);
+
+        if byte & 0x80 == 0 {
+            return Ok(Some((value, i + 1)));
+        }
+    }
+
+    Ok(None)
+}
+
 /// Manages the writing of packets to a player's connection.
 struct PlayerPacketWriter<W> {
     writer: W,
     player_id: u64,
     pending_packets: Vec<OrderedBytes>,
     io_vecs: Vec<IoSlice<'static>>,
+    /// Set once the player is in online mode. Packets are then copied into `encrypt_buf` and
+    /// encrypted before being written.
+    cipher: Option<Cipher>,
+    encrypt_buf: Vec<u8>,
 }
 
 impl<W: AsyncWrite + Unpin> PlayerPacketWriter<W> {
@@ -203,9 +468,22 @@ impl<W: AsyncWrite + Unpin> PlayerPacketWriter<W> {
             player_id,
             pending_packets: Vec::new(),
             io_vecs: vec![],
+            cipher: None,
+            encrypt_buf: Vec::new(),
         }
     }
 
+    /// Encrypts all packets written after this call. `key` is used as both the key and the IV.
+    fn enable_encryption(&mut self, key: &[u8]) -> anyhow::Result<()> {
+        ensure!(self.cipher.is_none(), "encryption is already enabled");
+
+        let cipher = Cipher::new_from_slices(key, key)
+            .map_err(|_| anyhow!("invalid encryption key length {}", key.len()))?;
+
+        self.cipher = Some(cipher);
+        Ok(())
+    }
+
     /// Adds a packet to the queue for writing.
     fn enqueue_packet(&mut self, packet: OrderedBytes) {
         self.pending_packets.push(packet);
@@ -233,7 +511,22 @@ impl<W: AsyncWrite + Unpin> PlayerPacketWriter<W> {
             }
         }
 
-        self.writer.write_vectored_all(&mut self.io_vecs).await?;
+        if let Some(cipher) = &mut self.cipher {
+            // the packet data is shared between players, so it has to be copied before encrypting
+            for iovec in &self.io_vecs {
+                self.encrypt_buf.extend_from_slice(iovec);
+            }
+
+            for chunk in self.encrypt_buf.chunks_mut(Cipher::block_size()) {
+                cipher.encrypt_block_mut(GenericArray::from_mut_slice(chunk));
+            }
+
+            self.writer.write_all(&self.encrypt_buf).await?;
+            self.encrypt_buf.clear();
+        } else {
+            self.writer.write_vectored_all(&mut self.io_vecs).await?;
+        }
+
         self.pending_packets.clear();
         self.io_vecs.clear();
 
@@ -246,7 +539,8 @@ fn prepare_io_vectors(
     packet_queue: &mut [OrderedBytes],
     player_id: u64,
 ) -> impl Iterator<Item = IoSlice<'_>> + '_ {
-    packet_queue.sort_unstable_by_key(|packet| packet.order);
+    // stable, so that delayed packets, which all have order 0, stay in the order they were sent in
+    packet_queue.sort_by_key(|packet| packet.order);
 
     packet_queue.iter_mut().flat_map(move |packet| {
         let packet_data = packet.data.as_ref();
@@ -341,3 +635,214 @@ impl RangeExt for std::ops::Range<usize> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use bytes::Bytes;
+    use hyperion_proto::{ArchivedProxyToServerMessage, PlayerDisconnectReason};
+    use rkyv::util::AlignedVec;
+    use rustc_hash::FxBuildHasher;
+    use tokio::io::{AsyncWriteExt, DuplexStream};
+
+    use super::forward_player_packets;
+    use crate::{
+        data::{PlayerHandle, PlayerSession, Rejoin},
+        limits::TokenBucket,
+        link::ServerLink,
+    };
+
+    const LOGIN_TIMEOUT: Duration = Duration::from_millis(20);
+
+    /// A link connected to a server which reads nothing.
+    fn link() -> &'static ServerLink {
+        let link: &'static ServerLink = Box::leak(Box::default());
+        let (sender, receiver) = kanal::bounded_async(16);
+        // keep the server open for the rest of the test
+        std::mem::forget(receiver);
+        link.connect(sender);
+        link
+    }
+
+    fn player_registry() -> &'static papaya::HashMap<u64, PlayerHandle, FxBuildHasher> {
+        Box::leak(Box::default())
+    }
+
+    /// A session of a player in the play state.
+    fn playing() -> Arc<PlayerSession> {
+        let session = PlayerSession::default();
+        session
+            .rejoin
+            .set(Rejoin {
+                uuid: [1; 16],
+                username: "player".into(),
+            })
+            .unwrap();
+        Arc::new(session)
+    }
+
+    /// The UUID and username a `PlayerConnect` sent to the server rejoins with.
+    fn rejoin_of(message: &AlignedVec) -> Option<([u8; 16], String)> {
+        let message = hyperion_proto::access::<ArchivedProxyToServerMessage<'_>>(message).unwrap();
+
+        let ArchivedProxyToServerMessage::PlayerConnect(connect) = message else {
+            panic!("expected a player connect");
+        };
+
+        connect
+            .rejoin
+            .as_ref()
+            .map(|rejoin| (rejoin.uuid, rejoin.username.to_string()))
+    }
+
+    async fn forward(
+        session: Arc<PlayerSession>,
+        socket: DuplexStream,
+        inbound: TokenBucket,
+    ) -> PlayerDisconnectReason<'static> {
+        forward_player_packets(
+            socket,
+            1,
+            session,
+            link(),
+            player_registry(),
+            None,
+            LOGIN_TIMEOUT,
+            inbound,
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_login_deadline() {
+        let (_client, socket) = tokio::io::duplex(1024);
+        let inbound = TokenBucket::new(1024.0, 1024.0);
+
+        let reason = forward(Arc::default(), socket, inbound).await;
+        assert_eq!(
+            reason,
+            PlayerDisconnectReason::Other("did not log in in time")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_no_login_deadline_while_playing() {
+        let (_client, socket) = tokio::io::duplex(1024);
+        let inbound = TokenBucket::new(1024.0, 1024.0);
+
+        let forwarding = forward(playing(), socket, inbound);
+        assert!(
+            tokio::time::timeout(LOGIN_TIMEOUT * 5, forwarding)
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_inbound_limit() {
+        let (mut client, socket) = tokio::io::duplex(1024);
+        let inbound = TokenBucket::new(0.0, 4.0);
+
+        client.write_all(&[0; 8]).await.unwrap();
+
+        let reason = forward(Arc::default(), socket, inbound).await;
+        assert_eq!(reason, PlayerDisconnectReason::Other("sent too much data"));
+    }
+
+    #[tokio::test]
+    async fn test_held_player_rejoins() {
+        let link: &'static ServerLink = Box::leak(Box::default());
+        let (sender, first) = kanal::bounded_async(16);
+        link.connect(sender);
+
+        let (_client, socket) = tokio::io::duplex(1024);
+        let forwarding = tokio::spawn(forward_player_packets(
+            socket,
+            1,
+            playing(),
+            link,
+            player_registry(),
+            None,
+            LOGIN_TIMEOUT,
+            TokenBucket::new(1024.0, 1024.0),
+        ));
+
+        let connect = first.recv().await.unwrap();
+        assert_eq!(rejoin_of(&connect), None);
+
+        link.disconnect();
+        let (sender, second) = kanal::bounded_async(16);
+        link.connect(sender);
+
+        let connect = second.recv().await.unwrap();
+        assert_eq!(rejoin_of(&connect), Some(([1; 16], "player".to_owned())));
+        assert!(!forwarding.is_finished());
+
+        forwarding.abort();
+    }
+
+    #[tokio::test]
+    async fn test_held_player_is_sent_limbo() {
+        let link: &'static ServerLink = Box::leak(Box::default());
+        let (sender, server) = kanal::bounded_async(16);
+        link.connect(sender);
+        link.set_limbo(Bytes::from_static(b"limbo"));
+
+        let session = playing();
+        let (writer, player) = kanal::bounded_async(16);
+        let registry = player_registry();
+        registry
+            .pin()
+            .insert(1, PlayerHandle::new(writer, session.clone()));
+
+        let (_client, socket) = tokio::io::duplex(1024);
+        let forwarding = tokio::spawn(forward_player_packets(
+            socket,
+            1,
+            session,
+            link,
+            registry,
+            None,
+            LOGIN_TIMEOUT,
+            TokenBucket::new(1024.0, 1024.0),
+        ));
+
+        server.recv().await.unwrap();
+        link.disconnect();
+
+        let limbo = player.recv().await.unwrap();
+        assert_eq!(limbo.data, Bytes::from_static(b"limbo"));
+        assert!(player.recv().await.unwrap().is_flush());
+        assert!(!forwarding.is_finished());
+
+        forwarding.abort();
+    }
+
+    #[tokio::test]
+    async fn test_player_not_playing_is_not_held() {
+        let link: &'static ServerLink = Box::leak(Box::default());
+        let (sender, server) = kanal::bounded_async(16);
+        link.connect(sender);
+
+        let (_client, socket) = tokio::io::duplex(1024);
+        let forwarding = tokio::spawn(forward_player_packets(
+            socket,
+            1,
+            Arc::default(),
+            link,
+            player_registry(),
+            None,
+            LOGIN_TIMEOUT * 50,
+            TokenBucket::new(1024.0, 1024.0),
+        ));
+
+        server.recv().await.unwrap();
+        link.disconnect();
+
+        assert_eq!(
+            forwarding.await.unwrap(),
+            PlayerDisconnectReason::LostConnection
+        );
+    }
+}
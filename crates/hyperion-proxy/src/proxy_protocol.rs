@@ -0,0 +1,380 @@
+//! Reads the [PROXY protocol](https://www.haproxy.org/download/2.9/doc/proxy-protocol.txt)
+//! header which a load balancer in front of the proxy sends before each client connection, so
+//! that the real address of the client is known.
+//!
+//! Only enable this behind a load balancer which always sends the header: otherwise, clients can
+//! claim to be at any address.
+
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use anyhow::{Context as _, bail, ensure};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt},
+    task::JoinSet,
+};
+use tokio_util::net::Listener;
+use tracing::{debug, warn};
+
+/// How long a connection may take to send its header.
+const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What a version 1 header starts with.
+const V1_PREFIX: &[u8; 6] = b"PROXY ";
+
+/// A version 1 header is at most this long, including the trailing CRLF.
+const V1_MAX_LEN: usize = 107;
+
+/// What a version 2 header starts with.
+const V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+
+/// Accepts connections from `L` once they sent their PROXY protocol header, with the address of
+/// the client in the header instead of the address of the load balancer.
+///
+/// Headers are read in the background, so a slow connection does not hold up the others.
+pub struct ProxyProtocolListener<L: Listener> {
+    inner: L,
+    pending: JoinSet<Option<(L::Io, SocketAddr)>>,
+}
+
+impl<L: Listener> ProxyProtocolListener<L> {
+    pub fn new(inner: L) -> Self {
+        Self {
+            inner,
+            pending: JoinSet::new(),
+        }
+    }
+}
+
+impl<L> Listener for ProxyProtocolListener<L>
+where
+    L: Listener<Addr = SocketAddr>,
+    L::Io: AsyncRead + Unpin + Send + 'static,
+{
+    type Addr = SocketAddr;
+    type Io = L::Io;
+
+    fn poll_accept(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<(Self::Io, Self::Addr)>> {
+        loop {
+            match self.inner.poll_accept(cx) {
+                Poll::Ready(Ok((socket, addr))) => {
+                    self.pending.spawn(accept(socket, addr));
+                }
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => break,
+            }
+        }
+
+        loop {
+            match self.pending.poll_join_next(cx) {
+                Poll::Ready(Some(Ok(Some(accepted)))) => return Poll::Ready(Ok(accepted)),
+                // the connection was dropped
+                Poll::Ready(Some(Ok(None) | Err(_))) => {}
+                Poll::Ready(None) | Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        self.inner.local_addr()
+    }
+}
+
+/// Reads the header of a connection from the load balancer at `addr`. Returns `None` if the
+/// connection should be dropped, which includes connections whose header has no client address:
+/// they are not from a client, and the address of the load balancer would be counted as theirs.
+async fn accept<Io: AsyncRead + Unpin>(
+    mut socket: Io,
+    addr: SocketAddr,
+) -> Option<(Io, SocketAddr)> {
+    match tokio::time::timeout(HEADER_TIMEOUT, read_header(&mut socket)).await {
+        Ok(Ok(Some(client))) => Some((socket, client)),
+        Ok(Ok(None)) => {
+            debug!("Dropping connection from {addr} whose PROXY protocol header has no client");
+            None
+        }
+        Ok(Err(e)) => {
+            warn!("Dropping connection from {addr} with an invalid PROXY protocol header: {e:?}");
+            None
+        }
+        Err(_) => {
+            warn!("Dropping connection from {addr} which did not send a PROXY protocol header");
+            None
+        }
+    }
+}
+
+/// Reads a version 1 or 2 header, and nothing after it. Returns the address of the client, or
+/// `None` if the header does not say, for example for health checks of the load balancer.
+pub async fn read_header(
+    reader: &mut (impl AsyncRead + Unpin),
+) -> anyhow::Result<Option<SocketAddr>> {
+    let mut start = [0; V1_PREFIX.len()];
+    reader.read_exact(&mut start).await?;
+
+    if &start == V1_PREFIX {
+        return read_v1(reader).await;
+    }
+
+    let mut signature = [0; V2_SIGNATURE.len()];
+    let (head, tail) = signature.split_at_mut(start.len());
+    head.copy_from_slice(&start);
+    reader.read_exact(tail).await?;
+
+    ensure!(&signature == V2_SIGNATURE, "not a PROXY protocol header");
+
+    read_v2(reader).await
+}
+
+/// Reads the rest of a version 1 header, like `PROXY TCP4 192.0.2.1 192.0.2.2 56324 25565\r\n`.
+async fn read_v1(reader: &mut (impl AsyncRead + Unpin)) -> anyhow::Result<Option<SocketAddr>> {
+    let mut line = Vec::with_capacity(V1_MAX_LEN);
+    line.extend_from_slice(V1_PREFIX);
+
+    // byte by byte, as anything after the header belongs to the client
+    while !line.ends_with(b"\r\n") {
+        ensure!(line.len() < V1_MAX_LEN, "version 1 header is too long");
+        line.push(reader.read_u8().await?);
+    }
+
+    let line = std::str::from_utf8(&line).context("version 1 header is not ASCII")?;
+    parse_v1(line)
+}
+
+fn parse_v1(line: &str) -> anyhow::Result<Option<SocketAddr>> {
+    let mut parts = line.trim_end().split(' ').skip(1);
+
+    let protocol = parts.next().context("missing protocol")?;
+
+    if protocol == "UNKNOWN" {
+        return Ok(None);
+    }
+
+    ensure!(
+        matches!(protocol, "TCP4" | "TCP6"),
+        "unknown protocol {protocol}"
+    );
+
+    let (Some(source), Some(_destination), Some(port), Some(_destination_port), None) = (
+        parts.next(),
+        parts.next(),
+        parts.next(),
+        parts.next(),
+        parts.next(),
+    ) else {
+        bail!("expected two addresses and two ports");
+    };
+
+    let ip: IpAddr = source.parse().context("invalid source address")?;
+    let port: u16 = port.parse().context("invalid source port")?;
+
+    Ok(Some(SocketAddr::new(ip, port)))
+}
+
+/// Reads the rest of a version 2 header after its signature.
+async fn read_v2(reader: &mut (impl AsyncRead + Unpin)) -> anyhow::Result<Option<SocketAddr>> {
+    let version_command = reader.read_u8().await?;
+    let family = reader.read_u8().await?;
+    let len = reader.read_u16().await?;
+
+    // the addresses are followed by optional TLVs, which are skipped
+    let mut body = vec![0; usize::from(len)];
+    reader.read_exact(&mut body).await?;
+
+    parse_v2(version_command, family, &body)
+}
+
+fn parse_v2(version_command: u8, family: u8, body: &[u8]) -> anyhow::Result<Option<SocketAddr>> {
+    const LOCAL: u8 = 0x0;
+    const PROXY: u8 = 0x1;
+    const UNSPEC: u8 = 0x00;
+    const TCP_OVER_IPV4: u8 = 0x11;
+    const UDP_OVER_IPV4: u8 = 0x12;
+    const TCP_OVER_IPV6: u8 = 0x21;
+    const UDP_OVER_IPV6: u8 = 0x22;
+    const UNIX_STREAM: u8 = 0x31;
+    const UNIX_DATAGRAM: u8 = 0x32;
+
+    ensure!(
+        version_command >> 4 == 2,
+        "unknown version {}",
+        version_command >> 4
+    );
+
+    match version_command & 0x0F {
+        // sent by the load balancer itself, for example for health checks
+        LOCAL => return Ok(None),
+        PROXY => {}
+        command => bail!("unknown command {command}"),
+    }
+
+    let source = match family {
+        TCP_OVER_IPV4 => {
+            let Some(&[a, b, c, d, _, _, _, _, p0, p1, ..]) = body.get(..12) else {
+                bail!("IPv4 addresses are truncated");
+            };
+
+            SocketAddr::new(
+                Ipv4Addr::new(a, b, c, d).into(),
+                u16::from_be_bytes([p0, p1]),
+            )
+        }
+        TCP_OVER_IPV6 => {
+            let (Some(ip), Some(&[p0, p1])) = (body.get(..16), body.get(32..34)) else {
+                bail!("IPv6 addresses are truncated");
+            };
+
+            let ip: [u8; 16] = ip.try_into()?;
+
+            SocketAddr::new(Ipv6Addr::from(ip).into(), u16::from_be_bytes([p0, p1]))
+        }
+        // UDP or Unix sockets, which say nothing about a Minecraft client
+        UNSPEC | UDP_OVER_IPV4 | UDP_OVER_IPV6 | UNIX_STREAM | UNIX_DATAGRAM => return Ok(None),
+        family => bail!("unknown address family {family:#04x}"),
+    };
+
+    Ok(Some(source))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROXY_OVER_V2: u8 = 0x21;
+    const LOCAL_OVER_V2: u8 = 0x20;
+
+    #[test]
+    fn test_parse_v1() {
+        assert_eq!(
+            parse_v1("PROXY TCP4 192.0.2.1 192.0.2.2 56324 25565\r\n").unwrap(),
+            Some("192.0.2.1:56324".parse().unwrap())
+        );
+        assert_eq!(
+            parse_v1("PROXY TCP6 2001:db8::1 2001:db8::2 56324 25565\r\n").unwrap(),
+            Some("[2001:db8::1]:56324".parse().unwrap())
+        );
+        assert_eq!(parse_v1("PROXY UNKNOWN\r\n").unwrap(), None);
+        assert_eq!(
+            parse_v1("PROXY UNKNOWN 192.0.2.1 192.0.2.2 56324 25565\r\n").unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_parse_v1_invalid() {
+        assert!(parse_v1("PROXY UDP4 192.0.2.1 192.0.2.2 56324 25565\r\n").is_err());
+        assert!(parse_v1("PROXY TCP4 192.0.2.1 192.0.2.2 56324\r\n").is_err());
+        assert!(parse_v1("PROXY TCP4 192.0.2.1 192.0.2.2 56324 25565 1\r\n").is_err());
+        assert!(parse_v1("PROXY TCP4 192.0.2.256 192.0.2.2 56324 25565\r\n").is_err());
+        assert!(parse_v1("PROXY TCP4 192.0.2.1 192.0.2.2 65536 25565\r\n").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_accept() {
+        let load_balancer = "192.0.2.2:40000".parse().unwrap();
+
+        let header = b"PROXY TCP4 192.0.2.1 192.0.2.2 56324 25565\r\n";
+        let (_, addr) = accept(&header[..], load_balancer).await.unwrap();
+        assert_eq!(addr, "192.0.2.1:56324".parse().unwrap());
+
+        // health checks of the load balancer
+        assert!(
+            accept(&b"PROXY UNKNOWN\r\n"[..], load_balancer)
+                .await
+                .is_none()
+        );
+
+        assert!(
+            accept(&b"GET / HTTP/1.1\r\n"[..], load_balancer)
+                .await
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_v1_too_long() {
+        let mut header = format!("PROXY UNKNOWN {}\r\n", "a".repeat(V1_MAX_LEN)).into_bytes();
+        header.extend_from_slice(b"rest");
+
+        assert!(read_header(&mut header.as_slice()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_read_v1_leaves_client_data() {
+        let header = b"PROXY TCP4 192.0.2.1 192.0.2.2 56324 25565\r\nclient";
+        let mut reader = &header[..];
+
+        let addr = read_header(&mut reader).await.unwrap();
+
+        assert_eq!(addr, Some("192.0.2.1:56324".parse().unwrap()));
+        assert_eq!(reader, b"client");
+    }
+
+    #[test]
+    fn test_parse_v2() {
+        let ipv4 = [192, 0, 2, 1, 192, 0, 2, 2, 0xDC, 0x04, 0x63, 0xDD];
+        assert_eq!(
+            parse_v2(PROXY_OVER_V2, 0x11, &ipv4).unwrap(),
+            Some("192.0.2.1:56324".parse().unwrap())
+        );
+
+        let mut ipv6 = Vec::new();
+        ipv6.extend_from_slice(&"2001:db8::1".parse::<Ipv6Addr>().unwrap().octets());
+        ipv6.extend_from_slice(&"2001:db8::2".parse::<Ipv6Addr>().unwrap().octets());
+        ipv6.extend_from_slice(&[0xDC, 0x04, 0x63, 0xDD]);
+        assert_eq!(
+            parse_v2(PROXY_OVER_V2, 0x21, &ipv6).unwrap(),
+            Some("[2001:db8::1]:56324".parse().unwrap())
+        );
+
+        // TLVs after the addresses are ignored
+        let mut with_tlv = ipv4.to_vec();
+        with_tlv.extend_from_slice(&[0x04, 0x00, 0x01, 0x00]);
+        assert_eq!(
+            parse_v2(PROXY_OVER_V2, 0x11, &with_tlv).unwrap(),
+            Some("192.0.2.1:56324".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_parse_v2_without_client() {
+        assert_eq!(parse_v2(LOCAL_OVER_V2, 0x00, &[]).unwrap(), None);
+        assert_eq!(parse_v2(LOCAL_OVER_V2, 0x11, &[0; 3]).unwrap(), None);
+        assert_eq!(parse_v2(PROXY_OVER_V2, 0x00, &[]).unwrap(), None);
+        assert_eq!(parse_v2(PROXY_OVER_V2, 0x12, &[0; 12]).unwrap(), None);
+        assert_eq!(parse_v2(PROXY_OVER_V2, 0x31, &[0; 216]).unwrap(), None);
+    }
+
+    #[test]
+    fn test_parse_v2_invalid() {
+        // truncated addresses
+        assert!(parse_v2(PROXY_OVER_V2, 0x11, &[0; 11]).is_err());
+        assert!(parse_v2(PROXY_OVER_V2, 0x21, &[0; 35]).is_err());
+
+        // bad family bytes
+        assert!(parse_v2(PROXY_OVER_V2, 0x13, &[0; 12]).is_err());
+        assert!(parse_v2(PROXY_OVER_V2, 0xFF, &[0; 36]).is_err());
+
+        // bad version or command
+        assert!(parse_v2(0x11, 0x11, &[0; 12]).is_err());
+        assert!(parse_v2(0x22, 0x11, &[0; 12]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_read_v2() {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend_from_slice(&[PROXY_OVER_V2, 0x11, 0x00, 0x0C]);
+        header.extend_from_slice(&[192, 0, 2, 1, 192, 0, 2, 2, 0xDC, 0x04, 0x63, 0xDD]);
+        header.extend_from_slice(b"client");
+        let mut reader = header.as_slice();
+
+        let addr = read_header(&mut reader).await.unwrap();
+
+        assert_eq!(addr, Some("192.0.2.1:56324".parse().unwrap()));
+        assert_eq!(reader, b"client");
+    }
+}
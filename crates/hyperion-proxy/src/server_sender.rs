@@ -1,5 +1,6 @@
 use std::io::IoSlice;
 
+use hyperion_proto::{FRAME_ALIGN, LEN_PREFIX};
 use rkyv::util::AlignedVec;
 use tracing::{Instrument, trace_span, warn};
 
@@ -7,6 +8,9 @@ use crate::util::AsyncWriteVectoredExt;
 
 pub type ServerSender = kanal::AsyncSender<AlignedVec>;
 
+/// Zeroes to pad frames with. See [`hyperion_proto::padding`].
+static PADDING: [u8; FRAME_ALIGN] = [0; FRAME_ALIGN];
+
 // todo: probably makes sense for caller to encode bytes
 #[must_use]
 pub fn launch_server_writer(mut write: tokio::net::tcp::OwnedWriteHalf) -> ServerSender {
@@ -14,21 +18,18 @@ pub fn launch_server_writer(mut write: tokio::net::tcp::OwnedWriteHalf) -> Serve
 
     tokio::spawn(
         async move {
-            let mut lengths: Vec<[u8; 8]> = Vec::new();
+            let mut lengths: Vec<[u8; LEN_PREFIX]> = Vec::new();
             let mut messages = Vec::new();
 
             // todo: remove allocation is there an easy way to do this?
             let mut io_slices = Vec::new();
 
             while let Ok(message) = rx.recv().await {
-                let len = message.len() as u64;
-
-                lengths.push(len.to_be_bytes());
+                lengths.push(hyperion_proto::encode_len(message.len()));
                 messages.push(message);
 
                 while let Ok(Some(message)) = rx.try_recv() {
-                    let len = message.len() as u64;
-                    lengths.push(len.to_be_bytes());
+                    lengths.push(hyperion_proto::encode_len(message.len()));
                     messages.push(message);
                 }
 
@@ -42,6 +43,11 @@ pub fn launch_server_writer(mut write: tokio::net::tcp::OwnedWriteHalf) -> Serve
 
                     io_slices.push(len);
                     io_slices.push(msg);
+
+                    let padding = hyperion_proto::padding(message.len());
+                    if padding != 0 {
+                        io_slices.push(IoSlice::new(&PADDING[..padding]));
+                    }
                 }
 
                 if let Err(e) = write.write_vectored_all(&mut io_slices).await {
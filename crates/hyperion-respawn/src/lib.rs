@@ -7,12 +7,12 @@ use hyperion::{
     },
     net::{ConnectionId, DataBundle},
     protocol::{
-        game_mode::OptGameMode,
         packets::play::{self, PlayerAbilitiesS2c},
-        BlockPos, ByteAngle, GlobalPos, VarInt,
+        BlockPos, ByteAngle, VarInt,
     },
-    server::{abilities::PlayerAbilitiesFlags, ident, GameMode},
+    server::{abilities::PlayerAbilitiesFlags, GameMode},
     simulation::{
+        dimension::{with_dimension, InWorld},
         event::{ClientStatusCommand, ClientStatusEvent},
         handlers::PacketSwitchQuery,
         metadata::{entity::Pose, living_entity::Health},
@@ -49,6 +49,7 @@ impl Module for RespawnModule {
                         &Xp,
                         &Flight,
                         &FlyingSpeed,
+                        Option<&InWorld>,
                     )>(
                         |(
                             connection,
@@ -61,6 +62,7 @@ impl Module for RespawnModule {
                             xp,
                             flight,
                             flying_speed,
+                            in_world,
                         )| {
                             health.heal(20.);
 
@@ -73,22 +75,6 @@ impl Module for RespawnModule {
                                 food_saturation: 5.0,
                             };
 
-                            let pkt_respawn = play::PlayerRespawnS2c {
-                                dimension_type_name: ident!("minecraft:overworld").into(),
-                                dimension_name: ident!("minecraft:overworld").into(),
-                                hashed_seed: 0,
-                                game_mode: GameMode::Survival,
-                                previous_game_mode: OptGameMode::default(),
-                                is_debug: false,
-                                is_flat: false,
-                                copy_metadata: false,
-                                last_death_location: Option::from(GlobalPos {
-                                    dimension_name: ident!("minecraft:overworld").into(),
-                                    position: BlockPos::from(position.as_dvec3()),
-                                }),
-                                portal_cooldown: VarInt::default(),
-                            };
-
                             let pkt_xp = play::ExperienceBarUpdateS2c {
                                 bar: xp.get_visual().prop,
                                 level: VarInt(i32::from(xp.get_visual().level)),
@@ -113,7 +99,15 @@ impl Module for RespawnModule {
 
                             let mut bundle = DataBundle::new(query.compose, query.system);
                             bundle.add_packet(&pkt_health).unwrap();
-                            bundle.add_packet(&pkt_respawn).unwrap();
+                            // respawn in the world the player died in
+                            with_dimension(query.world, in_world, |dimension| {
+                                let pkt_respawn = dimension.respawn_packet(
+                                    GameMode::Survival,
+                                    false,
+                                    Some(BlockPos::from(position.as_dvec3())),
+                                );
+                                bundle.add_packet(&pkt_respawn).unwrap();
+                            });
                             bundle.add_packet(&pkt_xp).unwrap();
                             bundle.add_packet(&pkt_abilities).unwrap();
 
@@ -6,8 +6,10 @@ use flecs_ecs::{
 
 mod cached_save;
 mod lifetime;
+mod prometheus;
 pub use cached_save::cached_save;
 pub use lifetime::*;
+pub use prometheus::serve_prometheus;
 
 pub trait EntityExt {
     fn minecraft_id(&self) -> i32;
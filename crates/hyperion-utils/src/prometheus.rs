@@ -0,0 +1,141 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Context;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpListener, ToSocketAddrs},
+};
+use tracing::{debug, info};
+
+/// Requests larger than this are rejected.
+const MAX_REQUEST_LEN: usize = 8 * 1024;
+
+/// How long a client may take to send its request, so that idle connections are closed.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Serves the Prometheus text returned by `render` at `/metrics` on `addr` until the process
+/// exits. `render` is called once per scrape.
+pub async fn serve_prometheus(
+    addr: impl ToSocketAddrs,
+    render: impl Fn() -> String + Send + Sync + 'static,
+) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(addr)
+        .await
+        .context("failed to bind the metrics listener")?;
+
+    info!(
+        "📈 Serving metrics on http://{}/metrics",
+        listener.local_addr()?
+    );
+
+    let render = Arc::new(render);
+
+    loop {
+        let (socket, addr) = listener.accept().await?;
+        let render = render.clone();
+
+        tokio::spawn(async move {
+            if let Err(e) = respond(socket, &*render).await {
+                debug!("failed to serve metrics to {addr}: {e:?}");
+            }
+        });
+    }
+}
+
+/// Answers a single HTTP request and closes the connection.
+async fn respond(
+    mut socket: impl AsyncRead + AsyncWrite + Unpin,
+    render: &impl Fn() -> String,
+) -> anyhow::Result<()> {
+    let request = tokio::time::timeout(REQUEST_TIMEOUT, read_request(&mut socket))
+        .await
+        .context("timed out reading the request")??;
+
+    let (status, body) = if request.starts_with(b"GET /metrics ") {
+        ("200 OK", render())
+    } else {
+        ("404 Not Found", String::from("try /metrics\n"))
+    };
+
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: \
+         {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+
+    socket.write_all(response.as_bytes()).await?;
+    socket.shutdown().await?;
+
+    Ok(())
+}
+
+/// Reads up to the end of the request headers.
+async fn read_request(socket: &mut (impl AsyncRead + Unpin)) -> anyhow::Result<Vec<u8>> {
+    let mut request = Vec::new();
+
+    while !request.ends_with(b"\r\n\r\n") {
+        anyhow::ensure!(request.len() < MAX_REQUEST_LEN, "request is too long");
+
+        if socket.read_buf(&mut request).await? == 0 {
+            anyhow::bail!("connection closed before the request ended");
+        }
+    }
+
+    Ok(request)
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::{REQUEST_TIMEOUT, respond};
+
+    fn render() -> String {
+        String::from("hyperion_players 3\n")
+    }
+
+    async fn request(request: &[u8]) -> String {
+        let (mut client, server) = tokio::io::duplex(1024);
+
+        client.write_all(request).await.unwrap();
+        respond(server, &render).await.unwrap();
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn test_metrics() {
+        let response = request(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+        assert!(response.contains("Content-Length: 19\r\n"), "{response}");
+        assert!(
+            response.ends_with("\r\n\r\nhyperion_players 3\n"),
+            "{response}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_not_found() {
+        let response = request(b"GET / HTTP/1.1\r\n\r\n").await;
+        assert!(
+            response.starts_with("HTTP/1.1 404 Not Found\r\n"),
+            "{response}"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_request_timeout() {
+        let (mut client, server) = tokio::io::duplex(1024);
+        client
+            .write_all(b"GET /metrics HTTP/1.1\r\n")
+            .await
+            .unwrap();
+
+        let start = tokio::time::Instant::now();
+        assert!(respond(server, &render).await.is_err());
+        assert!(start.elapsed() >= REQUEST_TIMEOUT);
+    }
+}
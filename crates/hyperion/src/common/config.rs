@@ -1,11 +1,28 @@
 //! Configuration for the server.
 
-use std::{fmt::Debug, fs::File, io::Read, path::Path};
+use std::{
+    fmt::{Debug, Write as _},
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
 
+use anyhow::{Context, ensure};
+use base64::{Engine as _, engine::general_purpose};
 use flecs_ecs::macros::Component;
+use glam::{IVec2, IVec3};
+use hyperion_text::Text;
 use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use tokio::time::MissedTickBehavior;
 use tracing::{info, instrument, warn};
 
+use crate::{runtime::AsyncRuntime, simulation::dimension::Dimension};
+
+/// How often [`ConfigWatcher`] checks whether the configuration file was modified.
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
 /// The configuration for the server representing a `toml` file.
 #[derive(Serialize, Deserialize, Debug, Component)]
 pub struct Config {
@@ -14,9 +31,81 @@ pub struct Config {
     pub view_distance: i16,
     pub simulation_distance: i32,
     pub server_desc: String,
+    /// The description shown in the server list. Falls back to [`Self::server_desc`] if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub motd: Option<Text<'static>>,
+    /// The path to a 64x64 PNG shown next to the server in the server list.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub favicon: Option<PathBuf>,
+    /// [`Self::favicon`] as a data URL, read once when the configuration is loaded.
+    #[serde(skip)]
+    pub favicon_data: Option<String>,
+    /// Whether players must authenticate with Mojang's session server. Without this, anyone can
+    /// join under any username.
+    #[serde(default)]
+    pub online_mode: bool,
+    /// Whether only players on the whitelist in [`crate::storage::AccessList`] may join.
+    #[serde(default)]
+    pub whitelist: bool,
+    /// The resource pack players are sent when they join.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resource_pack: Option<ResourcePack>,
     pub spawn: Spawn,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ResourcePack {
+    pub url: String,
+    /// The SHA-1 hash of the pack as 40 hex digits. Clients download the pack again when their
+    /// cached copy has a different hash.
+    pub sha1: String,
+    /// Whether players must accept the pack. Players who decline it or fail to download it are
+    /// kicked.
+    #[serde(default)]
+    pub forced: bool,
+}
+
+impl ResourcePack {
+    /// Checks that [`Self::sha1`] is a well-formed hash. Clients reject any other value and fail
+    /// to load the pack.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        ensure!(
+            self.sha1.len() == 40 && self.sha1.bytes().all(|b| b.is_ascii_hexdigit()),
+            "the resource pack hash {:?} is not 40 hex digits",
+            self.sha1
+        );
+
+        Ok(())
+    }
+
+    /// Downloads the pack and checks that it has the configured hash, since clients reject a pack
+    /// with the wrong hash.
+    pub async fn verify(&self) -> anyhow::Result<()> {
+        let bytes = reqwest::get(&self.url)
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+
+        let hash = Sha1::digest(&bytes)
+            .iter()
+            .fold(String::new(), |mut hash, byte| {
+                let _ = write!(hash, "{byte:02x}");
+                hash
+            });
+
+        ensure!(
+            hash.eq_ignore_ascii_case(&self.sha1),
+            "the resource pack at {} has the SHA-1 hash {hash}, but the configured hash is {}",
+            self.url,
+            self.sha1
+        );
+
+        Ok(())
+    }
+}
+
+/// Where players spawn: a random column within [`Self::radius`] blocks of the center.
 #[derive(Serialize, Deserialize, Debug, Component)]
 pub struct Spawn {
     pub kind: Radius,
@@ -26,6 +115,39 @@ pub struct Spawn {
     pub z: i32,
 }
 
+impl Spawn {
+    #[must_use]
+    pub const fn center(&self) -> IVec3 {
+        IVec3::new(self.x, self.y, self.z)
+    }
+
+    /// A random column of the spawn area.
+    #[must_use]
+    pub fn random_column(&self) -> IVec2 {
+        let radius = self.radius.max(0);
+
+        loop {
+            let offset = IVec2::new(
+                fastrand::i32(-radius..=radius),
+                fastrand::i32(-radius..=radius),
+            );
+
+            let inside = match self.kind {
+                Radius::Chebyshev => true,
+                Radius::Euclidean => {
+                    let distance_squared = i64::from(offset.x).pow(2) + i64::from(offset.y).pow(2);
+                    distance_squared <= i64::from(radius).pow(2)
+                }
+            };
+
+            if inside {
+                return IVec2::new(self.x, self.z) + offset;
+            }
+        }
+    }
+}
+
+/// How [`Spawn::radius`] is measured.
 #[derive(Serialize, Deserialize, Debug, Clone, Copy)]
 pub enum Radius {
     Chebyshev,
@@ -40,6 +162,12 @@ impl Default for Config {
             view_distance: 32,
             simulation_distance: 10,
             server_desc: "Hyperion Test Server".to_owned(),
+            motd: None,
+            favicon: None,
+            favicon_data: None,
+            online_mode: false,
+            whitelist: false,
+            resource_pack: None,
             spawn: Spawn::default(),
         }
     }
@@ -66,11 +194,7 @@ impl Config {
         info!("loading configuration file");
 
         if path.as_ref().exists() {
-            let mut file = File::open(path)?;
-            let mut contents = String::default();
-            file.read_to_string(&mut contents)?;
-            let config = toml::from_str::<Self>(contents.as_str())?;
-            return Ok(config);
+            return Self::read(path.as_ref());
         }
 
         info!("configuration file not found, using defaults");
@@ -97,4 +221,278 @@ impl Config {
 
         Ok(Self::default())
     }
+
+    /// Parses an existing configuration file.
+    fn read(path: &Path) -> anyhow::Result<Self> {
+        let mut file = File::open(path)?;
+        let mut contents = String::default();
+        file.read_to_string(&mut contents)?;
+        let mut config = Self::parse(&contents)?;
+        config.load_favicon();
+        Ok(config)
+    }
+
+    /// Parses and validates the contents of a configuration file.
+    fn parse(contents: &str) -> anyhow::Result<Self> {
+        let config = toml::from_str::<Self>(contents)?;
+
+        if let Some(pack) = &config.resource_pack {
+            pack.validate()?;
+        }
+
+        Ok(config)
+    }
+
+    /// Replaces this configuration with a reloaded one and applies its world border to
+    /// `dimension`, the main world. Returns the previous configuration.
+    pub fn reload(&mut self, new: Self, dimension: &mut Dimension) -> Box<Self> {
+        dimension.border_diameter = new.border_diameter;
+        Box::new(std::mem::replace(self, new))
+    }
+
+    /// The description shown in the server list.
+    #[must_use]
+    pub fn motd(&self) -> Text<'_> {
+        self.motd
+            .clone()
+            .unwrap_or_else(|| Text::new(&self.server_desc))
+    }
+
+    fn load_favicon(&mut self) {
+        let Some(path) = &self.favicon else {
+            return;
+        };
+
+        match read_favicon(path) {
+            Ok(data) => self.favicon_data = Some(data),
+            // a broken favicon should not prevent the server from starting
+            Err(e) => warn!("failed to load favicon {path:?}: {e}"),
+        }
+    }
+}
+
+/// Reloads the configuration file in the background whenever it is modified.
+///
+/// A file which fails to parse is logged and ignored, so a half-written edit never replaces a
+/// working configuration. The `reload_config` system swaps in new configurations and pushes a
+/// [`crate::simulation::event::ConfigChanged`] event.
+#[derive(Component)]
+pub struct ConfigWatcher {
+    reloaded: kanal::Receiver<Config>,
+}
+
+impl ConfigWatcher {
+    pub fn spawn(path: impl Into<PathBuf>, runtime: &AsyncRuntime) -> Self {
+        let path = path.into();
+        let (tx, reloaded) = kanal::unbounded();
+
+        runtime.spawn(async move {
+            let mut last_modified = modified(&path);
+
+            let mut interval = tokio::time::interval(WATCH_INTERVAL);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+            loop {
+                interval.tick().await;
+
+                let modified = modified(&path);
+                if modified.is_none() || modified == last_modified {
+                    continue;
+                }
+                last_modified = modified;
+
+                let read_path = path.clone();
+                let config = match tokio::task::spawn_blocking(move || Config::read(&read_path))
+                    .await
+                {
+                    Ok(Ok(config)) => config,
+                    Ok(Err(e)) => {
+                        warn!("failed to reload {path:?}, keeping the current configuration: {e}");
+                        continue;
+                    }
+                    Err(e) => {
+                        warn!("failed to reload {path:?}: {e}");
+                        continue;
+                    }
+                };
+
+                info!("reloaded configuration from {path:?}");
+
+                if tx.send(config).is_err() {
+                    // the server has shut down
+                    return;
+                }
+            }
+        });
+
+        Self { reloaded }
+    }
+
+    /// The most recently reloaded configuration, if the file changed since the last call.
+    #[must_use]
+    pub fn try_recv(&self) -> Option<Config> {
+        let mut latest = None;
+
+        while let Ok(Some(config)) = self.reloaded.try_recv() {
+            latest = Some(config);
+        }
+
+        latest
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+fn read_favicon(path: &Path) -> anyhow::Result<String> {
+    const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+    let bytes = std::fs::read(path).context("failed to read file")?;
+    ensure!(bytes.starts_with(PNG_SIGNATURE), "not a PNG file");
+
+    let encoded = general_purpose::STANDARD.encode(bytes);
+    Ok(format!("data:image/png;base64,{encoded}"))
+}
+
+#[cfg(test)]
+#[expect(clippy::unwrap_used, reason = "these are tests")]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+    };
+
+    use super::{Config, Radius, ResourcePack, Spawn};
+    use crate::{runtime::AsyncRuntime, simulation::dimension::Dimension};
+
+    const PACK: &[u8] = b"not really a zip";
+
+    /// The SHA-1 hash of [`PACK`].
+    const PACK_SHA1: &str = "240db3986ff0c20e0de52ae69943b98496cb779b";
+
+    /// Starts a stand-in for the pack host which serves [`PACK`] at `/pack.zip`. Returns the url
+    /// of the pack.
+    fn local_pack_host() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+
+                let mut request = [0; 4096];
+                let len = stream.read(&mut request).unwrap();
+                let request = String::from_utf8_lossy(&request[..len]);
+
+                let found = request.starts_with("GET /pack.zip ");
+                let (status, body) = if found {
+                    ("200 OK", PACK)
+                } else {
+                    ("404 Not Found", &[][..])
+                };
+
+                write!(
+                    stream,
+                    "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    body.len()
+                )
+                .unwrap();
+                stream.write_all(body).unwrap();
+            }
+        });
+
+        format!("http://{addr}/pack.zip")
+    }
+
+    #[test]
+    fn test_verify() {
+        let (tx, _rx) = kanal::bounded(1);
+        let tasks = AsyncRuntime::new(tx);
+        let url = local_pack_host();
+
+        let pack = ResourcePack {
+            url: url.clone(),
+            sha1: PACK_SHA1.to_owned(),
+            forced: true,
+        };
+        tasks.block_on(pack.verify()).unwrap();
+
+        let stale = ResourcePack {
+            sha1: "0".repeat(40),
+            ..pack.clone()
+        };
+        assert!(tasks.block_on(stale.verify()).is_err());
+
+        let missing = ResourcePack {
+            url: url.replace("pack.zip", "missing.zip"),
+            ..pack
+        };
+        assert!(tasks.block_on(missing.verify()).is_err());
+    }
+
+    #[test]
+    fn test_validate_sha1() {
+        let with_hash = |sha1: &str| Config {
+            resource_pack: Some(ResourcePack {
+                url: "https://example.com/pack.zip".to_owned(),
+                sha1: sha1.to_owned(),
+                forced: false,
+            }),
+            ..Config::default()
+        };
+
+        for valid in [PACK_SHA1, PACK_SHA1.to_uppercase().as_str()] {
+            let contents = toml::to_string(&with_hash(valid)).unwrap();
+            assert!(
+                Config::parse(&contents).is_ok(),
+                "{valid:?} should be accepted"
+            );
+        }
+
+        for invalid in [
+            "",
+            &PACK_SHA1[1..],
+            format!("{PACK_SHA1}0").as_str(),
+            "g".repeat(40).as_str(),
+        ] {
+            let contents = toml::to_string(&with_hash(invalid)).unwrap();
+            assert!(
+                Config::parse(&contents).is_err(),
+                "{invalid:?} should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn test_reload_changes_border_and_spawn() {
+        let mut config = Config::default();
+        let mut dimension = Dimension::overworld(config.border_diameter);
+
+        let new = Config {
+            border_diameter: Some(250.0),
+            spawn: Spawn {
+                kind: Radius::Euclidean,
+                radius: 8,
+                x: 100,
+                y: 70,
+                z: -100,
+            },
+            ..Config::default()
+        };
+
+        let previous = config.reload(new, &mut dimension);
+
+        assert_eq!(previous.border_diameter, Some(100.0));
+        assert_eq!(dimension.border_diameter, Some(250.0));
+        assert_eq!(config.spawn.center(), glam::IVec3::new(100, 70, -100));
+
+        for _ in 0..100 {
+            let offset = config.spawn.random_column() - glam::IVec2::new(100, -100);
+            assert!(
+                offset.length_squared() <= 64,
+                "{offset} is outside the spawn"
+            );
+        }
+    }
 }
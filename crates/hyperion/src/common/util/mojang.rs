@@ -19,6 +19,7 @@ use crate::runtime::AsyncRuntime;
 pub struct ApiProvider {
     username_base_url: &'static str,
     uuid_base_url: &'static str,
+    session_base_url: &'static str,
     max_requests: usize,
     interval: Duration,
 }
@@ -28,6 +29,7 @@ impl ApiProvider {
     pub const MAT_DOES_DEV: Self = Self {
         username_base_url: "https://mowojang.matdoes.dev/users/profiles/minecraft",
         uuid_base_url: "https://mowojang.matdoes.dev/session/minecraft/profile",
+        session_base_url: "https://sessionserver.mojang.com/session/minecraft",
         max_requests: 10_000,
         interval: Duration::from_secs(1),
     };
@@ -35,10 +37,19 @@ impl ApiProvider {
     pub const MOJANG: Self = Self {
         username_base_url: "https://api.mojang.com/users/profiles/minecraft",
         uuid_base_url: "https://sessionserver.mojang.com/session/minecraft/profile",
+        session_base_url: "https://sessionserver.mojang.com/session/minecraft",
         max_requests: 600,
         interval: Duration::from_mins(10),
     };
 
+    /// Uses a different session server to verify online-mode logins. Only the session server the
+    /// client joined through can verify it, so this is mainly useful for tests.
+    #[must_use]
+    pub const fn with_session_server(mut self, base_url: &'static str) -> Self {
+        self.session_base_url = base_url;
+        self
+    }
+
     fn username_url(&self, username: &str) -> String {
         format!("{}/{username}", self.username_base_url)
     }
@@ -47,6 +58,10 @@ impl ApiProvider {
         format!("{}/{uuid}?unsigned=false", self.uuid_base_url)
     }
 
+    fn has_joined_url(&self) -> String {
+        format!("{}/hasJoined", self.session_base_url)
+    }
+
     const fn max_requests(&self) -> usize {
         self.max_requests
     }
@@ -139,6 +154,40 @@ impl MojangClient {
         self.response_raw(&url).await
     }
 
+    /// Checks with the session server that the client logging in as `username` has joined the
+    /// server identified by `server_hash`.
+    ///
+    /// Returns the player's profile (in the same format as [`Self::data_from_uuid`]), or `None`
+    /// if the session could not be verified. This does not count towards the rate limit, as it
+    /// is a different endpoint.
+    pub async fn has_joined(
+        &self,
+        username: &str,
+        server_hash: &str,
+    ) -> anyhow::Result<Option<Value>> {
+        let response = self
+            .req
+            .get(self.provider.has_joined_url())
+            .query(&[("username", username), ("serverId", server_hash)])
+            .send()
+            .await?;
+
+        // the session server responds with 204 No Content if the player has not joined
+        if response.status() == reqwest::StatusCode::NO_CONTENT {
+            return Ok(None);
+        }
+
+        if !response.status().is_success() {
+            bail!("session server responded with {}", response.status());
+        }
+
+        let body = response.text().await?;
+        let json_object = serde_json::from_str::<Value>(&body)
+            .with_context(|| format!("failed to parse json from response: {body:?}"))?;
+
+        Ok(Some(json_object))
+    }
+
     async fn response_raw(&self, url: &str) -> anyhow::Result<Value> {
         self.rate_limit
             .acquire()
@@ -175,13 +224,78 @@ impl MojangClient {
 #[cfg(test)]
 #[expect(clippy::unwrap_used, clippy::print_stdout, reason = "these are tests")]
 mod tests {
-    use std::str::FromStr;
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+        str::FromStr,
+    };
 
     use crate::{
         runtime::AsyncRuntime,
         util::mojang::{ApiProvider, MojangClient},
     };
 
+    /// Starts a stand-in for the session server which only knows that `Emerald_Explorer` joined
+    /// the server with the hash `abc`. Returns its base url.
+    fn local_session_server() -> &'static str {
+        const PROFILE: &str = r#"{"id":"86271406118844a584967af10c906204","name":"Emerald_Explorer","properties":[]}"#;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+
+                let mut request = [0; 4096];
+                let len = stream.read(&mut request).unwrap();
+                let request = String::from_utf8_lossy(&request[..len]);
+
+                let request_line = request.lines().next().unwrap_or_default();
+                let joined = request_line.starts_with("GET /session/minecraft/hasJoined?")
+                    && request_line.contains("username=Emerald_Explorer")
+                    && request_line.contains("serverId=abc ");
+
+                let (status, body) = if joined {
+                    ("200 OK", PROFILE)
+                } else {
+                    ("204 No Content", "")
+                };
+
+                write!(
+                    stream,
+                    "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                )
+                .unwrap();
+            }
+        });
+
+        format!("http://{addr}/session/minecraft").leak()
+    }
+
+    #[test]
+    fn test_has_joined() {
+        let (tx, _rx) = kanal::bounded(1);
+        let tasks = AsyncRuntime::new(tx);
+        let provider = ApiProvider::MOJANG.with_session_server(local_session_server());
+        let mojang = MojangClient::new(&tasks, provider);
+
+        let profile = tasks
+            .block_on(mojang.has_joined("Emerald_Explorer", "abc"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(profile["name"], "Emerald_Explorer");
+
+        let profile = tasks
+            .block_on(mojang.has_joined("Emerald_Explorer", "not-abc"))
+            .unwrap();
+        assert!(profile.is_none());
+
+        let profile = tasks.block_on(mojang.has_joined("Notch", "abc")).unwrap();
+        assert!(profile.is_none());
+    }
+
     #[test]
     fn test_get_uuid() {
         let (tx, _rx) = kanal::bounded(1);
@@ -0,0 +1,101 @@
+use std::{
+    borrow::Cow,
+    time::{Duration, Instant},
+};
+
+use flecs_ecs::prelude::*;
+use tracing::{error, info, info_span};
+use valence_protocol::packets::play;
+
+use crate::{
+    egress::player_join::{PlayerListActions, PlayerListEntry, PlayerListS2c},
+    ingress::PendingRemove,
+    net::{Compose, ConnectionId},
+    simulation::{KeepAlive, Latency, Name, Player, Uuid},
+};
+
+/// How often a keep-alive is sent to every player, as the vanilla server does.
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// How often the latency of every player is sent to the player list.
+const LATENCY_BROADCAST_INTERVAL_TICKS: i64 = 100;
+
+#[derive(Component)]
+pub struct KeepAliveModule;
+
+impl Module for KeepAliveModule {
+    fn module(world: &World) {
+        system!(
+            "send_keep_alives",
+            world,
+            &Compose($),
+            &mut KeepAlive,
+            &ConnectionId,
+            &Name,
+        )
+        .with::<Player>()
+        .without::<PendingRemove>()
+        .kind::<flecs::pipeline::OnUpdate>()
+        .each_iter(|it, row, (compose, keep_alive, io, name)| {
+            let system = it.system();
+            let now = Instant::now();
+
+            if keep_alive.pending.is_some() {
+                if keep_alive.is_timed_out(now, compose.global().keep_alive_timeout) {
+                    info!("{name} did not answer a keep-alive in time");
+                    it.entity(row).set(PendingRemove::new("Timed out"));
+                }
+
+                return;
+            }
+
+            if now.saturating_duration_since(keep_alive.last_sent) < KEEP_ALIVE_INTERVAL {
+                return;
+            }
+
+            let pkt = play::KeepAliveS2c {
+                id: keep_alive.send(now),
+            };
+
+            if let Err(e) = compose.unicast(&pkt, *io, system) {
+                error!("failed to send keep alive: {e}");
+            }
+        });
+
+        let players = world.query::<(&Uuid, &Latency)>().with::<Player>().build();
+
+        system!("broadcast_latency", world, &Compose($))
+            .kind::<flecs::pipeline::OnUpdate>()
+            .each_iter(move |it, _, compose| {
+                if compose.global().tick % LATENCY_BROADCAST_INTERVAL_TICKS != 0 {
+                    return;
+                }
+
+                let span = info_span!("broadcast_latency");
+                let _enter = span.enter();
+
+                let mut entries = Vec::new();
+
+                players.each(|(uuid, latency)| {
+                    entries.push(PlayerListEntry {
+                        player_uuid: uuid.0,
+                        ping: latency.millis(),
+                        ..PlayerListEntry::default()
+                    });
+                });
+
+                if entries.is_empty() {
+                    return;
+                }
+
+                let pkt = PlayerListS2c {
+                    actions: PlayerListActions::default().with_update_latency(true),
+                    entries: Cow::Owned(entries),
+                };
+
+                if let Err(e) = compose.broadcast(&pkt, it.system()).send() {
+                    error!("failed to send player latency: {e}");
+                }
+            });
+    }
+}
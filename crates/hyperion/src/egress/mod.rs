@@ -1,26 +1,35 @@
-use byteorder::WriteBytesExt;
 use flecs_ecs::prelude::*;
 use hyperion_proto::{Flush, ServerToProxyMessage, UpdatePlayerChunkPositions};
-use rkyv::util::AlignedVec;
+use rustc_hash::FxHashMap;
 use tracing::{error, info_span};
 use valence_protocol::{VarInt, packets::play};
 
-use crate::{net::Compose, simulation::EgressComm};
+use crate::{
+    net::{Compose, Priority, proxy::encode_message},
+    simulation::EgressComm,
+};
 
+mod keep_alive;
 pub mod metadata;
 pub mod player_join;
 mod stats;
 pub mod sync_chunks;
 mod sync_entity_state;
 
+use keep_alive::KeepAliveModule;
 use player_join::PlayerJoinModule;
 use stats::StatsModule;
 use sync_chunks::SyncChunksModule;
 use sync_entity_state::EntityStateSyncModule;
 
 use crate::{
+    config::Config,
     net::ConnectionId,
-    simulation::{ChunkPosition, blocks::Blocks},
+    simulation::{
+        ChunkPosition, ViewDistance,
+        blocks::{Blocks, chunk::Column},
+        dimension::{Dimension, InWorld},
+    },
 };
 
 #[derive(Component)]
@@ -28,21 +37,7 @@ pub struct EgressModule;
 
 impl Module for EgressModule {
     fn module(world: &World) {
-        let flush = {
-            let flush = ServerToProxyMessage::Flush(Flush);
-
-            let mut v: AlignedVec = AlignedVec::new();
-            // length
-            v.write_u64::<byteorder::BigEndian>(0).unwrap();
-
-            rkyv::api::high::to_bytes_in::<_, rkyv::rancor::Error>(&flush, &mut v).unwrap();
-
-            let len = u64::try_from(v.len() - size_of::<u64>()).unwrap();
-            v[0..8].copy_from_slice(&len.to_be_bytes());
-
-            let s = Box::leak(v.into_boxed_slice());
-            bytes::Bytes::from_static(s)
-        };
+        let flush = encode_message(&ServerToProxyMessage::Flush(Flush));
 
         let pipeline = world
             .entity()
@@ -53,56 +48,149 @@ impl Module for EgressModule {
         world.import::<PlayerJoinModule>();
         world.import::<SyncChunksModule>();
         world.import::<EntityStateSyncModule>();
+        world.import::<KeepAliveModule>();
 
         system!(
             "broadcast_chunk_deltas",
             world,
             &Compose($),
+            &EgressComm($),
             &mut Blocks($),
         )
         .kind::<flecs::pipeline::OnUpdate>()
-        .each_iter(move |it: TableIter<'_, false>, _, (compose, mc)| {
+        .each_iter(move |it: TableIter<'_, false>, _, (compose, egress, mc)| {
             let span = info_span!("broadcast_chunk_deltas");
             let _enter = span.enter();
             let system = it.system();
 
             let world = it.world();
 
+            mc.update_light();
+
+            // bulk like the chunks themselves, so that changes to a chunk the proxy delayed do
+            // not arrive before it
             mc.for_each_to_update_mut(|chunk| {
+                let center = chunk.position.as_i16vec2();
+
+                evict_cached_chunk(chunk, compose, egress, &world);
+
                 for packet in chunk.delta_drain_packets() {
-                    if let Err(e) = compose.broadcast(packet, system).send() {
+                    if let Err(e) = compose
+                        .broadcast_local(packet, center, system)
+                        .world(0)
+                        .priority(Priority::Bulk)
+                        .send()
+                    {
                         error!("failed to send chunk delta packet: {e}");
                         return;
                     }
                 }
+
+                for packet in chunk.block_entity_drain_packets() {
+                    if let Err(e) = compose
+                        .broadcast_local(&packet, center, system)
+                        .world(0)
+                        .priority(Priority::Bulk)
+                        .send()
+                    {
+                        error!("failed to send block entity update packet: {e}");
+                        return;
+                    }
+                }
+
+                if let Some(packet) = chunk.light_drain_packet()
+                    && let Err(e) = compose
+                        .broadcast_local(&packet, center, system)
+                        .world(0)
+                        .priority(Priority::Bulk)
+                        .send()
+                {
+                    error!("failed to send light update packet: {e}");
+                }
             });
             mc.clear_should_update();
 
-            for to_confirm in mc.to_confirm.drain(..) {
-                let entity = world.entity_from_id(to_confirm.entity);
+            send_block_confirmations(&world, compose, mc, system);
+        });
+
+        // other worlds only send their changes to the players in them
+        system!(
+            "broadcast_world_chunk_deltas",
+            world,
+            &Compose($),
+            &EgressComm($),
+            &mut Blocks,
+            &Dimension,
+        )
+        .kind::<flecs::pipeline::OnUpdate>()
+        .each_iter(move |it, row, (compose, egress, mc, _)| {
+            let system = it.system();
+            let world = it.world();
+            // the id of a world in the proxy is its entity id; see `InWorld::proxy_id`
+            let world_id = it.entity(row).id().0;
+
+            mc.update_light();
+
+            mc.for_each_to_update_mut(|chunk| {
+                let center = chunk.position.as_i16vec2();
 
-                let pkt = play::PlayerActionResponseS2c {
-                    sequence: VarInt(to_confirm.sequence),
-                };
+                evict_cached_chunk(chunk, compose, egress, &world);
 
-                entity.get::<&ConnectionId>(|stream| {
-                    if let Err(e) = compose.unicast(&pkt, *stream, system) {
-                        error!("failed to send player action response: {e}");
+                for packet in chunk.delta_drain_packets() {
+                    if let Err(e) = compose
+                        .broadcast_local(packet, center, system)
+                        .world(world_id)
+                        .priority(Priority::Bulk)
+                        .send()
+                    {
+                        error!("failed to send chunk delta packet: {e}");
+                        return;
                     }
-                });
-            }
+                }
+
+                for packet in chunk.block_entity_drain_packets() {
+                    if let Err(e) = compose
+                        .broadcast_local(&packet, center, system)
+                        .world(world_id)
+                        .priority(Priority::Bulk)
+                        .send()
+                    {
+                        error!("failed to send block entity update packet: {e}");
+                        return;
+                    }
+                }
+
+                if let Some(packet) = chunk.light_drain_packet()
+                    && let Err(e) = compose
+                        .broadcast_local(&packet, center, system)
+                        .world(world_id)
+                        .priority(Priority::Bulk)
+                        .send()
+                {
+                    error!("failed to send light update packet: {e}");
+                }
+            });
+            mc.clear_should_update();
+
+            send_block_confirmations(&world, compose, mc, system);
         });
 
-        let player_location_query = world.new_query::<(&ConnectionId, &ChunkPosition)>();
+        let player_location_query = world.new_query::<(
+            &ConnectionId,
+            &ChunkPosition,
+            &ViewDistance,
+            Option<&InWorld>,
+        )>();
 
         system!(
             "egress",
             world,
             &mut Compose($),
-            &mut EgressComm($),
+            &EgressComm($),
+            &Config($),
         )
         .kind_id(pipeline)
-        .each(move |(compose, egress)| {
+        .each(move |(compose, egress, config)| {
             let span = info_span!("egress");
             let _enter = span.enter();
 
@@ -110,39 +198,36 @@ impl Module for EgressModule {
                 let span = info_span!("chunk_positions");
                 let _enter = span.enter();
 
-                let mut stream = Vec::new();
-                let mut positions = Vec::new();
+                // every proxy only needs to know about its own players
+                let mut updates = FxHashMap::<u16, UpdatePlayerChunkPositions>::default();
+
+                player_location_query.each(|(io, pos, view_distance, in_world)| {
+                    let update = updates.entry(io.proxy_id()).or_insert_with(|| {
+                        UpdatePlayerChunkPositions {
+                            stream: Vec::new(),
+                            positions: Vec::new(),
+                            view_distances: Vec::new(),
+                            worlds: Vec::new(),
+                        }
+                    });
 
-                player_location_query.each(|(io, pos)| {
-                    stream.push(io.inner());
+                    update.stream.push(io.inner());
 
                     let position = hyperion_proto::ChunkPosition {
                         x: pos.position.x,
                         z: pos.position.y,
                     };
 
-                    positions.push(position);
+                    update.positions.push(position);
+                    update
+                        .view_distances
+                        .push(view_distance.radius(config.view_distance));
+                    update.worlds.push(InWorld::proxy_id(in_world));
                 });
 
-                let packet = UpdatePlayerChunkPositions { stream, positions };
-
-                let chunk_positions = ServerToProxyMessage::UpdatePlayerChunkPositions(packet);
-
-                let mut v: AlignedVec = AlignedVec::new();
-                // length
-                v.write_u64::<byteorder::BigEndian>(0).unwrap();
-
-                rkyv::api::high::to_bytes_in::<_, rkyv::rancor::Error>(&chunk_positions, &mut v)
-                    .unwrap();
-
-                let len = u64::try_from(v.len() - size_of::<u64>()).unwrap();
-                v[0..8].copy_from_slice(&len.to_be_bytes());
-
-                let v = v.into_boxed_slice();
-                let bytes = bytes::Bytes::from(v);
-
-                if let Err(e) = egress.send(bytes) {
-                    error!("failed to send egress: {e}");
+                for (proxy_id, update) in updates {
+                    let chunk_positions = ServerToProxyMessage::UpdatePlayerChunkPositions(update);
+                    egress.send(proxy_id, encode_message(&chunk_positions));
                 }
             }
 
@@ -151,16 +236,18 @@ impl Module for EgressModule {
                 if bytes.is_empty() {
                     continue;
                 }
-                if let Err(e) = egress.send(bytes) {
-                    error!("failed to send egress: {e}");
-                }
+                egress.broadcast(&bytes);
             }
 
-            if let Err(e) = egress.send(flush.clone()) {
-                error!("failed to send flush: {e}");
+            for (proxy_id, bytes) in io.split_proxy_buffers() {
+                egress.send(proxy_id, bytes);
             }
+
+            egress.broadcast(&flush);
         });
 
+        stats::tick_end(world, pipeline);
+
         system!(
             "clear_bump",
             world,
@@ -174,3 +261,34 @@ impl Module for EgressModule {
         });
     }
 }
+
+/// Removes the packets of `chunk`, which changed this tick, from the chunk cache of the proxies.
+fn evict_cached_chunk(chunk: &mut Column, compose: &Compose, egress: &EgressComm, world: &World) {
+    if let Some(encoded) = chunk.take_encoded()
+        && egress.evict_chunk(encoded.hash)
+    {
+        compose.io_buf().evict_cached_chunk(encoded.hash, world);
+    }
+}
+
+/// Acknowledges the block changes players made so their clients stop predicting them.
+fn send_block_confirmations(
+    world: &World,
+    compose: &Compose,
+    blocks: &mut Blocks,
+    system: EntityView<'_>,
+) {
+    for to_confirm in blocks.to_confirm.drain(..) {
+        let entity = world.entity_from_id(to_confirm.entity);
+
+        let pkt = play::PlayerActionResponseS2c {
+            sequence: VarInt(to_confirm.sequence),
+        };
+
+        entity.get::<&ConnectionId>(|stream| {
+            if let Err(e) = compose.unicast(&pkt, *stream, system) {
+                error!("failed to send player action response: {e}");
+            }
+        });
+    }
+}
@@ -6,9 +6,9 @@ use glam::DVec3;
 use hyperion_crafting::{Action, CraftingRegistry, RecipeBookState};
 use hyperion_utils::EntityExt;
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
-use tracing::{info, instrument};
+use tracing::{error, info, instrument};
 use valence_protocol::{
-    ByteAngle, GameMode, Ident, PacketEncoder, RawBytes, VarInt, Velocity,
+    BlockPos, ByteAngle, GameMode, Ident, PacketEncoder, RawBytes, VarInt, Velocity,
     game_mode::OptGameMode,
     ident,
     packets::play::{
@@ -17,11 +17,10 @@ use valence_protocol::{
         team_s2c::{CollisionRule, Mode, NameTagVisibility, TeamColor, TeamFlags},
     },
 };
-use valence_registry::{BiomeRegistry, RegistryCodec};
 use valence_server::entity::EntityKind;
 use valence_text::IntoText;
 
-use crate::simulation::{MovementTracking, PacketState, Pitch};
+use crate::simulation::{MovementTracking, PacketState, Pitch, movement};
 
 mod list;
 pub use list::*;
@@ -34,10 +33,13 @@ use crate::{
     simulation::{
         Comms, Name, Position, Uuid, Yaw,
         command::{Command, ROOT_COMMAND, get_command_packet},
+        dimension::{Dimension, InWorld},
+        event,
         metadata::{MetadataChanges, entity::EntityFlags},
         skin::PlayerSkin,
         util::registry_codec_raw,
     },
+    storage::EventQueue,
     util::{SendableQuery, SendableRef},
 };
 
@@ -70,6 +72,8 @@ pub fn player_join_world(
     )>,
     crafting_registry: &CraftingRegistry,
     config: &Config,
+    dimension: &Dimension,
+    dimension_names: &BTreeSet<Ident<Cow<'static, str>>>,
 ) -> anyhow::Result<()> {
     static CACHED_DATA: once_cell::sync::OnceCell<bytes::Bytes> = once_cell::sync::OnceCell::new();
 
@@ -85,38 +89,33 @@ pub fn player_join_world(
         server_velocity: DVec3::ZERO,
         sprinting: false,
         was_on_ground: false,
+        horizontal_speed: 0.0,
+        vertical_speed: 0.0,
+        movement_balance: movement::MAX_MOVEMENT_BALANCE,
+        balance_refilled: None,
     });
 
     let registry_codec = registry_codec_raw();
-    let codec = RegistryCodec::default();
-
-    let dimension_names: BTreeSet<Ident<Cow<'_, str>>> = codec
-        .registry(BiomeRegistry::KEY)
-        .iter()
-        .map(|value| value.name.as_str_ident().into())
-        .collect();
-
-    let dimension_name = ident!("overworld");
-    // let dimension_name: Ident<Cow<str>> = chunk_layer.dimension_type_name().into();
 
+    // players always join the main world
     let pkt = GameJoinS2c {
         entity_id: id,
         is_hardcore: false,
-        dimension_names: Cow::Owned(dimension_names),
+        dimension_names: Cow::Borrowed(dimension_names),
         registry_codec: Cow::Borrowed(registry_codec),
         max_players: config.max_players.into(),
         view_distance: VarInt(i32::from(config.view_distance)),
         simulation_distance: config.simulation_distance.into(),
         reduced_debug_info: false,
         enable_respawn_screen: false,
-        dimension_name: dimension_name.into(),
+        dimension_name: dimension.name.as_str_ident().into(),
         hashed_seed: 0,
         game_mode: GameMode::Survival,
         is_flat: false,
         last_death_location: None,
         portal_cooldown: 60.into(),
         previous_game_mode: OptGameMode(Some(GameMode::Survival)),
-        dimension_type_name: ident!("minecraft:overworld").into(),
+        dimension_type_name: dimension.dimension_type.as_str_ident().into(),
         is_debug: false,
     };
 
@@ -124,6 +123,8 @@ pub fn player_join_world(
         .add_packet(&pkt)
         .context("failed to send player spawn packet")?;
 
+    bundle.add_packet(&dimension.border_packet())?;
+
     let center_chunk = position.to_chunk();
 
     let pkt = play::ChunkRenderDistanceCenterS2c {
@@ -134,7 +135,7 @@ pub fn player_join_world(
     bundle.add_packet(&pkt)?;
 
     let pkt = play::PlayerSpawnPositionS2c {
-        position: position.as_dvec3().into(),
+        position: spawn_position(config),
         angle: **yaw,
     };
 
@@ -373,6 +374,15 @@ pub fn player_join_world(
 
     bundle.add_packet(&command_packet)?;
 
+    if let Some(pack) = &config.resource_pack {
+        bundle.add_packet(&play::ResourcePackSendS2c {
+            url: &pack.url,
+            hash: &pack.sha1,
+            forced: pack.forced,
+            prompt_message: None,
+        })?;
+    }
+
     bundle.unicast(io)?;
 
     info!("{name} joined the world");
@@ -494,6 +504,12 @@ impl Index<usize> for RayonWorldStages {
     }
 }
 
+/// The position compasses point to.
+fn spawn_position(config: &Config) -> BlockPos {
+    let center = config.spawn.center();
+    BlockPos::new(center.x, center.y, center.z)
+}
+
 impl Module for PlayerJoinModule {
     fn module(world: &World) {
         let query = world.new_query::<(
@@ -536,6 +552,9 @@ impl Module for PlayerJoinModule {
 
         let root_command = root_command.id();
 
+        // the main world and every other world
+        let dimensions = world.new_query::<&Dimension>();
+
         system!(
             "player_joins",
             world,
@@ -544,13 +563,19 @@ impl Module for PlayerJoinModule {
             &CraftingRegistry($),
             &Config($),
             &RayonWorldStages($),
+            &Dimension($),
         )
         .kind::<flecs::pipeline::PreUpdate>()
         .each_iter(
-            move |it, _, (comms, compose, crafting_registry, config, stages)| {
+            move |it, _, (comms, compose, crafting_registry, config, stages, dimension)| {
                 let span = tracing::info_span!("joins");
                 let _enter = span.enter();
 
+                let mut dimension_names = BTreeSet::new();
+                dimensions.each(|dimension| {
+                    dimension_names.insert(dimension.name.clone().into());
+                });
+
                 let system = it.system().id();
 
                 let mut skins = Vec::new();
@@ -597,6 +622,8 @@ impl Module for PlayerJoinModule {
                                 query,
                                 crafting_registry,
                                 config,
+                                dimension,
+                                &dimension_names,
                             ) {
                                 entity.set(PendingRemove::new(e.to_string()));
                             }
@@ -610,5 +637,52 @@ impl Module for PlayerJoinModule {
                 });
             },
         );
+
+        // the border and the spawn of the main world are taken from the config, so players in it
+        // are resent them when the config is reloaded
+        let main_world_players = world
+            .query::<&ConnectionId>()
+            .with_enum(PacketState::Play)
+            .without::<InWorld>()
+            .build();
+
+        system!(
+            "sync_reloaded_config",
+            world,
+            &mut EventQueue<event::ConfigChanged>($),
+            &Compose($),
+            &Config($),
+            &Dimension($),
+        )
+        .kind::<flecs::pipeline::OnUpdate>()
+        .each_iter(move |it, _, (events, compose, config, dimension)| {
+            let system = it.system();
+
+            for event::ConfigChanged { previous } in events.drain() {
+                let border_changed = previous.border_diameter != config.border_diameter;
+                let spawn_changed = previous.spawn.center() != config.spawn.center();
+
+                if !border_changed && !spawn_changed {
+                    continue;
+                }
+
+                let spawn = play::PlayerSpawnPositionS2c {
+                    position: spawn_position(config),
+                    angle: 0.0,
+                };
+
+                main_world_players.each(|&stream| {
+                    if border_changed
+                        && let Err(e) = compose.unicast(&dimension.border_packet(), stream, system)
+                    {
+                        error!("failed to send world border: {e}");
+                    }
+
+                    if spawn_changed && let Err(e) = compose.unicast(&spawn, stream, system) {
+                        error!("failed to send spawn position: {e}");
+                    }
+                });
+            }
+        });
     }
 }
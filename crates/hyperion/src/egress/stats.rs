@@ -1,11 +1,245 @@
+use std::{fmt::Write as _, sync::atomic::Ordering, time::Instant};
+
 use flecs_ecs::prelude::*;
+use hyperion_stats::ParallelStats;
+use system_order::SystemOrder;
 use tracing::{error, info_span};
 
 use crate::{
+    metrics::{MetricsReport, WINDOW_TICKS},
     net::Compose,
-    simulation::{PacketState, blocks::Blocks},
+    simulation::{
+        EgressComm, PacketState,
+        blocks::{AUTOSAVE_INTERVAL_TICKS, Blocks},
+        dimension::Dimension,
+    },
+    storage::Events,
 };
 
+/// The columns of [`TickMetrics::per_tick`], which are followed by one column per event type in
+/// the order of [`Events::NAMES`].
+const TICK_SECONDS: usize = 0;
+const BYTES_SENT: usize = 1;
+const BUMP_BYTES: usize = 2;
+const CHUNK_LOADER_BACKLOG: usize = 3;
+const EVENTS: usize = 4;
+
+/// The quantiles of the tick duration which are reported, in thousandths.
+const QUANTILES: [usize; 3] = [500, 900, 990];
+
+/// Samples taken every tick, summarized once per window into [`MetricsReport`].
+#[derive(Component)]
+pub struct TickMetrics {
+    started: Instant,
+    ticks: u64,
+    per_tick: ParallelStats,
+    /// Every tick duration of the window, for quantiles.
+    tick_seconds: Vec<f64>,
+    /// The total of [`EgressComm::bytes_sent`] at the end of the last tick.
+    bytes_sent: u64,
+    /// Every system by [`SystemOrder`].
+    systems: Vec<SystemTimer>,
+    system_seconds: ParallelStats,
+}
+
+struct SystemTimer {
+    entity: Entity,
+    order: u16,
+    name: String,
+    /// The total time flecs has measured for the system at the end of the last tick.
+    time_spent: f64,
+}
+
+impl Default for TickMetrics {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            ticks: 0,
+            per_tick: ParallelStats::new(EVENTS + Events::NAMES.len()),
+            tick_seconds: Vec::new(),
+            bytes_sent: 0,
+            systems: Vec::new(),
+            system_seconds: ParallelStats::new(0),
+        }
+    }
+}
+
+impl TickMetrics {
+    /// Starts tracking the systems of `world` again if systems were added or removed.
+    fn refresh_systems(&mut self, world: &World, systems: &Query<&SystemOrder>) {
+        if usize::try_from(systems.count()).ok() == Some(self.systems.len()) {
+            return;
+        }
+
+        self.systems.clear();
+        systems.each_entity(|entity, order| {
+            self.systems.push(SystemTimer {
+                entity: entity.id(),
+                order: order.value(),
+                name: entity.name(),
+                time_spent: time_spent(world, entity.id()),
+            });
+        });
+        self.systems.sort_unstable_by_key(|system| system.order);
+
+        self.system_seconds = ParallelStats::new(self.systems.len());
+    }
+
+    fn sample_systems(&mut self, world: &World) {
+        let seconds: Vec<f64> = self
+            .systems
+            .iter_mut()
+            .map(|system| {
+                let total = time_spent(world, system.entity);
+                let spent = total - system.time_spent;
+                system.time_spent = total;
+                spent
+            })
+            .collect();
+
+        self.system_seconds.update(&seconds);
+    }
+
+    /// Renders the window in the Prometheus text format and starts the next one.
+    fn finish_window(&mut self, compose: &Compose) -> String {
+        let mut out = String::new();
+        let global = compose.global();
+
+        writeln!(out, "# HELP hyperion_tick Ticks since the server started.").unwrap();
+        writeln!(out, "# TYPE hyperion_tick counter").unwrap();
+        writeln!(out, "hyperion_tick {}", global.tick).unwrap();
+
+        writeln!(out, "# HELP hyperion_players Players in the play state.").unwrap();
+        writeln!(out, "# TYPE hyperion_players gauge").unwrap();
+        writeln!(
+            out,
+            "hyperion_players {}",
+            global.player_count.load(Ordering::Relaxed)
+        )
+        .unwrap();
+
+        self.tick_seconds.sort_unstable_by(f64::total_cmp);
+
+        let name = "hyperion_tick_seconds";
+        writeln!(
+            out,
+            "# HELP {name} Time from the start of a tick until its packets were sent."
+        )
+        .unwrap();
+        writeln!(out, "# TYPE {name} summary").unwrap();
+        for quantile in QUANTILES {
+            if let Some(seconds) = nearest_rank(&self.tick_seconds, quantile) {
+                let quantile = quantile as f64 / 1000.0;
+                writeln!(out, "{name}{{quantile=\"{quantile}\"}} {seconds}").unwrap();
+            }
+        }
+        let sum: f64 = self.tick_seconds.iter().sum();
+        writeln!(out, "{name}_sum {sum}").unwrap();
+        writeln!(out, "{name}_count {}", self.tick_seconds.len()).unwrap();
+
+        stat(
+            &mut out,
+            "hyperion_tick_seconds_window",
+            "Tick durations over the window.",
+            &[(&self.per_tick, TICK_SECONDS, String::new())],
+        );
+
+        stat(
+            &mut out,
+            "hyperion_bytes_sent_per_tick",
+            "Bytes sent to proxies each tick.",
+            &[(&self.per_tick, BYTES_SENT, String::new())],
+        );
+
+        stat(
+            &mut out,
+            "hyperion_bump_bytes_per_tick",
+            "Bytes allocated by the bump allocators of Compose each tick.",
+            &[(&self.per_tick, BUMP_BYTES, String::new())],
+        );
+
+        stat(
+            &mut out,
+            "hyperion_chunk_loader_backlog",
+            "Chunks requested from the loader but not in the cache yet.",
+            &[(&self.per_tick, CHUNK_LOADER_BACKLOG, String::new())],
+        );
+
+        let events: Vec<_> = Events::NAMES
+            .iter()
+            .enumerate()
+            .map(|(idx, event)| (&self.per_tick, EVENTS + idx, format!("event=\"{event}\"")))
+            .collect();
+        stat(
+            &mut out,
+            "hyperion_events_per_tick",
+            "Events pushed each tick by type.",
+            &events,
+        );
+
+        let systems: Vec<_> = self
+            .systems
+            .iter()
+            .enumerate()
+            .map(|(idx, system)| {
+                let labels = format!("system=\"{}\",order=\"{}\"", system.name, system.order);
+                (&self.system_seconds, idx, labels)
+            })
+            .collect();
+        stat(
+            &mut out,
+            "hyperion_system_seconds",
+            "Time spent in each system each tick.",
+            &systems,
+        );
+
+        self.per_tick = ParallelStats::new(EVENTS + Events::NAMES.len());
+        self.system_seconds = ParallelStats::new(self.systems.len());
+        self.tick_seconds.clear();
+
+        out
+    }
+}
+
+/// The `quantile` of `sorted`, in thousandths, by the nearest-rank method.
+fn nearest_rank(sorted: &[f64], quantile: usize) -> Option<f64> {
+    let rank = (sorted.len() * quantile).div_ceil(1000);
+    sorted.get(rank.saturating_sub(1)).copied()
+}
+
+/// The total time flecs has measured for `system`, in seconds.
+fn time_spent(world: &World, system: Entity) -> f64 {
+    // SAFETY: `system` is a system of `world` and its data is only read.
+    let system = unsafe { flecs_ecs::sys::ecs_system_get(world.world_ptr(), *system) };
+    // SAFETY: flecs returns null or a pointer to the system, which lives as long as the world.
+    unsafe { system.as_ref() }.map_or(0.0, |system| f64::from(system.time_spent))
+}
+
+/// Writes the mean, standard deviation, minimum and maximum of each `(stats, idx, labels)` as a
+/// gauge labelled by `stat`.
+fn stat(out: &mut String, name: &str, help: &str, columns: &[(&ParallelStats, usize, String)]) {
+    writeln!(out, "# HELP {name} {help}").unwrap();
+    writeln!(out, "# TYPE {name} gauge").unwrap();
+
+    for (stats, idx, labels) in columns {
+        let values = [
+            ("mean", stats.mean(*idx)),
+            ("std_dev", stats.std_dev(*idx)),
+            ("min", stats.min(*idx)),
+            ("max", stats.max(*idx)),
+        ];
+
+        let separator = if labels.is_empty() { "" } else { "," };
+
+        for (stat, value) in values {
+            let Some(value) = value else {
+                continue;
+            };
+            writeln!(out, "{name}{{{labels}{separator}stat=\"{stat}\"}} {value}").unwrap();
+        }
+    }
+}
+
 #[derive(Component)]
 pub struct StatsModule;
 
@@ -13,6 +247,16 @@ impl Module for StatsModule {
     fn module(world: &World) {
         let players = world.query::<()>().with_enum(PacketState::Play).build();
 
+        world.component::<TickMetrics>();
+        world.set(TickMetrics::default());
+        world.measure_system_time(true);
+
+        system!("tick_start", world, &mut TickMetrics($))
+            .kind::<flecs::pipeline::PreFrame>()
+            .each(|metrics| {
+                metrics.started = Instant::now();
+            });
+
         // let last_frame_time_total = 0.0;
 
         // let system_id = GLOBAL_STATS;
@@ -47,5 +291,133 @@ impl Module for StatsModule {
             let _enter = span.enter();
             blocks.load_pending();
         });
+
+        system!(
+            "save_dirty_chunks",
+            world,
+            &mut Blocks($),
+            &Compose($),
+        )
+        .kind::<flecs::pipeline::OnUpdate>()
+        .each_iter(|_iter, _, (blocks, compose)| {
+            if compose.global().tick % AUTOSAVE_INTERVAL_TICKS != 0 {
+                return;
+            }
+
+            let span = info_span!("save_dirty_chunks");
+            let _enter = span.enter();
+            blocks.save_dirty();
+        });
+
+        system!("load_pending_worlds", world, &mut Blocks, &Dimension,)
+            .kind::<flecs::pipeline::OnUpdate>()
+            .each(|(blocks, _)| {
+                blocks.load_pending();
+            });
+
+        system!(
+            "save_dirty_worlds",
+            world,
+            &mut Blocks,
+            &Dimension,
+            &Compose($),
+        )
+        .kind::<flecs::pipeline::OnUpdate>()
+        .each(|(blocks, _, compose)| {
+            if compose.global().tick % AUTOSAVE_INTERVAL_TICKS != 0 {
+                return;
+            }
+
+            blocks.save_dirty();
+        });
+    }
+}
+
+/// Registers the system which samples the tick into [`TickMetrics`]. It is added to `pipeline`
+/// after the packets of the tick are sent and before the bump allocators are cleared.
+pub(super) fn tick_end(world: &World, pipeline: EntityView<'_>) {
+    let worlds = world.query::<&Blocks>().with::<Dimension>().build();
+    let systems = world
+        .query::<&SystemOrder>()
+        .with::<flecs::system::System>()
+        .build();
+
+    system!(
+        "tick_end",
+        world,
+        &mut TickMetrics($),
+        &Compose($),
+        &EgressComm($),
+        &Events($),
+        &Blocks($),
+        &MetricsReport($),
+    )
+    .kind_id(pipeline)
+    .each_iter(
+        move |it, _, (metrics, compose, egress, events, blocks, report)| {
+            let world = it.world();
+
+            let mut backlog = blocks.loader_backlog();
+            worlds.each(|blocks| backlog += blocks.loader_backlog());
+
+            let bytes_sent = egress.bytes_sent();
+            let tick_seconds = metrics.started.elapsed().as_secs_f64();
+
+            // in the order of the column constants
+            let mut sample = vec![
+                tick_seconds,
+                (bytes_sent - metrics.bytes_sent) as f64,
+                compose.bump_allocated() as f64,
+                backlog as f64,
+            ];
+            sample.extend(events.take_pushed().map(|pushed| pushed as f64));
+
+            metrics.bytes_sent = bytes_sent;
+            metrics.per_tick.update(&sample);
+            metrics.tick_seconds.push(tick_seconds);
+
+            metrics.refresh_systems(&world, &systems);
+            metrics.sample_systems(&world);
+
+            metrics.ticks += 1;
+            if metrics.ticks % WINDOW_TICKS == 0 {
+                report.set(metrics.finish_window(compose));
+            }
+        },
+    );
+}
+
+#[cfg(test)]
+#[expect(
+    clippy::float_cmp,
+    reason = "the quantiles are picked from the samples, not computed"
+)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nearest_rank() {
+        let sorted: Vec<f64> = (1..=100).map(f64::from).collect();
+
+        assert_eq!(nearest_rank(&sorted, 500), Some(50.0));
+        assert_eq!(nearest_rank(&sorted, 900), Some(90.0));
+        assert_eq!(nearest_rank(&sorted, 990), Some(99.0));
+        assert_eq!(nearest_rank(&sorted, 1000), Some(100.0));
+    }
+
+    #[test]
+    fn test_nearest_rank_rounds_up() {
+        let sorted = [1.0, 2.0, 3.0];
+
+        // the ranks are 1.5, 2.7 and 2.97
+        assert_eq!(nearest_rank(&sorted, 500), Some(2.0));
+        assert_eq!(nearest_rank(&sorted, 900), Some(3.0));
+        assert_eq!(nearest_rank(&sorted, 990), Some(3.0));
+        assert_eq!(nearest_rank(&sorted, 0), Some(1.0));
+    }
+
+    #[test]
+    fn test_nearest_rank_of_nothing() {
+        assert_eq!(nearest_rank(&[], 500), None);
     }
 }
@@ -1,5 +1,7 @@
 use std::cmp::Ordering;
 
+use anyhow::Context;
+use bytes::Bytes;
 use derive_more::derive::{Deref, DerefMut};
 use flecs_ecs::prelude::*;
 use glam::I16Vec2;
@@ -14,14 +16,20 @@ use crate::{
     config::Config,
     net::{Compose, ConnectionId, DataBundle},
     simulation::{
-        ChunkPosition, PacketState, Position,
-        blocks::{Blocks, GetChunk},
+        ChunkPosition, EgressComm, PacketState, Position, ViewDistance,
+        blocks::{Blocks, GetChunk, chunk::Column},
+        dimension::InWorld,
     },
 };
 
 #[derive(Component, Deref, DerefMut, Default)]
 pub struct ChunkSendQueue {
+    #[deref]
+    #[deref_mut]
     changes: Vec<I16Vec2>,
+    /// The radius the chunks around [`ChunkPosition`] were last sent with. This is zero until
+    /// the first chunks are sent.
+    radius: i16,
 }
 
 #[derive(Component)]
@@ -31,50 +39,83 @@ impl Module for SyncChunksModule {
     fn module(world: &World) {
         world.component::<ChunkSendQueue>();
 
-        let radius = world.get::<&Config>(|config| config.view_distance);
-        let liberal_radius = radius + 2;
-
         system!(
             "generate_chunk_changes",
             world,
             &Compose($),
+            &Config($),
             &mut ChunkPosition,
             &Position,
+            &ViewDistance,
             &ConnectionId,
             &mut ChunkSendQueue,
+            ?&InWorld,
         )
         .with_enum(PacketState::Play)
         .kind::<flecs::pipeline::OnUpdate>()
         .each_iter(
-            move |it, _, (compose, last_sent, pose, &stream_id, chunk_changes)| {
+            move |it,
+                  _,
+                  (
+                compose,
+                config,
+                last_sent,
+                pose,
+                view_distance,
+                &stream_id,
+                chunk_changes,
+                in_world,
+            )| {
                 let system = it.system();
+                let world = it.world();
 
                 let last_sent_chunk = last_sent.position;
+                let last_radius = chunk_changes.radius;
 
                 let current_chunk = pose.to_chunk();
+                let radius = view_distance.radius(config.view_distance);
+                let liberal_radius = radius + 2;
 
-                if last_sent_chunk == current_chunk {
+                if last_sent_chunk == current_chunk && last_radius == radius {
                     return;
                 }
 
-                // center chunk
-                let center_chunk = play::ChunkRenderDistanceCenterS2c {
-                    chunk_x: VarInt(i32::from(current_chunk.x)),
-                    chunk_z: VarInt(i32::from(current_chunk.y)),
-                };
+                if last_sent_chunk != current_chunk {
+                    // center chunk
+                    let center_chunk = play::ChunkRenderDistanceCenterS2c {
+                        chunk_x: VarInt(i32::from(current_chunk.x)),
+                        chunk_z: VarInt(i32::from(current_chunk.y)),
+                    };
+
+                    if let Err(e) = compose.unicast(&center_chunk, stream_id, system) {
+                        error!(
+                            "failed to send chunk render distance center packet: {e}. Chunk \
+                             location: {current_chunk:?}"
+                        );
+                        return;
+                    }
 
-                if let Err(e) = compose.unicast(&center_chunk, stream_id, system) {
-                    error!(
-                        "failed to send chunk render distance center packet: {e}. Chunk location: \
-                         {current_chunk:?}"
-                    );
-                    return;
+                    last_sent.position = current_chunk;
                 }
 
-                last_sent.position = current_chunk;
+                if last_radius != radius {
+                    // the client only renders chunks within the distance it was last sent
+                    let load_distance = play::ChunkLoadDistanceS2c {
+                        view_distance: VarInt(i32::from(radius)),
+                    };
+
+                    if let Err(e) = compose.unicast(&load_distance, stream_id, system) {
+                        error!("failed to send chunk load distance packet: {e}");
+                        return;
+                    }
+
+                    chunk_changes.radius = radius;
+                }
 
-                let last_sent_range_x = (last_sent_chunk.x - radius)..(last_sent_chunk.x + radius);
-                let last_sent_range_z = (last_sent_chunk.y - radius)..(last_sent_chunk.y + radius);
+                let last_sent_range_x =
+                    (last_sent_chunk.x - last_radius)..(last_sent_chunk.x + last_radius);
+                let last_sent_range_z =
+                    (last_sent_chunk.y - last_radius)..(last_sent_chunk.y + last_radius);
 
                 let current_range_x = (current_chunk.x - radius)..(current_chunk.x + radius);
                 let current_range_z = (current_chunk.y - radius)..(current_chunk.y + radius);
@@ -97,7 +138,15 @@ impl Module for SyncChunksModule {
 
                 let mut bundle = DataBundle::new(compose, system);
 
+                let world_id = InWorld::proxy_id(in_world);
+
                 for chunk in removed_chunks {
+                    // the chunk may still be delayed in the proxy, and would stay loaded if it
+                    // arrived after this
+                    compose
+                        .io_buf()
+                        .discard_delayed(stream_id, world_id, Some(chunk), &world);
+
                     let pos = ChunkPos::new(i32::from(chunk.x), i32::from(chunk.y));
                     let unload_chunk = play::UnloadChunkS2c { pos };
 
@@ -157,73 +206,136 @@ impl Module for SyncChunksModule {
             },
         );
 
-        system!("send_full_loaded_chunks", world, &Blocks($), &Compose($), &ConnectionId, &mut ChunkSendQueue)
-            .with_enum(PacketState::Play)
-            .kind::<flecs::pipeline::OnUpdate>()
-
-            .each_iter(
-                move |it, _, (chunks, compose, &stream_id, queue)| {
-                    const MAX_CHUNKS_PER_TICK: usize = 16;
-
-                    let system = it.system();
-
-                    let last = None;
+        system!(
+            "send_full_loaded_chunks",
+            world,
+            &Blocks($),
+            &Compose($),
+            &EgressComm($),
+            &ConnectionId,
+            &mut ChunkSendQueue,
+            ?&InWorld,
+        )
+        .with_enum(PacketState::Play)
+        .kind::<flecs::pipeline::OnUpdate>()
+        .each_iter(
+            move |it, _, (chunks, compose, egress, &stream_id, queue, in_world)| {
+                let system = it.system();
+                let world_id = InWorld::proxy_id(in_world);
+
+                match in_world {
+                    Some(in_world) => {
+                        it.world()
+                            .entity_from_id(**in_world)
+                            .get::<&Blocks>(|chunks| {
+                                send_queued_chunks(
+                                    chunks, world_id, compose, egress, stream_id, queue, system,
+                                )
+                            })
+                    }
+                    None => send_queued_chunks(
+                        chunks, world_id, compose, egress, stream_id, queue, system,
+                    ),
+                }
+            },
+        );
+    }
+}
 
-                    let mut iter_count = 0;
+/// Sends the closest chunks in `queue` which are loaded, up to a limit per tick. `world_id` is the
+/// proxy id of the world `chunks` belong to.
+fn send_queued_chunks(
+    chunks: &Blocks,
+    world_id: u64,
+    compose: &Compose,
+    egress: &EgressComm,
+    stream_id: ConnectionId,
+    queue: &mut ChunkSendQueue,
+    system: EntityView<'_>,
+) {
+    const MAX_CHUNKS_PER_TICK: usize = 16;
+
+    let last = None;
+
+    let mut iter_count = 0;
+
+    #[expect(
+        clippy::cast_possible_wrap,
+        reason = "realistically queue.changes.len() will never be large enough to wrap"
+    )]
+    let mut idx = (queue.changes.len() as isize) - 1;
+
+    while idx >= 0 {
+        #[expect(clippy::cast_sign_loss, reason = "we are checking if < 0")]
+        let Some(elem) = queue.changes.get(idx as usize).copied() else {
+            // should never happen but we do not want to panic if wrong
+            // logic/assumptions are made
+            error!("failed to get element from queue.changes");
+            continue;
+        };
+
+        // de-duplicate. todo: there are cases where duplicate will not be removed properly
+        // since sort is unstable
+        if last == Some(elem) {
+            #[expect(clippy::cast_sign_loss, reason = "we are checking if < 0")]
+            queue.changes.swap_remove(idx as usize);
+            idx -= 1;
+            continue;
+        }
+
+        if iter_count >= MAX_CHUNKS_PER_TICK {
+            break;
+        }
+
+        match chunks.get_cached_or_load(elem) {
+            GetChunk::Loaded(chunk) => {
+                let encoded = match chunk.encoded_or_init(|chunk| encode(chunk, compose, system)) {
+                    Ok(encoded) => encoded,
+                    Err(e) => {
+                        error!("failed to encode chunk: {e:?}");
+                        return;
+                    }
+                };
 
-                    let mut bundle = DataBundle::new(compose, system);
+                compose
+                    .io_buf()
+                    .unicast_chunk(encoded, elem, world_id, stream_id, egress, system);
 
-                    #[expect(
-                        clippy::cast_possible_wrap,
-                        reason = "realistically queue.changes.len() will never be large enough to wrap"
-                    )]
-                    let mut idx = (queue.changes.len() as isize) - 1;
+                iter_count += 1;
+                #[expect(clippy::cast_sign_loss, reason = "we are checking if < 0")]
+                queue.changes.swap_remove(idx as usize);
+            }
+            GetChunk::Loading => {}
+        }
 
-                    while idx >= 0 {
-                        #[expect(clippy::cast_sign_loss, reason = "we are checking if < 0")]
-                        let Some(elem) = queue.changes.get(idx as usize).copied() else {
-                            // should never happen but we do not want to panic if wrong
-                            // logic/assumptions are made
-                            error!("failed to get element from queue.changes");
-                            continue;
-                        };
+        idx -= 1;
+    }
+}
 
-                        // de-duplicate. todo: there are cases where duplicate will not be removed properly
-                        // since sort is unstable
-                        if last == Some(elem) {
-                            #[expect(clippy::cast_sign_loss, reason = "we are checking if < 0")]
-                            queue.changes.swap_remove(idx as usize);
-                            idx -= 1;
-                            continue;
-                        }
+/// Encodes the packets a player is sent when `chunk` comes into view. The proxies cache them, so
+/// they are only sent to each proxy once until the chunk changes.
+fn encode(chunk: &Column, compose: &Compose, system: EntityView<'_>) -> anyhow::Result<Bytes> {
+    let mut bundle = DataBundle::new(compose, system);
 
-                        if iter_count >= MAX_CHUNKS_PER_TICK {
-                            break;
-                        }
+    bundle.add_raw(&chunk.base_packet_bytes);
 
-                        match chunks.get_cached_or_load(elem) {
-                            GetChunk::Loaded(chunk) => {
-                                bundle.add_raw(&chunk.base_packet_bytes);
-
-                                for packet in chunk.original_delta_packets() {
-                                    if let Err(e) = bundle.add_packet(packet) {
-                                        error!("failed to send chunk delta packet: {e}");
-                                        return;
-                                    }
-                                }
-
-                                iter_count += 1;
-                                #[expect(clippy::cast_sign_loss, reason = "we are checking if < 0")]
-                                queue.changes.swap_remove(idx as usize);
-                            }
-                            GetChunk::Loading => {}
-                        }
+    for packet in chunk.original_delta_packets() {
+        bundle
+            .add_packet(packet)
+            .context("failed to encode chunk delta packet")?;
+    }
 
-                        idx -= 1;
-                    }
+    for packet in chunk.original_block_entity_packets() {
+        bundle
+            .add_packet(&packet)
+            .context("failed to encode block entity update packet")?;
+    }
 
-                    bundle.unicast(stream_id).unwrap();
-                },
-            );
+    if let Some(packet) = chunk.original_light_packet() {
+        bundle
+            .add_packet(&packet)
+            .context("failed to encode light update packet")?;
     }
+
+    Ok(bundle.into_bytes())
 }
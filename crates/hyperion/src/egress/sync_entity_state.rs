@@ -1,4 +1,4 @@
-use std::fmt::Debug;
+use std::{fmt::Debug, time::Instant};
 
 use flecs_ecs::prelude::*;
 use glam::{IVec3, Vec3};
@@ -11,15 +11,16 @@ use valence_protocol::{
 
 use crate::{
     Prev,
-    net::{Compose, ConnectionId, DataBundle},
+    net::{Compose, ConnectionId, DataBundle, Priority},
     simulation::{
         Flight, MovementTracking, Owner, PendingTeleportation, Pitch, Position, Velocity, Xp, Yaw,
         animation::ActiveAnimation,
-        blocks::Blocks,
+        dimension::{InWorld, with_blocks},
         entity_kind::EntityKind,
         event::{self, HitGroundEvent},
         handlers::is_grounded,
         metadata::{MetadataChanges, get_and_clear_metadata},
+        movement,
     },
     spatial::get_first_collision,
     storage::Events,
@@ -111,9 +112,10 @@ impl Module for EntityStateSyncModule {
                         tracked_values: RawBytes(&view),
                     };
                     if entity.has::<Position>() {
-                        entity.get::<&Position>(|position| {
+                        entity.get::<(&Position, Option<&InWorld>)>(|(position, in_world)| {
                             compose
                                 .broadcast_local(&pkt, position.to_chunk(), system)
+                                .world(InWorld::proxy_id(in_world))
                                 .send()
                                 .unwrap();
                         });
@@ -131,10 +133,11 @@ impl Module for EntityStateSyncModule {
         &Compose($),
         ?&ConnectionId,
         &mut ActiveAnimation,
+        ?&InWorld,
         )
         .kind::<flecs::pipeline::OnStore>()
         .each_iter(
-            move |it, row, (position, compose, connection_id, animation)| {
+            move |it, row, (position, compose, connection_id, animation, in_world)| {
                 let io = connection_id.copied();
 
                 let entity = it.entity(row);
@@ -147,7 +150,9 @@ impl Module for EntityStateSyncModule {
                 for pkt in animation.packets(entity_id) {
                     compose
                         .broadcast_local(&pkt, chunk_pos, system)
+                        .world(InWorld::proxy_id(in_world))
                         .exclude(io)
+                        .priority(Priority::Cosmetic)
                         .send()
                         .unwrap();
                 }
@@ -172,6 +177,7 @@ impl Module for EntityStateSyncModule {
             ?&mut PendingTeleportation,
             &mut MovementTracking,
             &Flight,
+            ?&InWorld,
         )
         .kind::<flecs::pipeline::PreStore>()
         .each_iter(
@@ -189,6 +195,7 @@ impl Module for EntityStateSyncModule {
                 pending_teleport,
                 tracking,
                 flight,
+                in_world,
             )| {
                 let world = it.system().world();
                 let system = it.system();
@@ -229,7 +236,7 @@ impl Module for EntityStateSyncModule {
                         return;
                     }
 
-                    world.get::<&mut Blocks>(|blocks| {
+                    with_blocks(&world, in_world, |blocks| {
                         let grounded = is_grounded(position, blocks);
                         tracking.was_on_ground = grounded;
                         if grounded
@@ -312,10 +319,13 @@ impl Module for EntityStateSyncModule {
                         velocity.0 = Vec3::ZERO;
                     }
 
-                    bundle.broadcast_local(chunk_pos).unwrap();
+                    bundle
+                        .broadcast_local(chunk_pos, InWorld::proxy_id(in_world))
+                        .unwrap();
                 }
 
                 tracking.received_movement_packets = 0;
+                movement::refill_movement_balance(tracking, Instant::now());
                 tracking.last_tick_position = **position;
                 tracking.last_tick_flying = flight.is_flying;
 
@@ -324,7 +334,7 @@ impl Module for EntityStateSyncModule {
                 if tracking.was_on_ground {
                     tracking.server_velocity.y = 0.;
                     #[allow(clippy::cast_possible_truncation)]
-                    world.get::<&mut Blocks>(|blocks| {
+                    with_blocks(&world, in_world, |blocks| {
                         let block_x = position.x as i32;
                         let block_y = (position.y.ceil() - 1.0) as i32; // Check the block directly below
                         let block_z = position.z as i32;
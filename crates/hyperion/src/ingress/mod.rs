@@ -1,6 +1,6 @@
-use std::{borrow::Cow, sync::Arc};
+use std::{borrow::Cow, sync::Arc, time::Instant};
 
-use anyhow::Context;
+use anyhow::{Context, ensure};
 use colored::Colorize;
 use flecs_ecs::prelude::*;
 use hyperion_utils::EntityExt;
@@ -17,24 +17,29 @@ use valence_text::IntoText;
 
 use crate::{
     Prev, Shutdown,
+    config::Config,
     egress::sync_chunks::ChunkSendQueue,
     net::{
-        Compose, ConnectionId, MINECRAFT_VERSION, PROTOCOL_VERSION, PacketDecoder,
-        decoder::BorrowedPacketFrame, proxy::ReceiveState,
+        Compose, ConnectionId, MINECRAFT_VERSION, PROTOCOL_VERSION, PacketDecoder, RemoteAddr,
+        decoder::BorrowedPacketFrame,
+        encryption::LoginKeys,
+        proxy::{NewConnection, ReceiveState},
     },
     runtime::AsyncRuntime,
     simulation::{
-        AiTargetable, ChunkPosition, Comms, ConfirmBlockSequences, EntitySize, IgnMap,
-        ImmuneStatus, Name, PacketState, Pitch, Player, Position, StreamLookup, Uuid, Velocity, Xp,
-        Yaw,
+        AiTargetable, ChunkPosition, ClientSettings, Comms, ConfirmBlockSequences, EntitySize,
+        IgnMap, ImmuneStatus, KeepAlive, Latency, Name, PacketState, Pitch, Player, Position,
+        StreamLookup, Uuid, Velocity, ViewDistance, Xp, Yaw,
         animation::ActiveAnimation,
         blocks::Blocks,
+        channel::RegisteredChannels,
+        dimension::{Dimension, InWorld},
         handlers::PacketSwitchQuery,
         metadata::{MetadataPrefabs, entity::Pose},
         packet::HandlerRegistry,
         skin::PlayerSkin,
     },
-    storage::{Events, PlayerJoinServer, SkinHandler},
+    storage::{AccessList, Events, PlayerJoinServer, SkinHandler},
     util::{TracingExt, mojang::MojangClient},
 };
 
@@ -54,7 +59,10 @@ impl PendingRemove {
 
 fn process_handshake(
     login_state: &mut PacketState,
+    system: EntityView<'_>,
     packet: &BorrowedPacketFrame<'_>,
+    stream_id: ConnectionId,
+    compose: &Compose,
 ) -> anyhow::Result<()> {
     debug_assert!(
         *login_state == PacketState::Handshake,
@@ -62,20 +70,56 @@ fn process_handshake(
     );
 
     let handshake: packets::handshaking::HandshakeC2s<'_> = packet.decode()?;
+    let protocol = handshake.protocol_version.0;
 
-    // todo: check version is correct
-    match handshake.next_state {
-        HandshakeNextState::Status => {
-            *login_state = PacketState::Status;
-        }
-        HandshakeNextState::Login => {
-            *login_state = PacketState::Login;
+    match handshake_state(handshake.next_state, protocol) {
+        Ok(state) => *login_state = state,
+        Err(msg) => {
+            info!("rejecting login from client with unsupported protocol {protocol}");
+
+            compose.unicast_no_compression(
+                &login::LoginDisconnectS2c {
+                    reason: msg.into_cow_text(),
+                },
+                stream_id,
+                system,
+            )?;
+
+            *login_state = PacketState::Terminate;
         }
     }
 
     Ok(())
 }
 
+/// The state a handshake with the client's `protocol` moves the connection to, or why its login
+/// is rejected.
+fn handshake_state(next_state: HandshakeNextState, protocol: i32) -> Result<PacketState, String> {
+    match next_state {
+        // the client compares the protocol in the status response itself
+        HandshakeNextState::Status => Ok(PacketState::Status),
+        HandshakeNextState::Login if protocol != PROTOCOL_VERSION => Err(format!(
+            "§cIncompatible client! Please use {MINECRAFT_VERSION}"
+        )),
+        HandshakeNextState::Login => Ok(PacketState::Login),
+    }
+}
+
+/// A player in online mode who has been sent `LoginHelloS2c` but has not finished logging in.
+#[derive(Component, Debug)]
+pub struct PendingLogin {
+    pub username: Arc<str>,
+    verify_token: [u8; 4],
+}
+
+/// A player who was playing before their proxy reconnected and is rejoined by the
+/// `finish_rejoins` system.
+#[derive(Component, Debug)]
+pub struct PendingRejoin {
+    pub username: Arc<str>,
+    pub uuid: uuid::Uuid,
+}
+
 #[expect(clippy::too_many_arguments, reason = "todo; refactor")]
 fn process_login(
     world: &WorldRef<'_>,
@@ -97,55 +141,202 @@ fn process_login(
         "process_login called with invalid state: {login_state:?}"
     );
 
+    if packet.id == login::LoginKeyC2s::ID {
+        return process_login_key(
+            world, tasks, comms, mojang, packet, stream_id, compose, entity,
+        );
+    }
+
     let login::LoginHelloC2s {
         username,
         profile_id,
     } = packet.decode()?;
 
-    let username = username.0;
+    let username = Arc::from(username.0);
 
-    let player_join = PlayerJoinServer {
-        username: username.to_string(),
-        entity: entity.id(),
-    };
+    let online_mode = world.get::<&Config>(|config| config.online_mode);
 
-    let username = player_join.username.as_str();
+    if !online_mode {
+        let uuid = profile_id.unwrap_or_else(|| offline_uuid(&username));
+        return finish_login(
+            world,
+            tasks,
+            login_state,
+            decoder,
+            comms,
+            skins_collection,
+            mojang,
+            stream_id,
+            compose,
+            entity,
+            system,
+            ign_map,
+            username,
+            uuid,
+            None,
+        );
+    }
 
-    let global = compose.global();
+    let verify_token = fastrand::u32(..).to_be_bytes();
 
-    let pkt = LoginCompressionS2c {
-        threshold: VarInt(global.shared.compression_threshold.0),
-    };
+    world.get::<&LoginKeys>(|keys| {
+        let pkt = login::LoginHelloS2c {
+            server_id: Bounded(""),
+            public_key: keys.public_key_der(),
+            verify_token: &verify_token,
+        };
 
-    compose.unicast_no_compression(&pkt, stream_id, system)?;
+        compose.unicast_no_compression(&pkt, stream_id, system)
+    })?;
 
-    decoder.set_compression(global.shared.compression_threshold);
+    entity.set(PendingLogin {
+        username,
+        verify_token,
+    });
 
-    let username = Arc::from(username);
+    Ok(())
+}
 
-    let uuid = profile_id.unwrap_or_else(|| offline_uuid(&username));
-    let uuid_s = format!("{uuid:?}").dimmed();
-    info!("Starting login: {username} {uuid_s}");
+/// Enables encryption and asks the session server whether the player really is who they claim to
+/// be. The login is finished by the `finish_online_logins` system once it responds.
+///
+/// The proxy both encrypts and decrypts, so that it can hold the player if it loses its connection
+/// to the server.
+#[expect(clippy::too_many_arguments, reason = "todo; refactor")]
+fn process_login_key(
+    world: &WorldRef<'_>,
+    tasks: &AsyncRuntime,
+    comms: &Comms,
+    mojang: MojangClient,
+    packet: &BorrowedPacketFrame<'_>,
+    stream_id: ConnectionId,
+    compose: &Compose,
+    entity: &EntityView<'_>,
+) -> anyhow::Result<()> {
+    let login::LoginKeyC2s {
+        shared_secret,
+        verify_token,
+    } = packet.decode()?;
 
-    let skins = comms.skins_tx.clone();
+    let (username, expected_token) = entity
+        .try_get::<&PendingLogin>(|pending| (pending.username.clone(), pending.verify_token))
+        .context("received encryption response before login start")?;
+
+    let (shared_secret, verify_token, server_hash) = world.get::<&LoginKeys>(|keys| {
+        let shared_secret = keys.decrypt(shared_secret)?;
+        let verify_token = keys.decrypt(verify_token)?;
+        let server_hash = keys.server_hash(&shared_secret);
+        anyhow::Ok((shared_secret, verify_token, server_hash))
+    })?;
+
+    ensure!(verify_token == expected_token, "verify tokens do not match");
+
+    let key: [u8; 16] = shared_secret
+        .as_slice()
+        .try_into()
+        .context("shared secret has the wrong length")?;
+
+    compose.io_buf().set_encryption(stream_id, key, world);
+
+    let logins = comms.logins_tx.clone();
     let id = entity.id();
 
     tasks.spawn(async move {
-        let skin = match PlayerSkin::from_uuid(uuid, &mojang, &skins_collection).await {
+        let profile = match mojang.has_joined(&username, &server_hash).await {
+            Ok(Some(profile)) => profile,
+            Ok(None) => {
+                warn!("{username} could not be verified by the session server");
+                logins.send((id, None)).unwrap();
+                return;
+            }
+            Err(e) => {
+                error!("failed to verify {username} with the session server: {e}");
+                logins.send((id, None)).unwrap();
+                return;
+            }
+        };
+
+        let uuid = profile["id"]
+            .as_str()
+            .and_then(|id| uuid::Uuid::parse_str(id).ok());
+
+        let Some(uuid) = uuid else {
+            error!("session server returned a profile without a valid id: {profile}");
+            logins.send((id, None)).unwrap();
+            return;
+        };
+
+        let skin = match PlayerSkin::from_profile(&profile) {
             Ok(Some(skin)) => skin,
             Err(e) => {
                 error!("failed to get skin {e}. Using empty skin");
                 PlayerSkin::EMPTY
             }
-            Ok(None) => {
-                error!("failed to get skin. Using empty skin");
-                PlayerSkin::EMPTY
-            }
+            Ok(None) => PlayerSkin::EMPTY,
         };
 
-        skins.send((id, skin)).unwrap();
+        logins.send((id, Some((uuid, skin)))).unwrap();
     });
 
+    Ok(())
+}
+
+/// Enables compression, sends `LoginSuccessS2c` and moves the player into the play state.
+///
+/// If `skin` is `None`, it is looked up by `uuid` in the background.
+#[expect(clippy::too_many_arguments, reason = "todo; refactor")]
+fn finish_login(
+    world: &WorldRef<'_>,
+    tasks: &AsyncRuntime,
+    login_state: &mut PacketState,
+    decoder: &PacketDecoder,
+    comms: &Comms,
+    skins_collection: SkinHandler,
+    mojang: MojangClient,
+    stream_id: ConnectionId,
+    compose: &Compose,
+    entity: &EntityView<'_>,
+    system: EntityView<'_>,
+    ign_map: &IgnMap,
+    username: Arc<str>,
+    uuid: uuid::Uuid,
+    skin: Option<PlayerSkin>,
+) -> anyhow::Result<()> {
+    if let Some(reason) = check_login_gate(world, compose, uuid)? {
+        info!("rejecting login of {username}: {reason}");
+
+        compose.unicast_no_compression(
+            &login::LoginDisconnectS2c {
+                reason: reason.into_cow_text(),
+            },
+            stream_id,
+            system,
+        )?;
+
+        // the client closes the connection once it receives the disconnect
+        *login_state = PacketState::Terminate;
+        return Ok(());
+    }
+
+    let player_join = PlayerJoinServer {
+        username: username.to_string(),
+        entity: entity.id(),
+    };
+
+    let username = player_join.username.as_str();
+
+    let global = compose.global();
+
+    let pkt = LoginCompressionS2c {
+        threshold: VarInt(global.shared.compression_threshold.0),
+    };
+
+    compose.unicast_no_compression(&pkt, stream_id, system)?;
+
+    decoder.set_compression(global.shared.compression_threshold);
+
+    let username = Arc::from(username);
+
     let pkt = login::LoginSuccessS2c {
         uuid,
         username: Bounded(&username),
@@ -158,8 +349,138 @@ fn process_login(
 
     *login_state = PacketState::Play;
 
+    enter_play(
+        world,
+        tasks,
+        comms,
+        skins_collection,
+        mojang,
+        stream_id,
+        compose,
+        entity,
+        ign_map,
+        username,
+        uuid,
+        skin,
+    );
+
+    Ok(())
+}
+
+/// Moves a player which was held by their proxy while it reconnected back into the play state.
+/// The client is still in the play state with compression enabled, so it is sent the usual join
+/// packets once its skin is known, which makes it load the world again.
+#[expect(clippy::too_many_arguments, reason = "todo; refactor")]
+fn rejoin(
+    world: &WorldRef<'_>,
+    tasks: &AsyncRuntime,
+    login_state: &mut PacketState,
+    decoder: &PacketDecoder,
+    comms: &Comms,
+    skins_collection: SkinHandler,
+    mojang: MojangClient,
+    stream_id: ConnectionId,
+    compose: &Compose,
+    entity: &EntityView<'_>,
+    system: EntityView<'_>,
+    ign_map: &IgnMap,
+    username: Arc<str>,
+    uuid: uuid::Uuid,
+) -> anyhow::Result<()> {
+    decoder.set_compression(compose.global().shared.compression_threshold);
+
+    if let Some(reason) = check_login_gate(world, compose, uuid)? {
+        info!("rejecting rejoin of {username}: {reason}");
+
+        compose.unicast(
+            &play::DisconnectS2c {
+                reason: reason.into_cow_text(),
+            },
+            stream_id,
+            system,
+        )?;
+
+        *login_state = PacketState::Terminate;
+        return Ok(());
+    }
+
+    // the proxy showed a title while it was reconnecting
+    compose.unicast(&play::ClearTitleS2c { reset: true }, stream_id, system)?;
+
+    *login_state = PacketState::Play;
+
+    enter_play(
+        world,
+        tasks,
+        comms,
+        skins_collection,
+        mojang,
+        stream_id,
+        compose,
+        entity,
+        ign_map,
+        username,
+        uuid,
+        None,
+    );
+
+    Ok(())
+}
+
+/// Turns a player who has just entered the play state into a player entity.
+///
+/// If `skin` is `None`, it is looked up by `uuid` in the background.
+#[expect(clippy::too_many_arguments, reason = "todo; refactor")]
+fn enter_play(
+    world: &WorldRef<'_>,
+    tasks: &AsyncRuntime,
+    comms: &Comms,
+    skins_collection: SkinHandler,
+    mojang: MojangClient,
+    stream_id: ConnectionId,
+    compose: &Compose,
+    entity: &EntityView<'_>,
+    ign_map: &IgnMap,
+    username: Arc<str>,
+    uuid: uuid::Uuid,
+    skin: Option<PlayerSkin>,
+) {
+    let uuid_s = format!("{uuid:?}").dimmed();
+    info!("Starting login: {username} {uuid_s}");
+
+    let skins = comms.skins_tx.clone();
+    let id = entity.id();
+
+    if let Some(skin) = skin {
+        if let Err(e) = skins_collection.insert(uuid, &skin) {
+            error!("failed to cache skin {e}");
+        }
+
+        skins.send((id, skin)).unwrap();
+    } else {
+        tasks.spawn(async move {
+            let skin = match PlayerSkin::from_uuid(uuid, &mojang, &skins_collection).await {
+                Ok(Some(skin)) => skin,
+                Err(e) => {
+                    error!("failed to get skin {e}. Using empty skin");
+                    PlayerSkin::EMPTY
+                }
+                Ok(None) => {
+                    error!("failed to get skin. Using empty skin");
+                    PlayerSkin::EMPTY
+                }
+            };
+
+            skins.send((id, skin)).unwrap();
+        });
+    }
+
     ign_map.insert(username.clone(), entity.id(), world);
 
+    let io_buf = compose.io_buf();
+    io_buf.set_receive_broadcasts(stream_id, world);
+    io_buf.set_rejoin(stream_id, uuid, &username, world);
+
     world.get::<&MetadataPrefabs>(|prefabs| {
         entity
             .is_a_id(prefabs.player_base)
@@ -172,11 +493,61 @@ fn process_login(
             .add::<ChunkSendQueue>()
             .add::<Velocity>()
             .set(ChunkPosition::null())
+            .add::<ViewDistance>()
+            .add::<ClientSettings>()
+            .add::<RegisteredChannels>()
+            .set(KeepAlive::new(Instant::now()))
+            .add::<Latency>()
     });
+}
 
-    compose.io_buf().set_receive_broadcasts(stream_id, world);
+/// Decides whether a player may join before they are sent `LoginSuccessS2c`. Returns the reason
+/// shown to the player if they may not.
+fn check_login_gate(
+    world: &WorldRef<'_>,
+    compose: &Compose,
+    uuid: uuid::Uuid,
+) -> anyhow::Result<Option<String>> {
+    let online = compose
+        .global()
+        .player_count
+        .load(std::sync::atomic::Ordering::Relaxed);
+
+    world.get::<&AccessList>(|access| {
+        world.get::<&Config>(|config| login_rejection(access, config, online, uuid))
+    })
+}
 
-    Ok(())
+/// See [`check_login_gate`]. `online` is the number of players already online.
+fn login_rejection(
+    access: &AccessList,
+    config: &Config,
+    online: usize,
+    uuid: uuid::Uuid,
+) -> anyhow::Result<Option<String>> {
+    if let Some(ban) = access.active_ban(uuid)? {
+        let mut reason = format!(
+            "§cYou are banned from this server.\n\n§7Reason: §r{}",
+            ban.reason
+        );
+
+        if let Some(expires) = ban.expires_at() {
+            let expires = humantime::format_rfc3339_seconds(expires);
+            reason.push_str(&format!("\n§7Expires: §r{expires}"));
+        }
+
+        return Ok(Some(reason));
+    }
+
+    if config.whitelist && !access.is_whitelisted(uuid)? {
+        return Ok(Some("You are not white-listed on this server!".to_owned()));
+    }
+
+    if online >= usize::try_from(config.max_players).unwrap_or(0) {
+        return Ok(Some("The server is full!".to_owned()));
+    }
+
+    Ok(None)
 }
 
 /// Get a [`uuid::Uuid`] based on the given user's name.
@@ -190,12 +561,17 @@ fn offline_uuid(username: &str) -> uuid::Uuid {
     uuid::Uuid::from_u128(digest)
 }
 
+/// The most players listed when hovering over the player count in the server list, as in vanilla.
+const MAX_STATUS_SAMPLE: usize = 12;
+
 fn process_status(
     login_state: &mut PacketState,
     system: EntityView<'_>,
     packet: &BorrowedPacketFrame<'_>,
     packets: ConnectionId,
     compose: &Compose,
+    config: &Config,
+    ign_map: &IgnMap,
 ) -> anyhow::Result<()> {
     debug_assert!(
         *login_state == PacketState::Status,
@@ -206,31 +582,21 @@ fn process_status(
         packets::status::QueryRequestC2s::ID => {
             let query_request: packets::status::QueryRequestC2s = packet.decode()?;
 
-            // let img_bytes = include_bytes!("data/hyperion.png");
-
-            // let favicon = general_purpose::STANDARD.encode(img_bytes);
-            // let favicon = format!("data:image/png;base64,{favicon}");
+            let world = system.world();
 
             let online = compose
                 .global()
                 .player_count
                 .load(std::sync::atomic::Ordering::Relaxed);
 
-            // https://wiki.vg/Server_List_Ping#Response
-            let json = json!({
-                "version": {
-                    "name": MINECRAFT_VERSION,
-                    "protocol": PROTOCOL_VERSION,
-                },
-                "players": {
-                    "online": online,
-                    "max": 12_000,
-                    "sample": [],
-                },
-                "description": "Getting 10k Players to PvP at Once on a Minecraft Server to Break the Guinness World Record",
-                // "favicon": favicon,
+            let players = ign_map.iter().filter_map(|(name, &entity)| {
+                let uuid = world
+                    .entity_from_id(entity)
+                    .try_get::<&Uuid>(|uuid| uuid.0)?;
+                Some((&**name, uuid))
             });
 
+            let json = status_json(config, online, players);
             let json = serde_json::to_string_pretty(&json)?;
 
             let send = packets::status::QueryResponseS2c { json: &json };
@@ -255,26 +621,84 @@ fn process_status(
         _ => warn!("unexpected packet id during status: {packet:?}"),
     }
 
-    // todo: check version is correct
-
     Ok(())
 }
 
+/// The response to a status request. `players` are the names and UUIDs of the players online, of
+/// which the first [`MAX_STATUS_SAMPLE`] are listed.
+fn status_json<'a>(
+    config: &Config,
+    online: usize,
+    players: impl IntoIterator<Item = (&'a str, uuid::Uuid)>,
+) -> serde_json::Value {
+    let sample: Vec<_> = players
+        .into_iter()
+        .take(MAX_STATUS_SAMPLE)
+        .map(|(name, uuid)| json!({ "name": name, "id": uuid.to_string() }))
+        .collect();
+
+    // https://wiki.vg/Server_List_Ping#Response
+    let mut json = json!({
+        "version": {
+            "name": MINECRAFT_VERSION,
+            "protocol": PROTOCOL_VERSION,
+        },
+        "players": {
+            "online": online,
+            "max": config.max_players,
+            "sample": sample,
+        },
+        "description": config.motd(),
+    });
+
+    if let Some(favicon) = &config.favicon_data {
+        json["favicon"] = favicon.as_str().into();
+    }
+
+    json
+}
+
+/// Spawns the entity of a player who has just connected through a proxy.
+fn spawn_connection<'a>(world: &WorldRef<'a>, connection: &NewConnection) -> EntityView<'a> {
+    let entity = world.entity();
+
+    if let Some(addr) = connection.addr {
+        entity.set(RemoteAddr::new(addr));
+    }
+
+    entity
+        .set(ConnectionId::new(connection.stream))
+        .set(hyperion_inventory::PlayerInventory::default())
+        .set(ConfirmBlockSequences::default())
+        .set(PacketState::Handshake)
+        .set(ActiveAnimation::NONE)
+        .set(PacketDecoder::default())
+        .add::<Player>()
+}
+
 #[derive(Component)]
 pub struct IngressModule;
 
 impl Module for IngressModule {
     #[expect(clippy::too_many_lines)]
     fn module(world: &World) {
+        let other_worlds = world.new_query::<(&mut Blocks, &Dimension)>();
+
         system!(
             "shutdown",
             world,
             &Shutdown($),
+            &mut Blocks($),
+            &AsyncRuntime($),
         )
         .kind::<flecs::pipeline::OnLoad>()
-        .each_iter(|it, _, shutdown| {
+        .each_iter(move |it, _, (shutdown, blocks, runtime)| {
             let world = it.world();
             if shutdown.value.load(std::sync::atomic::Ordering::Relaxed) {
+                info!("saving chunks");
+                blocks.save_all(runtime);
+                other_worlds.each(|(blocks, _)| blocks.save_all(runtime));
+
                 info!("shutting down");
                 world.quit();
             }
@@ -313,34 +737,172 @@ impl Module for IngressModule {
 
             let recv = &receive.0;
 
-            for connect in recv.player_connect.lock().drain(..) {
-                info!("player_connect");
-                let view = world
-                    .entity()
-                    .set(ConnectionId::new(connect))
-                    .set(hyperion_inventory::PlayerInventory::default())
-                    .set(ConfirmBlockSequences::default())
-                    .set(PacketState::Handshake)
-                    .set(ActiveAnimation::NONE)
-                    .set(PacketDecoder::default())
-                    .add::<Player>();
-
-                lookup.insert(connect, view.id());
-            }
-
+            // before connects, as a player rejoining through a reconnected proxy keeps the
+            // stream id they were disconnected with
             for disconnect in recv.player_disconnect.lock().drain(..) {
                 // will initiate the removal of entity
                 info!("queue pending remove");
-                let Some(id) = lookup.get(&disconnect).copied() else {
+                let Some(id) = lookup.remove(&disconnect) else {
                     error!("failed to get id for disconnect stream {disconnect:?}");
                     continue;
                 };
                 world
-                    .entity_from_id(*id)
+                    .entity_from_id(id)
                     .set(PendingRemove::new("disconnected"));
             }
+
+            for connect in recv.player_connect.lock().drain(..) {
+                info!("player_connect from {:?}", connect.addr);
+                let view = spawn_connection(&world, &connect);
+                lookup.insert(connect.stream, view.id());
+            }
+
+            for rejoin in recv.player_rejoin.lock().drain(..) {
+                info!("player_rejoin from {:?}", rejoin.connection.addr);
+                let view = spawn_connection(&world, &rejoin.connection).set(PendingRejoin {
+                    username: rejoin.username,
+                    uuid: rejoin.uuid,
+                });
+                lookup.insert(rejoin.connection.stream, view.id());
+            }
         });
 
+        system!(
+            "finish_online_logins",
+            world,
+            &Compose($),
+            &AsyncRuntime($),
+            &Comms($),
+            &SkinHandler($),
+            &MojangClient($),
+            &IgnMap($),
+        )
+        .kind::<flecs::pipeline::OnUpdate>()
+        .each_iter(
+            |it, _, (compose, tasks, comms, skins_collection, mojang, ign_map)| {
+                let system = it.system();
+                let world = it.world();
+
+                while let Ok(Some((id, profile))) = comms.logins_rx.try_recv() {
+                    if !world.is_alive(id) {
+                        continue;
+                    }
+
+                    let entity = world.entity_from_id(id);
+
+                    let Some(username) =
+                        entity.try_get::<&PendingLogin>(|pending| pending.username.clone())
+                    else {
+                        continue;
+                    };
+
+                    entity.remove::<PendingLogin>();
+
+                    entity.get::<(&mut PacketState, &PacketDecoder, &ConnectionId)>(
+                        |(login_state, decoder, &stream_id)| {
+                            let result = match profile {
+                                Some((uuid, skin)) => finish_login(
+                                    &world,
+                                    tasks,
+                                    login_state,
+                                    decoder,
+                                    comms,
+                                    skins_collection.clone(),
+                                    mojang.clone(),
+                                    stream_id,
+                                    compose,
+                                    &entity,
+                                    system,
+                                    ign_map,
+                                    username,
+                                    uuid,
+                                    Some(skin),
+                                ),
+                                None => Err(anyhow::anyhow!("Failed to verify username!")),
+                            };
+
+                            let Err(e) = result else {
+                                return;
+                            };
+
+                            warn!("failed to finish online login: {e}");
+
+                            // compression has not been enabled yet; the proxy handles encryption
+                            if let Err(e) = compose.unicast_no_compression(
+                                &login::LoginDisconnectS2c {
+                                    reason: e.to_string().into_cow_text(),
+                                },
+                                stream_id,
+                                system,
+                            ) {
+                                error!("failed to send login disconnect packet: {e}");
+                            }
+
+                            entity.destruct();
+                        },
+                    );
+                }
+            },
+        );
+
+        system!(
+            "finish_rejoins",
+            world,
+            &Compose($),
+            &AsyncRuntime($),
+            &Comms($),
+            &SkinHandler($),
+            &MojangClient($),
+            &IgnMap($),
+            &PendingRejoin,
+            &mut PacketState,
+            &PacketDecoder,
+            &ConnectionId,
+        )
+        .kind::<flecs::pipeline::OnUpdate>()
+        .each_iter(
+            |it,
+             row,
+             (
+                compose,
+                tasks,
+                comms,
+                skins_collection,
+                mojang,
+                ign_map,
+                pending,
+                login_state,
+                decoder,
+                &stream_id,
+            )| {
+                let system = it.system();
+                let world = it.world();
+                let entity = it.entity(row);
+
+                entity.remove::<PendingRejoin>();
+
+                if let Err(e) = rejoin(
+                    &world,
+                    tasks,
+                    login_state,
+                    decoder,
+                    comms,
+                    skins_collection.clone(),
+                    mojang.clone(),
+                    stream_id,
+                    compose,
+                    &entity,
+                    system,
+                    ign_map,
+                    pending.username.clone(),
+                    pending.uuid,
+                ) {
+                    error!("failed to rejoin {}: {e}", pending.username);
+                    entity.destruct();
+                }
+            },
+        );
+
         world
             .system_named::<(&ReceiveState, &ConnectionId, &mut PacketDecoder)>("ingress_to_ecs")
             .term_at(0u32)
@@ -442,6 +1004,8 @@ impl Module for IngressModule {
             &mut ActiveAnimation,
             &hyperion_crafting::CraftingRegistry($),
             &IgnMap($),
+            &Config($),
+            ?&InWorld,
         )
         .kind::<flecs::pipeline::OnUpdate>()
         .each_iter(
@@ -469,6 +1033,8 @@ impl Module for IngressModule {
                 animation,
                 crafting_registry,
                 ign_map,
+                config,
+                in_world,
             )| {
                 let system = it.system();
                 let world = it.world();
@@ -492,8 +1058,10 @@ impl Module for IngressModule {
 
                     match *login_state {
                         PacketState::Handshake => {
-                            if process_handshake(login_state, &frame).is_err() {
-                                error!("failed to process handshake");
+                            if let Err(e) =
+                                process_handshake(login_state, system, &frame, io_ref, compose)
+                            {
+                                error!("failed to process handshake: {e}");
 
                                 entity.destruct();
 
@@ -501,9 +1069,15 @@ impl Module for IngressModule {
                             }
                         }
                         PacketState::Status => {
-                            if let Err(e) =
-                                process_status(login_state, system, &frame, io_ref, compose)
-                            {
+                            if let Err(e) = process_status(
+                                login_state,
+                                system,
+                                &frame,
+                                io_ref,
+                                compose,
+                                config,
+                                ign_map,
+                            ) {
                                 error!("failed to process status packet: {e}");
                                 entity.destruct();
                                 break;
@@ -558,35 +1132,48 @@ impl Module for IngressModule {
                                 let world = &world;
                                 let id = entity.id();
 
-                                let mut query = PacketSwitchQuery {
-                                    id,
-                                    view: entity,
-                                    compose,
-                                    io_ref,
-                                    position,
-                                    yaw,
-                                    pitch,
-                                    size,
-                                    pose,
-                                    events: event_queue,
-                                    world,
-                                    blocks,
-                                    system,
-                                    confirm_block_sequences,
-                                    inventory,
-                                    animation,
-                                    crafting_registry,
-                                    handler_registry,
+                                let process = |blocks: &Blocks| {
+                                    let mut query = PacketSwitchQuery {
+                                        id,
+                                        view: entity,
+                                        compose,
+                                        io_ref,
+                                        position,
+                                        yaw,
+                                        pitch,
+                                        size,
+                                        pose,
+                                        events: event_queue,
+                                        world,
+                                        blocks,
+                                        in_world: in_world.copied(),
+                                        system,
+                                        confirm_block_sequences,
+                                        inventory,
+                                        animation,
+                                        crafting_registry,
+                                        handler_registry,
+                                    };
+
+                                    // info_span!("ingress", ign = name).in_scope(|| {
+                                    // SAFETY: The packet bytes are allocated in the compose bump
+                                    if let Err(err) = unsafe {
+                                        crate::simulation::handlers::packet_switch(
+                                            frame, &mut query,
+                                        )
+                                    } {
+                                        error!("failed to process packet {frame:?}: {err}");
+                                    }
+                                    // });
                                 };
 
-                                // info_span!("ingress", ign = name).in_scope(|| {
-                                // SAFETY: The packet bytes are allocated in the compose bump
-                                if let Err(err) = unsafe {
-                                    crate::simulation::handlers::packet_switch(frame, &mut query)
-                                } {
-                                    error!("failed to process packet {frame:?}: {err}");
+                                // players outside the main world interact with their own world
+                                match in_world {
+                                    Some(in_world) => {
+                                        world.entity_from_id(**in_world).get::<&Blocks>(process)
+                                    }
+                                    None => process(blocks),
                                 }
-                                // });
                             }
                         }
                         PacketState::Terminate => {
@@ -598,3 +1185,159 @@ impl Module for IngressModule {
         );
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use valence_protocol::packets::handshaking::handshake_c2s::HandshakeNextState;
+
+    use super::{MAX_STATUS_SAMPLE, handshake_state, login_rejection, status_json};
+    use crate::{
+        config::Config,
+        net::{MINECRAFT_VERSION, PROTOCOL_VERSION},
+        simulation::PacketState,
+        storage::{AccessList, Ban, LocalDb},
+    };
+
+    fn access_list() -> AccessList {
+        let path = std::env::temp_dir().join(format!("hyperion-access-{}", fastrand::u64(..)));
+        let db = LocalDb::open(&path).unwrap();
+        AccessList::new(&db).unwrap()
+    }
+
+    #[test]
+    fn test_banned_players_are_rejected() {
+        let access = access_list();
+        let config = Config::default();
+        let uuid = uuid::Uuid::from_u128(1);
+
+        assert_eq!(login_rejection(&access, &config, 0, uuid).unwrap(), None);
+
+        access.ban(uuid, &Ban::permanent("griefing")).unwrap();
+        let reason = login_rejection(&access, &config, 0, uuid).unwrap().unwrap();
+        assert!(reason.contains("griefing"), "{reason}");
+
+        access.unban(uuid).unwrap();
+        assert_eq!(login_rejection(&access, &config, 0, uuid).unwrap(), None);
+    }
+
+    #[test]
+    fn test_expired_bans_are_ignored() {
+        let access = access_list();
+        let config = Config::default();
+        let uuid = uuid::Uuid::from_u128(1);
+
+        let expired = SystemTime::now() - Duration::from_secs(60);
+        access
+            .ban(uuid, &Ban::temporary("griefing", expired))
+            .unwrap();
+        assert_eq!(login_rejection(&access, &config, 0, uuid).unwrap(), None);
+
+        let active = SystemTime::now() + Duration::from_secs(60);
+        access
+            .ban(uuid, &Ban::temporary("griefing", active))
+            .unwrap();
+        let reason = login_rejection(&access, &config, 0, uuid).unwrap().unwrap();
+        assert!(reason.contains("Expires"), "{reason}");
+    }
+
+    #[test]
+    fn test_whitelist() {
+        let access = access_list();
+        let config = Config {
+            whitelist: true,
+            ..Config::default()
+        };
+        let uuid = uuid::Uuid::from_u128(1);
+
+        assert!(
+            login_rejection(&access, &config, 0, uuid)
+                .unwrap()
+                .is_some()
+        );
+
+        access.whitelist_add(uuid).unwrap();
+        assert_eq!(login_rejection(&access, &config, 0, uuid).unwrap(), None);
+
+        access.whitelist_remove(uuid).unwrap();
+        assert!(
+            login_rejection(&access, &config, 0, uuid)
+                .unwrap()
+                .is_some()
+        );
+    }
+
+    #[test]
+    fn test_max_players() {
+        let access = access_list();
+        let config = Config {
+            max_players: 2,
+            ..Config::default()
+        };
+        let uuid = uuid::Uuid::from_u128(1);
+
+        assert_eq!(login_rejection(&access, &config, 1, uuid).unwrap(), None);
+        assert_eq!(
+            login_rejection(&access, &config, 2, uuid)
+                .unwrap()
+                .as_deref(),
+            Some("The server is full!")
+        );
+    }
+
+    #[test]
+    fn test_protocol_mismatch() {
+        assert_eq!(
+            handshake_state(HandshakeNextState::Login, PROTOCOL_VERSION),
+            Ok(PacketState::Login)
+        );
+
+        let reason = handshake_state(HandshakeNextState::Login, PROTOCOL_VERSION - 1).unwrap_err();
+        assert!(reason.contains(MINECRAFT_VERSION), "{reason}");
+
+        // clients of any version may ask for the status
+        assert_eq!(
+            handshake_state(HandshakeNextState::Status, PROTOCOL_VERSION - 1),
+            Ok(PacketState::Status)
+        );
+    }
+
+    #[test]
+    fn test_status_sample_is_capped() {
+        let config = Config::default();
+        let names: Vec<_> = (0..20).map(|idx| format!("player{idx}")).collect();
+        let players = names
+            .iter()
+            .zip(0..)
+            .map(|(name, idx)| (name.as_str(), uuid::Uuid::from_u128(idx)));
+
+        let json = status_json(&config, names.len(), players);
+
+        assert_eq!(json["players"]["online"], 20);
+        assert_eq!(json["players"]["max"], config.max_players);
+        assert_eq!(json["version"]["protocol"], PROTOCOL_VERSION);
+
+        let sample = json["players"]["sample"].as_array().unwrap();
+        assert_eq!(sample.len(), MAX_STATUS_SAMPLE);
+        assert_eq!(sample[0]["name"], "player0");
+        assert_eq!(sample[0]["id"], uuid::Uuid::from_u128(0).to_string());
+    }
+
+    #[test]
+    fn test_status_favicon() {
+        let config = Config::default();
+        let json = status_json(&config, 0, []);
+
+        assert!(json.get("favicon").is_none());
+        assert_eq!(json["players"]["sample"].as_array().unwrap().len(), 0);
+
+        let config = Config {
+            favicon_data: Some("data:image/png;base64,AAAA".to_owned()),
+            ..Config::default()
+        };
+        let json = status_json(&config, 0, []);
+
+        assert_eq!(json["favicon"], "data:image/png;base64,AAAA");
+    }
+}
@@ -49,8 +49,8 @@ use ingress::IngressModule;
 use libc::{RLIMIT_NOFILE, getrlimit, setrlimit};
 use libdeflater::CompressionLvl;
 use simulation::{Comms, SimModule, StreamLookup, blocks::Blocks};
-use storage::{Events, LocalDb, SkinHandler, ThreadLocal};
-use tracing::{info, info_span, warn};
+use storage::{AccessList, Events, LocalDb, SkinHandler, ThreadLocal};
+use tracing::{error, info, info_span, warn};
 use util::mojang::MojangClient;
 pub use uuid;
 pub use valence_protocol as protocol;
@@ -65,7 +65,10 @@ pub use valence_protocol::{
 pub use valence_server as server;
 
 use crate::{
-    net::{Compose, Compressors, IoBuf, MAX_PACKET_SIZE, proxy::init_proxy_comms},
+    net::{
+        Compose, Compressors, IoBuf, MAX_PACKET_SIZE,
+        proxy::{init_proxy_comms, limbo_packets},
+    },
     runtime::AsyncRuntime,
     simulation::{Pitch, Yaw},
 };
@@ -77,15 +80,20 @@ use system_order::SystemOrderModule;
 pub use valence_ident;
 
 use crate::{
-    ingress::PendingRemove,
-    net::{ConnectionId, PacketDecoder, proxy::ReceiveState},
+    ingress::{PendingLogin, PendingRemove},
+    metrics::{MetricsEndpoint, MetricsReport},
+    net::{ConnectionId, PacketDecoder, RemoteAddr, encryption::LoginKeys, proxy::ReceiveState},
     runtime::Tasks,
-    simulation::{EgressComm, EntitySize, IgnMap, PacketState, Player, packet::HandlerRegistry},
+    simulation::{
+        EgressComm, EntitySize, IgnMap, PacketState, Player, channel::ChannelRegistry,
+        packet::HandlerRegistry,
+    },
     util::mojang::ApiProvider,
 };
 
 pub mod egress;
 pub mod ingress;
+pub mod metrics;
 pub mod net;
 pub mod simulation;
 pub mod spatial;
@@ -246,6 +254,7 @@ impl HyperionCore {
 
         component!(world, IVec2 { x: i32, y: i32 });
         world.component::<PendingRemove>();
+        world.component::<PendingLogin>();
 
         world.component::<Yaw>().meta();
 
@@ -256,12 +265,14 @@ impl HyperionCore {
         world.component::<PacketState>();
 
         world.component::<ConnectionId>();
+        world.component::<RemoteAddr>();
         world.component::<ReceiveState>();
         world.component::<Compose>();
         world.component::<CraftingRegistry>();
 
         world.component::<LocalDb>();
         world.component::<SkinHandler>();
+        world.component::<AccessList>();
         world.component::<MojangClient>();
         world.component::<Events>();
         world.component::<Comms>();
@@ -288,14 +299,52 @@ impl HyperionCore {
         world.component::<IgnMap>();
 
         world.component::<config::Config>();
+        world.component::<config::ConfigWatcher>();
+
+        system!(
+            "reload_config",
+            world,
+            &mut config::Config($),
+            &config::ConfigWatcher($),
+            &mut simulation::dimension::Dimension($),
+            &Events($),
+        )
+        .with::<flecs::pipeline::OnUpdate>()
+        .each_iter(|it, _, (config, watcher, dimension, events)| {
+            let Some(new) = watcher.try_recv() else {
+                return;
+            };
+
+            let previous = config.reload(new, dimension);
+            events.push(simulation::event::ConfigChanged { previous }, &it.world());
+        });
 
         info!("starting hyperion");
         let config = config::Config::load("run/config.toml")?;
+
+        // generated in offline mode as well, since reloading the config can turn on online mode
+        info!("generating login keys");
+        world.component::<LoginKeys>();
+        world.set(LoginKeys::generate()?);
+
+        world.set(simulation::dimension::Dimension::overworld(
+            config.border_diameter,
+        ));
+        let resource_pack = config.resource_pack.clone();
         world.set(config);
 
         let (task_tx, task_rx) = kanal::bounded(32);
         let runtime = AsyncRuntime::new(task_tx);
 
+        if let Some(pack) = resource_pack {
+            runtime.spawn(async move {
+                match pack.verify().await {
+                    Ok(()) => info!("verified resource pack {}", pack.url),
+                    Err(e) => warn!("players may reject the resource pack: {e:?}"),
+                }
+            });
+        }
+
         #[cfg(unix)]
         #[allow(clippy::redundant_pub_crate)]
         runtime.spawn(async move {
@@ -326,29 +375,58 @@ impl HyperionCore {
         world.component::<HandlerRegistry>();
         world.set(HandlerRegistry::default());
 
+        world.component::<ChannelRegistry>();
+        world.set(ChannelRegistry::default());
+
         info!("initializing database");
         let db = LocalDb::new()?;
         let skins = SkinHandler::new(&db)?;
+        let access = AccessList::new(&db)?;
         info!("database initialized");
 
         world.set(db);
         world.set(skins);
+        world.set(access);
 
         world.set(MojangClient::new(&runtime, ApiProvider::MAT_DOES_DEV));
 
+        let limbo = limbo_packets(&shared).context("failed to encode limbo packets")?;
+
         #[rustfmt::skip]
         world
             .observer::<flecs::OnSet, (&GameServerEndpoint, &AsyncRuntime)>()
             .term_at(0).singleton()
             .term_at(1).filter().singleton()
-            .each_iter(|it, _, (address, runtime)| {
+            .each_iter(move |it, _, (address, runtime)| {
                 let world = it.world();
                 let address = address.0;
-                let (receive_state, egress_comm) = init_proxy_comms(runtime, address);
+                let (receive_state, egress_comm) =
+                    init_proxy_comms(runtime, address, limbo.clone());
                 world.set(receive_state);
                 world.set(egress_comm);
             });
 
+        world.component::<MetricsEndpoint>();
+        world.component::<MetricsReport>();
+        world.set(MetricsReport::default());
+
+        #[rustfmt::skip]
+        world
+            .observer::<flecs::OnSet, (&MetricsEndpoint, &AsyncRuntime, &MetricsReport)>()
+            .term_at(0).singleton()
+            .term_at(1).filter().singleton()
+            .term_at(2).filter().singleton()
+            .each(|(endpoint, runtime, report)| {
+                let address = endpoint.address();
+                let report = report.clone();
+                runtime.spawn(async move {
+                    let render = move || report.get();
+                    if let Err(e) = hyperion_utils::serve_prometheus(address, render).await {
+                        error!("failed to serve metrics: {e:?}");
+                    }
+                });
+            });
+
         let global = Global::new(shared.clone());
 
         world.set(Compose::new(
@@ -365,6 +443,7 @@ impl HyperionCore {
         let events = Events::initialize(world);
         world.set(events);
 
+        world.set(config::ConfigWatcher::spawn("run/config.toml", &runtime));
         world.set(runtime);
         world.set(StreamLookup::default());
 
@@ -0,0 +1,46 @@
+//! Tick and system timings of the game server, served over HTTP in the Prometheus text format.
+//!
+//! The numbers are collected by the stats systems of [`crate::egress::EgressModule`] and rendered
+//! once per window of [`WINDOW_TICKS`] ticks, so a scrape always sees a whole window.
+
+use std::{
+    net::SocketAddr,
+    sync::{Arc, RwLock},
+};
+
+use flecs_ecs::macros::Component;
+
+/// The number of ticks summarized by each report.
+pub const WINDOW_TICKS: u64 = 200;
+
+/// Set this singleton to serve metrics at `/metrics` on the address.
+#[derive(Component, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MetricsEndpoint(SocketAddr);
+
+impl From<SocketAddr> for MetricsEndpoint {
+    fn from(value: SocketAddr) -> Self {
+        Self(value)
+    }
+}
+
+impl MetricsEndpoint {
+    #[must_use]
+    pub const fn address(&self) -> SocketAddr {
+        self.0
+    }
+}
+
+/// The report of the last full window, shared with the HTTP server.
+#[derive(Component, Clone, Debug, Default)]
+pub struct MetricsReport(Arc<RwLock<String>>);
+
+impl MetricsReport {
+    pub fn set(&self, report: String) {
+        *self.0.write().unwrap() = report;
+    }
+
+    #[must_use]
+    pub fn get(&self) -> String {
+        self.0.read().unwrap().clone()
+    }
+}
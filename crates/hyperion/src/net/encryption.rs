@@ -0,0 +1,85 @@
+//! Key material and helpers for online-mode logins. See [`LoginKeys`].
+
+use anyhow::Context;
+use flecs_ecs::macros::Component;
+use num_bigint::BigInt;
+use rsa::{Pkcs1v15Encrypt, RsaPrivateKey, pkcs8::EncodePublicKey, rand_core::OsRng};
+use sha1::{Digest, Sha1};
+
+/// The size of the RSA key the vanilla server uses.
+const KEY_BITS: usize = 1024;
+
+/// The RSA key pair used to exchange the shared secret with online-mode clients.
+///
+/// This is a singleton which is generated on startup whether or not
+/// [`crate::config::Config::online_mode`] is enabled, so that it can be enabled by a reload.
+#[derive(Component)]
+pub struct LoginKeys {
+    private_key: RsaPrivateKey,
+    public_key_der: Box<[u8]>,
+}
+
+impl LoginKeys {
+    /// Generates a new key pair. This takes a noticeable amount of time, so it should only be done
+    /// once on startup.
+    pub fn generate() -> anyhow::Result<Self> {
+        let private_key =
+            RsaPrivateKey::new(&mut OsRng, KEY_BITS).context("failed to generate RSA key")?;
+
+        let public_key_der = private_key
+            .to_public_key()
+            .to_public_key_der()
+            .context("failed to encode RSA public key")?
+            .into_vec()
+            .into_boxed_slice();
+
+        Ok(Self {
+            private_key,
+            public_key_der,
+        })
+    }
+
+    /// The public key in the DER format sent in `LoginHelloS2c`.
+    #[must_use]
+    pub fn public_key_der(&self) -> &[u8] {
+        &self.public_key_der
+    }
+
+    /// Decrypts a value the client encrypted with [`Self::public_key_der`].
+    pub fn decrypt(&self, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+        self.private_key
+            .decrypt(Pkcs1v15Encrypt, data)
+            .context("failed to decrypt with RSA private key")
+    }
+
+    /// The hash the client and the session server use to identify this login. The server id
+    /// that is normally hashed first is always empty.
+    #[must_use]
+    pub fn server_hash(&self, shared_secret: &[u8]) -> String {
+        let hash = Sha1::new()
+            .chain_update(shared_secret)
+            .chain_update(&self.public_key_der)
+            .finalize();
+
+        auth_digest(&hash)
+    }
+}
+
+/// Minecraft's non-standard hex digest: the bytes are read as a signed two's complement integer.
+fn auth_digest(bytes: &[u8]) -> String {
+    BigInt::from_signed_bytes_be(bytes).to_str_radix(16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_auth_digest() {
+        let digest = |name: &str| auth_digest(&Sha1::digest(name));
+
+        assert_eq!(digest("Notch"), "4ed1f46bbe04bc756bcb17c0c7ce3e4632f06a48");
+        assert_eq!(digest("jeb_"), "-7c9d5b0044c130109a5d7b5fb5c317c02b4e28c1");
+        assert_eq!(digest("simon"), "88e16a1019277b15d58faf0541e11910eb756f6");
+    }
+}
@@ -1,12 +1,12 @@
 //! All the networking related code.
 
 use std::{
-    cell::{Cell, RefCell},
+    cell::{Cell, RefCell, RefMut},
     fmt::Debug,
+    net::SocketAddr,
 };
 
 use bumpalo::Bump;
-use byteorder::WriteBytesExt;
 use bytes::{Bytes, BytesMut};
 pub use decoder::PacketDecoder;
 use derive_more::Deref;
@@ -15,21 +15,25 @@ use flecs_ecs::{
     macros::Component,
 };
 use glam::I16Vec2;
+pub use hyperion_proto::Priority;
 use hyperion_proto::{ChunkPosition, ServerToProxyMessage};
 use hyperion_utils::LifetimeTracker;
 use libdeflater::CompressionLvl;
 use rkyv::util::AlignedVec;
+use rustc_hash::FxHashMap;
 use system_order::SystemOrder;
 
 use crate::{
     Global, PacketBundle, Scratch, Scratches,
     net::encoder::{PacketEncoder, append_packet_without_compression},
+    simulation::{EgressComm, blocks::chunk::EncodedColumn},
     storage::ThreadLocal,
 };
 
 pub mod agnostic;
 pub mod decoder;
 pub mod encoder;
+pub mod encryption;
 pub mod packets;
 pub mod proxy;
 
@@ -99,6 +103,26 @@ impl ConnectionId {
     pub const fn inner(self) -> u64 {
         self.stream_id
     }
+
+    /// Returns the id of the proxy the connection goes through.
+    #[must_use]
+    pub fn proxy_id(self) -> u16 {
+        hyperion_proto::proxy_id(self.stream_id)
+    }
+}
+
+/// The address a player connected from. If their proxy is behind a load balancer which speaks the
+/// PROXY protocol, this is the address the load balancer reported.
+///
+/// Players who connected to their proxy over a Unix socket do not have one.
+#[derive(Component, Copy, Clone, Debug, Deref, PartialEq, Eq)]
+pub struct RemoteAddr(SocketAddr);
+
+impl RemoteAddr {
+    #[must_use]
+    pub const fn new(addr: SocketAddr) -> Self {
+        Self(addr)
+    }
 }
 
 /// A singleton that can be used to compose and encode packets.
@@ -117,6 +141,7 @@ pub struct DataBundle<'a, 'b> {
     compose: &'a Compose,
     system: EntityView<'b>,
     data: BytesMut,
+    priority: Priority,
 }
 
 impl<'a, 'b> DataBundle<'a, 'b> {
@@ -125,9 +150,15 @@ impl<'a, 'b> DataBundle<'a, 'b> {
             compose,
             system,
             data: BytesMut::new(),
+            priority: Priority::High,
         }
     }
 
+    /// Sends the bundle with `priority` instead of [`Priority::High`].
+    pub const fn priority(self, priority: Priority) -> Self {
+        Self { priority, ..self }
+    }
+
     pub fn add_packet(&mut self, pkt: impl PacketBundle) -> anyhow::Result<()> {
         let world = self.system.world();
         let data = self
@@ -143,6 +174,12 @@ impl<'a, 'b> DataBundle<'a, 'b> {
         self.data.extend_from_slice(raw);
     }
 
+    /// The packets added so far, for sending them more than once.
+    #[must_use]
+    pub fn into_bytes(self) -> Bytes {
+        self.data.freeze()
+    }
+
     pub fn unicast(&self, stream: ConnectionId) -> anyhow::Result<()> {
         if self.data.is_empty() {
             return Ok(());
@@ -150,19 +187,27 @@ impl<'a, 'b> DataBundle<'a, 'b> {
 
         self.compose
             .io_buf
-            .unicast_raw(&self.data, stream, self.system);
+            .unicast_raw(&self.data, stream, self.priority, self.system);
         Ok(())
     }
 
     // todo: use builder pattern for excluding
-    pub fn broadcast_local(&self, center: I16Vec2) -> anyhow::Result<()> {
+    /// Broadcasts to players near `center` in `world`. See
+    /// [`crate::simulation::dimension::InWorld::proxy_id`].
+    pub fn broadcast_local(&self, center: I16Vec2, world: u64) -> anyhow::Result<()> {
         if self.data.is_empty() {
             return Ok(());
         }
 
-        self.compose
-            .io_buf
-            .broadcast_local_raw(&self.data, center, 0, self.system);
+        self.compose.io_buf.broadcast_local_raw(
+            &self.data,
+            center,
+            world,
+            0,
+            &[],
+            self.priority,
+            self.system,
+        );
         Ok(())
     }
 }
@@ -241,6 +286,9 @@ impl Compose {
                 x: center.x,
                 z: center.y,
             },
+            world: 0,
+            exclude_more: Vec::new(),
+            priority: Priority::High,
             system,
         }
     }
@@ -307,6 +355,12 @@ impl Compose {
         self.compressor.get(world)
     }
 
+    /// Bytes allocated by every thread's bump allocator since they were last cleared.
+    #[must_use]
+    pub fn bump_allocated(&self) -> usize {
+        self.bump.iter().map(Bump::allocated_bytes).sum()
+    }
+
     pub fn clear_bump(&mut self) {
         self.bump_tracker.assert_no_references();
         for bump in &mut self.bump {
@@ -323,6 +377,8 @@ pub struct IoBuf {
     // broadcast_buffer: ThreadLocal<RefCell<BytesMut>>,
     temp_buffer: ThreadLocal<RefCell<BytesMut>>,
     idx: ThreadLocal<Cell<u16>>,
+    /// Messages about a single player, which only go to the proxy the player is connected through.
+    proxy_buffers: ThreadLocal<RefCell<FxHashMap<u16, AlignedVec>>>,
 }
 
 impl IoBuf {
@@ -411,7 +467,10 @@ pub struct BroadcastLocal<'a, 'b, P> {
     packet: P,
     compose: &'a Compose,
     center: ChunkPosition,
+    world: u64,
     exclude: u64,
+    exclude_more: Vec<u64>,
+    priority: Priority,
     system: EntityView<'b>,
 }
 
@@ -428,9 +487,15 @@ impl<P> BroadcastLocal<'_, '_, P> {
             .io_buf
             .encode_packet(self.packet, self.compose, &world)?;
 
-        self.compose
-            .io_buf
-            .broadcast_local_raw(&bytes, self.center, self.exclude, self.system);
+        self.compose.io_buf.broadcast_local_raw(
+            &bytes,
+            self.center,
+            self.world,
+            self.exclude,
+            &self.exclude_more,
+            self.priority,
+            self.system,
+        );
 
         Ok(())
     }
@@ -439,13 +504,25 @@ impl<P> BroadcastLocal<'_, '_, P> {
     pub fn exclude(self, exclude: impl Into<Option<ConnectionId>>) -> Self {
         let exclude = exclude.into();
         let exclude = exclude.map(|id| id.stream_id).unwrap_or_default();
-        BroadcastLocal {
-            packet: self.packet,
-            compose: self.compose,
-            center: self.center,
-            exclude,
-            system: self.system,
-        }
+        BroadcastLocal { exclude, ..self }
+    }
+
+    /// Excludes `players` from the broadcast as well as the player passed to [`Self::exclude`].
+    pub fn exclude_all(mut self, players: impl IntoIterator<Item = ConnectionId>) -> Self {
+        self.exclude_more
+            .extend(players.into_iter().map(|id| id.stream_id));
+        self
+    }
+
+    /// Only broadcast to players in `world` instead of the main world. See
+    /// [`crate::simulation::dimension::InWorld::proxy_id`].
+    pub const fn world(self, world: u64) -> Self {
+        BroadcastLocal { world, ..self }
+    }
+
+    /// Sends the packet with `priority` instead of [`Priority::High`].
+    pub const fn priority(self, priority: Priority) -> Self {
+        BroadcastLocal { priority, ..self }
     }
 }
 
@@ -464,6 +541,28 @@ impl IoBuf {
         })
     }
 
+    /// Like [`Self::reset_and_split`], but for messages which only go to one proxy. Returns the id
+    /// of the proxy with each part.
+    pub fn split_proxy_buffers(&mut self) -> impl Iterator<Item = (u16, Bytes)> + '_ {
+        self.proxy_buffers
+            .iter_mut()
+            .flat_map(|buffers| buffers.get_mut().iter_mut())
+            .filter(|(_, buffer)| !buffer.is_empty())
+            .map(|(&proxy_id, buffer)| {
+                let res = Bytes::copy_from_slice(buffer.as_slice());
+                buffer.clear();
+                (proxy_id, res)
+            })
+    }
+
+    /// The buffer of messages for the proxy `stream` is connected through.
+    fn proxy_buffer(&self, stream: ConnectionId, world: &World) -> RefMut<'_, AlignedVec> {
+        let buffers = self.proxy_buffers.get(world).borrow_mut();
+        RefMut::map(buffers, |buffers| {
+            buffers.entry(stream.proxy_id()).or_default()
+        })
+    }
+
     fn encode_packet<P>(
         &self,
         packet: P,
@@ -521,15 +620,19 @@ impl IoBuf {
             self.encode_packet_no_compression(packet, &world)?
         };
 
-        self.unicast_raw(&bytes, id, system);
+        self.unicast_raw(&bytes, id, Priority::High, system);
         Ok(())
     }
 
+    #[expect(clippy::too_many_arguments, reason = "todo; refactor")]
     fn broadcast_local_raw(
         &self,
         data: &[u8],
         center: impl Into<ChunkPosition>,
+        world_id: u64,
         exclude: u64,
+        exclude_more: &[u64],
+        priority: Priority,
         system: EntityView<'_>,
     ) {
         let center = center.into();
@@ -544,20 +647,16 @@ impl IoBuf {
         let to_send = hyperion_proto::BroadcastLocal {
             data,
             center,
+            world: world_id,
             exclude,
+            exclude_more,
             order,
+            priority,
         };
 
         let to_send = ServerToProxyMessage::BroadcastLocal(to_send);
 
-        let len = buffer.len();
-        buffer.write_u64::<byteorder::BigEndian>(0x00).unwrap();
-
-        rkyv::api::high::to_bytes_in::<_, rkyv::rancor::Error>(&to_send, &mut *buffer).unwrap();
-
-        let new_len = buffer.len();
-        let packet_len = u64::try_from(new_len - len - size_of::<u64>()).unwrap();
-        buffer[len..(len + 8)].copy_from_slice(&packet_len.to_be_bytes());
+        hyperion_proto::encode_frame(&to_send, buffer);
     }
 
     pub(crate) fn broadcast_raw(&self, data: &[u8], exclude: u64, system: EntityView<'_>) {
@@ -580,22 +679,21 @@ impl IoBuf {
 
         let to_send = ServerToProxyMessage::BroadcastGlobal(to_send);
 
-        let len = buffer.len();
-        buffer.write_u64::<byteorder::BigEndian>(0x00).unwrap();
-
-        rkyv::api::high::to_bytes_in::<_, rkyv::rancor::Error>(&to_send, &mut *buffer).unwrap();
-
-        let new_len = buffer.len();
-        let packet_len = u64::try_from(new_len - len - size_of::<u64>()).unwrap();
-        buffer[len..(len + 8)].copy_from_slice(&packet_len.to_be_bytes());
+        hyperion_proto::encode_frame(&to_send, buffer);
     }
 
-    pub(crate) fn unicast_raw(&self, data: &[u8], stream: ConnectionId, system: EntityView<'_>) {
+    pub(crate) fn unicast_raw(
+        &self,
+        data: &[u8],
+        stream: ConnectionId,
+        priority: Priority,
+        system: EntityView<'_>,
+    ) {
         let world = system.world();
         let system_order = SystemOrder::of(system);
 
-        let buffer = self.buffer.get(&world);
-        let buffer = &mut *buffer.borrow_mut();
+        let mut buffer = self.proxy_buffer(stream, &world);
+        let buffer = &mut *buffer;
 
         let order = self.order_id(system_order, &world);
 
@@ -603,37 +701,148 @@ impl IoBuf {
             data,
             stream: stream.stream_id,
             order,
+            priority,
         };
 
         let to_send = ServerToProxyMessage::Unicast(to_send);
 
-        let len = buffer.len();
-        buffer.write_u64::<byteorder::BigEndian>(0x00).unwrap();
+        hyperion_proto::encode_frame(&to_send, buffer);
+    }
+
+    /// Sends `chunk`, the chunk at `position` of the world with the proxy id `world_id`, to
+    /// `stream` from the chunk cache of its proxy, which is sent the chunk first if it does not
+    /// have it yet.
+    pub(crate) fn unicast_chunk(
+        &self,
+        chunk: &EncodedColumn,
+        position: I16Vec2,
+        world_id: u64,
+        stream: ConnectionId,
+        egress: &EgressComm,
+        system: EntityView<'_>,
+    ) {
+        let world = system.world();
+        let system_order = SystemOrder::of(system);
+
+        let order = self.order_id(system_order, &world);
+
+        let mut buffer = self.proxy_buffer(stream, &world);
+        let buffer = &mut *buffer;
 
-        rkyv::api::high::to_bytes_in::<_, rkyv::rancor::Error>(&to_send, &mut *buffer).unwrap();
+        let mut evicted = Vec::new();
 
-        let new_len = buffer.len();
-        let packet_len = u64::try_from(new_len - len - size_of::<u64>()).unwrap();
-        buffer[len..(len + 8)].copy_from_slice(&packet_len.to_be_bytes());
+        if egress.cache_chunk(stream.proxy_id(), chunk.hash, &mut evicted) {
+            for hash in evicted {
+                let to_send =
+                    ServerToProxyMessage::EvictCachedChunk(hyperion_proto::EvictCachedChunk {
+                        hash,
+                    });
+
+                hyperion_proto::encode_frame(&to_send, buffer);
+            }
+
+            let to_send = ServerToProxyMessage::CacheChunk(hyperion_proto::CacheChunk {
+                hash: chunk.hash,
+                data: &chunk.bytes,
+            });
+
+            hyperion_proto::encode_frame(&to_send, buffer);
+        }
+
+        let to_send =
+            ServerToProxyMessage::UnicastCachedChunk(hyperion_proto::UnicastCachedChunk {
+                stream: stream.stream_id,
+                order,
+                hash: chunk.hash,
+                world: world_id,
+                position: position.into(),
+            });
+
+        hyperion_proto::encode_frame(&to_send, buffer);
     }
 
-    pub(crate) fn set_receive_broadcasts(&self, stream: ConnectionId, world: &World) {
+    /// Makes the proxy drop the chunks it delayed for `stream` which the packets sent next make
+    /// outdated: the chunk at `position` of the world `world_id` before it is unloaded, or
+    /// everything delayed before the player leaves `world_id` for `None`. See
+    /// [`hyperion_proto::DiscardDelayed`].
+    pub(crate) fn discard_delayed(
+        &self,
+        stream: ConnectionId,
+        world_id: u64,
+        position: Option<I16Vec2>,
+        world: &World,
+    ) {
+        let mut buffer = self.proxy_buffer(stream, world);
+        let buffer = &mut *buffer;
+
+        let to_send = ServerToProxyMessage::DiscardDelayed(hyperion_proto::DiscardDelayed {
+            stream: stream.stream_id,
+            world: world_id,
+            position: position.map(Into::into),
+        });
+
+        hyperion_proto::encode_frame(&to_send, buffer);
+    }
+
+    /// Removes the chunk `hash` from the chunk cache of every proxy.
+    pub(crate) fn evict_cached_chunk(&self, hash: u64, world: &World) {
         let buffer = self.buffer.get(world);
         let buffer = &mut *buffer.borrow_mut();
 
+        let to_send =
+            ServerToProxyMessage::EvictCachedChunk(hyperion_proto::EvictCachedChunk { hash });
+
+        hyperion_proto::encode_frame(&to_send, buffer);
+    }
+
+    /// Makes the proxy encrypt everything it writes to `stream` from now on.
+    pub(crate) fn set_encryption(&self, stream: ConnectionId, key: [u8; 16], world: &World) {
+        let mut buffer = self.proxy_buffer(stream, world);
+        let buffer = &mut *buffer;
+
+        let to_send = hyperion_proto::SetEncryption {
+            stream: stream.stream_id,
+            key,
+        };
+
+        let to_send = ServerToProxyMessage::SetEncryption(to_send);
+
+        hyperion_proto::encode_frame(&to_send, buffer);
+    }
+
+    pub(crate) fn set_receive_broadcasts(&self, stream: ConnectionId, world: &World) {
+        let mut buffer = self.proxy_buffer(stream, world);
+        let buffer = &mut *buffer;
+
         let to_send = hyperion_proto::SetReceiveBroadcasts {
             stream: stream.stream_id,
         };
 
         let to_send = ServerToProxyMessage::SetReceiveBroadcasts(to_send);
 
-        let len = buffer.len();
-        buffer.write_u64::<byteorder::BigEndian>(0x00).unwrap();
+        hyperion_proto::encode_frame(&to_send, buffer);
+    }
+
+    /// Lets the proxy hold `stream` while it reconnects, so that the player can rejoin as
+    /// `username` afterwards.
+    pub(crate) fn set_rejoin(
+        &self,
+        stream: ConnectionId,
+        uuid: uuid::Uuid,
+        username: &str,
+        world: &World,
+    ) {
+        let mut buffer = self.proxy_buffer(stream, world);
+        let buffer = &mut *buffer;
+
+        let to_send = hyperion_proto::SetRejoin {
+            stream: stream.stream_id,
+            uuid: uuid.into_bytes(),
+            username,
+        };
 
-        rkyv::api::high::to_bytes_in::<_, rkyv::rancor::Error>(&to_send, &mut *buffer).unwrap();
+        let to_send = ServerToProxyMessage::SetRejoin(to_send);
 
-        let new_len = buffer.len();
-        let packet_len = u64::try_from(new_len - len - size_of::<u64>()).unwrap();
-        buffer[len..(len + 8)].copy_from_slice(&packet_len.to_be_bytes());
+        hyperion_proto::encode_frame(&to_send, buffer);
     }
 }
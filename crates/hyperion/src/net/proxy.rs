@@ -1,22 +1,54 @@
 //! Communication to a proxy which forwards packets to the players.
 
-use std::{io::Cursor, net::SocketAddr, process::Command, sync::Arc};
+use std::{net::SocketAddr, process::Command, sync::Arc, time::Duration};
 
-use bytes::{Buf, BytesMut};
+use anyhow::Context;
+use bytes::{Buf, Bytes, BytesMut};
 use dashmap::DashMap;
 use flecs_ecs::macros::Component;
-use hyperion_proto::ArchivedProxyToServerMessage;
+use hyperion_proto::{
+    ArchivedProxyToServerMessage, AssignProxyId, ClientAddr, Handshake, LEN_PREFIX, Pong,
+    ServerToProxyMessage, SetLimbo,
+};
 use parking_lot::Mutex;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use rkyv::util::AlignedVec;
+use rustc_hash::FxHashSet;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
 use tracing::{error, info, warn};
+use valence_protocol::packets::play;
+use valence_text::IntoText;
 
-use crate::{runtime::AsyncRuntime, simulation::EgressComm};
+use crate::{
+    Scratch, Shared, net::encoder::PacketEncoder, runtime::AsyncRuntime, simulation::EgressComm,
+};
+
+/// A player who has just connected through a proxy.
+#[derive(Debug)]
+pub struct NewConnection {
+    pub stream: u64,
+    /// See [`hyperion_proto::PlayerConnect::addr`].
+    pub addr: Option<SocketAddr>,
+}
+
+/// A player who was playing before their proxy reconnected. See [`hyperion_proto::Rejoin`].
+#[derive(Debug)]
+pub struct PlayerRejoin {
+    pub connection: NewConnection,
+    pub uuid: uuid::Uuid,
+    pub username: Arc<str>,
+}
 
 /// This is used
 #[derive(Default)]
 pub struct ReceiveStateInner {
     /// All players who have recently connected to the server.
-    pub player_connect: Mutex<Vec<u64>>,
+    pub player_connect: Mutex<Vec<NewConnection>>,
+    /// Players who were playing before their proxy lost its connection to the server and have
+    /// reconnected through it. They are not in [`Self::player_connect`].
+    pub player_rejoin: Mutex<Vec<PlayerRejoin>>,
     /// All players who have recently disconnected from the server.
     pub player_disconnect: Mutex<Vec<u64>>,
     /// A map of stream ids to the corresponding [`BytesMut`] buffers. This represents data from the client to the server.
@@ -42,10 +74,26 @@ fn get_pid_from_port(port: u16) -> Result<Option<u32>, std::io::Error> {
     Ok(pid)
 }
 
+/// How long a proxy has to send its [`Handshake`] after connecting.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Sends our [`Handshake`] and checks the one of the proxy.
+async fn handshake(socket: &mut TcpStream) -> anyhow::Result<Handshake> {
+    socket.write_all(&Handshake::local().encode()).await?;
+
+    let mut handshake = [0; Handshake::LEN];
+    tokio::time::timeout(HANDSHAKE_TIMEOUT, socket.read_exact(&mut handshake))
+        .await
+        .context("timed out")??;
+
+    Ok(Handshake::decode(handshake)?)
+}
+
 async fn inner(
     socket: SocketAddr,
-    mut server_to_proxy: tokio::sync::mpsc::UnboundedReceiver<bytes::Bytes>,
+    egress: EgressComm,
     shared: Arc<ReceiveStateInner>,
+    limbo: Bytes,
 ) {
     let listener = match tokio::net::TcpListener::bind(socket).await {
         Ok(listener) => listener,
@@ -74,97 +122,292 @@ async fn inner(
         Err(e) => panic!("Failed to bind to address {socket}: {e}"),
     };
 
-    tokio::spawn(
-        async move {
-            loop {
-                let (socket, _) = listener.accept().await.unwrap();
-                socket.set_nodelay(true).unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (socket, addr) = listener.accept().await.unwrap();
+            socket.set_nodelay(true).unwrap();
+
+            tokio::spawn(accept_proxy(
+                socket,
+                addr,
+                egress.clone(),
+                shared.clone(),
+                limbo.clone(),
+            ));
+        }
+    });
+}
+
+/// Exchanges handshakes with a newly connected proxy, assigns it an id and then serves it until
+/// it disconnects.
+async fn accept_proxy(
+    mut socket: TcpStream,
+    addr: SocketAddr,
+    egress: EgressComm,
+    shared: Arc<ReceiveStateInner>,
+    limbo: Bytes,
+) {
+    let handshake = match handshake(&mut socket).await {
+        Ok(handshake) => handshake,
+        Err(e) => {
+            error!("Rejecting proxy connection from {addr}: {e:#}");
+            return;
+        }
+    };
 
-                let addr = socket.peer_addr().unwrap();
+    let (read, mut write) = socket.into_split();
+    let mut reader = ProxyReader::new(read);
+
+    let requested = match tokio::time::timeout(HANDSHAKE_TIMEOUT, read_requested_id(&mut reader))
+        .await
+        .context("timed out")
+    {
+        Ok(Ok(requested)) => requested,
+        Ok(Err(e)) | Err(e) => {
+            error!("Rejecting proxy connection from {addr}: {e:#}");
+            return;
+        }
+    };
 
-                info!("Proxy connection established on {addr}");
+    let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
 
-                let shared = shared.clone();
+    let Some(proxy_id) = egress.register(requested, tx) else {
+        error!("Rejecting proxy connection from {addr}: every proxy id is taken");
+        return;
+    };
 
-                let (read, mut write) = socket.into_split();
+    if requested != 0 && requested != proxy_id {
+        warn!("proxy {addr} asked for its previous id {requested}, which is taken");
+    }
 
-                let proxy_writer_task = tokio::spawn(async move {
-                    while let Some(bytes) = server_to_proxy.recv().await {
-                        if write.write_all(&bytes).await.is_err() {
-                            error!("error writing to proxy");
-                            return server_to_proxy;
-                        }
-                    }
+    info!(
+        "Proxy connection established on {addr} with id {proxy_id} and {:?}",
+        handshake.features
+    );
 
-                    warn!("proxy shut down");
+    // these must arrive before anything sent to the proxy through `egress`
+    let assign = encode_message(&ServerToProxyMessage::AssignProxyId(AssignProxyId {
+        proxy_id,
+    }));
+    let limbo = encode_message(&ServerToProxyMessage::SetLimbo(SetLimbo { data: &limbo }));
+
+    for bytes in [assign, limbo] {
+        if let Err(e) = write.write_all(&bytes).await {
+            error!("error writing to proxy {proxy_id}: {e}");
+            egress.remove(proxy_id);
+            return;
+        }
+    }
 
-                    server_to_proxy
-                });
+    tokio::spawn(write_to_proxy(proxy_id, write, rx));
+    read_from_proxy(proxy_id, reader, shared, egress).await;
+}
+
+/// Reads the [`hyperion_proto::RequestProxyId`] every proxy sends first and returns the id it
+/// asks for.
+async fn read_requested_id(reader: &mut ProxyReader) -> anyhow::Result<u16> {
+    let buffer = reader.next_server_packet_buffer().await?;
+
+    let mut aligned = AlignedVec::<16>::new();
+    aligned.extend_from_slice(&buffer);
+
+    let message = hyperion_proto::access::<ArchivedProxyToServerMessage<'_>>(&aligned)?;
+
+    let ArchivedProxyToServerMessage::RequestProxyId(request) = message else {
+        anyhow::bail!("expected the proxy to request an id first");
+    };
+
+    let Ok(previous) = rkyv::deserialize::<u16, !>(&request.previous);
+
+    Ok(previous)
+}
+
+async fn write_to_proxy(
+    proxy_id: u16,
+    mut write: tokio::net::tcp::OwnedWriteHalf,
+    mut server_to_proxy: tokio::sync::mpsc::UnboundedReceiver<bytes::Bytes>,
+) {
+    while let Some(bytes) = server_to_proxy.recv().await {
+        if write.write_all(&bytes).await.is_err() {
+            error!("error writing to proxy {proxy_id}");
+            return;
+        }
+    }
+}
+
+/// Reads messages from the proxy `proxy_id` until it disconnects, at which point every player
+/// connected through it is disconnected.
+async fn read_from_proxy(
+    proxy_id: u16,
+    mut reader: ProxyReader,
+    shared: Arc<ReceiveStateInner>,
+    egress: EgressComm,
+) {
+    let mut streams = FxHashSet::default();
+
+    // frames are copied here so that they are aligned
+    let mut aligned = AlignedVec::<16>::new();
+
+    loop {
+        let buffer = match reader.next_server_packet_buffer().await {
+            Ok(message) => message,
+            Err(err) => {
+                warn!("proxy {proxy_id} shut down: {err:?}");
+                break;
+            }
+        };
+
+        aligned.clear();
+        aligned.extend_from_slice(&buffer);
+
+        let result = match hyperion_proto::access::<ArchivedProxyToServerMessage<'_>>(&aligned) {
+            Ok(result) => result,
+            Err(err) => {
+                error!("proxy {proxy_id} sent an invalid frame: {err}");
+                break;
+            }
+        };
+
+        let stream = match result {
+            ArchivedProxyToServerMessage::RequestProxyId(_) => {
+                warn!("proxy {proxy_id} requested an id after it was assigned one");
+                continue;
+            }
+            ArchivedProxyToServerMessage::Ping(ping) => {
+                let Ok(nonce) = rkyv::deserialize::<u64, !>(&ping.nonce);
+                let pong = ServerToProxyMessage::Pong(Pong { nonce });
+                egress.send(proxy_id, encode_message(&pong));
+                continue;
+            }
+            ArchivedProxyToServerMessage::PlayerConnect(message) => &message.stream,
+            ArchivedProxyToServerMessage::PlayerDisconnect(message) => &message.stream,
+            ArchivedProxyToServerMessage::PlayerPackets(message) => &message.stream,
+        };
 
-                tokio::spawn(async move {
-                    let mut reader = ProxyReader::new(read);
-
-                    loop {
-                        let buffer = match reader.next_server_packet_buffer().await {
-                            Ok(message) => message,
-                            Err(err) => {
-                                error!("failed to process packet {err:?}");
-                                return;
-                            }
-                        };
-
-                        let result = unsafe {
-                            rkyv::access_unchecked::<ArchivedProxyToServerMessage<'_>>(&buffer)
-                        };
-
-                        match result {
-                            ArchivedProxyToServerMessage::PlayerConnect(message) => {
-                                let Ok(stream) = rkyv::deserialize::<u64, !>(&message.stream);
-
-                                shared.player_connect.lock().push(stream);
-                            }
-                            ArchivedProxyToServerMessage::PlayerDisconnect(message) => {
-                                let Ok(stream) = rkyv::deserialize::<u64, !>(&message.stream);
-                                shared.player_disconnect.lock().push(stream);
-                            }
-                            ArchivedProxyToServerMessage::PlayerPackets(message) => {
-                                let Ok(stream) = rkyv::deserialize::<u64, !>(&message.stream);
-
-                                shared
-                                    .packets
-                                    .entry(stream)
-                                    .or_default()
-                                    .extend_from_slice(&message.data);
-                            }
-                        }
-                    }
+        let Ok(stream) = rkyv::deserialize::<u64, !>(stream);
+
+        if hyperion_proto::proxy_id(stream) != proxy_id {
+            warn!("proxy {proxy_id} sent data for stream {stream:#x} of another proxy");
+            continue;
+        }
+
+        match result {
+            ArchivedProxyToServerMessage::RequestProxyId(_)
+            | ArchivedProxyToServerMessage::Ping(_) => unreachable!(),
+            ArchivedProxyToServerMessage::PlayerConnect(message) => {
+                streams.insert(stream);
+
+                let addr = message.addr.as_ref().map(|addr| {
+                    let Ok(addr) = rkyv::deserialize::<ClientAddr, !>(addr);
+                    SocketAddr::from(addr)
                 });
 
-                // todo: handle player disconnects on proxy shut down
-                // Ideally, we should design for there being multiple proxies,
-                // and all proxies should store all the players on them.
-                // Then we can disconnect all those players related to that proxy.
-                server_to_proxy = proxy_writer_task.await.unwrap();
+                let connection = NewConnection { stream, addr };
+
+                if let Some(rejoin) = message.rejoin.as_ref() {
+                    shared.player_rejoin.lock().push(PlayerRejoin {
+                        connection,
+                        uuid: uuid::Uuid::from_bytes(rejoin.uuid),
+                        username: Arc::from(&*rejoin.username),
+                    });
+                } else {
+                    shared.player_connect.lock().push(connection);
+                }
             }
-        }, // .instrument(info_span!("proxy reader")),
-    );
+            ArchivedProxyToServerMessage::PlayerDisconnect(_) => {
+                streams.remove(&stream);
+                shared.player_disconnect.lock().push(stream);
+            }
+            ArchivedProxyToServerMessage::PlayerPackets(message) => {
+                shared
+                    .packets
+                    .entry(stream)
+                    .or_default()
+                    .extend_from_slice(&message.data);
+            }
+        }
+    }
+
+    // this also stops the writer
+    egress.remove(proxy_id);
+
+    if !streams.is_empty() {
+        info!(
+            "disconnecting {} players of proxy {proxy_id}",
+            streams.len()
+        );
+    }
+
+    shared.player_disconnect.lock().extend(streams);
+}
+
+/// Frames `message` on its own. See [`hyperion_proto::encode_frame`].
+#[must_use]
+pub fn encode_message(message: &ServerToProxyMessage<'_>) -> Bytes {
+    let mut v: AlignedVec = AlignedVec::new();
+    hyperion_proto::encode_frame(message, &mut v);
+
+    Bytes::from(v.into_boxed_slice())
 }
 
 /// A wrapper around [`ReceiveStateInner`]
 #[derive(Component)]
 pub struct ReceiveState(pub Arc<ReceiveStateInner>);
 
-/// Initializes proxy communications.
+/// The id of the keep alive sent to players in limbo. Their proxy drops the response.
+const LIMBO_KEEP_ALIVE_ID: u64 = 0;
+
+/// Encodes what proxies send to the players they hold while they reconnect. See
+/// [`hyperion_proto::SetLimbo`].
+pub fn limbo_packets(shared: &Shared) -> anyhow::Result<Bytes> {
+    let encoder = PacketEncoder::new(shared.compression_threshold);
+
+    let mut buf = Vec::new();
+    let mut scratch = Scratch::default();
+    let mut compressor = libdeflater::Compressor::new(shared.compression_level);
+
+    // in ticks; the title is sent again before it fades out
+    let fade = play::TitleFadeS2c {
+        fade_in: 0,
+        stay: 200,
+        fade_out: 20,
+    };
+    encoder.append_packet(&fade, &mut buf, &mut scratch, &mut compressor)?;
+
+    let title = play::TitleS2c {
+        title_text: "§eReconnecting…".into_cow_text(),
+    };
+    encoder.append_packet(&title, &mut buf, &mut scratch, &mut compressor)?;
+
+    let subtitle = play::SubtitleS2c {
+        subtitle_text: "§7The server is restarting".into_cow_text(),
+    };
+    encoder.append_packet(&subtitle, &mut buf, &mut scratch, &mut compressor)?;
+
+    let keep_alive = play::KeepAliveS2c {
+        id: LIMBO_KEEP_ALIVE_ID,
+    };
+    encoder.append_packet(&keep_alive, &mut buf, &mut scratch, &mut compressor)?;
+
+    Ok(Bytes::from(buf))
+}
+
+/// Initializes proxy communications. Any number of proxies can connect to `socket`. `limbo` is
+/// sent to every proxy; see [`limbo_packets`].
 #[must_use]
-pub fn init_proxy_comms(tasks: &AsyncRuntime, socket: SocketAddr) -> (ReceiveState, EgressComm) {
-    let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+pub fn init_proxy_comms(
+    tasks: &AsyncRuntime,
+    socket: SocketAddr,
+    limbo: Bytes,
+) -> (ReceiveState, EgressComm) {
+    let egress = EgressComm::default();
     let shared = Arc::new(ReceiveStateInner::default());
 
     tasks.block_on(async {
-        inner(socket, rx, shared.clone()).await;
+        inner(socket, egress.clone(), shared.clone(), limbo).await;
     });
 
-    (ReceiveState(shared), EgressComm::from(tx))
+    (ReceiveState(shared), egress)
 }
 
 #[derive(Debug)]
@@ -181,33 +424,148 @@ impl ProxyReader {
         }
     }
 
+    /// Reads the next frame and returns the message in it.
     // #[instrument]
     pub async fn next_server_packet_buffer(&mut self) -> anyhow::Result<BytesMut> {
-        let len = loop {
-            if !self.buffer.is_empty() {
-                let mut cursor = Cursor::new(&self.buffer);
-
-                // todo: handle invalid varint
-                if let Ok(len) =
-                    byteorder::ReadBytesExt::read_u64::<byteorder::BigEndian>(&mut cursor)
-                {
-                    self.buffer.advance(usize::try_from(cursor.position())?);
-                    break usize::try_from(len)?;
-                }
-            }
+        while self.buffer.len() < LEN_PREFIX {
+            self.read_more().await?;
+        }
 
-            self.server_read.read_buf(&mut self.buffer).await?;
-        };
+        let mut prefix = [0; LEN_PREFIX];
+        self.buffer.copy_to_slice(&mut prefix);
 
-        // todo: this needed?
-        self.buffer.reserve(len);
+        let len = hyperion_proto::decode_len(prefix)?;
+        let padded_len = len + hyperion_proto::padding(len);
 
-        while self.buffer.len() < len {
-            self.server_read.read_buf(&mut self.buffer).await?;
+        self.buffer.reserve(padded_len);
+
+        while self.buffer.len() < padded_len {
+            self.read_more().await?;
         }
 
         let buffer = self.buffer.split_to(len);
+        self.buffer.advance(padded_len - len);
 
         Ok(buffer)
     }
+
+    async fn read_more(&mut self) -> anyhow::Result<()> {
+        if self.server_read.read_buf(&mut self.buffer).await? == 0 {
+            anyhow::bail!("the proxy closed the connection");
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use hyperion_proto::{
+        PlayerConnect, PlayerDisconnect, PlayerDisconnectReason, ProxyToServerMessage, Rejoin,
+        stream_id,
+    };
+    use tokio::{
+        io::AsyncWriteExt,
+        net::{TcpListener, TcpStream},
+    };
+
+    use super::{ProxyReader, ReceiveStateInner, read_from_proxy};
+    use crate::simulation::EgressComm;
+
+    /// Frames `message` the way the proxy does.
+    fn frame(message: &ProxyToServerMessage<'_>) -> Vec<u8> {
+        let message = rkyv::to_bytes::<rkyv::rancor::Error>(message).unwrap();
+
+        let mut frame = hyperion_proto::encode_len(message.len()).to_vec();
+        frame.extend_from_slice(&message);
+        frame.resize(frame.len() + hyperion_proto::padding(message.len()), 0);
+        frame
+    }
+
+    /// Returns the proxy's end of a connection and the server's reader of it.
+    async fn connect() -> (TcpStream, ProxyReader) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        let (read, _write) = server.into_split();
+
+        (proxy, ProxyReader::new(read))
+    }
+
+    #[tokio::test]
+    async fn test_rejoin_and_proxy_drop() {
+        let (mut proxy, reader) = connect().await;
+        let shared = Arc::new(ReceiveStateInner::default());
+        let egress = EgressComm::default();
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        let proxy_id = egress.register(0, tx).unwrap();
+
+        let rejoining = stream_id(proxy_id, 1);
+        let connecting = stream_id(proxy_id, 2);
+        let left = stream_id(proxy_id, 3);
+        let foreign = stream_id(proxy_id + 1, 1);
+
+        let messages = [
+            ProxyToServerMessage::PlayerConnect(PlayerConnect {
+                stream: rejoining,
+                rejoin: Some(Rejoin {
+                    uuid: [1; 16],
+                    username: "player",
+                }),
+                addr: None,
+            }),
+            ProxyToServerMessage::PlayerConnect(PlayerConnect {
+                stream: connecting,
+                rejoin: None,
+                addr: None,
+            }),
+            ProxyToServerMessage::PlayerConnect(PlayerConnect {
+                stream: left,
+                rejoin: None,
+                addr: None,
+            }),
+            ProxyToServerMessage::PlayerDisconnect(PlayerDisconnect {
+                stream: left,
+                reason: PlayerDisconnectReason::LostConnection,
+            }),
+            // proxies may only connect their own streams
+            ProxyToServerMessage::PlayerConnect(PlayerConnect {
+                stream: foreign,
+                rejoin: None,
+                addr: None,
+            }),
+        ];
+
+        for message in &messages {
+            proxy.write_all(&frame(message)).await.unwrap();
+        }
+
+        drop(proxy);
+        read_from_proxy(proxy_id, reader, shared.clone(), egress.clone()).await;
+
+        let rejoins = std::mem::take(&mut *shared.player_rejoin.lock());
+        assert_eq!(rejoins.len(), 1);
+        assert_eq!(rejoins[0].connection.stream, rejoining);
+        assert_eq!(rejoins[0].uuid, uuid::Uuid::from_bytes([1; 16]));
+        assert_eq!(&*rejoins[0].username, "player");
+
+        let connects: Vec<_> = shared
+            .player_connect
+            .lock()
+            .iter()
+            .map(|connect| connect.stream)
+            .collect();
+        assert_eq!(connects, [connecting, left]);
+
+        // every player still connected through the proxy is disconnected once when it drops
+        let mut disconnects = shared.player_disconnect.lock().clone();
+        disconnects.sort_unstable();
+        assert_eq!(disconnects, [rejoining, connecting, left]);
+
+        assert!(!egress.is_connected(proxy_id));
+    }
 }
@@ -0,0 +1,316 @@
+//! Typed access to common block entities, on top of [`Blocks::get_block_entity`] and
+//! [`Blocks::update_block_entity`].
+
+use glam::IVec3;
+use valence_generated::item::ItemKind;
+use valence_nbt::{Compound, List, Value, compound};
+use valence_server::ItemStack;
+use valence_text::Text;
+
+use super::{Blocks, TrySetBlockDeltaError};
+
+/// One side of a sign.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SignSide {
+    Front,
+    Back,
+}
+
+impl SignSide {
+    const fn key(self) -> &'static str {
+        match self {
+            Self::Front => "front_text",
+            Self::Back => "back_text",
+        }
+    }
+}
+
+/// The text on one side of a sign.
+#[derive(Clone, Debug)]
+pub struct SignText {
+    pub messages: [Text; 4],
+    /// The name of the dye the text is colored with, e.g. `black`.
+    pub color: String,
+    pub glowing: bool,
+}
+
+impl Default for SignText {
+    fn default() -> Self {
+        Self {
+            messages: Default::default(),
+            color: "black".to_owned(),
+            glowing: false,
+        }
+    }
+}
+
+impl SignText {
+    fn from_nbt(nbt: &Compound) -> Self {
+        let mut text = Self::default();
+
+        if let Some(Value::List(List::String(messages))) = nbt.get("messages") {
+            for (line, message) in text.messages.iter_mut().zip(messages) {
+                // messages are JSON text components, but older worlds may contain plain strings
+                *line = serde_json::from_str(message).unwrap_or_else(|_| message.clone().into());
+            }
+        }
+
+        if let Some(Value::String(color)) = nbt.get("color") {
+            text.color.clone_from(color);
+        }
+
+        if let Some(Value::Byte(glowing)) = nbt.get("has_glowing_text") {
+            text.glowing = *glowing != 0;
+        }
+
+        text
+    }
+
+    fn to_nbt(&self) -> Compound {
+        let messages = self
+            .messages
+            .iter()
+            .map(|message| serde_json::to_string(message).unwrap())
+            .collect();
+
+        compound! {
+            "messages" => List::String(messages),
+            "color" => self.color.clone(),
+            "has_glowing_text" => i8::from(self.glowing),
+        }
+    }
+}
+
+/// An item in a container such as a chest or a barrel.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ContainerItem {
+    /// The slot in the container, starting at `0` in the top left.
+    pub slot: i8,
+    pub stack: ItemStack,
+}
+
+impl ContainerItem {
+    fn from_nbt(nbt: &Compound) -> Option<Self> {
+        let Some(Value::Byte(slot)) = nbt.get("Slot") else {
+            return None;
+        };
+
+        let Some(Value::String(id)) = nbt.get("id") else {
+            return None;
+        };
+
+        let item = ItemKind::from_str(id.strip_prefix("minecraft:").unwrap_or(id))?;
+
+        let count = match nbt.get("Count") {
+            Some(Value::Byte(count)) => *count,
+            _ => 1,
+        };
+
+        let tag = match nbt.get("tag") {
+            Some(Value::Compound(tag)) => Some(tag.clone()),
+            _ => None,
+        };
+
+        Some(Self {
+            slot: *slot,
+            stack: ItemStack::new(item, count, tag),
+        })
+    }
+
+    fn to_nbt(&self) -> Compound {
+        let mut nbt = compound! {
+            "Slot" => self.slot,
+            "id" => format!("minecraft:{}", self.stack.item.to_str()),
+            "Count" => self.stack.count,
+        };
+
+        if let Some(tag) = &self.stack.nbt {
+            nbt.insert("tag", tag.clone());
+        }
+
+        nbt
+    }
+}
+
+impl Blocks {
+    /// The text on `side` of the sign at `position`, or `None` if there is no loaded sign.
+    #[must_use]
+    pub fn sign_text(&self, position: IVec3, side: SignSide) -> Option<SignText> {
+        let block_entity = self.get_block_entity(position)?;
+
+        match block_entity.get(side.key()) {
+            Some(Value::Compound(text)) => Some(SignText::from_nbt(text)),
+            _ => Some(SignText::default()),
+        }
+    }
+
+    /// Sets the text on `side` of the sign at `position`.
+    pub fn set_sign_text(
+        &mut self,
+        position: IVec3,
+        side: SignSide,
+        text: &SignText,
+    ) -> Result<(), TrySetBlockDeltaError> {
+        self.update_block_entity(position, |block_entity| {
+            block_entity.insert(side.key(), text.to_nbt());
+        })
+    }
+
+    /// The items in the container at `position`, or `None` if there is no loaded container.
+    /// Empty slots are left out.
+    #[must_use]
+    pub fn container_items(&self, position: IVec3) -> Option<Vec<ContainerItem>> {
+        let block_entity = self.get_block_entity(position)?;
+
+        let Some(Value::List(List::Compound(items))) = block_entity.get("Items") else {
+            return Some(Vec::new());
+        };
+
+        Some(items.iter().filter_map(ContainerItem::from_nbt).collect())
+    }
+
+    /// Replaces the contents of the container at `position`.
+    ///
+    /// Players who have the container open are not updated; this only changes what is shown the
+    /// next time it is opened.
+    pub fn set_container_items(
+        &mut self,
+        position: IVec3,
+        items: &[ContainerItem],
+    ) -> Result<(), TrySetBlockDeltaError> {
+        let items = items
+            .iter()
+            .filter(|item| !item.stack.is_empty())
+            .map(ContainerItem::to_nbt)
+            .collect();
+
+        self.update_block_entity(position, |block_entity| {
+            block_entity.insert("Items", List::Compound(items));
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use glam::I16Vec2;
+    use valence_generated::block::{BlockState, PropName, PropValue};
+    use valence_text::IntoText;
+
+    use super::*;
+    use crate::{
+        CHUNK_HEIGHT_SPAN,
+        simulation::blocks::{
+            ChunkLoaderHandle, ColumnData, chunk::Column, loader::parse::section::Section,
+        },
+    };
+
+    /// Blocks with a single loaded chunk at the origin.
+    fn blocks() -> Blocks {
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut blocks = Blocks::from(ChunkLoaderHandle::new(tx, std::sync::Arc::default()));
+
+        let data = ColumnData::new_with(CHUNK_HEIGHT_SPAN, Section::empty_sky);
+        let column = Column::new(Bytes::new(), data, glam::IVec2::ZERO);
+        blocks.cache_mut().insert(I16Vec2::ZERO, column);
+
+        blocks
+    }
+
+    /// Blocks with an oak sign at `position` which has text on its front.
+    fn signed(position: IVec3) -> Blocks {
+        let mut blocks = blocks();
+        blocks.set_block(position, BlockState::OAK_SIGN).unwrap();
+
+        let text = SignText {
+            messages: [
+                "Hello".into_text(),
+                Text::default(),
+                Text::default(),
+                Text::default(),
+            ],
+            ..SignText::default()
+        };
+        blocks
+            .set_sign_text(position, SignSide::Front, &text)
+            .unwrap();
+
+        blocks
+    }
+
+    #[test]
+    fn test_sign_text_round_trip() {
+        let text = SignText {
+            messages: [
+                "Welcome".into_text(),
+                "to".into_text(),
+                "Hyperion".into_text(),
+                Text::default(),
+            ],
+            color: "red".to_owned(),
+            glowing: true,
+        };
+
+        let parsed = SignText::from_nbt(&text.to_nbt());
+
+        assert_eq!(parsed.messages, text.messages);
+        assert_eq!(parsed.color, "red");
+        assert!(parsed.glowing);
+    }
+
+    #[test]
+    fn test_sign_text_plain_messages() {
+        let nbt = compound! {
+            "messages" => List::String(vec!["plain".to_owned()]),
+        };
+
+        let parsed = SignText::from_nbt(&nbt);
+
+        assert_eq!(parsed.messages[0], "plain".into_text());
+        assert_eq!(parsed.color, "black");
+    }
+
+    #[test]
+    fn test_container_item_round_trip() {
+        let item = ContainerItem {
+            slot: 13,
+            stack: ItemStack::new(ItemKind::Diamond, 5, None),
+        };
+
+        assert_eq!(ContainerItem::from_nbt(&item.to_nbt()), Some(item));
+    }
+
+    #[test]
+    fn test_replaced_block_loses_block_entity() {
+        let position = IVec3::new(3, 10, 5);
+        let mut blocks = signed(position);
+
+        // a different sign has the same kind of block entity, but is a new block
+        blocks.set_block(position, BlockState::BIRCH_SIGN).unwrap();
+
+        assert!(blocks.get_block_entity(position).is_none());
+    }
+
+    #[test]
+    fn test_changed_state_keeps_block_entity() {
+        let position = IVec3::new(3, 10, 5);
+        let mut blocks = signed(position);
+
+        let waterlogged = BlockState::OAK_SIGN.set(PropName::Waterlogged, PropValue::True);
+        blocks.set_block(position, waterlogged).unwrap();
+
+        let text = blocks.sign_text(position, SignSide::Front).unwrap();
+        assert_eq!(text.messages[0], "Hello".into_text());
+    }
+
+    #[test]
+    fn test_pasted_block_loses_block_entity() {
+        let position = IVec3::new(3, 10, 5);
+        let mut blocks = signed(position);
+
+        let frame = ndarray::Array3::from_elem((2, 2, 2), BlockState::STONE);
+        blocks.paste(position, frame.view());
+
+        assert!(blocks.get_block_entity(position).is_none());
+    }
+}
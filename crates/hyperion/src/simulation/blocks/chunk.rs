@@ -1,4 +1,9 @@
-use std::fmt::Debug;
+use std::{
+    collections::BTreeSet,
+    fmt::Debug,
+    hash::{DefaultHasher, Hasher},
+    sync::OnceLock,
+};
 
 use bytes::Bytes;
 use glam::{IVec2, IVec3};
@@ -67,6 +72,51 @@ pub struct Column {
     pub data: ColumnData,
 
     pub position: IVec2,
+
+    /// Block entities changed since the chunk was loaded, which players are sent after
+    /// [`Self::base_packet_bytes`]. These are keys of [`ColumnData::block_entities`].
+    pub block_entities_changed: BTreeSet<u32>,
+
+    /// Block entities changed since the last tick, which are broadcast to players that already
+    /// have the chunk.
+    pub block_entities_changed_since_last_tick: BTreeSet<u32>,
+
+    /// Sections (as a bitmask of their indices) whose light changed since the chunk was loaded.
+    pub light_changed: u32,
+
+    /// Sections whose light changed since the last tick.
+    pub light_changed_since_last_tick: u32,
+
+    /// Everything a player is sent when the chunk comes into view, encoded by the first send
+    /// after each change. See [`Self::encoded_or_init`].
+    encoded: OnceLock<EncodedColumn>,
+}
+
+/// The packets of a [`Column`] as they are sent to a player, which proxies cache by their hash.
+#[derive(Debug)]
+pub struct EncodedColumn {
+    pub hash: u64,
+    pub bytes: Bytes,
+}
+
+impl EncodedColumn {
+    #[must_use]
+    pub fn new(bytes: Bytes) -> Self {
+        let mut hasher = DefaultHasher::new();
+        hasher.write(&bytes);
+
+        Self {
+            hash: hasher.finish(),
+            bytes,
+        }
+    }
+}
+
+/// The key of the block at `x`, `y`, `z` (relative to the bottom of the column) in
+/// [`ColumnData::block_entities`].
+#[must_use]
+pub const fn block_entity_idx(x: u32, y: u32, z: u32) -> u32 {
+    x + z * 16 + y * 16 * 16
 }
 
 fn y_index(y: i16) -> u16 {
@@ -79,9 +129,48 @@ impl Column {
             base_packet_bytes,
             data,
             position,
+            block_entities_changed: BTreeSet::new(),
+            block_entities_changed_since_last_tick: BTreeSet::new(),
+            light_changed: 0,
+            light_changed_since_last_tick: 0,
+            encoded: OnceLock::new(),
         }
     }
 
+    /// The packets of the chunk, which are encoded with `encode` if the chunk changed since they
+    /// were last encoded.
+    pub fn encoded_or_init(
+        &self,
+        encode: impl FnOnce(&Self) -> anyhow::Result<Bytes>,
+    ) -> anyhow::Result<&EncodedColumn> {
+        if let Some(encoded) = self.encoded.get() {
+            return Ok(encoded);
+        }
+
+        let encoded = EncodedColumn::new(encode(self)?);
+
+        // another thread may have encoded the same packets in the meantime
+        Ok(self.encoded.get_or_init(|| encoded))
+    }
+
+    /// Forgets the encoded packets because the chunk changed. Returns them if there were any.
+    pub fn take_encoded(&mut self) -> Option<EncodedColumn> {
+        self.encoded.take()
+    }
+
+    /// Queues the light of the section at `section_idx` to be sent to players.
+    pub const fn mark_light_changed(&mut self, section_idx: usize) {
+        let bit = 1 << section_idx;
+        self.light_changed |= bit;
+        self.light_changed_since_last_tick |= bit;
+    }
+
+    /// Queues the block entity at `idx` (see [`block_entity_idx`]) to be sent to players.
+    pub fn mark_block_entity_changed(&mut self, idx: u32) {
+        self.block_entities_changed.insert(idx);
+        self.block_entities_changed_since_last_tick.insert(idx);
+    }
+
     pub fn sections(&self) -> impl Iterator<Item = (IVec3, &Section)> + '_ {
         let column_start_position = IVec3::new(
             self.position.x << 4,
@@ -1,10 +1,15 @@
-use std::io::Write;
+use std::{borrow::Cow, io::Write, sync::Arc};
 
 use glam::IVec2;
+use valence_nbt::Compound;
 use valence_protocol::{
-    ChunkSectionPos, Encode, Packet, VarInt,
-    packets::play::{ChunkDeltaUpdateS2c, chunk_delta_update_s2c::ChunkDeltaUpdateEntry},
+    BlockPos, ChunkSectionPos, Encode, FixedArray, Packet, VarInt,
+    packets::play::{
+        BlockEntityUpdateS2c, ChunkDeltaUpdateS2c, LightUpdateS2c,
+        chunk_delta_update_s2c::ChunkDeltaUpdateEntry,
+    },
 };
+use valence_server::layer::chunk::Chunk;
 
 use crate::{
     PacketBundle,
@@ -111,7 +116,7 @@ impl Column {
 
                 DeltaDrainPacket {
                     position: ChunkSectionPos::new(x, y, z),
-                    section,
+                    section: Arc::make_mut(section),
                 }
             })
     }
@@ -134,4 +139,100 @@ impl Column {
                 }
             })
     }
+
+    /// Updates for the block entities changed since the last tick.
+    pub fn block_entity_drain_packets(
+        &mut self,
+    ) -> impl Iterator<Item = BlockEntityUpdateS2c<'_>> + '_ {
+        let changed = std::mem::take(&mut self.block_entities_changed_since_last_tick);
+        let this = &*self;
+
+        changed
+            .into_iter()
+            .filter_map(move |idx| this.block_entity_packet(idx))
+    }
+
+    /// Updates for every block entity changed since the chunk was loaded.
+    pub fn original_block_entity_packets(
+        &self,
+    ) -> impl Iterator<Item = BlockEntityUpdateS2c<'_>> + '_ {
+        self.block_entities_changed
+            .iter()
+            .filter_map(|&idx| self.block_entity_packet(idx))
+    }
+
+    /// `None` if the block can no longer have a block entity, in which case the client already
+    /// dropped it when the block changed.
+    fn block_entity_packet(&self, idx: u32) -> Option<BlockEntityUpdateS2c<'_>> {
+        let x = idx % 16;
+        let z = idx / 16 % 16;
+        let y = idx / (16 * 16);
+
+        let kind = self.data.block_state(x, y, z).block_entity_kind()?;
+
+        // an empty compound resets the block entity on the client
+        let data = self
+            .data
+            .block_entities
+            .get(&idx)
+            .map_or_else(|| Cow::Owned(Compound::new()), Cow::Borrowed);
+
+        let position = BlockPos::new(
+            (self.position.x << 4) + i32::try_from(x).unwrap(),
+            i32::try_from(y).unwrap() + i32::from(START_Y),
+            (self.position.y << 4) + i32::try_from(z).unwrap(),
+        );
+
+        Some(BlockEntityUpdateS2c {
+            position,
+            kind,
+            data,
+        })
+    }
+
+    /// The light of the sections relit since the last tick, if there are any.
+    pub fn light_drain_packet(&mut self) -> Option<LightUpdateS2c<'static>> {
+        let sections = std::mem::take(&mut self.light_changed_since_last_tick);
+        (sections != 0).then(|| self.light_packet(sections))
+    }
+
+    /// The light of every section relit since the chunk was loaded, if there are any.
+    #[must_use]
+    pub fn original_light_packet(&self) -> Option<LightUpdateS2c<'static>> {
+        (self.light_changed != 0).then(|| self.light_packet(self.light_changed))
+    }
+
+    fn light_packet(&self, sections: u32) -> LightUpdateS2c<'static> {
+        let mut sky_light_mask = 0_u64;
+        let mut block_light_mask = 0_u64;
+        let mut sky_light_arrays = Vec::new();
+        let mut block_light_arrays = Vec::new();
+
+        for (i, section) in self.data.sections.iter().enumerate() {
+            if sections & (1 << i) == 0 {
+                continue;
+            }
+
+            // bit 0 is the section below the world
+            let bit = 1 << (i + 1);
+
+            // the same defaults as when the chunk is encoded
+            sky_light_mask |= bit;
+            sky_light_arrays.push(FixedArray(section.sky_light.unwrap_or([0xff; 2048])));
+
+            block_light_mask |= bit;
+            block_light_arrays.push(FixedArray(section.block_light.unwrap_or([0; 2048])));
+        }
+
+        LightUpdateS2c {
+            chunk_x: VarInt(self.position.x),
+            chunk_z: VarInt(self.position.y),
+            sky_light_mask: Cow::Owned(vec![sky_light_mask]),
+            block_light_mask: Cow::Owned(vec![block_light_mask]),
+            empty_sky_light_mask: Cow::Borrowed(&[]),
+            empty_block_light_mask: Cow::Borrowed(&[]),
+            sky_light_arrays: Cow::Owned(sky_light_arrays),
+            block_light_arrays: Cow::Owned(block_light_arrays),
+        }
+    }
 }
@@ -1,11 +1,16 @@
 #![allow(clippy::cast_sign_loss, clippy::cast_possible_truncation)]
+use std::sync::Arc;
+
 use glam::{I16Vec2, IVec3};
 use ndarray::ArrayView3;
 use valence_generated::block::BlockState;
 
 use crate::simulation::{
     Position,
-    blocks::{Blocks, chunk::START_Y},
+    blocks::{
+        Blocks,
+        chunk::{START_Y, block_entity_idx},
+    },
 };
 
 impl Blocks {
@@ -18,6 +23,7 @@ impl Blocks {
         };
 
         self.should_update.insert(index as u32);
+        self.should_save.insert(index as u32);
     }
 
     pub fn paste(&mut self, offset: IVec3, frame: ArrayView3<'_, BlockState>) {
@@ -48,13 +54,14 @@ impl Blocks {
                 };
 
                 self.should_update.insert(idx as u32);
+                self.should_save.insert(idx as u32);
 
                 let chunk = &mut loaded_chunk.data;
 
                 for section_y in start_chunk.y..=end_chunk.y {
                     let section_idx = (section_y - (START_Y / 16)) as usize;
 
-                    let section = &mut chunk.sections[section_idx];
+                    let section = Arc::make_mut(&mut chunk.sections[section_idx]);
 
                     // idx is yzx
                     // todo: section.set_delta(idx, block)
@@ -84,7 +91,21 @@ impl Blocks {
 
                                 let idx = ((y & 15) << 8) | ((z & 15) << 4) | (x & 15);
                                 let idx = idx as u16;
-                                section.set_delta(idx, block);
+
+                                let old = section.set_delta(idx, block);
+
+                                if old == block {
+                                    continue;
+                                }
+
+                                // see `Blocks::set_block`
+                                if old.to_kind() != block.to_kind() {
+                                    let y = (y - i32::from(START_Y)) as u32;
+                                    let idx = block_entity_idx(x as u32 & 15, y, z as u32 & 15);
+                                    chunk.block_entities.remove(&idx);
+                                }
+
+                                self.light_queue.push(IVec3::new(x, y, z));
                             }
                         }
                     }
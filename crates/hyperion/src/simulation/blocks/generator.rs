@@ -0,0 +1,300 @@
+//! Procedural generation of chunks which are not present on disk. See [`WorldGenerator`].
+
+use glam::{IVec2, IVec3};
+use valence_generated::block::BlockState;
+use valence_server::layer::chunk::Chunk;
+
+use crate::{
+    CHUNK_HEIGHT_SPAN,
+    simulation::blocks::{ColumnData, chunk::START_Y},
+};
+
+/// Generates the contents of chunks which are not present on disk.
+///
+/// The chunk loader calls this from its worker tasks, so implementations must be deterministic for
+/// a given position and cheap enough to run for every chunk a player walks into.
+pub trait WorldGenerator: Send + Sync + 'static {
+    /// Fills `chunk` for the column at `position` (in chunk coordinates). `chunk` starts out as
+    /// air with full sky light.
+    fn generate(&self, position: IVec2, chunk: &mut ColumnData);
+}
+
+/// Generates nothing; every chunk is air.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct VoidGenerator;
+
+impl WorldGenerator for VoidGenerator {
+    fn generate(&self, _position: IVec2, _chunk: &mut ColumnData) {}
+}
+
+/// A superflat world made of horizontal layers stacked upwards from the bottom of the world.
+#[derive(Debug, Clone)]
+pub struct FlatGenerator {
+    /// Layers from the bottom up as `(block, thickness)`.
+    pub layers: Vec<(BlockState, u32)>,
+}
+
+impl Default for FlatGenerator {
+    /// The classic superflat preset: bedrock, two layers of dirt and grass on top.
+    fn default() -> Self {
+        Self {
+            layers: vec![
+                (BlockState::BEDROCK, 1),
+                (BlockState::DIRT, 2),
+                (BlockState::GRASS_BLOCK, 1),
+            ],
+        }
+    }
+}
+
+impl WorldGenerator for FlatGenerator {
+    fn generate(&self, _position: IVec2, chunk: &mut ColumnData) {
+        let mut y = 0;
+
+        for &(block, thickness) in &self.layers {
+            for _ in 0..thickness {
+                if y >= CHUNK_HEIGHT_SPAN {
+                    return;
+                }
+
+                fill_layer(chunk, y, block);
+                y += 1;
+            }
+        }
+    }
+}
+
+/// An empty world with a single square platform, useful for arenas and lobbies.
+#[derive(Debug, Clone, Copy)]
+pub struct VoidPlatformGenerator {
+    /// The block in the center of the platform.
+    pub center: IVec3,
+    /// Half the side length of the platform in blocks.
+    pub radius: i32,
+    pub block: BlockState,
+}
+
+impl Default for VoidPlatformGenerator {
+    fn default() -> Self {
+        Self {
+            center: IVec3::new(0, 64, 0),
+            radius: 16,
+            block: BlockState::STONE,
+        }
+    }
+}
+
+impl WorldGenerator for VoidPlatformGenerator {
+    fn generate(&self, position: IVec2, chunk: &mut ColumnData) {
+        let Some(y) = column_y(self.center.y) else {
+            return;
+        };
+
+        let min = self.center - self.radius;
+        let max = self.center + self.radius;
+
+        for_each_column(position, |x, z, world| {
+            if (min.x..=max.x).contains(&world.x) && (min.z..=max.z).contains(&world.y) {
+                chunk.set_block_state(x, y, z, self.block);
+            }
+        });
+    }
+}
+
+/// Rolling hills from seeded fractal value noise, with water filling everything below sea level.
+#[derive(Debug, Clone, Copy)]
+pub struct NoiseGenerator {
+    pub seed: u64,
+    /// The average height of the terrain.
+    pub base_height: i32,
+    /// The maximum distance the terrain deviates from [`Self::base_height`].
+    pub amplitude: f32,
+    /// The horizontal size of the largest hills in blocks.
+    pub scale: f32,
+    pub octaves: u32,
+    pub sea_level: i32,
+}
+
+impl NoiseGenerator {
+    #[must_use]
+    pub const fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            base_height: 64,
+            amplitude: 24.0,
+            scale: 128.0,
+            octaves: 4,
+            sea_level: 62,
+        }
+    }
+
+    /// The y coordinate of the topmost solid block at the given world position.
+    #[must_use]
+    #[expect(clippy::cast_possible_truncation)]
+    pub fn height(&self, x: i32, z: i32) -> i32 {
+        let mut frequency = 1.0 / self.scale;
+        let mut amplitude = 1.0;
+        let mut total = 0.0;
+        let mut max = 0.0;
+
+        for octave in 0..self.octaves {
+            let seed = self.seed.wrapping_add(u64::from(octave));
+            total += value_noise(seed, x as f32 * frequency, z as f32 * frequency) * amplitude;
+            max += amplitude;
+
+            frequency *= 2.0;
+            amplitude *= 0.5;
+        }
+
+        let noise = if max > 0.0 { total / max } else { 0.0 };
+
+        self.base_height + (noise * self.amplitude).round() as i32
+    }
+}
+
+impl WorldGenerator for NoiseGenerator {
+    fn generate(&self, position: IVec2, chunk: &mut ColumnData) {
+        let top = i32::from(START_Y) + i32::try_from(CHUNK_HEIGHT_SPAN).unwrap() - 1;
+
+        for_each_column(position, |x, z, world| {
+            let height = self.height(world.x, world.y).clamp(i32::from(START_Y), top);
+
+            for y in i32::from(START_Y)..=height.max(self.sea_level).min(top) {
+                let block = match y {
+                    y if y == i32::from(START_Y) => BlockState::BEDROCK,
+                    y if y > height => BlockState::WATER,
+                    y if y == height && height >= self.sea_level => BlockState::GRASS_BLOCK,
+                    y if y == height => BlockState::SAND,
+                    y if y > height - 4 => BlockState::DIRT,
+                    _ => BlockState::STONE,
+                };
+
+                if let Some(y) = column_y(y) {
+                    chunk.set_block_state(x, y, z, block);
+                }
+            }
+        });
+    }
+}
+
+fn fill_layer(chunk: &mut ColumnData, y: u32, block: BlockState) {
+    for x in 0..16 {
+        for z in 0..16 {
+            chunk.set_block_state(x, y, z, block);
+        }
+    }
+}
+
+/// Calls `f` with the in-chunk `x` and `z` and the world `(x, z)` of every block column.
+fn for_each_column(position: IVec2, mut f: impl FnMut(u32, u32, IVec2)) {
+    let start = position << 4;
+
+    for x in 0..16_u32 {
+        for z in 0..16_u32 {
+            let world = start + IVec2::new(i32::try_from(x).unwrap(), i32::try_from(z).unwrap());
+            f(x, z, world);
+        }
+    }
+}
+
+/// Converts a world y coordinate into the y index used by [`ColumnData`].
+fn column_y(y: i32) -> Option<u32> {
+    let y = u32::try_from(y - i32::from(START_Y)).ok()?;
+    (y < CHUNK_HEIGHT_SPAN).then_some(y)
+}
+
+/// Smoothly interpolated noise in `[-1, 1]` over a lattice of seeded random values.
+#[expect(clippy::cast_possible_truncation)]
+fn value_noise(seed: u64, x: f32, z: f32) -> f32 {
+    let x0 = x.floor();
+    let z0 = z.floor();
+
+    let tx = smoothstep(x - x0);
+    let tz = smoothstep(z - z0);
+
+    let x0 = x0 as i32;
+    let z0 = z0 as i32;
+
+    let a = lattice(seed, x0, z0);
+    let b = lattice(seed, x0 + 1, z0);
+    let c = lattice(seed, x0, z0 + 1);
+    let d = lattice(seed, x0 + 1, z0 + 1);
+
+    let top = a + (b - a) * tx;
+    let bottom = c + (d - c) * tx;
+
+    top + (bottom - top) * tz
+}
+
+fn smoothstep(t: f32) -> f32 {
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// A random value in `[-1, 1]` for a lattice point.
+#[expect(clippy::cast_sign_loss)]
+fn lattice(seed: u64, x: i32, z: i32) -> f32 {
+    let hash = splitmix64(
+        seed ^ (x as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+            ^ (z as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F),
+    );
+
+    // the top 24 bits fit into the mantissa of an f32 exactly
+    let unit = (hash >> 40) as f32 / (1 << 24) as f32;
+    unit.mul_add(2.0, -1.0)
+}
+
+const fn splitmix64(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    x ^ (x >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::blocks::loader::parse::section::Section;
+
+    fn empty() -> ColumnData {
+        ColumnData::new_with(CHUNK_HEIGHT_SPAN, Section::empty_sky)
+    }
+
+    #[test]
+    fn test_flat_layers() {
+        let mut chunk = empty();
+        FlatGenerator::default().generate(IVec2::ZERO, &mut chunk);
+
+        assert_eq!(chunk.block_state(3, 0, 5), BlockState::BEDROCK);
+        assert_eq!(chunk.block_state(3, 1, 5), BlockState::DIRT);
+        assert_eq!(chunk.block_state(3, 2, 5), BlockState::DIRT);
+        assert_eq!(chunk.block_state(3, 3, 5), BlockState::GRASS_BLOCK);
+        assert_eq!(chunk.block_state(3, 4, 5), BlockState::AIR);
+    }
+
+    #[test]
+    fn test_platform_only_near_center() {
+        let generator = VoidPlatformGenerator::default();
+        let y = column_y(generator.center.y).unwrap();
+
+        let mut chunk = empty();
+        generator.generate(IVec2::new(-1, 0), &mut chunk);
+        assert_eq!(chunk.block_state(15, y, 0), BlockState::STONE);
+
+        let mut chunk = empty();
+        generator.generate(IVec2::new(8, 8), &mut chunk);
+        assert_eq!(chunk.block_state(0, y, 0), BlockState::AIR);
+    }
+
+    #[test]
+    fn test_noise_is_deterministic() {
+        let a = NoiseGenerator::new(42);
+        let b = NoiseGenerator::new(42);
+
+        for (x, z) in [(0, 0), (-100, 37), (1234, -5678)] {
+            assert_eq!(a.height(x, z), b.height(x, z));
+
+            let deviation = (a.height(x, z) - a.base_height).abs();
+            assert!(deviation <= 24, "height deviates by {deviation}");
+        }
+    }
+}
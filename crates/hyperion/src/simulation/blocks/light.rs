@@ -0,0 +1,343 @@
+//! Incremental block and sky light propagation. See [`Blocks::update_light`].
+//!
+//! Light spreads between loaded chunks like in vanilla, except that every non-opaque block
+//! lowers it by one: water and leaves do not dim sky light any further.
+
+use std::{collections::VecDeque, sync::Arc};
+
+use glam::IVec3;
+use valence_generated::block::BlockState;
+
+use super::{Blocks, chunk::START_Y, column_offset, loader::parse::section::Section};
+use crate::CHUNK_HEIGHT_SPAN;
+
+const DIRECTIONS: [IVec3; 6] = [
+    IVec3::NEG_Y,
+    IVec3::Y,
+    IVec3::NEG_X,
+    IVec3::X,
+    IVec3::NEG_Z,
+    IVec3::Z,
+];
+
+const MAX_LEVEL: u8 = 15;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum LightKind {
+    Block,
+    Sky,
+}
+
+impl LightKind {
+    /// The level of sections without stored light. This matches what is sent when the chunk is
+    /// encoded.
+    const fn default_level(self) -> u8 {
+        match self {
+            Self::Block => 0,
+            Self::Sky => MAX_LEVEL,
+        }
+    }
+
+    const fn light(self, section: &Section) -> Option<&[u8; 2048]> {
+        match self {
+            Self::Block => section.block_light.as_ref(),
+            Self::Sky => section.sky_light.as_ref(),
+        }
+    }
+
+    const fn light_mut(self, section: &mut Section) -> &mut Option<[u8; 2048]> {
+        match self {
+            Self::Block => &mut section.block_light,
+            Self::Sky => &mut section.sky_light,
+        }
+    }
+
+    /// The level of a block next to a block at `level` in `direction`. Full sky light travels
+    /// straight down without getting dimmer.
+    fn spread(self, level: u8, direction: IVec3) -> u8 {
+        if self == Self::Sky && level == MAX_LEVEL && direction == IVec3::NEG_Y {
+            MAX_LEVEL
+        } else {
+            level.saturating_sub(1)
+        }
+    }
+}
+
+/// The index of the block at `x`, `y`, `z` in a section's light array, where each byte holds two
+/// blocks.
+const fn nibble_idx(x: u32, y: u32, z: u32) -> usize {
+    (x + z * 16 + (y % 16) * 16 * 16) as usize
+}
+
+const fn get_nibble(light: &[u8; 2048], idx: usize) -> u8 {
+    (light[idx / 2] >> ((idx % 2) * 4)) & 0xF
+}
+
+const fn set_nibble(light: &mut [u8; 2048], idx: usize, level: u8) {
+    let shift = (idx % 2) * 4;
+    light[idx / 2] = (light[idx / 2] & !(0xF << shift)) | (level << shift);
+}
+
+impl Blocks {
+    /// Relights around every block changed with [`Self::set_block`] or [`Self::paste`] since the
+    /// last call. The
+    /// relit sections are sent to players with the next chunk deltas.
+    pub fn update_light(&mut self) {
+        if self.light_queue.is_empty() {
+            return;
+        }
+
+        let changed = std::mem::take(&mut self.light_queue);
+
+        for kind in [LightKind::Block, LightKind::Sky] {
+            self.relight(kind, &changed);
+        }
+    }
+
+    /// First darkens everything which was lit through the changed blocks, then spreads light
+    /// back in from the edges of the darkened area and from light sources.
+    fn relight(&mut self, kind: LightKind, changed: &[IVec3]) {
+        let mut removals = VecDeque::new();
+        let mut additions = VecDeque::new();
+
+        for &position in changed {
+            let Some(level) = self.light(kind, position) else {
+                continue;
+            };
+
+            self.set_light(kind, position, 0);
+            removals.push_back((position, level));
+        }
+
+        while let Some((position, level)) = removals.pop_front() {
+            for direction in DIRECTIONS {
+                let neighbor = position + direction;
+
+                let Some(neighbor_level) = self.light(kind, neighbor) else {
+                    continue;
+                };
+
+                if neighbor_level == 0 {
+                    continue;
+                }
+
+                let lit_by_position = neighbor_level < level
+                    || (neighbor_level == MAX_LEVEL && kind.spread(level, direction) == MAX_LEVEL);
+
+                if lit_by_position {
+                    self.set_light(kind, neighbor, 0);
+                    removals.push_back((neighbor, neighbor_level));
+                    self.emit(kind, neighbor, &mut additions);
+                } else {
+                    additions.push_back(neighbor);
+                }
+            }
+        }
+
+        for &position in changed {
+            self.emit(kind, position, &mut additions);
+
+            // light flows back in unless the block became opaque
+            additions.extend(DIRECTIONS.map(|direction| position + direction));
+        }
+
+        while let Some(position) = additions.pop_front() {
+            let Some(level) = self.light(kind, position) else {
+                continue;
+            };
+
+            for direction in DIRECTIONS {
+                let neighbor = position + direction;
+                let target = kind.spread(level, direction);
+
+                if target == 0 {
+                    continue;
+                }
+
+                let Some(current) = self.light(kind, neighbor) else {
+                    continue;
+                };
+
+                if current >= target {
+                    continue;
+                }
+
+                if self.get_block(neighbor).is_none_or(BlockState::is_opaque) {
+                    continue;
+                }
+
+                self.set_light(kind, neighbor, target);
+                additions.push_back(neighbor);
+            }
+        }
+    }
+
+    /// Lights `position` if the block there is a light source.
+    fn emit(&mut self, kind: LightKind, position: IVec3, additions: &mut VecDeque<IVec3>) {
+        if kind != LightKind::Block {
+            return;
+        }
+
+        let Some(luminance) = self.get_block(position).map(BlockState::luminance) else {
+            return;
+        };
+
+        if luminance > 0 {
+            self.set_light(kind, position, luminance);
+            additions.push_back(position);
+        }
+    }
+
+    /// `None` for unloaded chunks and positions outside the world. Everything above the world
+    /// has full sky light.
+    fn light(&self, kind: LightKind, position: IVec3) -> Option<u8> {
+        let top = i32::from(START_Y) + i32::try_from(CHUNK_HEIGHT_SPAN).unwrap();
+
+        if kind == LightKind::Sky && position.y >= top {
+            return Some(MAX_LEVEL);
+        }
+
+        let (chunk_pos, [x, y, z]) = column_offset(position)?;
+        let column = self.get_loaded_chunk(chunk_pos)?;
+        let section = &column.data.sections[y as usize / 16];
+
+        let level = kind.light(section).map_or(kind.default_level(), |light| {
+            get_nibble(light, nibble_idx(x, y, z))
+        });
+
+        Some(level)
+    }
+
+    fn set_light(&mut self, kind: LightKind, position: IVec3, level: u8) {
+        let Some((chunk_pos, [x, y, z])) = column_offset(position) else {
+            return;
+        };
+
+        let Some((chunk_idx, _, column)) = self.chunk_cache.get_full_mut(&chunk_pos) else {
+            return;
+        };
+
+        let section_idx = y as usize / 16;
+        let default = kind.default_level() * 0x11;
+
+        let light = kind
+            .light_mut(Arc::make_mut(&mut column.data.sections[section_idx]))
+            .get_or_insert([default; 2048]);
+
+        let idx = nibble_idx(x, y, z);
+
+        if get_nibble(light, idx) == level {
+            return;
+        }
+
+        set_nibble(light, idx, level);
+        column.mark_light_changed(section_idx);
+
+        let chunk_idx = u32::try_from(chunk_idx).unwrap();
+        self.should_update.insert(chunk_idx);
+        self.should_save.insert(chunk_idx);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use glam::I16Vec2;
+
+    use super::*;
+    use crate::simulation::blocks::{ChunkLoaderHandle, ColumnData, chunk::Column};
+
+    /// Blocks with a single loaded chunk at the origin which is open to the sky.
+    fn blocks() -> Blocks {
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut blocks = Blocks::from(ChunkLoaderHandle::new(tx, std::sync::Arc::default()));
+
+        let data = ColumnData::new_with(CHUNK_HEIGHT_SPAN, Section::empty_sky);
+        let column = Column::new(Bytes::new(), data, glam::IVec2::ZERO);
+        blocks.cache_mut().insert(I16Vec2::ZERO, column);
+
+        blocks
+    }
+
+    #[test]
+    fn test_light_source() {
+        let mut blocks = blocks();
+        let position = IVec3::new(8, 0, 8);
+
+        blocks.set_block(position, BlockState::GLOWSTONE).unwrap();
+        blocks.update_light();
+
+        assert_eq!(blocks.light(LightKind::Block, position), Some(15));
+        assert_eq!(
+            blocks.light(LightKind::Block, position + IVec3::X),
+            Some(14)
+        );
+        assert_eq!(
+            blocks.light(LightKind::Block, position + IVec3::new(2, 3, 0)),
+            Some(10)
+        );
+
+        blocks.set_block(position, BlockState::AIR).unwrap();
+        blocks.update_light();
+
+        assert_eq!(blocks.light(LightKind::Block, position), Some(0));
+        assert_eq!(blocks.light(LightKind::Block, position + IVec3::X), Some(0));
+    }
+
+    #[test]
+    fn test_pasted_light_source() {
+        let mut blocks = blocks();
+        let offset = IVec3::new(4, 0, 4);
+
+        let mut frame = ndarray::Array3::from_elem((3, 1, 3), BlockState::STONE);
+        frame[[1, 0, 1]] = BlockState::GLOWSTONE;
+        blocks.paste(offset, frame.view());
+        blocks.update_light();
+
+        let glowstone = offset + IVec3::new(1, 0, 1);
+        assert_eq!(blocks.light(LightKind::Block, glowstone), Some(15));
+        assert_eq!(
+            blocks.light(LightKind::Block, glowstone + IVec3::Y),
+            Some(14)
+        );
+
+        // the stone around the glowstone is opaque
+        assert_eq!(blocks.light(LightKind::Block, offset), Some(0));
+        assert_eq!(blocks.light(LightKind::Sky, offset), Some(0));
+    }
+
+    #[test]
+    fn test_sky_light_blocked() {
+        let mut blocks = blocks();
+        let roof = IVec3::new(8, 100, 8);
+        let below = roof - IVec3::Y;
+
+        blocks.set_block(roof, BlockState::STONE).unwrap();
+        blocks.update_light();
+
+        assert_eq!(blocks.light(LightKind::Sky, roof), Some(0));
+        // still lit from the sides
+        assert_eq!(blocks.light(LightKind::Sky, below), Some(14));
+
+        blocks.set_block(roof, BlockState::AIR).unwrap();
+        blocks.update_light();
+
+        assert_eq!(blocks.light(LightKind::Sky, roof), Some(15));
+        assert_eq!(blocks.light(LightKind::Sky, below), Some(15));
+    }
+
+    #[test]
+    fn test_relit_sections_are_sent() {
+        let mut blocks = blocks();
+
+        blocks
+            .set_block(IVec3::new(8, 0, 8), BlockState::GLOWSTONE)
+            .unwrap();
+        blocks.update_light();
+
+        let column = blocks.get_loaded_chunk_mut(I16Vec2::ZERO).unwrap();
+        assert!(column.light_drain_packet().is_some());
+        assert!(column.light_drain_packet().is_none());
+        assert!(column.original_light_packet().is_some());
+    }
+}
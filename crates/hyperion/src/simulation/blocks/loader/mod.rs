@@ -1,6 +1,14 @@
-use std::{borrow::Cow, cell::RefCell, io::Write, sync::Arc};
+use std::{
+    borrow::Cow,
+    cell::RefCell,
+    io::Write,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
 
-use anyhow::{Context, bail};
+use anyhow::bail;
 use bytes::BytesMut;
 use derive_more::Constructor;
 use glam::{I16Vec2, IVec2};
@@ -11,14 +19,22 @@ use parse::ColumnData;
 use rustc_hash::FxHashSet;
 use tracing::{debug, warn};
 use valence_generated::block::BlockState;
-use valence_nbt::{List, compound};
-use valence_protocol::{ChunkPos, CompressionThreshold, FixedArray, packets::play};
+use valence_nbt::{Compound, List, compound};
+use valence_protocol::{
+    ChunkPos, CompressionThreshold, FixedArray,
+    packets::play::{self, chunk_data_s2c::ChunkDataBlockEntity},
+};
 use valence_registry::RegistryIdx;
 use valence_server::layer::chunk::{BiomeContainer, Chunk, bit_width};
 
 pub mod parse;
+pub mod serialize;
 
-use super::{chunk::Column, shared::WorldShared};
+use super::{
+    chunk::{Column, START_Y},
+    generator::WorldGenerator,
+    shared::WorldShared,
+};
 use crate::{
     CHUNK_HEIGHT_SPAN, Scratch,
     net::encoder::PacketEncoder,
@@ -56,33 +72,52 @@ struct ChunkLoader {
     rx_load_chunk_requests: tokio::sync::mpsc::UnboundedReceiver<Message>,
     received_request: FxHashSet<I16Vec2>,
     shared: Arc<WorldShared>,
+    generator: Arc<dyn WorldGenerator>,
     runtime: AsyncRuntime,
+    in_flight: Arc<AtomicUsize>,
 }
 
 #[derive(Constructor)]
 pub struct ChunkLoaderHandle {
     tx_load_chunk_requests: tokio::sync::mpsc::UnboundedSender<Message>,
+    /// Requests which have been sent but not answered yet.
+    in_flight: Arc<AtomicUsize>,
 }
 
 impl ChunkLoaderHandle {
     pub fn send(&self, position: I16Vec2, tx: tokio::sync::mpsc::UnboundedSender<Column>) {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
         self.tx_load_chunk_requests
             .send(Message { position, tx })
             .unwrap();
     }
+
+    /// The number of requests the loader has not answered yet.
+    #[must_use]
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
+    }
 }
 
-pub fn launch_loader(shared: Arc<WorldShared>, runtime: &AsyncRuntime) -> ChunkLoaderHandle {
+pub fn launch_loader(
+    shared: Arc<WorldShared>,
+    generator: Arc<dyn WorldGenerator>,
+    runtime: &AsyncRuntime,
+) -> ChunkLoaderHandle {
     let (tx_load_chunk_requests, rx_load_chunk_requests) = tokio::sync::mpsc::unbounded_channel();
+    let in_flight = Arc::new(AtomicUsize::new(0));
 
     runtime.spawn({
         let runtime = runtime.clone();
+        let in_flight = in_flight.clone();
         async move {
             ChunkLoader {
                 rx_load_chunk_requests,
                 received_request: FxHashSet::default(),
                 shared,
+                generator,
                 runtime,
+                in_flight,
             }
             .run()
             .await;
@@ -91,21 +126,31 @@ pub fn launch_loader(shared: Arc<WorldShared>, runtime: &AsyncRuntime) -> ChunkL
 
     ChunkLoaderHandle {
         tx_load_chunk_requests,
+        in_flight,
     }
 }
 
-pub fn launch_empty_loader(runtime: &AsyncRuntime) -> ChunkLoaderHandle {
+/// Launches a loader which is not backed by region files and generates every chunk.
+pub fn launch_generator_loader(
+    generator: Arc<dyn WorldGenerator>,
+    runtime: &AsyncRuntime,
+) -> ChunkLoaderHandle {
     let (tx_loaded_chunks, mut rx_loaded_chunks) =
         tokio::sync::mpsc::unbounded_channel::<Message>();
+    let in_flight = Arc::new(AtomicUsize::new(0));
 
-    runtime.spawn(async move {
-        while let Some(msg) = rx_loaded_chunks.recv().await {
-            let column = empty_column(msg.position);
-            msg.tx.send(column).unwrap();
+    runtime.spawn({
+        let in_flight = in_flight.clone();
+        async move {
+            while let Some(msg) = rx_loaded_chunks.recv().await {
+                let column = generated_column(msg.position, &*generator);
+                msg.tx.send(column).unwrap();
+                in_flight.fetch_sub(1, Ordering::Relaxed);
+            }
         }
     });
 
-    ChunkLoaderHandle::new(tx_loaded_chunks)
+    ChunkLoaderHandle::new(tx_loaded_chunks, in_flight)
 }
 
 impl ChunkLoader {
@@ -122,14 +167,17 @@ impl ChunkLoader {
         if !newly_inserted {
             // people should already have a cached version of this chunk
             // or we are about to send it to them
+            self.in_flight.fetch_sub(1, Ordering::Relaxed);
             return;
         }
 
         let tx_load_chunks = message.tx;
         let shared = self.shared.clone();
+        let generator = self.generator.clone();
+        let in_flight = self.in_flight.clone();
 
         self.runtime.spawn(async move {
-            let loaded_chunk = match load_chunk(position, &shared).await {
+            let loaded_chunk = match load_chunk(position, &shared, &*generator).await {
                 Ok(loaded_chunk) => {
                     let chunk_height = loaded_chunk.data.height();
                     if chunk_height == CHUNK_HEIGHT_SPAN {
@@ -161,6 +209,7 @@ impl ChunkLoader {
             debug!("{NERD_ROCKET} loaded chunk {position} with {unique_blocks} unique blocks");
 
             tx_load_chunks.send(loaded_chunk).unwrap();
+            in_flight.fetch_sub(1, Ordering::Relaxed);
         });
     }
 }
@@ -168,20 +217,34 @@ impl ChunkLoader {
 fn empty_column(position: I16Vec2) -> Column {
     // height: 24
     let unloaded = ColumnData::new_with(CHUNK_HEIGHT_SPAN, Section::empty_sky);
+    encoded_column(position, unloaded)
+}
+
+fn generated_column(position: I16Vec2, generator: &dyn WorldGenerator) -> Column {
+    let mut generated = ColumnData::new_with(CHUNK_HEIGHT_SPAN, Section::empty_sky);
+    generator.generate(position.as_ivec2(), &mut generated);
+    encoded_column(position, generated)
+}
+
+fn encoded_column(position: I16Vec2, data: ColumnData) -> Column {
     let position = position.as_ivec2();
 
     let bytes = STATE.with_borrow_mut(|state| {
-        encode_chunk_packet(&unloaded, position, state)
+        encode_chunk_packet(&data, position, state)
             .unwrap()
             .unwrap()
     });
 
-    debug_assert_eq!(unloaded.height(), CHUNK_HEIGHT_SPAN);
+    debug_assert_eq!(data.height(), CHUNK_HEIGHT_SPAN);
 
-    Column::new(bytes.freeze(), unloaded, position)
+    Column::new(bytes.freeze(), data, position)
 }
 
-async fn load_chunk(position: I16Vec2, shared: &WorldShared) -> anyhow::Result<Column> {
+async fn load_chunk(
+    position: I16Vec2,
+    shared: &WorldShared,
+    generator: &dyn WorldGenerator,
+) -> anyhow::Result<Column> {
     let x = position.x;
     let y = position.y;
 
@@ -190,18 +253,21 @@ async fn load_chunk(position: I16Vec2, shared: &WorldShared) -> anyhow::Result<C
 
     // https://rust-lang.github.io/rust-clippy/master/index.html#/large_futures
     let Ok(region) = shared.regions.get_region_from_chunk(x, y).await else {
-        // most likely the file representing the region does not exist so we will just return en empty chunk
-        warn!("region file for {position} does not exist; returning empty chunk");
-        return Ok(empty_column(position));
+        // most likely the file representing the region does not exist so we will generate the chunk
+        debug!("region file for {position} does not exist; generating chunk");
+        return Ok(generated_column(position, generator));
     };
 
     let raw_chunk = {
         // todo: note that this is likely blocking to tokio
         let x = i32::from(x);
         let y = i32::from(y);
-        region
-            .get_chunk(x, y, &mut decompress_buf, shared.regions.root())?
-            .context("no chunk found")?
+        region.get_chunk(x, y, &mut decompress_buf, shared.regions.root())?
+    };
+
+    let Some(raw_chunk) = raw_chunk else {
+        debug!("chunk {position} is not in its region file; generating chunk");
+        return Ok(generated_column(position, generator));
     };
 
     let chunk = match parse::parse_chunk(raw_chunk.data, &shared.biome_to_id) {
@@ -295,6 +361,12 @@ fn encode_chunk_packet(
     let sky_light_data = sky_light_mask.into_data();
     let block_light_data = block_light_mask.into_data();
 
+    let block_entities: Vec<_> = chunk
+        .block_entities
+        .iter()
+        .filter_map(|(&idx, nbt)| chunk_block_entity(chunk, idx, nbt))
+        .collect();
+
     let pkt = play::ChunkDataS2c {
         pos: ChunkPos::new(location.x, location.y),
 
@@ -303,7 +375,7 @@ fn encode_chunk_packet(
             "MOTION_BLOCKING" => List::Long(map),
         }),
         blocks_and_biomes: &section_bytes,
-        block_entities: Cow::Borrowed(&[]),
+        block_entities: Cow::Owned(block_entities),
 
         sky_light_mask: Cow::Borrowed(&sky_light_data),
         block_light_mask: Cow::Borrowed(&block_light_data),
@@ -324,6 +396,31 @@ fn encode_chunk_packet(
     Ok(Some(result))
 }
 
+/// `None` for block entities left behind on blocks which cannot have one.
+#[expect(
+    clippy::cast_possible_truncation,
+    clippy::cast_possible_wrap,
+    reason = "the packed x and z fit into a byte which the protocol treats as signed"
+)]
+fn chunk_block_entity<'a>(
+    chunk: &ColumnData,
+    idx: u32,
+    nbt: &'a Compound,
+) -> Option<ChunkDataBlockEntity<'a>> {
+    let x = idx % 16;
+    let z = idx / 16 % 16;
+    let y = idx / (16 * 16);
+
+    let kind = chunk.block_state(x, y, z).block_entity_kind()?;
+
+    Some(ChunkDataBlockEntity {
+        packed_xz: ((x << 4) | z) as i8,
+        y: i16::try_from(y).ok()? + START_Y,
+        kind,
+        data: Cow::Borrowed(nbt),
+    })
+}
+
 fn write_block_states(
     states: &hyperion_palette::PalettedContainer,
     writer: &mut impl Write,
@@ -1,4 +1,4 @@
-use std::{borrow::Cow, collections::BTreeMap};
+use std::{borrow::Cow, collections::BTreeMap, sync::Arc};
 
 use thiserror::Error;
 use tracing::warn;
@@ -80,21 +80,23 @@ pub mod section;
 
 #[derive(Clone, Default, Debug)]
 pub struct ColumnData {
-    pub sections: Vec<Section>,
+    /// Cloning a column only shares its sections, e.g. with the chunk saver while it writes them.
+    /// Changing a shared section copies it first.
+    pub sections: Vec<Arc<Section>>,
     pub block_entities: BTreeMap<u32, Compound>,
 }
 
 impl ColumnData {
     pub fn new(height: u32) -> Self {
         Self {
-            sections: vec![Section::default(); height as usize / 16],
+            sections: vec![Arc::default(); height as usize / 16],
             block_entities: BTreeMap::new(),
         }
     }
 
     pub fn new_with(height: u32, f: impl Fn() -> Section) -> Self {
         Self {
-            sections: vec![f(); height as usize / 16],
+            sections: vec![Arc::new(f()); height as usize / 16],
             block_entities: BTreeMap::new(),
         }
     }
@@ -109,7 +111,7 @@ impl ColumnData {
         // todo: remove try_unwrap when we show this is safe
         let idx = u16::try_from(idx).unwrap();
 
-        self.sections[y as usize / 16].set_delta(idx, block)
+        Arc::make_mut(&mut self.sections[y as usize / 16]).set_delta(idx, block)
     }
 }
 
@@ -141,13 +143,13 @@ impl Chunk for ColumnData {
         // todo: remove try_unwrap when we show this is safe
         let idx = u16::try_from(idx).unwrap();
 
-        self.sections[y as usize / 16].set(idx, block)
+        Arc::make_mut(&mut self.sections[y as usize / 16]).set(idx, block)
     }
 
     fn fill_block_state_section(&mut self, sect_y: u32, block: BlockState) {
         check_section_oob(self, sect_y);
 
-        self.sections[sect_y as usize]
+        Arc::make_mut(&mut self.sections[sect_y as usize])
             .block_states
             .fill(block.to_raw());
     }
@@ -198,7 +200,7 @@ impl Chunk for ColumnData {
         check_biome_oob(self, x, y, z);
 
         let idx = x + z * 4 + y % 4 * 4 * 4;
-        self.sections[y as usize / 4]
+        Arc::make_mut(&mut self.sections[y as usize / 4])
             .biomes
             .set(idx as usize, biome)
     }
@@ -206,11 +208,14 @@ impl Chunk for ColumnData {
     fn fill_biome_section(&mut self, sect_y: u32, biome: BiomeId) {
         check_section_oob(self, sect_y);
 
-        self.sections[sect_y as usize].biomes.fill(biome);
+        Arc::make_mut(&mut self.sections[sect_y as usize])
+            .biomes
+            .fill(biome);
     }
 
     fn shrink_to_fit(&mut self) {
-        for sect in &mut self.sections {
+        // shared sections are left alone rather than copied
+        for sect in self.sections.iter_mut().filter_map(Arc::get_mut) {
             // sect.block_states.shrink_to_fit(); todo:
             sect.biomes.shrink_to_fit();
         }
@@ -266,7 +271,7 @@ pub fn parse_chunk(
                     return Err(ParseChunkError::InvalidBlockLight);
                 };
 
-                chunk.sections[idx].block_light = Some(block_light);
+                Arc::make_mut(&mut chunk.sections[idx]).block_light = Some(block_light);
             }
             Some(_) => return Err(ParseChunkError::MissingBlockLight),
             _ => {}
@@ -282,7 +287,7 @@ pub fn parse_chunk(
                     return Err(ParseChunkError::InvalidSkyLight);
                 };
 
-                chunk.sections[idx].sky_light = Some(sky_light);
+                Arc::make_mut(&mut chunk.sections[idx]).sky_light = Some(sky_light);
             }
             Some(_) => return Err(ParseChunkError::MissingSkyLight),
             _ => {}
@@ -459,11 +464,12 @@ pub fn parse_chunk(
 
     if let List::Compound(block_entities) = block_entities {
         for mut comp in block_entities {
-            let Some(Value::String(ident)) = comp.remove("id") else {
+            // the id is kept so that the block entity can be written back to the region file
+            let Some(Value::String(ident)) = comp.get("id") else {
                 return Err(ParseChunkError::MissingBlockEntityIdent);
             };
 
-            if let Err(e) = Ident::new(ident) {
+            if let Err(e) = Ident::new(ident.clone()) {
                 return Err(ParseChunkError::InvalidBlockEntityName(e.0));
             }
 
@@ -0,0 +1,208 @@
+use std::collections::BTreeMap;
+
+use glam::IVec2;
+use rustc_hash::FxHashMap;
+use valence_generated::block::BlockState;
+use valence_nbt::{Compound, List, Value, compound};
+use valence_protocol::Ident;
+use valence_registry::biome::BiomeId;
+use valence_server::layer::chunk::bit_width;
+
+use crate::simulation::blocks::{
+    chunk::START_Y,
+    loader::parse::{ColumnData, section::Section},
+};
+
+/// The `DataVersion` of chunks written by Minecraft 1.20.1.
+const DATA_VERSION: i32 = 3465;
+
+const BLOCKS_PER_SECTION: usize = 16 * 16 * 16;
+const BIOMES_PER_SECTION: usize = 4 * 4 * 4;
+
+/// The inverse of [`super::parse::parse_chunk`]: converts a column into the Anvil chunk NBT format.
+pub fn serialize_chunk(
+    chunk: &ColumnData,
+    position: IVec2,
+    biome_names: &BTreeMap<BiomeId, Ident<String>>,
+) -> Compound {
+    let min_sect_y = i32::from(START_Y >> 4);
+
+    let sections = chunk
+        .sections
+        .iter()
+        .enumerate()
+        .map(|(idx, section)| {
+            let sect_y = i32::try_from(idx).unwrap() + min_sect_y;
+            let sect_y = i8::try_from(sect_y).unwrap();
+            serialize_section(section, sect_y, biome_names)
+        })
+        .collect();
+
+    let block_entities = chunk
+        .block_entities
+        .iter()
+        .map(|(&idx, block_entity)| {
+            let idx = i32::try_from(idx).unwrap();
+
+            let mut block_entity = block_entity.clone();
+            block_entity.insert("x", position.x * 16 + idx % 16);
+            block_entity.insert("y", idx / (16 * 16) + i32::from(START_Y));
+            block_entity.insert("z", position.y * 16 + idx / 16 % 16);
+            block_entity
+        })
+        .collect();
+
+    compound! {
+        "DataVersion" => DATA_VERSION,
+        "xPos" => position.x,
+        "zPos" => position.y,
+        "yPos" => min_sect_y,
+        "Status" => "minecraft:full",
+        "LastUpdate" => 0_i64,
+        "sections" => List::Compound(sections),
+        "block_entities" => List::Compound(block_entities),
+    }
+}
+
+fn serialize_section(
+    section: &Section,
+    sect_y: i8,
+    biome_names: &BTreeMap<BiomeId, Ident<String>>,
+) -> Compound {
+    let mut nbt = compound! {
+        "Y" => sect_y,
+        "block_states" => serialize_block_states(section),
+        "biomes" => serialize_biomes(section, biome_names),
+    };
+
+    if let Some(block_light) = &section.block_light {
+        let block_light: &[i8] = bytemuck::cast_slice(block_light);
+        nbt.insert("BlockLight", Value::ByteArray(block_light.to_vec()));
+    }
+
+    if let Some(sky_light) = &section.sky_light {
+        let sky_light: &[i8] = bytemuck::cast_slice(sky_light);
+        nbt.insert("SkyLight", Value::ByteArray(sky_light.to_vec()));
+    }
+
+    nbt
+}
+
+fn serialize_block_states(section: &Section) -> Compound {
+    let mut palette = Vec::new();
+    let mut palette_lookup = FxHashMap::default();
+    let mut indices = Vec::with_capacity(BLOCKS_PER_SECTION);
+
+    for raw in &section.block_states {
+        let idx = *palette_lookup.entry(raw).or_insert_with(|| {
+            palette.push(raw);
+            palette.len() - 1
+        });
+        indices.push(idx);
+    }
+
+    let palette_nbt = palette
+        .iter()
+        .map(|&raw| {
+            let state = BlockState::from_raw(raw).unwrap_or(BlockState::AIR);
+            block_state_nbt(state)
+        })
+        .collect();
+
+    let mut nbt = compound! {
+        "palette" => List::Compound(palette_nbt),
+    };
+
+    if palette.len() > 1 {
+        let bits_per_idx = bit_width(palette.len() - 1).max(4);
+        nbt.insert(
+            "data",
+            Value::LongArray(pack_indices(&indices, bits_per_idx)),
+        );
+    }
+
+    nbt
+}
+
+fn serialize_biomes(section: &Section, biome_names: &BTreeMap<BiomeId, Ident<String>>) -> Compound {
+    let mut palette: Vec<BiomeId> = Vec::new();
+    let mut indices = Vec::with_capacity(BIOMES_PER_SECTION);
+
+    for i in 0..BIOMES_PER_SECTION {
+        let biome = section.biomes.get(i);
+
+        let idx = palette.iter().position(|&b| b == biome).unwrap_or_else(|| {
+            palette.push(biome);
+            palette.len() - 1
+        });
+
+        indices.push(idx);
+    }
+
+    let palette_nbt = palette
+        .iter()
+        .map(|biome| {
+            biome_names.get(biome).map_or_else(
+                || "minecraft:plains".to_owned(),
+                |name| name.as_str().to_owned(),
+            )
+        })
+        .collect();
+
+    let mut nbt = compound! {
+        "palette" => List::String(palette_nbt),
+    };
+
+    if palette.len() > 1 {
+        let bits_per_idx = bit_width(palette.len() - 1);
+        nbt.insert(
+            "data",
+            Value::LongArray(pack_indices(&indices, bits_per_idx)),
+        );
+    }
+
+    nbt
+}
+
+fn block_state_nbt(state: BlockState) -> Compound {
+    let kind = state.to_kind();
+
+    let mut nbt = compound! {
+        "Name" => format!("minecraft:{}", kind.to_str()),
+    };
+
+    let properties: Compound = kind
+        .props()
+        .iter()
+        .filter_map(|&name| {
+            let value = state.get(name)?;
+            Some((
+                name.to_str().to_owned(),
+                Value::String(value.to_str().to_owned()),
+            ))
+        })
+        .collect();
+
+    if !properties.is_empty() {
+        nbt.insert("Properties", properties);
+    }
+
+    nbt
+}
+
+/// Packs palette indices into longs without letting an index span two longs (the 1.16+ format).
+#[expect(clippy::cast_possible_wrap)]
+fn pack_indices(indices: &[usize], bits_per_idx: usize) -> Vec<i64> {
+    let idxs_per_long = 64 / bits_per_idx;
+
+    indices
+        .chunks(idxs_per_long)
+        .map(|idxs| {
+            let long = idxs.iter().enumerate().fold(0_u64, |long, (j, &idx)| {
+                long | ((idx as u64) << (bits_per_idx * j))
+            });
+
+            long as i64
+        })
+        .collect()
+}
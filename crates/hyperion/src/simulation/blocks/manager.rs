@@ -12,6 +12,8 @@ use tokio::{
     sync::{mpsc, oneshot},
 };
 use tracing::info;
+use valence_anvil::{Compression, RegionError};
+use valence_nbt::Compound;
 
 use super::region::Region;
 
@@ -20,6 +22,15 @@ enum RegionRequest {
         coord: IVec2,
         response: oneshot::Sender<std::io::Result<Arc<Region>>>,
     },
+    Save {
+        pos_x: i32,
+        pos_z: i32,
+        chunk: Compound,
+        response: oneshot::Sender<Result<(), RegionError>>,
+    },
+    Flush {
+        response: oneshot::Sender<std::io::Result<()>>,
+    },
 }
 
 pub struct RegionManager {
@@ -54,9 +65,7 @@ impl RegionManager {
         let pos_x = i32::from(pos_x);
         let pos_z = i32::from(pos_z);
 
-        let region_x = pos_x.div_euclid(32);
-        let region_z = pos_z.div_euclid(32);
-        let coord = IVec2::new(region_x, region_z);
+        let coord = region_coord(pos_x, pos_z);
 
         let (response_tx, response_rx) = oneshot::channel();
         self.sender
@@ -71,17 +80,73 @@ impl RegionManager {
             .await
             .expect("RegionManagerTask has been dropped")
     }
+
+    /// Writes the chunk NBT at the given chunk position, creating the region file if needed.
+    pub async fn save_chunk(
+        &self,
+        pos_x: i16,
+        pos_z: i16,
+        chunk: Compound,
+    ) -> Result<(), RegionError> {
+        let (response_tx, response_rx) = oneshot::channel();
+        self.sender
+            .send(RegionRequest::Save {
+                pos_x: i32::from(pos_x),
+                pos_z: i32::from(pos_z),
+                chunk,
+                response: response_tx,
+            })
+            .await
+            .expect("RegionManagerTask has been dropped");
+
+        response_rx
+            .await
+            .expect("RegionManagerTask has been dropped")
+    }
+
+    /// Waits until every chunk saved so far has been synced to disk.
+    pub async fn flush(&self) -> std::io::Result<()> {
+        let (response_tx, response_rx) = oneshot::channel();
+        self.sender
+            .send(RegionRequest::Flush {
+                response: response_tx,
+            })
+            .await
+            .expect("RegionManagerTask has been dropped");
+
+        response_rx
+            .await
+            .expect("RegionManagerTask has been dropped")
+    }
+}
+
+const fn region_coord(pos_x: i32, pos_z: i32) -> IVec2 {
+    IVec2::new(pos_x.div_euclid(32), pos_z.div_euclid(32))
 }
 
 struct RegionManagerTask {
     root: PathBuf,
     receiver: mpsc::Receiver<RegionRequest>,
     regions: HashMap<IVec2, std::sync::Weak<Region>>,
+    writer: RegionWriter,
+}
+
+/// The region files opened for writing. Writing blocks, so this is moved to a blocking thread
+/// for every write.
+#[derive(Default)]
+struct RegionWriter {
+    root: PathBuf,
+    writers: HashMap<IVec2, Region>,
+    compress_buf: Vec<u8>,
 }
 
 impl RegionManagerTask {
     fn new(root: PathBuf, receiver: mpsc::Receiver<RegionRequest>) -> Self {
         Self {
+            writer: RegionWriter {
+                root: root.clone(),
+                ..RegionWriter::default()
+            },
             root,
             receiver,
             regions: HashMap::new(),
@@ -109,9 +174,50 @@ impl RegionManagerTask {
                 // todo: what should we  do here
                 drop(response.send(region));
             }
+            RegionRequest::Save {
+                pos_x,
+                pos_z,
+                chunk,
+                response,
+            } => {
+                let result = self
+                    .write(move |writer| writer.save_chunk(pos_x, pos_z, &chunk))
+                    .await;
+
+                // readers mapped the old version of the file; the next read will reopen it
+                self.regions.remove(&region_coord(pos_x, pos_z));
+
+                drop(response.send(result));
+            }
+            RegionRequest::Flush { response } => {
+                let result = self
+                    .write(|writer| writer.writers.values().try_for_each(Region::sync))
+                    .await;
+
+                drop(response.send(result));
+            }
         }
     }
 
+    /// Runs `f` with the writer on a blocking thread. Requests are handled one at a time, so
+    /// writes stay in order.
+    async fn write<R: Send + 'static>(
+        &mut self,
+        f: impl FnOnce(&mut RegionWriter) -> R + Send + 'static,
+    ) -> R {
+        let mut writer = std::mem::take(&mut self.writer);
+
+        let (writer, result) = tokio::task::spawn_blocking(move || {
+            let result = f(&mut writer);
+            (writer, result)
+        })
+        .await
+        .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()));
+
+        self.writer = writer;
+        result
+    }
+
     async fn get_or_create_region(&mut self, coord: IVec2) -> std::io::Result<Arc<Region>> {
         if let Some(region) = self.regions.get(&coord) {
             if let Some(region) = region.upgrade() {
@@ -123,12 +229,35 @@ impl RegionManagerTask {
     }
 
     async fn create_and_insert_region(&mut self, coord: IVec2) -> std::io::Result<Arc<Region>> {
-        let file = self.region_file(coord.x, coord.y).await?;
+        let file = self.region_file(coord.x, coord.y).await?.into_std().await;
         let region =
-            Region::open(&file).map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+            Region::open(file).map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
         let region = Arc::new(region);
         let region_weak = Arc::downgrade(&region);
         self.regions.insert(coord, region_weak);
         Ok(region)
     }
 }
+
+impl RegionWriter {
+    fn save_chunk(&mut self, pos_x: i32, pos_z: i32, chunk: &Compound) -> Result<(), RegionError> {
+        let coord = region_coord(pos_x, pos_z);
+
+        let region = match self.writers.entry(coord) {
+            std::collections::hash_map::Entry::Occupied(entry) => entry.into_mut(),
+            std::collections::hash_map::Entry::Vacant(entry) => {
+                let path = self.root.join(format!("r.{}.{}.mca", coord.x, coord.y));
+                entry.insert(Region::open_writable(&path)?)
+            }
+        };
+
+        region.set_chunk(
+            pos_x,
+            pos_z,
+            chunk,
+            Compression::Zlib,
+            &mut self.compress_buf,
+            &self.root,
+        )
+    }
+}
@@ -10,36 +10,44 @@ use flecs_ecs::{
     core::{Entity, World, WorldGet},
     macros::Component,
 };
+use generator::{VoidGenerator, WorldGenerator};
 use geometry::ray::Ray;
 use glam::{I16Vec2, IVec2, IVec3, Vec3};
 use indexmap::IndexMap;
-use loader::{ChunkLoaderHandle, launch_loader};
+use loader::{ChunkLoaderHandle, launch_generator_loader, launch_loader};
 use rayon::iter::ParallelIterator;
 use roaring::RoaringBitmap;
 use rustc_hash::FxBuildHasher;
+use saver::{ChunkSaverHandle, launch_saver};
 use shared::WorldShared;
 use tracing::error;
-use valence_generated::block::BlockState;
+use valence_generated::block::{BlockEntityKind, BlockState};
+use valence_nbt::{Compound, compound};
 use valence_server::layer::chunk::Chunk;
 
 use crate::{
     CHUNK_HEIGHT_SPAN,
     runtime::AsyncRuntime,
-    simulation::{
-        blocks::loader::{launch_empty_loader, parse::section::Section},
-        util::generate_biome_registry,
-    },
+    simulation::{blocks::loader::parse::section::Section, util::generate_biome_registry},
 };
 
+pub mod block_entity;
 pub mod chunk;
+pub mod generator;
+mod light;
 
 mod loader;
 mod manager;
 
 pub mod frame;
+pub use loader::parse::ColumnData;
 mod region;
+mod saver;
 mod shared;
 
+/// How often modified chunks are written back to the region files.
+pub const AUTOSAVE_INTERVAL_TICKS: i64 = 20 * 60;
+
 pub enum GetChunk<'a> {
     Loaded(&'a Column),
     Loading,
@@ -55,6 +63,8 @@ pub struct EntityAndSequence {
 pub enum TrySetBlockDeltaError {
     OutOfBounds,
     ChunkNotLoaded,
+    /// The block at the position cannot have a block entity.
+    NoBlockEntity,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -72,12 +82,18 @@ pub struct Blocks {
     /// Map to a Chunk by Entity ID
     chunk_cache: IndexMap<I16Vec2, Column, FxBuildHasher>,
     should_update: RoaringBitmap,
+    /// Indices of chunks modified since they were last written to disk
+    should_save: RoaringBitmap,
 
     loader_handle: ChunkLoaderHandle,
+    /// `None` if the world is not backed by region files
+    saver_handle: Option<ChunkSaverHandle>,
 
     tx_loaded_chunks: tokio::sync::mpsc::UnboundedSender<Column>,
     rx_loaded_chunks: tokio::sync::mpsc::UnboundedReceiver<Column>,
     pub to_confirm: Vec<EntityAndSequence>,
+    /// Blocks changed since light was last updated. See [`Self::update_light`].
+    light_queue: Vec<IVec3>,
 }
 
 impl From<ChunkLoaderHandle> for Blocks {
@@ -86,16 +102,29 @@ impl From<ChunkLoaderHandle> for Blocks {
         Self {
             chunk_cache: IndexMap::default(),
             should_update: RoaringBitmap::default(),
+            should_save: RoaringBitmap::default(),
             loader_handle,
+            saver_handle: None,
             tx_loaded_chunks,
             rx_loaded_chunks,
             to_confirm: vec![],
+            light_queue: vec![],
         }
     }
 }
 
 impl Blocks {
+    /// Loads the world saved at `path`. Chunks missing from the save are left empty.
     pub fn new(world: &World, path: &Path) -> anyhow::Result<Self> {
+        Self::with_generator(world, path, VoidGenerator)
+    }
+
+    /// Loads the world saved at `path`, using `generator` for chunks missing from the save.
+    pub fn with_generator(
+        world: &World,
+        path: &Path,
+        generator: impl WorldGenerator,
+    ) -> anyhow::Result<Self> {
         world.get::<&AsyncRuntime>(|runtime| {
             let biome_registry =
                 generate_biome_registry().context("failed to generate biome registry")?;
@@ -103,9 +132,11 @@ impl Blocks {
             let shared = WorldShared::new(&biome_registry, runtime, path)?;
             let shared = Arc::new(shared);
 
-            let loader_handle = launch_loader(shared, runtime);
+            let loader_handle = launch_loader(shared.clone(), Arc::new(generator), runtime);
+            let saver_handle = launch_saver(shared, runtime);
 
-            let result = Self::from(loader_handle);
+            let mut result = Self::from(loader_handle);
+            result.saver_handle = Some(saver_handle);
 
             Ok(result)
         })
@@ -113,8 +144,14 @@ impl Blocks {
 
     #[must_use]
     pub fn empty(world: &World) -> Self {
+        Self::generated(world, VoidGenerator)
+    }
+
+    /// A world which is not backed by region files; every chunk comes from `generator`.
+    #[must_use]
+    pub fn generated(world: &World, generator: impl WorldGenerator) -> Self {
         world.get::<&AsyncRuntime>(|runtime| {
-            let loader_handle = launch_empty_loader(runtime);
+            let loader_handle = launch_generator_loader(Arc::new(generator), runtime);
             Self::from(loader_handle)
         })
     }
@@ -199,6 +236,37 @@ impl Blocks {
         self.should_update.clear();
     }
 
+    /// Sends every chunk modified since the last save to the background saver. The saver shares
+    /// the sections of the chunks instead of copying them.
+    pub fn save_dirty(&mut self) {
+        let Some(saver) = &self.saver_handle else {
+            self.should_save.clear();
+            return;
+        };
+
+        for idx in &self.should_save {
+            let (&position, column) = self.chunk_cache.get_index(idx as usize).unwrap();
+            saver.save(position, column.data.clone());
+        }
+
+        self.should_save.clear();
+    }
+
+    /// Saves all modified chunks and blocks until they have been written to disk.
+    pub fn save_all(&mut self, runtime: &AsyncRuntime) {
+        self.save_dirty();
+
+        let Some(saver) = &self.saver_handle else {
+            return;
+        };
+
+        let done = saver.flush();
+
+        if runtime.block_on(done).is_err() {
+            error!("chunk saver stopped before all chunks were saved");
+        }
+    }
+
     pub const fn cache_mut(&mut self) -> &mut IndexMap<I16Vec2, Column, FxBuildHasher> {
         &mut self.chunk_cache
     }
@@ -249,6 +317,13 @@ impl Blocks {
         }
     }
 
+    /// The number of chunks which have been requested from the loader but are not in the cache
+    /// yet.
+    #[must_use]
+    pub fn loader_backlog(&self) -> usize {
+        self.loader_handle.in_flight() + self.rx_loaded_chunks.len()
+    }
+
     /// Returns the unloaded chunk if it is loaded, otherwise `None`.
     // todo: return type: what do you think about the type right here?
     // This seems really complicated.
@@ -423,12 +498,100 @@ impl Blocks {
         let old_state = chunk.data.set_delta(x, y, z, state);
 
         if old_state != state {
-            self.should_update.insert(u32::try_from(chunk_idx).unwrap());
+            // the client drops the block entity itself when the block changes, even if the new
+            // block has the same kind of block entity
+            if old_state.to_kind() != state.to_kind() {
+                chunk.data.set_block_entity(x, y, z, None);
+            }
+
+            let chunk_idx = u32::try_from(chunk_idx).unwrap();
+            self.should_update.insert(chunk_idx);
+            self.should_save.insert(chunk_idx);
+
+            self.light_queue.push(position);
         }
 
         Ok(old_state)
     }
 
+    /// The block entity (e.g. the text of a sign) at `position`, if its chunk is loaded. See
+    /// [`block_entity`] for typed access.
+    #[must_use]
+    pub fn get_block_entity(&self, position: IVec3) -> Option<&Compound> {
+        let (chunk_pos, [x, y, z]) = column_offset(position)?;
+        self.get_loaded_chunk(chunk_pos)?.data.block_entity(x, y, z)
+    }
+
+    /// Replaces the block entity at `position` and sends it to players on the next tick. `None`
+    /// resets it to the default of the block, e.g. a blank sign. Returns the old block entity.
+    pub fn set_block_entity(
+        &mut self,
+        position: IVec3,
+        block_entity: Option<Compound>,
+    ) -> Result<Option<Compound>, TrySetBlockDeltaError> {
+        self.modify_block_entity(position, |current, kind| {
+            let Some(mut block_entity) = block_entity else {
+                return current.take();
+            };
+
+            // the id is needed to save the block entity
+            if !block_entity.contains_key("id") {
+                block_entity.insert("id", kind.ident().to_string());
+            }
+
+            current.replace(block_entity)
+        })
+    }
+
+    /// Calls `f` with the block entity at `position`, which is created if the block does not have
+    /// one yet, and sends the result to players on the next tick.
+    pub fn update_block_entity<R>(
+        &mut self,
+        position: IVec3,
+        f: impl FnOnce(&mut Compound) -> R,
+    ) -> Result<R, TrySetBlockDeltaError> {
+        self.modify_block_entity(position, |current, kind| {
+            let block_entity = current.get_or_insert_with(|| {
+                compound! {
+                    "id" => kind.ident().to_string(),
+                }
+            });
+
+            f(block_entity)
+        })
+    }
+
+    fn modify_block_entity<R>(
+        &mut self,
+        position: IVec3,
+        f: impl FnOnce(&mut Option<Compound>, BlockEntityKind) -> R,
+    ) -> Result<R, TrySetBlockDeltaError> {
+        let (chunk_pos, [x, y, z]) =
+            column_offset(position).ok_or(TrySetBlockDeltaError::OutOfBounds)?;
+
+        let Some((chunk_idx, _, chunk)) = self.chunk_cache.get_full_mut(&chunk_pos) else {
+            return Err(TrySetBlockDeltaError::ChunkNotLoaded);
+        };
+
+        let kind = chunk
+            .data
+            .block_state(x, y, z)
+            .block_entity_kind()
+            .ok_or(TrySetBlockDeltaError::NoBlockEntity)?;
+
+        let mut block_entity = chunk.data.set_block_entity(x, y, z, None);
+        let result = f(&mut block_entity, kind);
+        chunk.data.set_block_entity(x, y, z, block_entity);
+
+        chunk.mark_block_entity_changed(chunk::block_entity_idx(x, y, z));
+
+        let chunk_idx = u32::try_from(chunk_idx).unwrap();
+        self.should_update.insert(chunk_idx);
+        self.should_save.insert(chunk_idx);
+
+        Ok(result)
+    }
+
     // todo: allow modifying the chunk. we will need to implement resending
     // So,
     // for instance, if a player modifies a chunk, we're going to need to rebroadcast it to all the players in that region.
@@ -458,3 +621,19 @@ impl Blocks {
         GetChunk::Loading
     }
 }
+
+/// Splits a block position into its chunk and its position relative to the bottom of that chunk,
+/// or `None` if it is outside the height of the world.
+fn column_offset(position: IVec3) -> Option<(I16Vec2, [u32; 3])> {
+    let y = u32::try_from(position.y - i32::from(chunk::START_Y)).ok()?;
+
+    if y >= CHUNK_HEIGHT_SPAN {
+        return None;
+    }
+
+    let chunk_pos = IVec2::new(position.x, position.z) >> 4;
+    let x = u32::try_from(position.x & 0b1111).unwrap();
+    let z = u32::try_from(position.z & 0b1111).unwrap();
+
+    Some((chunk_pos.as_i16vec2(), [x, y, z]))
+}
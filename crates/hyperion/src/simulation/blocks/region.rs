@@ -1,14 +1,17 @@
 use std::{
     hash::Hash,
-    io::Read,
+    io::{ErrorKind, Read, Seek, SeekFrom, Write},
     path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
 };
 
 use bitfield_struct::bitfield;
-use flate2::bufread::{GzDecoder, ZlibDecoder};
-use tokio::fs::File;
+use flate2::{
+    bufread::{GzDecoder, ZlibDecoder},
+    write::{GzEncoder, ZlibEncoder},
+};
 use valence_anvil::{Compression, RawChunk, RegionError};
-use valence_nbt::binary::FromModifiedUtf8;
+use valence_nbt::{Compound, binary::FromModifiedUtf8};
 
 #[bitfield(u32)]
 struct Location {
@@ -31,16 +34,22 @@ use memmap2::MmapOptions;
 
 #[derive(Debug)]
 pub struct Region {
+    file: std::fs::File,
     mmap: memmap2::Mmap,
     locations: [Location; 1024],
     timestamps: [u32; 1024],
+    used_sectors: bitvec::vec::BitVec,
 }
 
 const SECTOR_SIZE: usize = 4096;
 
+/// The largest number of sectors a chunk can occupy inside the region file. Anything larger is
+/// written to an external `.mcc` file.
+const MAX_CHUNK_SECTORS: usize = u8::MAX as usize;
+
 impl Region {
-    pub fn open(file: &File) -> Result<Self, RegionError> {
-        let mmap = unsafe { MmapOptions::new().map(file)? };
+    pub fn open(file: std::fs::File) -> Result<Self, RegionError> {
+        let mmap = unsafe { MmapOptions::new().map(&file)? };
 
         let Some(header) = &mmap.get(..SECTOR_SIZE * 2) else {
             return Err(RegionError::Io(std::io::Error::new(
@@ -86,13 +95,31 @@ impl Region {
         }
 
         Ok(Self {
+            file,
             mmap,
             locations,
             timestamps,
-            // used_sectors,
+            used_sectors,
         })
     }
 
+    /// Opens a region file for writing, creating it with an empty header if it does not exist yet.
+    pub fn open_writable(path: &Path) -> Result<Self, RegionError> {
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+
+        if file.metadata()?.len() < (SECTOR_SIZE * 2) as u64 {
+            // a fresh (or truncated) region file: every location and timestamp is zero
+            file.set_len((SECTOR_SIZE * 2) as u64)?;
+        }
+
+        Self::open(file)
+    }
+
     pub fn get_chunk<S>(
         &self,
         pos_x: i32,
@@ -136,8 +163,9 @@ impl Region {
             return Err(RegionError::MissingChunkStream);
         }
 
-        // size of this chunk in sectors must always be >= the exact size.
-        if sector_count * SECTOR_SIZE < exact_chunk_size {
+        // size of this chunk in sectors must always be >= the exact size (including the length
+        // prefix).
+        if sector_count * SECTOR_SIZE < exact_chunk_size + 4 {
             return Err(RegionError::InvalidChunkSize);
         }
 
@@ -149,7 +177,10 @@ impl Region {
             let external_mmap = unsafe { MmapOptions::new().map(&external_file)? };
             external_mmap.to_vec().into_boxed_slice()
         } else {
-            chunk_data[5..exact_chunk_size].to_vec().into_boxed_slice()
+            // the length prefix counts the compression byte, so the stream ends at `4 + exact_chunk_size`
+            chunk_data[5..4 + exact_chunk_size]
+                .to_vec()
+                .into_boxed_slice()
         };
 
         let r: &[u8] = data_buf.as_ref();
@@ -157,7 +188,7 @@ impl Region {
         decompress_buf.clear();
 
         // What compression does the chunk use?
-        let mut nbt_slice = match compression_from_u8(compression) {
+        let mut nbt_slice = match compression_from_u8(Self::external_chunk_version(compression)) {
             Some(Compression::Gzip) => {
                 let mut z = GzDecoder::new(r);
                 z.read_to_end(decompress_buf)?;
@@ -186,6 +217,106 @@ impl Region {
         Ok(Some(RawChunk { data, timestamp }))
     }
 
+    /// Writes a chunk to the region file, replacing the previous version of it.
+    ///
+    /// Chunks which do not fit into [`MAX_CHUNK_SECTORS`] sectors are written to an external
+    /// `c.{x}.{z}.mcc` file next to the region file.
+    pub fn set_chunk(
+        &mut self,
+        pos_x: i32,
+        pos_z: i32,
+        chunk: &Compound,
+        compression: Compression,
+        compress_buf: &mut Vec<u8>,
+        region_root: &Path,
+    ) -> Result<(), RegionError> {
+        let chunk_idx = Self::chunk_idx(pos_x, pos_z);
+
+        let Some(compression_id) = compression_to_u8(compression) else {
+            return Err(RegionError::Io(std::io::Error::new(
+                ErrorKind::Unsupported,
+                "unsupported region compression scheme",
+            )));
+        };
+
+        compress_buf.clear();
+
+        match compression {
+            Compression::Gzip => {
+                let mut z = GzEncoder::new(&mut *compress_buf, flate2::Compression::default());
+                valence_nbt::to_binary(chunk, &mut z, "")?;
+                z.finish()?;
+            }
+            Compression::Zlib => {
+                let mut z = ZlibEncoder::new(&mut *compress_buf, flate2::Compression::default());
+                valence_nbt::to_binary(chunk, &mut z, "")?;
+                z.finish()?;
+            }
+            _ => valence_nbt::to_binary(chunk, &mut *compress_buf, "")?,
+        }
+
+        // 4 bytes of length and 1 byte of compression scheme precede the stream
+        let sector_count = (compress_buf.len() + 5).div_ceil(SECTOR_SIZE);
+
+        let external = sector_count > MAX_CHUNK_SECTORS;
+        let external_path = Self::external_chunk_file(pos_x, pos_z, region_root);
+
+        let (stream, compression_id, sector_count) = if external {
+            std::fs::write(&external_path, &compress_buf)?;
+            (&[][..], compression_id | 0x80, 1)
+        } else {
+            Self::delete_external_chunk_file(&external_path)?;
+            (compress_buf.as_slice(), compression_id, sector_count)
+        };
+
+        // free the sectors of the previous version of this chunk before looking for space
+        let previous = self.locations[chunk_idx];
+        if !previous.is_none() {
+            let (sector_offset, previous_count) = previous.offset_and_count();
+            Self::release_sectors(&mut self.used_sectors, sector_offset, previous_count);
+        }
+
+        let sector_offset = Self::allocate_sectors(&self.used_sectors, sector_count);
+        Self::reserve_sectors(&mut self.used_sectors, sector_offset, sector_count);
+
+        let mut sectors = Vec::with_capacity(sector_count * SECTOR_SIZE);
+        sectors.extend_from_slice(&u32::try_from(stream.len() + 1).unwrap().to_be_bytes());
+        sectors.push(compression_id);
+        sectors.extend_from_slice(stream);
+        sectors.resize(sector_count * SECTOR_SIZE, 0);
+
+        // the chunk data must be on disk before the header points at it
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(sector_offset * SECTOR_SIZE as u64))?;
+        file.write_all(&sectors)?;
+
+        let location = Location::new()
+            .with_offset(u32::try_from(sector_offset).unwrap())
+            .with_count(u8::try_from(sector_count).unwrap());
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| {
+                u32::try_from(duration.as_secs()).unwrap_or(u32::MAX)
+            });
+
+        file.seek(SeekFrom::Start((chunk_idx * 4) as u64))?;
+        file.write_all(&location.0.to_be_bytes())?;
+
+        file.seek(SeekFrom::Start((SECTOR_SIZE + chunk_idx * 4) as u64))?;
+        file.write_all(&timestamp.to_be_bytes())?;
+
+        self.locations[chunk_idx] = location;
+        self.timestamps[chunk_idx] = timestamp;
+
+        Ok(())
+    }
+
+    /// Flushes all written chunks to disk.
+    pub fn sync(&self) -> std::io::Result<()> {
+        self.file.sync_data()
+    }
+
     // fn chunk_positions(
     //     &self,
     //     region_x: i32,
@@ -214,17 +345,13 @@ impl Region {
             .join(format!("c.{pos_x}.{pos_z}.mcc"))
     }
 
-    // fn delete_external_chunk_file(
-    //     pos_x: i32,
-    //     pos_z: i32,
-    //     region_root: &Path,
-    // ) -> Result<(), RegionError> {
-    //     match std::fs::remove_file(Self::external_chunk_file(pos_x, pos_z, region_root)) {
-    //         Ok(()) => Ok(()),
-    //         Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
-    //         Err(err) => Err(err.into()),
-    //     }
-    // }
+    fn delete_external_chunk_file(path: &Path) -> Result<(), RegionError> {
+        match std::fs::remove_file(path) {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(err.into()),
+        }
+    }
 
     fn reserve_sectors(
         used_sectors: &mut bitvec::vec::BitVec,
@@ -241,6 +368,42 @@ impl Region {
         }
     }
 
+    fn release_sectors(
+        used_sectors: &mut bitvec::vec::BitVec,
+        sector_offset: u64,
+        sector_count: usize,
+    ) {
+        let start_index = usize::try_from(sector_offset).unwrap().max(2);
+        let end_index =
+            (usize::try_from(sector_offset).unwrap() + sector_count).min(used_sectors.len());
+
+        if start_index < end_index {
+            used_sectors[start_index..end_index].fill(false);
+        }
+    }
+
+    /// Finds the first run of `sector_count` free sectors. If there is none, the returned offset
+    /// points at the (possibly partially free) tail of the file.
+    fn allocate_sectors(used_sectors: &bitvec::vec::BitVec, sector_count: usize) -> u64 {
+        let mut run_start = 2;
+        let mut run_len = 0;
+
+        for (idx, used) in used_sectors.iter().by_vals().enumerate().skip(2) {
+            if used {
+                run_start = idx + 1;
+                run_len = 0;
+                continue;
+            }
+
+            run_len += 1;
+            if run_len == sector_count {
+                break;
+            }
+        }
+
+        run_start as u64
+    }
+
     #[expect(clippy::cast_sign_loss, reason = "todo")]
     const fn chunk_idx(pos_x: i32, pos_z: i32) -> usize {
         (pos_x.rem_euclid(32) + pos_z.rem_euclid(32) * 32) as usize
@@ -250,7 +413,6 @@ impl Region {
         (stream_version & 0x80) != 0
     }
 
-    #[expect(unused, reason = "todo")]
     const fn external_chunk_version(stream_version: u8) -> u8 {
         stream_version & !0x80
     }
@@ -264,3 +426,88 @@ const fn compression_from_u8(compression: u8) -> Option<Compression> {
         _ => None,
     }
 }
+
+const fn compression_to_u8(compression: Compression) -> Option<u8> {
+    match compression {
+        Compression::Gzip => Some(1),
+        Compression::Zlib => Some(2),
+        Compression::None => Some(3),
+        #[allow(
+            unreachable_patterns,
+            reason = "other schemes depend on valence_anvil features"
+        )]
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use valence_nbt::{Value, compound};
+
+    use super::*;
+
+    #[test]
+    fn test_set_chunk_round_trip() {
+        let root = std::env::temp_dir().join(format!("hyperion-region-{}", fastrand::u64(..)));
+        std::fs::create_dir_all(&root).unwrap();
+        let path = root.join("r.0.0.mca");
+
+        let chunk = compound! {
+            "xPos" => 3,
+            "zPos" => 7,
+            "Status" => "minecraft:full",
+        };
+
+        let mut buf = Vec::new();
+
+        let mut region = Region::open_writable(&path).unwrap();
+        region
+            .set_chunk(3, 7, &chunk, Compression::Zlib, &mut buf, &root)
+            .unwrap();
+        region.sync().unwrap();
+        drop(region);
+
+        let region = Region::open(std::fs::File::open(&path).unwrap()).unwrap();
+        let raw = region
+            .get_chunk::<String>(3, 7, &mut buf, &root)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(raw.data.get("xPos"), Some(&Value::Int(3)));
+        assert_eq!(raw.data.get("zPos"), Some(&Value::Int(7)));
+        assert!(
+            region
+                .get_chunk::<String>(0, 0, &mut buf, &root)
+                .unwrap()
+                .is_none()
+        );
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_set_chunk_reuses_freed_sectors() {
+        let root = std::env::temp_dir().join(format!("hyperion-region-{}", fastrand::u64(..)));
+        std::fs::create_dir_all(&root).unwrap();
+        let path = root.join("r.0.0.mca");
+
+        let chunk = compound! { "xPos" => 0 };
+        let mut buf = Vec::new();
+
+        let mut region = Region::open_writable(&path).unwrap();
+        region
+            .set_chunk(0, 0, &chunk, Compression::None, &mut buf, &root)
+            .unwrap();
+        let first = region.locations[0].offset_and_count();
+
+        region
+            .set_chunk(0, 0, &chunk, Compression::None, &mut buf, &root)
+            .unwrap();
+        let second = region.locations[0].offset_and_count();
+
+        assert_eq!(first, (2, 1));
+        assert_eq!(first, second);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
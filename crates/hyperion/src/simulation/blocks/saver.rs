@@ -0,0 +1,69 @@
+use std::sync::Arc;
+
+use glam::I16Vec2;
+use tokio::sync::{mpsc, oneshot};
+use tracing::{debug, error};
+
+use super::{
+    loader::{parse::ColumnData, serialize::serialize_chunk},
+    shared::WorldShared,
+};
+use crate::runtime::AsyncRuntime;
+
+enum SaveRequest {
+    Chunk { position: I16Vec2, data: ColumnData },
+    Flush { done: oneshot::Sender<()> },
+}
+
+/// Writes modified columns back to the region files of the world on a background task.
+pub struct ChunkSaverHandle {
+    tx_save_requests: mpsc::UnboundedSender<SaveRequest>,
+}
+
+impl ChunkSaverHandle {
+    pub fn save(&self, position: I16Vec2, data: ColumnData) {
+        self.tx_save_requests
+            .send(SaveRequest::Chunk { position, data })
+            .unwrap();
+    }
+
+    /// The returned receiver completes once every chunk sent before this call is on disk.
+    pub fn flush(&self) -> oneshot::Receiver<()> {
+        let (done, rx) = oneshot::channel();
+        self.tx_save_requests
+            .send(SaveRequest::Flush { done })
+            .unwrap();
+        rx
+    }
+}
+
+pub fn launch_saver(shared: Arc<WorldShared>, runtime: &AsyncRuntime) -> ChunkSaverHandle {
+    let (tx_save_requests, mut rx_save_requests) = mpsc::unbounded_channel();
+
+    runtime.spawn(async move {
+        while let Some(request) = rx_save_requests.recv().await {
+            match request {
+                SaveRequest::Chunk { position, data } => {
+                    let nbt = serialize_chunk(&data, position.as_ivec2(), &shared.id_to_biome);
+
+                    if let Err(e) = shared.regions.save_chunk(position.x, position.y, nbt).await {
+                        error!("failed to save chunk {position}: {e}");
+                        continue;
+                    }
+
+                    debug!("saved chunk {position}");
+                }
+                SaveRequest::Flush { done } => {
+                    if let Err(e) = shared.regions.flush().await {
+                        error!("failed to flush region files: {e}");
+                    }
+
+                    // the caller may have stopped waiting
+                    drop(done.send(()));
+                }
+            }
+        }
+    });
+
+    ChunkSaverHandle { tx_save_requests }
+}
@@ -11,6 +11,7 @@ use super::manager::RegionManager;
 pub struct WorldShared {
     pub regions: RegionManager,
     pub biome_to_id: BTreeMap<Ident<String>, BiomeId>,
+    pub id_to_biome: BTreeMap<BiomeId, Ident<String>>,
 }
 
 impl WorldShared {
@@ -21,14 +22,20 @@ impl WorldShared {
     ) -> anyhow::Result<Self> {
         let regions = RegionManager::new(runtime, path).context("failed to get anvil data")?;
 
-        let biome_to_id = biomes
+        let biome_to_id: BTreeMap<_, _> = biomes
             .iter()
             .map(|(id, name, _)| (name.to_string_ident(), id))
             .collect();
 
+        let id_to_biome = biome_to_id
+            .iter()
+            .map(|(name, &id)| (id, name.clone()))
+            .collect();
+
         Ok(Self {
             regions,
             biome_to_id,
+            id_to_biome,
         })
     }
 }
@@ -0,0 +1,283 @@
+//! Plugin messaging: namespaced channels which mods and plugins use to exchange data over custom
+//! payload packets.
+//!
+//! A module registers a [`Channel`] and its handler with the [`ChannelRegistry`] singleton. The
+//! channels a client listens on are tracked in its [`RegisteredChannels`] from the
+//! `minecraft:register` and `minecraft:unregister` channels, and the server answers a client's
+//! `minecraft:register` with its own channels.
+
+use std::collections::HashMap;
+
+use anyhow::Context;
+use flecs_ecs::{
+    core::{EntityView, EntityViewGet, WorldGet},
+    macros::Component,
+};
+use glam::I16Vec2;
+use hyperion_utils::LifetimeHandle;
+use rustc_hash::{FxBuildHasher, FxHashSet};
+use tracing::debug;
+use valence_ident::Ident;
+use valence_protocol::{Decode, Encode, RawBytes, packets::play};
+
+use crate::{
+    net::{Compose, ConnectionId},
+    simulation::handlers::PacketSwitchQuery,
+};
+
+/// The channel clients and servers announce the channels they listen on with.
+const REGISTER: &str = "minecraft:register";
+
+/// The channel clients and servers stop listening on channels with.
+const UNREGISTER: &str = "minecraft:unregister";
+
+/// How many channels a client may listen on, as in Bukkit. More are ignored.
+const MAX_CHANNELS: usize = 128;
+
+/// The longest channel name a client may listen on, as in Bukkit. Longer names are ignored.
+const MAX_CHANNEL_NAME_LEN: usize = 64;
+
+/// A namespaced plugin channel and the messages sent over it.
+pub trait Channel: 'static {
+    /// The name of the channel, such as `voicechat:request_secret`.
+    const NAME: &'static str;
+
+    type Message: Encode + for<'a> Decode<'a>;
+}
+
+type ChannelHandler =
+    Box<dyn Fn(&[u8], &mut PacketSwitchQuery<'_>) -> anyhow::Result<()> + Send + Sync>;
+
+/// The channels the server listens on. Payloads on other channels are ignored.
+#[derive(Component, Default)]
+pub struct ChannelRegistry {
+    handlers: HashMap<&'static str, ChannelHandler, FxBuildHasher>,
+}
+
+impl ChannelRegistry {
+    /// Calls `handler` with every message a client sends on `C`.
+    ///
+    /// # Panics
+    /// If `C` is already registered.
+    pub fn register<C: Channel>(
+        &mut self,
+        handler: impl Fn(C::Message, &mut PacketSwitchQuery<'_>) -> anyhow::Result<()>
+        + Send
+        + Sync
+        + 'static,
+    ) {
+        let handler: ChannelHandler = Box::new(move |mut bytes, query| {
+            let message = C::Message::decode(&mut bytes)
+                .with_context(|| format!("failed to decode message on {}", C::NAME))?;
+
+            handler(message, query)
+        });
+
+        let previous = self.handlers.insert(C::NAME, handler);
+        assert!(
+            previous.is_none(),
+            "channel {} is registered twice",
+            C::NAME
+        );
+    }
+
+    /// The names of the registered channels.
+    pub fn channels(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.handlers.keys().copied()
+    }
+
+    #[must_use]
+    pub fn contains(&self, channel: &str) -> bool {
+        self.handlers.contains_key(channel)
+    }
+}
+
+/// The channels a player's client listens on.
+#[derive(Component, Default, Debug)]
+pub struct RegisteredChannels {
+    channels: FxHashSet<String>,
+    /// Whether the client was sent the server's channels, which is done once.
+    announced: bool,
+}
+
+impl RegisteredChannels {
+    #[must_use]
+    pub fn contains(&self, channel: &str) -> bool {
+        self.channels.contains(channel)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        self.channels.iter().map(String::as_str)
+    }
+
+    /// Adds the channels in `names` up to [`MAX_CHANNELS`], skipping names longer than
+    /// [`MAX_CHANNEL_NAME_LEN`]. Returns how many were ignored.
+    fn register<'a>(&mut self, names: impl Iterator<Item = &'a str>) -> usize {
+        let mut ignored = 0;
+
+        for name in names {
+            if name.len() > MAX_CHANNEL_NAME_LEN || self.channels.len() >= MAX_CHANNELS {
+                ignored += 1;
+                continue;
+            }
+
+            self.channels.insert(name.to_owned());
+        }
+
+        ignored
+    }
+}
+
+/// Splits the payload of `minecraft:register` or `minecraft:unregister` into channel names.
+fn channel_list(bytes: &[u8]) -> impl Iterator<Item = &str> {
+    bytes
+        .split(|&b| b == 0)
+        .filter_map(|name| std::str::from_utf8(name).ok())
+        .filter(|name| !name.is_empty())
+}
+
+fn payload<'a>(channel: &'a str, data: &'a [u8]) -> anyhow::Result<play::CustomPayloadS2c<'a>> {
+    let channel = Ident::new(channel).with_context(|| format!("invalid channel {channel}"))?;
+
+    Ok(play::CustomPayloadS2c {
+        channel,
+        data: RawBytes::from(data).into(),
+    })
+}
+
+/// Sends `message` on `C` to `player` if their client listens on it. Returns whether it was
+/// sent.
+pub fn send<C: Channel>(
+    message: &C::Message,
+    player: EntityView<'_>,
+    compose: &Compose,
+    system: EntityView<'_>,
+) -> anyhow::Result<bool> {
+    let listening = player.get::<&RegisteredChannels>(|channels| channels.contains(C::NAME));
+
+    if !listening {
+        return Ok(false);
+    }
+
+    let mut data = Vec::new();
+    message.encode(&mut data)?;
+
+    let io = player.get::<&ConnectionId>(|io| *io);
+    compose.unicast(&payload(C::NAME, &data)?, io, system)?;
+
+    Ok(true)
+}
+
+/// Sends `message` on `C` to every player near `center` in the world with the proxy id `world`.
+/// Unlike [`send`], this does not check which players listen on `C`, which clients without the
+/// channel ignore.
+pub fn broadcast_local<C: Channel>(
+    message: &C::Message,
+    center: I16Vec2,
+    world: u64,
+    compose: &Compose,
+    system: EntityView<'_>,
+) -> anyhow::Result<()> {
+    let mut data = Vec::new();
+    message.encode(&mut data)?;
+
+    compose
+        .broadcast_local(&payload(C::NAME, &data)?, center, system)
+        .world(world)
+        .send()
+}
+
+pub fn custom_payload(
+    pkt: &play::CustomPayloadC2s<'_>,
+    _: &dyn LifetimeHandle<'_>,
+    query: &mut PacketSwitchQuery<'_>,
+) -> anyhow::Result<()> {
+    let channel = pkt.channel.as_str();
+    let data = pkt.data.0.0;
+
+    match channel {
+        REGISTER => {
+            let announced = query.view.get::<&mut RegisteredChannels>(|registered| {
+                let ignored = registered.register(channel_list(data));
+                if ignored > 0 {
+                    debug!("ignoring {ignored} channels past the limits of a client");
+                }
+
+                std::mem::replace(&mut registered.announced, true)
+            });
+
+            if announced {
+                return Ok(());
+            }
+
+            // announce the server's channels so that the client sends on them
+            let channels = query.world.get::<&ChannelRegistry>(|registry| {
+                let mut channels = Vec::new();
+                for name in registry.channels() {
+                    channels.extend_from_slice(name.as_bytes());
+                    channels.push(0);
+                }
+                channels
+            });
+
+            if !channels.is_empty() {
+                query.compose.unicast(
+                    &payload(REGISTER, &channels)?,
+                    query.io_ref,
+                    query.system,
+                )?;
+            }
+        }
+        UNREGISTER => {
+            query.view.get::<&mut RegisteredChannels>(|registered| {
+                for name in channel_list(data) {
+                    registered.channels.remove(name);
+                }
+            });
+        }
+        _ => {
+            let world = query.world;
+            world.get::<&ChannelRegistry>(|registry| {
+                let Some(handler) = registry.handlers.get(channel) else {
+                    debug!("ignoring payload on unregistered channel {channel}");
+                    return Ok(());
+                };
+
+                handler(data, query)
+            })?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MAX_CHANNEL_NAME_LEN, MAX_CHANNELS, RegisteredChannels, channel_list};
+
+    #[test]
+    fn test_channel_list() {
+        let channels: Vec<_> =
+            channel_list(b"voicechat:request_secret\0fabric:registry/sync\0").collect();
+
+        assert_eq!(channels, [
+            "voicechat:request_secret",
+            "fabric:registry/sync"
+        ]);
+    }
+
+    #[test]
+    fn test_register_limits() {
+        let mut registered = RegisteredChannels::default();
+
+        let too_long = "a".repeat(MAX_CHANNEL_NAME_LEN + 1);
+        assert_eq!(registered.register([too_long.as_str()].into_iter()), 1);
+        assert!(!registered.contains(&too_long));
+
+        let names: Vec<_> = (0..MAX_CHANNELS + 10)
+            .map(|i| format!("test:channel_{i}"))
+            .collect();
+        assert_eq!(registered.register(names.iter().map(String::as_str)), 10);
+        assert_eq!(registered.iter().count(), MAX_CHANNELS);
+    }
+}
@@ -12,6 +12,8 @@ use valence_protocol::{
 #[derive(Component)]
 pub struct Command {
     data: NodeData,
+    /// Whether the command can run when it ends at this node.
+    executable: bool,
     has_permission: fn(world: &World, caller: Entity) -> bool,
 }
 
@@ -25,6 +27,7 @@ pub fn get_root_command_entity() -> Entity {
 impl Command {
     pub const ROOT: Self = Self {
         data: NodeData::Root,
+        executable: false,
         has_permission: |_: _, _: _| true,
     };
 
@@ -36,6 +39,7 @@ impl Command {
         let name = name.into();
         Self {
             data: NodeData::Literal { name },
+            executable: true,
             has_permission,
         }
     }
@@ -49,9 +53,18 @@ impl Command {
                 parser,
                 suggestion: Some(Suggestion::AskServer),
             },
+            executable: true,
             has_permission: |_: _, _: _| true,
         }
     }
+
+    /// Sets whether the command can run when it ends at this node, such as when the arguments
+    /// after it are optional. Nodes are executable by default.
+    #[must_use]
+    pub const fn executable(mut self, executable: bool) -> Self {
+        self.executable = executable;
+        self
+    }
 }
 
 // we want a get command packet
@@ -107,7 +120,7 @@ pub fn get_command_packet(
 
                 commands.push(Node {
                     data: command.data.clone(),
-                    executable: true,
+                    executable: command.executable,
                     children: Vec::new(),
                     redirect_node: None,
                 });
@@ -161,6 +174,7 @@ mod tests {
                 data: NodeData::Literal {
                     name: "test".to_string(),
                 },
+                executable: true,
                 has_permission: |_: _, _: _| true,
             })
             .child_of_id(root);
@@ -189,6 +203,7 @@ mod tests {
                 data: NodeData::Literal {
                     name: "parent".to_string(),
                 },
+                executable: true,
                 has_permission: |_: _, _: _| true,
             })
             .child_of_id(root);
@@ -199,6 +214,7 @@ mod tests {
                 data: NodeData::Literal {
                     name: "child".to_string(),
                 },
+                executable: true,
                 has_permission: |_: _, _: _| true,
             })
             .child_of_id(parent);
@@ -217,6 +233,28 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_required_argument() {
+        let world = World::new();
+        world.component::<Command>();
+        let root = world.entity();
+
+        let literal = world
+            .entity()
+            .set(Command::literal("xp", |_: _, _: _| true).executable(false))
+            .child_of_id(root);
+
+        world
+            .entity()
+            .set(Command::argument("amount", Parser::Bool))
+            .child_of_id(literal);
+
+        let packet = get_command_packet(&world, root.id(), None);
+
+        assert!(!packet.commands[1].executable);
+        assert!(packet.commands[2].executable);
+    }
+
     #[test]
     fn test_max_depth() {
         let world = World::new();
@@ -232,6 +270,7 @@ mod tests {
                     data: NodeData::Literal {
                         name: format!("command_{i}"),
                     },
+                    executable: true,
                     has_permission: |_: _, _: _| true,
                 })
                 .child_of_id(parent);
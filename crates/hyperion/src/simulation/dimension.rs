@@ -0,0 +1,326 @@
+//! Several worlds in one server, e.g. lobbies and arenas next to the main map. See [`Dimension`].
+
+use std::borrow::Cow;
+
+use anyhow::Context;
+use derive_more::Deref;
+use flecs_ecs::prelude::*;
+use glam::Vec3;
+use hyperion_utils::EntityExt;
+use tracing::error;
+use valence_protocol::{
+    BlockPos, ByteAngle, GameMode, GlobalPos, Ident, VarInt, VarLong, game_mode::OptGameMode,
+    ident, packets::play,
+};
+
+use crate::{
+    egress::sync_chunks::ChunkSendQueue,
+    net::{Compose, ConnectionId, DataBundle},
+    simulation::{
+        ChunkPosition, MovementTracking, PendingTeleportation, Pitch, Player, Position, Uuid, Yaw,
+        blocks::Blocks,
+    },
+};
+
+/// The diameter of the vanilla world border, which is as good as no border at all.
+const NO_BORDER_DIAMETER: f64 = 59_999_968.0;
+
+/// A world players can be in.
+///
+/// The main world is made of the [`Dimension`] and [`Blocks`] singletons. Every other world is an
+/// entity with its own [`Dimension`] and [`Blocks`], created with [`spawn_world`]. Players outside
+/// the main world have an [`InWorld`] pointing at that entity.
+///
+/// All worlds are as tall as the overworld, so [`Self::dimension_type`] must name a dimension type
+/// with the same height.
+#[derive(Component, Debug, Clone)]
+pub struct Dimension {
+    /// The name sent to clients, e.g. `hyperion:lobby`. Names must be unique: clients only drop
+    /// the chunks they have loaded when they respawn into a world with a different name.
+    pub name: Ident<String>,
+    /// An entry of `minecraft:dimension_type` in the registry codec. This decides the sky and the
+    /// ambient light.
+    pub dimension_type: Ident<String>,
+    /// The diameter of the world border centered on the origin, or `None` for no border.
+    pub border_diameter: Option<f64>,
+}
+
+impl Dimension {
+    /// A world with the overworld dimension type.
+    #[must_use]
+    pub fn new(name: Ident<String>, border_diameter: Option<f64>) -> Self {
+        Self {
+            name,
+            dimension_type: ident!("minecraft:overworld").to_string_ident(),
+            border_diameter,
+        }
+    }
+
+    /// The dimension of the main world.
+    #[must_use]
+    pub fn overworld(border_diameter: Option<f64>) -> Self {
+        Self::new(
+            ident!("minecraft:overworld").to_string_ident(),
+            border_diameter,
+        )
+    }
+
+    /// The packet which moves a client into this world. `copy_metadata` keeps the entity
+    /// metadata (health, pose, ...) of the player, which is what changing worlds without dying
+    /// should do.
+    #[must_use]
+    pub fn respawn_packet(
+        &self,
+        game_mode: GameMode,
+        copy_metadata: bool,
+        last_death_position: Option<BlockPos>,
+    ) -> play::PlayerRespawnS2c<'_> {
+        play::PlayerRespawnS2c {
+            dimension_type_name: self.dimension_type.as_str_ident().into(),
+            dimension_name: self.name.as_str_ident().into(),
+            hashed_seed: 0,
+            game_mode,
+            previous_game_mode: OptGameMode::default(),
+            is_debug: false,
+            is_flat: false,
+            copy_metadata,
+            last_death_location: last_death_position.map(|position| GlobalPos {
+                dimension_name: self.name.as_str_ident().into(),
+                position,
+            }),
+            portal_cooldown: VarInt::default(),
+        }
+    }
+
+    /// The packet which sets up the world border of this world.
+    #[must_use]
+    pub fn border_packet(&self) -> play::WorldBorderInitializeS2c {
+        let diameter = self.border_diameter.unwrap_or(NO_BORDER_DIAMETER);
+
+        play::WorldBorderInitializeS2c {
+            x: 0.0,
+            z: 0.0,
+            old_diameter: diameter,
+            new_diameter: diameter,
+            duration_millis: VarLong(0),
+            portal_teleport_boundary: VarInt(29_999_984),
+            warning_blocks: VarInt(5),
+            warning_time: VarInt(15),
+        }
+    }
+}
+
+/// The world a player is in, for players outside the main world. See [`Dimension`].
+#[derive(Component, Debug, Copy, Clone, PartialEq, Eq, Deref)]
+pub struct InWorld(Entity);
+
+impl InWorld {
+    /// The id of the world used by the proxy to only send local broadcasts to players in the same
+    /// world. The main world is `0`.
+    #[must_use]
+    pub fn proxy_id(in_world: Option<&Self>) -> u64 {
+        in_world.map_or(0, |in_world| in_world.0.0)
+    }
+}
+
+/// Creates a world next to the main one. Move players into it with [`change_world`].
+pub fn spawn_world(world: &World, dimension: Dimension, blocks: Blocks) -> EntityView<'_> {
+    world.entity().set(dimension).set(blocks)
+}
+
+/// Calls `f` with the blocks of the world `in_world` refers to, or of the main world for `None`.
+pub fn with_blocks<R>(
+    world: &World,
+    in_world: Option<&InWorld>,
+    f: impl FnOnce(&mut Blocks) -> R,
+) -> R {
+    match in_world {
+        Some(in_world) => world.entity_from_id(**in_world).get::<&mut Blocks>(f),
+        None => world.get::<&mut Blocks>(f),
+    }
+}
+
+/// Calls `f` with the dimension of the world `in_world` refers to, or of the main world for `None`.
+pub fn with_dimension<R>(
+    world: &World,
+    in_world: Option<&InWorld>,
+    f: impl FnOnce(&Dimension) -> R,
+) -> R {
+    match in_world {
+        Some(in_world) => world.entity_from_id(**in_world).get::<&Dimension>(f),
+        None => world.get::<&Dimension>(f),
+    }
+}
+
+/// Moves `player` into the world `target` at `position`. `None` is the main world.
+///
+/// The client is respawned into the new dimension, which drops its chunks and entities. The chunks
+/// around `position` are then sent again from the [`Blocks`] of the new world.
+pub fn change_world(
+    player: EntityView<'_>,
+    target: Option<Entity>,
+    position: Vec3,
+    compose: &Compose,
+    system: EntityView<'_>,
+) -> anyhow::Result<()> {
+    let world = player.world();
+
+    let dimension = match target {
+        Some(target) => world
+            .entity_from_id(target)
+            .get::<Option<&Dimension>>(|dimension| dimension.cloned())
+            .context("target is not a world")?,
+        None => world.get::<&Dimension>(Clone::clone),
+    };
+
+    let (connection, previous, previous_chunk) = player
+        .get::<(&ConnectionId, Option<&InWorld>, &Position)>(|(connection, in_world, old)| {
+            (*connection, in_world.copied(), old.to_chunk())
+        });
+
+    if previous.map(|in_world| *in_world) == target {
+        return Ok(());
+    }
+
+    // chunks and block changes of the previous world the proxy delayed would otherwise arrive in
+    // the new one
+    compose.io_buf().discard_delayed(
+        connection,
+        InWorld::proxy_id(previous.as_ref()),
+        None,
+        &world,
+    );
+
+    let mut bundle = DataBundle::new(compose, system);
+    bundle.add_packet(&dimension.respawn_packet(GameMode::Survival, true, None))?;
+    bundle.add_packet(&dimension.border_packet())?;
+
+    let entity_id = VarInt(player.minecraft_id());
+    let target_world = target.map(InWorld);
+
+    // the client dropped every entity, so show it the players already in the new world
+    world
+        .query::<(&Uuid, &Position, &Yaw, &Pitch, Option<&InWorld>)>()
+        .with::<Player>()
+        .build()
+        .each_entity(|other, (uuid, other_position, yaw, pitch, in_world)| {
+            if other.id() == player.id() || in_world.copied() != target_world {
+                return;
+            }
+
+            let pkt = play::PlayerSpawnS2c {
+                entity_id: VarInt(other.minecraft_id()),
+                player_uuid: uuid.0,
+                position: other_position.as_dvec3(),
+                yaw: ByteAngle::from_degrees(**yaw),
+                pitch: ByteAngle::from_degrees(**pitch),
+            };
+
+            if let Err(e) = bundle.add_packet(&pkt) {
+                error!("failed to add player spawn packet: {e}");
+            }
+        });
+
+    bundle.unicast(connection)?;
+
+    // players in the previous world would otherwise keep seeing this player where they left
+    let entity_ids = [entity_id];
+    let destroy = play::EntitiesDestroyS2c {
+        entity_ids: Cow::Borrowed(&entity_ids),
+    };
+
+    compose
+        .broadcast_local(&destroy, previous_chunk, system)
+        .world(InWorld::proxy_id(previous.as_ref()))
+        .exclude(connection)
+        .send()?;
+
+    let (uuid, yaw, pitch) =
+        player.get::<(&Uuid, &Yaw, &Pitch)>(|(uuid, yaw, pitch)| (uuid.0, **yaw, **pitch));
+
+    let spawn = play::PlayerSpawnS2c {
+        entity_id,
+        player_uuid: uuid,
+        position: position.as_dvec3(),
+        yaw: ByteAngle::from_degrees(yaw),
+        pitch: ByteAngle::from_degrees(pitch),
+    };
+
+    compose
+        .broadcast_local(&spawn, Position::from(position).to_chunk(), system)
+        .world(InWorld::proxy_id(target_world.as_ref()))
+        .exclude(connection)
+        .send()?;
+
+    match target_world {
+        Some(in_world) => {
+            player.set(in_world);
+        }
+        None => {
+            player.remove::<InWorld>();
+        }
+    }
+
+    player.get::<(&mut Position, &mut MovementTracking)>(|(current, tracking)| {
+        **current = position;
+        tracking.last_tick_position = position;
+        tracking.fall_start_y = position.y;
+    });
+
+    // resend every chunk around the new position
+    player
+        .set(ChunkPosition::null())
+        .set(ChunkSendQueue::default())
+        .set(PendingTeleportation::new(position));
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use flecs_ecs::prelude::*;
+    use valence_protocol::ident;
+
+    use super::{Dimension, InWorld, spawn_world, with_blocks, with_dimension};
+    use crate::{
+        runtime::AsyncRuntime,
+        simulation::blocks::{Blocks, EntityAndSequence},
+    };
+
+    fn worlds() -> (World, Entity) {
+        let world = World::new();
+
+        let (tx, _rx) = kanal::bounded(1);
+        world.set(AsyncRuntime::new(tx));
+
+        world.set(Dimension::overworld(Some(100.0)));
+        world.set(Blocks::empty(&world));
+
+        let lobby = Dimension::new(ident!("hyperion:lobby").to_string_ident(), None);
+        let blocks = Blocks::empty(&world);
+        let lobby = spawn_world(&world, lobby, blocks).id();
+
+        (world, lobby)
+    }
+
+    #[test]
+    fn test_in_world_routing() {
+        let (world, lobby) = worlds();
+        let in_lobby = InWorld(lobby);
+
+        with_blocks(&world, Some(&in_lobby), |blocks| {
+            blocks.to_confirm.push(EntityAndSequence::new(lobby, 1));
+        });
+
+        let main_edits = with_blocks(&world, None, |blocks| blocks.to_confirm.len());
+        let lobby_edits = with_blocks(&world, Some(&in_lobby), |blocks| blocks.to_confirm.len());
+        assert_eq!((main_edits, lobby_edits), (0, 1));
+
+        let name = |in_world| with_dimension(&world, in_world, |dimension| dimension.name.clone());
+        assert_eq!(name(None).as_str(), "minecraft:overworld");
+        assert_eq!(name(Some(&in_lobby)).as_str(), "hyperion:lobby");
+
+        assert_eq!(InWorld::proxy_id(None), 0);
+        assert_eq!(InWorld::proxy_id(Some(&in_lobby)), lobby.0);
+    }
+}
@@ -6,12 +6,15 @@ use hyperion_utils::{Lifetime, RuntimeLifetime};
 use valence_generated::block::BlockState;
 use valence_protocol::{
     Hand, ItemStack,
-    packets::play::click_slot_c2s::{ClickMode, SlotChange},
+    packets::play::{
+        ResourcePackStatusC2s,
+        click_slot_c2s::{ClickMode, SlotChange},
+    },
 };
 use valence_server::ItemKind;
 
-use super::blocks::RayCollision;
-use crate::simulation::skin::PlayerSkin;
+use super::{blocks::RayCollision, dimension::InWorld, movement::MovementViolationKind};
+use crate::{config::Config, simulation::skin::PlayerSkin};
 
 #[derive(Component, Default, Debug)]
 pub struct ItemDropEvent {
@@ -50,6 +53,8 @@ pub struct AttackEntity {
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct StartDestroyBlock {
     pub position: IVec3,
+    /// The world the block is in, or `None` for the main world.
+    pub world: Option<InWorld>,
     pub from: Entity,
     pub sequence: i32,
 }
@@ -57,6 +62,8 @@ pub struct StartDestroyBlock {
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct DestroyBlock {
     pub position: IVec3,
+    /// The world the block is in, or `None` for the main world.
+    pub world: Option<InWorld>,
     pub from: Entity,
     pub sequence: i32,
 }
@@ -64,6 +71,8 @@ pub struct DestroyBlock {
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct PlaceBlock {
     pub position: IVec3,
+    /// The world the block is in, or `None` for the main world.
+    pub world: Option<InWorld>,
     pub block: BlockState,
     pub from: Entity,
     pub sequence: i32,
@@ -72,6 +81,8 @@ pub struct PlaceBlock {
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct ToggleDoor {
     pub position: IVec3,
+    /// The world the block is in, or `None` for the main world.
+    pub world: Option<InWorld>,
     pub from: Entity,
     pub sequence: i32,
 }
@@ -181,3 +192,24 @@ pub struct HitGroundEvent {
     /// This is at least 3
     pub fall_distance: f32,
 }
+
+/// The configuration file was reloaded. The new values are already in the
+/// [`Config`] singleton.
+#[derive(Debug)]
+pub struct ConfigChanged {
+    pub previous: Box<Config>,
+}
+
+/// A player sent movement which is impossible, and was teleported back to where they were.
+#[derive(Clone, Debug)]
+pub struct MovementViolation {
+    pub client: Entity,
+    pub kind: MovementViolationKind,
+}
+
+/// A player's client reported how loading the resource pack in [`Config::resource_pack`] went.
+#[derive(Clone, Debug)]
+pub struct ResourcePackStatus {
+    pub client: Entity,
+    pub status: ResourcePackStatusC2s,
+}
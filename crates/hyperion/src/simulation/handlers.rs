@@ -4,8 +4,10 @@
 #![allow(clippy::unnecessary_wraps)]
 #![allow(clippy::trivially_copy_pass_by_ref)]
 
+use std::time::Instant;
+
 use anyhow::bail;
-use flecs_ecs::core::{Entity, EntityView, EntityViewGet, World};
+use flecs_ecs::core::{Entity, EntityView, EntityViewGet, World, WorldGet};
 use geometry::aabb::Aabb;
 use glam::{DVec3, IVec3, Vec3};
 use hyperion_utils::{EntityExt, LifetimeHandle, RuntimeLifetime};
@@ -24,18 +26,28 @@ use valence_protocol::{
 use valence_text::IntoText;
 
 use super::{
-    ConfirmBlockSequences, EntitySize, Flight, MovementTracking, PendingTeleportation, Position,
+    ClientSettings, ConfirmBlockSequences, EntitySize, Flight, FlyingSpeed, HidesChat, KeepAlive,
+    Latency, MainArm, MovementTracking, PendingTeleportation, Position, RunningSpeed, ViewDistance,
     animation::{self, ActiveAnimation},
     block_bounds,
     blocks::Blocks,
+    channel,
+    dimension::InWorld,
     event::ClientStatusEvent,
     inventory::{handle_click_slot, handle_update_selected_slot},
+    movement::{self, MovementViolationKind},
 };
 use crate::{
+    config::Config,
+    ingress::PendingRemove,
     net::{Compose, ConnectionId, decoder::BorrowedPacketFrame},
     simulation::{
         Pitch, Yaw, aabb, event,
-        metadata::{entity::Pose, living_entity::HandStates},
+        metadata::{
+            entity::Pose,
+            living_entity::HandStates,
+            player::{DisplayedSkinParts, MainHand},
+        },
         packet::HandlerRegistry,
     },
     storage::{CommandCompletionRequest, Events, InteractEvent},
@@ -51,9 +63,6 @@ fn full(
     _: &dyn LifetimeHandle<'_>,
     query: &mut PacketSwitchQuery<'_>,
 ) -> anyhow::Result<()> {
-    // check to see if the player is moving too fast
-    // if they are, ignore the packet
-
     let position = position.as_vec3();
     change_position_or_correct_client(query, position, on_ground);
 
@@ -83,28 +92,88 @@ fn change_position_or_correct_client(
             warn!("Failed to send error message to player: {e}");
         }
 
+        query.events.push(
+            event::MovementViolation {
+                client: query.id,
+                kind: MovementViolationKind::Collision,
+            },
+            query.world,
+        );
+
         query
             .id
             .entity_view(query.world)
             .set(PendingTeleportation::new(pose.position));
+
+        return;
     }
-    query.view.get::<&mut MovementTracking>(|tracking| {
-        tracking.received_movement_packets += 1;
-        let y_delta = proposed.y - pose.y;
-
-        if y_delta > 0. && tracking.was_on_ground && !on_ground {
-            tracking.server_velocity.y = 0.419_999_986_886_978_15;
-
-            if tracking.sprinting {
-                let smth = query.yaw.yaw * 0.017_453_292;
-                tracking.server_velocity += DVec3::new(
-                    f64::from(-smth.sin()) * 0.2,
-                    0.0,
-                    f64::from(smth.cos()) * 0.2,
-                );
+
+    let checked = query.view.get::<(
+        &mut MovementTracking,
+        &Flight,
+        &FlyingSpeed,
+        Option<&RunningSpeed>,
+        Option<&PendingTeleportation>,
+    )>(
+        |(tracking, flight, flying_speed, running_speed, pending_teleport)| {
+            tracking.received_movement_packets += 1;
+            let y_delta = proposed.y - pose.y;
+            let jumped = y_delta > 0. && tracking.was_on_ground && !on_ground;
+
+            // the client moves from where it was until it confirms the teleport
+            if pending_teleport.is_none() {
+                let abilities = movement::Abilities {
+                    flight: *flight,
+                    running_speed: running_speed.map_or(0.1, |speed| speed.0),
+                    flying_speed: flying_speed.speed,
+                };
+
+                let movement = movement::Movement {
+                    from: **pose,
+                    to: proposed,
+                    on_ground,
+                    jumped,
+                };
+
+                let surroundings =
+                    movement::Surroundings::of(**pose, proposed, *query.size, query.blocks);
+
+                if let Err(kind) = movement::check(tracking, abilities, movement, surroundings) {
+                    tracking.horizontal_speed = 0.0;
+                    tracking.vertical_speed = 0.0;
+                    return Err(kind);
+                }
             }
-        }
-    });
+
+            if jumped {
+                tracking.server_velocity.y = 0.419_999_986_886_978_15;
+
+                if tracking.sprinting {
+                    let smth = query.yaw.yaw * 0.017_453_292;
+                    tracking.server_velocity += DVec3::new(
+                        f64::from(-smth.sin()) * 0.2,
+                        0.0,
+                        f64::from(smth.cos()) * 0.2,
+                    );
+                }
+            }
+
+            Ok(())
+        },
+    );
+
+    if let Err(kind) = checked {
+        query.events.push(
+            event::MovementViolation {
+                client: query.id,
+                kind,
+            },
+            query.world,
+        );
+
+        query.view.set(PendingTeleportation::new(pose.position));
+        return;
+    }
 
     **pose = proposed;
 }
@@ -152,7 +221,7 @@ pub fn is_grounded(position: &Vec3, blocks: &Blocks) -> bool {
         .is_air()
 }
 
-fn has_block_collision(position: &Vec3, size: EntitySize, blocks: &Blocks) -> bool {
+pub(super) fn has_block_collision(position: &Vec3, size: EntitySize, blocks: &Blocks) -> bool {
     use std::ops::ControlFlow;
 
     let (min, max) = block_bounds(*position, size);
@@ -273,6 +342,8 @@ pub struct PacketSwitchQuery<'a> {
     pub size: &'a mut EntitySize,
     pub world: &'a World,
     pub blocks: &'a Blocks,
+    /// The world the player is in, which [`Self::blocks`] belong to.
+    pub in_world: Option<InWorld>,
     pub pose: &'a mut Pose,
     pub events: &'a Events,
     pub confirm_block_sequences: &'a mut ConfirmBlockSequences,
@@ -295,6 +366,7 @@ fn player_action(
         PlayerAction::StartDestroyBlock => {
             let event = event::StartDestroyBlock {
                 position,
+                world: query.in_world,
                 from: query.id,
                 sequence,
             };
@@ -303,6 +375,7 @@ fn player_action(
         PlayerAction::StopDestroyBlock => {
             let event = event::DestroyBlock {
                 position,
+                world: query.in_world,
                 from: query.id,
                 sequence,
             };
@@ -433,6 +506,7 @@ pub fn player_interact_block(
         query.events.push(
             event::ToggleDoor {
                 position: interacted_block_pos_vec,
+                world: query.in_world,
                 from: query.id,
                 sequence: packet.sequence.0,
             },
@@ -478,6 +552,7 @@ pub fn player_interact_block(
         query.events.push(
             event::PlaceBlock {
                 position,
+                world: query.in_world,
                 from: query.id,
                 sequence: packet.sequence.0,
                 block: block_state,
@@ -595,12 +670,34 @@ pub fn confirm_teleportation(
 
             **query.position = pending_teleport.destination;
             entity.remove::<PendingTeleportation>();
+
+            // the client stops moving when it is teleported
+            entity.get::<&mut MovementTracking>(|tracking| {
+                tracking.horizontal_speed = 0.0;
+                tracking.vertical_speed = 0.0;
+            });
         }
     });
 
     Ok(())
 }
 
+pub fn keep_alive(
+    pkt: &play::KeepAliveC2s,
+    _: &dyn LifetimeHandle<'_>,
+    query: &mut PacketSwitchQuery<'_>,
+) -> anyhow::Result<()> {
+    query
+        .view
+        .get::<(&mut KeepAlive, &mut Latency)>(|(keep_alive, latency)| {
+            if let Some(rtt) = keep_alive.receive(pkt.id, Instant::now()) {
+                *latency = Latency(rtt);
+            }
+        });
+
+    Ok(())
+}
+
 pub fn player_abilities(
     pkt: &play::UpdatePlayerAbilitiesC2s,
     _: &dyn LifetimeHandle<'_>,
@@ -615,10 +712,80 @@ pub fn player_abilities(
     Ok(())
 }
 
+pub fn client_settings(
+    pkt: &play::ClientSettingsC2s<'_>,
+    _: &dyn LifetimeHandle<'_>,
+    query: &mut PacketSwitchQuery<'_>,
+) -> anyhow::Result<()> {
+    let settings = ClientSettings {
+        locale: pkt.locale.to_owned(),
+        chat_mode: pkt.chat_mode,
+        chat_colors: pkt.chat_colors,
+        displayed_skin_parts: u8::from(pkt.displayed_skin_parts),
+        main_arm: pkt.main_arm,
+        allow_server_listings: pkt.allow_server_listings,
+    };
+
+    let main_hand = match settings.main_arm {
+        MainArm::Left => 0,
+        MainArm::Right => 1,
+    };
+
+    if settings.shows_chat() {
+        query.view.remove::<HidesChat>();
+    } else {
+        query.view.add::<HidesChat>();
+    }
+
+    query
+        .view
+        .set(ViewDistance::new(i16::from(pkt.view_distance)))
+        .set(DisplayedSkinParts::new(settings.displayed_skin_parts))
+        .set(MainHand::new(main_hand))
+        .set(settings);
+
+    Ok(())
+}
+
+pub fn resource_pack_status(
+    pkt: &play::ResourcePackStatusC2s,
+    _: &dyn LifetimeHandle<'_>,
+    query: &mut PacketSwitchQuery<'_>,
+) -> anyhow::Result<()> {
+    query.events.push(
+        event::ResourcePackStatus {
+            client: query.id,
+            status: *pkt,
+        },
+        query.world,
+    );
+
+    let declined = matches!(
+        pkt,
+        play::ResourcePackStatusC2s::Declined | play::ResourcePackStatusC2s::FailedDownload
+    );
+
+    let forced = query.world.get::<&Config>(|config| {
+        config
+            .resource_pack
+            .as_ref()
+            .is_some_and(|pack| pack.forced)
+    });
+
+    if declined && forced {
+        query.view.set(PendingRemove::new(
+            "§cThis server requires its resource pack",
+        ));
+    }
+
+    Ok(())
+}
+
 pub fn add_builtin_handlers(registry: &mut HandlerRegistry) {
     registry.add_handler(Box::new(chat_message));
     registry.add_handler(Box::new(click_slot));
     registry.add_handler(Box::new(client_command));
+    registry.add_handler(Box::new(client_settings));
     registry.add_handler(Box::new(client_status));
     registry.add_handler(Box::new(chat_command));
     registry.add_handler(Box::new(creative_inventory_action));
@@ -634,6 +801,9 @@ pub fn add_builtin_handlers(registry: &mut HandlerRegistry) {
     registry.add_handler(Box::new(update_selected_slot));
     registry.add_handler(Box::new(confirm_teleportation));
     registry.add_handler(Box::new(player_abilities));
+    registry.add_handler(Box::new(keep_alive));
+    registry.add_handler(Box::new(channel::custom_payload));
+    registry.add_handler(Box::new(resource_pack_status));
 }
 
 /// # Safety
@@ -20,7 +20,7 @@ use valence_protocol::{
 use valence_server::ItemStack;
 use valence_text::IntoText;
 
-use super::{Player, event, handlers::PacketSwitchQuery};
+use super::{Player, dimension::InWorld, event, handlers::PacketSwitchQuery};
 use crate::{
     net::{Compose, ConnectionId, DataBundle},
     simulation::Position,
@@ -122,92 +122,106 @@ impl Module for InventoryModule {
             &CursorItem,
             ?&OpenInventory,
             &ConnectionId,
+            ?&InWorld,
         )
+        .kind::<flecs_ecs::prelude::flecs::pipeline::OnStore>()
+        .each_iter(
+            |it,
+             row,
+             (
+                compose,
+                inventory,
+                inv_state,
+                position,
+                cursor_item,
+                open_inventory,
+                io,
+                in_world,
+            )| {
+                let system = it.system();
+                let world = it.world();
+                let entity = it.entity(row);
+                let entity_id = VarInt(entity.minecraft_id());
+                let stream_id = *io;
 
-            .kind::<flecs_ecs::prelude::flecs::pipeline::OnStore>()
-            .each_iter(
-                |
-                    it,
-                    row,
-                    (compose, inventory, inv_state, position, cursor_item, open_inventory, io)
-                | {
-                    let system = it.system();
-                    let world = it.world();
-                    let entity = it.entity(row);
-                    let entity_id = VarInt(entity.minecraft_id());
-                    let stream_id = *io;
-
-                    // update held item, offhand, and equipment
-                    let mut equipment_changes: Vec<EquipmentEntry> = Vec::new();
-                    let hand_slot = inventory.get_cursor_index();
-                    for (idx, slot) in inventory.slots_mut().iter_mut().enumerate() {
-                        if slot.changed {
-                            if idx == usize::from(hand_slot) {
-                                equipment_changes.push(EquipmentEntry {
-                                    slot: 0,
-                                    item: slot.stack.clone(),
-                                });
-                            }
-
-                            if idx == 45 {
-                                equipment_changes.push(EquipmentEntry {
-                                    slot: 1,
-                                    item: slot.stack.clone(),
-                                });
-                            }
-
-                            if (5..=8).contains(&idx) {
-                                let index = match idx {
-                                    5 => 5,
-                                    6 => 4,
-                                    7 => 3,
-                                    8 => 2,
-                                    _ => 0,
-                                };
-                                equipment_changes.push(EquipmentEntry {
-                                    slot: index,
-                                    item: slot.stack.clone(),
-                                });
-                            }
+                // update held item, offhand, and equipment
+                let mut equipment_changes: Vec<EquipmentEntry> = Vec::new();
+                let hand_slot = inventory.get_cursor_index();
+                for (idx, slot) in inventory.slots_mut().iter_mut().enumerate() {
+                    if slot.changed {
+                        if idx == usize::from(hand_slot) {
+                            equipment_changes.push(EquipmentEntry {
+                                slot: 0,
+                                item: slot.stack.clone(),
+                            });
                         }
-                    }
 
-                    if !equipment_changes.is_empty() {
-                        let packet = &(play::EntityEquipmentUpdateS2c {
-                            entity_id,
-                            equipment: equipment_changes,
-                        });
+                        if idx == 45 {
+                            equipment_changes.push(EquipmentEntry {
+                                slot: 1,
+                                item: slot.stack.clone(),
+                            });
+                        }
 
-                        compose
-                            .broadcast_local(packet, position.to_chunk(), system)
-                            .exclude(stream_id)
-                            .send()
-                            .unwrap();
+                        if (5..=8).contains(&idx) {
+                            let index = match idx {
+                                5 => 5,
+                                6 => 4,
+                                7 => 3,
+                                8 => 2,
+                                _ => 0,
+                            };
+                            equipment_changes.push(EquipmentEntry {
+                                slot: index,
+                                item: slot.stack.clone(),
+                            });
+                        }
                     }
+                }
+
+                if !equipment_changes.is_empty() {
+                    let packet = &(play::EntityEquipmentUpdateS2c {
+                        entity_id,
+                        equipment: equipment_changes,
+                    });
+
+                    compose
+                        .broadcast_local(packet, position.to_chunk(), system)
+                        .world(InWorld::proxy_id(in_world))
+                        .exclude(stream_id)
+                        .send()
+                        .unwrap();
+                }
 
-                    if let Some(open_inventory) = open_inventory {
-                        open_inventory.entity.entity_view(world).get::<&mut Inventory>(|open_inv| {
+                if let Some(open_inventory) = open_inventory {
+                    open_inventory
+                        .entity
+                        .entity_view(world)
+                        .get::<&mut Inventory>(|open_inv| {
                             update_player_inventory_inner(
                                 compose,
                                 stream_id,
                                 system,
                                 inv_state,
                                 cursor_item,
-                                open_inv.slots_mut().iter_mut().chain(inventory.slots_inventory_mut().iter_mut()),
+                                open_inv
+                                    .slots_mut()
+                                    .iter_mut()
+                                    .chain(inventory.slots_inventory_mut().iter_mut()),
                             );
                         });
-                    } else {
-                        update_player_inventory_inner(
-                            compose,
-                            stream_id,
-                            system,
-                            inv_state,
-                            cursor_item,
-                            inventory.slots_mut().iter_mut(),
-                        );
-                    }
+                } else {
+                    update_player_inventory_inner(
+                        compose,
+                        stream_id,
+                        system,
+                        inv_state,
+                        cursor_item,
+                        inventory.slots_mut().iter_mut(),
+                    );
                 }
-            );
+            },
+        );
     }
 }
 
@@ -1,6 +1,16 @@
-use std::{borrow::Borrow, collections::HashMap, hash::Hash, sync::Arc};
+use std::{
+    borrow::Borrow,
+    collections::HashMap,
+    hash::Hash,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
 
 use bytemuck::{Pod, Zeroable};
+use dashmap::DashMap;
 use derive_more::{Constructor, Deref, DerefMut, Display, From};
 use flecs_ecs::prelude::*;
 use geometry::aabb::Aabb;
@@ -12,6 +22,7 @@ use skin::PlayerSkin;
 use tracing::{debug, error};
 use uuid;
 use valence_generated::block::BlockState;
+pub use valence_protocol::packets::play::client_settings_c2s::{ChatMode, MainArm};
 use valence_protocol::{
     ByteAngle, VarInt,
     packets::play::{
@@ -33,12 +44,15 @@ use crate::{
 
 pub mod animation;
 pub mod blocks;
+pub mod channel;
 pub mod command;
+pub mod dimension;
 pub mod entity_kind;
 pub mod event;
 pub mod handlers;
 pub mod inventory;
 pub mod metadata;
+pub mod movement;
 pub mod packet;
 pub mod skin;
 pub mod util;
@@ -55,10 +69,148 @@ pub struct PlayerUuidLookup {
     inner: HashMap<Uuid, Entity>,
 }
 
-/// Communicates with the proxy server.
-#[derive(Component, Deref, DerefMut, From)]
+/// Communicates with the connected proxy servers.
+#[derive(Component, Clone, Default)]
 pub struct EgressComm {
-    tx: tokio::sync::mpsc::UnboundedSender<bytes::Bytes>,
+    /// The writer of every connected proxy by proxy id.
+    proxies: Arc<DashMap<u16, tokio::sync::mpsc::UnboundedSender<bytes::Bytes>>>,
+    /// Bytes handed to proxy writers since the server started.
+    bytes_sent: Arc<AtomicU64>,
+    /// The [`blocks::chunk::EncodedColumn`]s in the chunk cache of every proxy.
+    cached_chunks: Arc<DashMap<u16, ProxyChunkCache>>,
+}
+
+/// The most chunks a proxy is made to cache. Proxies only remove the chunks the server evicts.
+const CHUNK_CACHE_CAPACITY: usize = 8192;
+
+/// The hashes of the chunks one proxy caches and when each was last sent.
+#[derive(Default)]
+struct ProxyChunkCache {
+    last_sent: FxHashMap<u64, u64>,
+    sends: u64,
+}
+
+impl ProxyChunkCache {
+    /// Records that the chunk `hash` is sent. Returns `false` if it is already cached. Otherwise,
+    /// if `capacity` chunks are cached, the least recently sent quarter of them is evicted and
+    /// pushed to `evicted` first.
+    fn send(&mut self, hash: u64, capacity: usize, evicted: &mut Vec<u64>) -> bool {
+        self.sends += 1;
+
+        if let Some(last_sent) = self.last_sent.get_mut(&hash) {
+            *last_sent = self.sends;
+            return false;
+        }
+
+        if !self.last_sent.is_empty() && self.last_sent.len() >= capacity {
+            // evicting in batches keeps this amortized O(1)
+            let mut by_age: Vec<_> = self
+                .last_sent
+                .iter()
+                .map(|(&hash, &last_sent)| (last_sent, hash))
+                .collect();
+
+            let count = (capacity / 4).clamp(1, by_age.len());
+            by_age.select_nth_unstable(count - 1);
+
+            for &(_, hash) in &by_age[..count] {
+                self.last_sent.remove(&hash);
+                evicted.push(hash);
+            }
+        }
+
+        self.last_sent.insert(hash, self.sends);
+        true
+    }
+}
+
+impl EgressComm {
+    /// Sends `bytes` to every connected proxy.
+    pub fn broadcast(&self, bytes: &bytes::Bytes) {
+        for proxy in self.proxies.iter() {
+            self.count_sent(bytes);
+            // the proxy is disconnecting and about to be removed
+            drop(proxy.send(bytes.clone()));
+        }
+    }
+
+    /// Sends `bytes` to the proxy with the id `proxy_id` if it is still connected.
+    pub fn send(&self, proxy_id: u16, bytes: bytes::Bytes) {
+        let Some(proxy) = self.proxies.get(&proxy_id) else {
+            debug!("dropping data for disconnected proxy {proxy_id}");
+            return;
+        };
+
+        self.count_sent(&bytes);
+        drop(proxy.send(bytes));
+    }
+
+    fn count_sent(&self, bytes: &bytes::Bytes) {
+        self.bytes_sent
+            .fetch_add(bytes.len() as u64, Ordering::Relaxed);
+    }
+
+    /// Bytes sent to proxies since the server started.
+    #[must_use]
+    pub fn bytes_sent(&self) -> u64 {
+        self.bytes_sent.load(Ordering::Relaxed)
+    }
+
+    /// Records that the proxy `proxy_id` is sent the chunk `hash`. Returns `true` if it has to be
+    /// cached first, in which case the chunks pushed to `evicted` have to be evicted from the
+    /// proxy to make room for it.
+    pub(crate) fn cache_chunk(&self, proxy_id: u16, hash: u64, evicted: &mut Vec<u64>) -> bool {
+        self.cached_chunks
+            .entry(proxy_id)
+            .or_default()
+            .send(hash, CHUNK_CACHE_CAPACITY, evicted)
+    }
+
+    /// Records that every proxy removes the chunk `hash` from its cache. Returns `false` if no
+    /// proxy had it.
+    pub(crate) fn evict_chunk(&self, hash: u64) -> bool {
+        let mut evicted = false;
+
+        for mut cache in self.cached_chunks.iter_mut() {
+            evicted |= cache.last_sent.remove(&hash).is_some();
+        }
+
+        evicted
+    }
+
+    #[must_use]
+    pub fn is_connected(&self, proxy_id: u16) -> bool {
+        self.proxies.contains_key(&proxy_id)
+    }
+
+    /// Registers the writer of a newly connected proxy and returns its id. `requested` is used if
+    /// it is free; otherwise, the lowest free id is.
+    pub(crate) fn register(
+        &self,
+        requested: u16,
+        writer: tokio::sync::mpsc::UnboundedSender<bytes::Bytes>,
+    ) -> Option<u16> {
+        // 0 is never a proxy id, so no stream id is 0
+        let candidates = std::iter::once(requested)
+            .filter(|&id| id != 0)
+            .chain(1..=u16::MAX);
+
+        for proxy_id in candidates {
+            if let dashmap::mapref::entry::Entry::Vacant(entry) = self.proxies.entry(proxy_id) {
+                entry.insert(writer);
+                // a proxy starts every connection with an empty chunk cache
+                self.cached_chunks.remove(&proxy_id);
+                return Some(proxy_id);
+            }
+        }
+
+        None
+    }
+
+    pub(crate) fn remove(&self, proxy_id: u16) {
+        self.proxies.remove(&proxy_id);
+        self.cached_chunks.remove(&proxy_id);
+    }
 }
 
 #[derive(Debug)]
@@ -94,6 +246,21 @@ impl<K: Eq + Hash, V> DeferredMap<K, V> {
     pub fn remove(&self, key: K, world: &World) {
         self.to_remove.push(key, world);
     }
+
+    /// Iterates over the entries as of the last [`Self::update`].
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.map.iter()
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
 }
 
 impl<K: Eq + Hash, V> DeferredMap<K, V> {
@@ -324,13 +491,23 @@ pub struct Comms {
     pub skins_rx: kanal::Receiver<(Entity, PlayerSkin)>,
     /// Skin tx channel.
     pub skins_tx: kanal::Sender<(Entity, PlayerSkin)>,
+    /// Online-mode login rx channel. `None` if the session server could not verify the player.
+    pub logins_rx: kanal::Receiver<(Entity, Option<(uuid::Uuid, PlayerSkin)>)>,
+    /// Online-mode login tx channel.
+    pub logins_tx: kanal::Sender<(Entity, Option<(uuid::Uuid, PlayerSkin)>)>,
 }
 
 impl Default for Comms {
     fn default() -> Self {
         let (skins_tx, skins_rx) = kanal::unbounded();
+        let (logins_tx, logins_rx) = kanal::unbounded();
 
-        Self { skins_rx, skins_tx }
+        Self {
+            skins_rx,
+            skins_tx,
+            logins_rx,
+            logins_tx,
+        }
     }
 }
 
@@ -509,6 +686,87 @@ impl ChunkPosition {
     }
 }
 
+/// The view distance a player's client asked for in `ClientSettingsC2s`.
+///
+/// The radius actually used for the player is capped by [`crate::config::Config::view_distance`]
+/// (see [`Self::radius`]), so lowering the configured view distance also applies to players who
+/// asked for more.
+#[derive(Component, Debug, Copy, Clone, PartialEq, Eq)]
+#[meta]
+pub struct ViewDistance {
+    pub requested: i16,
+}
+
+impl ViewDistance {
+    /// The smallest view distance the vanilla client allows.
+    pub const MIN: i16 = 2;
+
+    #[must_use]
+    pub fn new(requested: i16) -> Self {
+        Self {
+            requested: requested.max(Self::MIN),
+        }
+    }
+
+    /// The radius in chunks to send to the player, given the configured maximum.
+    #[must_use]
+    pub fn radius(self, max: i16) -> i16 {
+        self.requested.min(max).max(Self::MIN)
+    }
+}
+
+impl Default for ViewDistance {
+    /// Until the client sends its settings, it gets the configured maximum.
+    fn default() -> Self {
+        Self {
+            requested: SANE_MAX_RADIUS,
+        }
+    }
+}
+
+/// The settings a player's client last sent, other than the view distance, which is kept in
+/// [`ViewDistance`].
+#[derive(Component, Debug, Clone, PartialEq, Eq)]
+pub struct ClientSettings {
+    /// The language of the client, such as `en_us`. Text sent only to this player can be
+    /// translated into it.
+    pub locale: String,
+    pub chat_mode: ChatMode,
+    pub chat_colors: bool,
+    /// The skin parts the player shows, as in [`metadata::player::DisplayedSkinParts`].
+    pub displayed_skin_parts: u8,
+    pub main_arm: MainArm,
+    pub allow_server_listings: bool,
+}
+
+impl ClientSettings {
+    /// Whether the player wants to see chat messages from other players.
+    #[must_use]
+    pub fn shows_chat(&self) -> bool {
+        self.chat_mode == ChatMode::Enabled
+    }
+}
+
+impl Default for ClientSettings {
+    /// Until the client sends its settings, it is assumed to have the settings of a new vanilla
+    /// client.
+    fn default() -> Self {
+        Self {
+            locale: "en_us".to_owned(),
+            chat_mode: ChatMode::Enabled,
+            chat_colors: true,
+            displayed_skin_parts: 0x7f,
+            main_arm: MainArm::Right,
+            allow_server_listings: true,
+        }
+    }
+}
+
+/// Added to players whose [`ClientSettings`] hide chat, so that chat can be broadcast to everyone
+/// else without looking at the settings of every player.
+#[derive(Component, Debug, Default)]
+pub struct HidesChat;
+
 #[must_use]
 pub fn aabb(position: Vec3, size: EntitySize) -> Aabb {
     let half_width = size.half_width;
@@ -592,6 +850,66 @@ impl PendingTeleportation {
     }
 }
 
+/// The keep-alive a player's client has yet to answer. Players who do not answer in time are
+/// removed.
+#[derive(Component, Debug, Copy, Clone, PartialEq, Eq)]
+pub struct KeepAlive {
+    /// The id and send time of the keep-alive the client has yet to answer.
+    pub pending: Option<(u64, Instant)>,
+    pub last_sent: Instant,
+}
+
+impl KeepAlive {
+    #[must_use]
+    pub const fn new(now: Instant) -> Self {
+        Self {
+            pending: None,
+            last_sent: now,
+        }
+    }
+
+    /// Sends a new keep-alive at `now`, returning its id.
+    pub fn send(&mut self, now: Instant) -> u64 {
+        let id = fastrand::u64(..);
+        self.pending = Some((id, now));
+        self.last_sent = now;
+        id
+    }
+
+    /// Accepts the client's answer `id`, returning the round-trip time if it answers the pending
+    /// keep-alive.
+    pub fn receive(&mut self, id: u64, now: Instant) -> Option<Duration> {
+        let (pending, sent) = self.pending?;
+
+        if pending != id {
+            return None;
+        }
+
+        self.pending = None;
+        Some(now.saturating_duration_since(sent))
+    }
+
+    /// Whether the pending keep-alive has gone unanswered for `timeout` at `now`.
+    #[must_use]
+    pub fn is_timed_out(&self, now: Instant, timeout: Duration) -> bool {
+        self.pending
+            .is_some_and(|(_, sent)| now.saturating_duration_since(sent) >= timeout)
+    }
+}
+
+/// The round-trip time to a player's client, measured with keep-alives. It is zero until the
+/// first keep-alive is answered.
+#[derive(Component, Default, Debug, Copy, Clone, PartialEq, Eq, Deref)]
+pub struct Latency(pub Duration);
+
+impl Latency {
+    /// The latency in milliseconds, as the player list shows it.
+    #[must_use]
+    pub fn millis(self) -> i32 {
+        i32::try_from(self.0.as_millis()).unwrap_or(i32::MAX)
+    }
+}
+
 #[derive(Component, Debug, Copy, Clone, PartialEq)]
 pub struct FlyingSpeed {
     pub speed: f32,
@@ -619,6 +937,15 @@ pub struct MovementTracking {
     pub server_velocity: DVec3,
     pub sprinting: bool,
     pub was_on_ground: bool,
+    /// How far the player moved horizontally in their last position packet.
+    pub horizontal_speed: f32,
+    /// How far the player moved vertically in their last position packet.
+    pub vertical_speed: f32,
+    /// How many more position packets the player may send. See
+    /// [`movement::refill_movement_balance`].
+    pub movement_balance: i16,
+    /// When [`Self::movement_balance`] was last refilled.
+    pub balance_refilled: Option<Instant>,
 }
 
 #[derive(Component, Default, Debug, Copy, Clone)]
@@ -664,6 +991,8 @@ impl Module for SimModule {
         world.component::<PendingTeleportation>();
         world.component::<FlyingSpeed>();
         world.component::<MovementTracking>();
+        world.component::<KeepAlive>();
+        world.component::<Latency>();
         world.component::<Flight>().meta();
 
         world.component::<EntityKind>().meta();
@@ -700,6 +1029,12 @@ impl Module for SimModule {
         component!(world, Uuid).opaque_func(meta_ser_stringify_type_display::<Uuid>);
 
         world.component::<ChunkPosition>().meta();
+        world.component::<ViewDistance>().meta();
+        world.component::<ClientSettings>();
+        world.component::<HidesChat>();
+        world.component::<channel::RegisteredChannels>();
+        world.component::<dimension::Dimension>();
+        world.component::<dimension::InWorld>();
         world.component::<ConfirmBlockSequences>();
         world.component::<animation::ActiveAnimation>();
 
@@ -756,7 +1091,8 @@ impl Module for SimModule {
 
                 bundle.add_packet(&packet).unwrap();
 
-                bundle.broadcast_local(position.to_chunk()).unwrap();
+                let world = entity.get::<Option<&dimension::InWorld>>(dimension::InWorld::proxy_id);
+                bundle.broadcast_local(position.to_chunk(), world).unwrap();
 
                 Ok(())
             };
@@ -890,3 +1226,209 @@ pub fn get_direction_from_rotation(yaw: f32, pitch: f32) -> Vec3 {
         pitch_rad.cos() * yaw_rad.cos(),  // z = cos(pitch) * cos(yaw)
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{EgressComm, KeepAlive, ProxyChunkCache, ViewDistance};
+
+    const TIMEOUT: Duration = Duration::from_secs(20);
+
+    #[test]
+    fn test_keep_alive_round_trip() {
+        let start = Instant::now();
+        let mut keep_alive = KeepAlive::new(start);
+
+        let id = keep_alive.send(start);
+        let rtt = keep_alive.receive(id, start + Duration::from_millis(40));
+
+        assert_eq!(rtt, Some(Duration::from_millis(40)));
+        assert_eq!(keep_alive.pending, None);
+
+        // answering twice does not count
+        assert_eq!(
+            keep_alive.receive(id, start + Duration::from_millis(50)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_keep_alive_wrong_id() {
+        let start = Instant::now();
+        let mut keep_alive = KeepAlive::new(start);
+
+        let id = keep_alive.send(start);
+
+        assert_eq!(keep_alive.receive(id.wrapping_add(1), start), None);
+        assert_eq!(keep_alive.pending, Some((id, start)));
+    }
+
+    #[test]
+    fn test_keep_alive_late_answer() {
+        let start = Instant::now();
+        let mut keep_alive = KeepAlive::new(start);
+
+        assert!(!keep_alive.is_timed_out(start + TIMEOUT, TIMEOUT));
+
+        let id = keep_alive.send(start);
+
+        assert!(!keep_alive.is_timed_out(start + TIMEOUT - Duration::from_millis(1), TIMEOUT));
+        assert!(keep_alive.is_timed_out(start + TIMEOUT, TIMEOUT));
+
+        // the answer still measures the latency, but the player is removed for timing out first
+        assert_eq!(
+            keep_alive.receive(id, start + TIMEOUT * 2),
+            Some(TIMEOUT * 2)
+        );
+        assert!(!keep_alive.is_timed_out(start + TIMEOUT * 2, TIMEOUT));
+    }
+
+    #[test]
+    fn test_chunk_cache_resend() {
+        let mut cache = ProxyChunkCache::default();
+        let mut evicted = Vec::new();
+
+        assert!(cache.send(1, 4, &mut evicted));
+        assert!(!cache.send(1, 4, &mut evicted));
+        assert!(evicted.is_empty());
+    }
+
+    #[test]
+    fn test_chunk_cache_evicts_least_recently_sent() {
+        let mut cache = ProxyChunkCache::default();
+        let mut evicted = Vec::new();
+
+        for hash in 0..8 {
+            assert!(cache.send(hash, 8, &mut evicted));
+        }
+
+        // 0 and 1 were sent again, so 2 and 3 are the least recently sent
+        assert!(!cache.send(0, 8, &mut evicted));
+        assert!(!cache.send(1, 8, &mut evicted));
+        assert!(evicted.is_empty());
+
+        assert!(cache.send(8, 8, &mut evicted));
+        evicted.sort_unstable();
+        assert_eq!(evicted, [2, 3]);
+        assert_eq!(cache.last_sent.len(), 7);
+
+        // evicted chunks have to be cached again
+        evicted.clear();
+        assert!(cache.send(2, 8, &mut evicted));
+        assert!(evicted.is_empty());
+        assert!(!cache.send(0, 8, &mut evicted));
+    }
+
+    #[test]
+    fn test_chunk_cache_never_exceeds_capacity() {
+        let mut cache = ProxyChunkCache::default();
+        let mut evicted = Vec::new();
+
+        for hash in 0..100 {
+            cache.send(hash, 10, &mut evicted);
+            assert!(cache.last_sent.len() <= 10);
+        }
+
+        assert_eq!(cache.last_sent.len() + evicted.len(), 100);
+    }
+
+    #[test]
+    fn test_register_requested_id() {
+        let egress = EgressComm::default();
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+
+        assert_eq!(egress.register(7, tx), Some(7));
+        assert!(egress.is_connected(7));
+    }
+
+    #[test]
+    fn test_register_never_assigns_zero() {
+        let egress = EgressComm::default();
+
+        for expected in 1..=3 {
+            let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+            assert_eq!(egress.register(0, tx), Some(expected));
+        }
+
+        assert!(!egress.is_connected(0));
+    }
+
+    #[test]
+    fn test_register_taken_id() {
+        let egress = EgressComm::default();
+        let (first, _first_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (second, _second_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (third, _third_rx) = tokio::sync::mpsc::unbounded_channel();
+
+        assert_eq!(egress.register(1, first), Some(1));
+        // the lowest free id is used instead
+        assert_eq!(egress.register(1, second), Some(2));
+        assert_eq!(egress.register(2, third), Some(3));
+    }
+
+    #[test]
+    fn test_register_reuses_removed_id() {
+        let egress = EgressComm::default();
+        let (first, _first_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (second, _second_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (third, _third_rx) = tokio::sync::mpsc::unbounded_channel();
+
+        assert_eq!(egress.register(0, first), Some(1));
+        assert_eq!(egress.register(0, second), Some(2));
+
+        egress.remove(1);
+        assert!(!egress.is_connected(1));
+
+        assert_eq!(egress.register(0, third), Some(1));
+    }
+
+    #[test]
+    fn test_remove_cleans_up() {
+        let egress = EgressComm::default();
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut evicted = Vec::new();
+
+        let proxy_id = egress.register(0, tx).unwrap();
+        assert!(egress.cache_chunk(proxy_id, 1, &mut evicted));
+
+        egress.remove(proxy_id);
+
+        // the writer stops once its sender is dropped
+        assert!(rx.try_recv().is_err());
+        assert!(rx.is_closed());
+
+        // data for the proxy is dropped
+        egress.send(proxy_id, bytes::Bytes::from_static(b"data"));
+        assert_eq!(egress.bytes_sent(), 0);
+
+        // a proxy which takes the id starts with an empty chunk cache
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        assert_eq!(egress.register(proxy_id, tx), Some(proxy_id));
+        assert!(egress.cache_chunk(proxy_id, 1, &mut evicted));
+        assert!(!egress.evict_chunk(2));
+    }
+
+    #[test]
+    fn test_view_distance_capped_by_config() {
+        assert_eq!(ViewDistance::new(32).radius(10), 10);
+        assert_eq!(ViewDistance::new(6).radius(10), 6);
+        assert_eq!(ViewDistance::new(10).radius(10), 10);
+    }
+
+    #[test]
+    fn test_view_distance_minimum() {
+        // clients may send anything, including negative view distances
+        for requested in [i16::MIN, -1, 0, 1] {
+            assert_eq!(ViewDistance::new(requested).radius(10), ViewDistance::MIN);
+        }
+
+        // the minimum wins over a configured view distance below it
+        assert_eq!(ViewDistance::new(8).radius(1), ViewDistance::MIN);
+    }
+
+    #[test]
+    fn test_view_distance_before_client_settings() {
+        assert_eq!(ViewDistance::default().radius(12), 12);
+    }
+}
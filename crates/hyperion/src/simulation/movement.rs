@@ -0,0 +1,529 @@
+//! Checks that the movement a player sends is possible, so that speed, fly, no-fall and timer
+//! cheats are caught. See [`check`].
+//!
+//! The checks follow the physics of the vanilla client closely enough to bound what it can do,
+//! but not to predict it exactly, so every limit has some slack.
+
+use std::{
+    ops::ControlFlow,
+    time::{Duration, Instant},
+};
+
+use glam::{IVec3, Vec3, Vec3Swizzles};
+use valence_generated::block::{BlockKind, BlockState};
+
+use super::{EntitySize, Flight, MovementTracking, block_bounds, blocks::Blocks, handlers};
+
+/// Horizontal acceleration on the ground at a movement speed of `1` on a block with a
+/// slipperiness of `0.6`, which is most blocks.
+const GROUND_ACCELERATION: f32 = 0.216;
+
+/// Horizontal acceleration in the air, which does not depend on the movement speed.
+const AIR_ACCELERATION: f32 = 0.02;
+
+/// What horizontal velocity is multiplied by each tick in the air. On the ground, it is also
+/// multiplied by the slipperiness of the block below.
+const DRAG: f32 = 0.91;
+
+const SPRINT_MULTIPLIER: f32 = 1.3;
+
+const FLY_SPRINT_MULTIPLIER: f32 = 2.0;
+
+/// Horizontal speed added by jumping while sprinting.
+const SPRINT_JUMP_BOOST: f32 = 0.2;
+
+const JUMP_VELOCITY: f32 = 0.42;
+
+/// How far up a player walks without jumping, such as onto a slab.
+const STEP_HEIGHT: f32 = 0.6;
+
+const GRAVITY: f32 = 0.08;
+
+/// What vertical velocity is multiplied by each tick in the air.
+const VERTICAL_DRAG: f32 = 0.98;
+
+/// How much faster than the limit a player may move, for rounding and for the client and the
+/// server disagreeing about the block below.
+const SPEED_TOLERANCE: f32 = 1.1;
+
+/// Blocks per tick a player may move beyond any limit.
+const EPSILON: f32 = 0.03;
+
+/// How far below a player a block may be for them to stand on it.
+const GROUND_PROBE: f32 = 0.06;
+
+/// How many position packets a player may send ahead of time, for when their packets arrive in
+/// bursts after lagging. Players start with this many.
+pub const MAX_MOVEMENT_BALANCE: i16 = 10;
+
+/// How often the client sends a position packet, once per client tick.
+const MOVEMENT_PACKET_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How a player's movement was impossible.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MovementViolationKind {
+    /// The player moved into a solid block.
+    Collision,
+    /// The player moved further horizontally in one tick than they can.
+    Speed { speed: f32, max: f32 },
+    /// The player rose or hovered in the air without being allowed to fly.
+    Fly { vertical_speed: f32, max: f32 },
+    /// The player claimed to be on the ground while falling through the air.
+    NoFall { fall_distance: f32 },
+    /// The player sent more position packets than the server ticked, as if their game ran faster.
+    Timer,
+}
+
+/// What limits a player's movement.
+#[derive(Copy, Clone, Debug)]
+pub struct Abilities {
+    pub flight: Flight,
+    /// See [`super::RunningSpeed`].
+    pub running_speed: f32,
+    /// See [`super::FlyingSpeed`].
+    pub flying_speed: f32,
+}
+
+/// A single position packet.
+#[derive(Copy, Clone, Debug)]
+pub struct Movement {
+    pub from: Vec3,
+    pub to: Vec3,
+    /// What the client claims.
+    pub on_ground: bool,
+    pub jumped: bool,
+}
+
+/// The blocks around a [`Movement`] which change what a player can do.
+#[derive(Copy, Clone, Debug)]
+pub struct Surroundings {
+    /// Whether the player stood on a block before moving.
+    pub supported_before: bool,
+    /// Whether the player stands on a block after moving.
+    pub supported_after: bool,
+    /// The slipperiness of the block the player stood on.
+    pub slipperiness: f32,
+    /// Whether the player was in a liquid, on something climbable or on something bouncy, where
+    /// gravity does not apply as usual.
+    pub free_vertical: bool,
+}
+
+impl Surroundings {
+    /// Looks at the blocks around a player of `size` moving from `from` to `to`.
+    #[must_use]
+    pub fn of(from: Vec3, to: Vec3, size: EntitySize, blocks: &Blocks) -> Self {
+        let below = (from - Vec3::new(0.0, GROUND_PROBE, 0.0))
+            .floor()
+            .as_ivec3();
+
+        let slipperiness = blocks
+            .get_block(below)
+            .map_or(0.6, |state| state.to_kind().slipperiness());
+
+        Self {
+            supported_before: is_supported(from, size, blocks),
+            supported_after: is_supported(to, size, blocks),
+            slipperiness,
+            free_vertical: is_free_vertical(from, size, blocks)
+                || is_free_vertical(to, size, blocks),
+        }
+    }
+}
+
+/// Gives the player one position packet for every [`MOVEMENT_PACKET_INTERVAL`] since the balance
+/// was last refilled, up to [`MAX_MOVEMENT_BALANCE`]. This goes by the wall clock rather than by
+/// server ticks, which are slower than the client's when the server is behind.
+pub fn refill_movement_balance(tracking: &mut MovementTracking, now: Instant) {
+    let Some(refilled) = tracking.balance_refilled else {
+        tracking.balance_refilled = Some(now);
+        return;
+    };
+
+    let elapsed = now.saturating_duration_since(refilled);
+    let packets = elapsed.as_millis() / MOVEMENT_PACKET_INTERVAL.as_millis();
+    let packets = u32::try_from(packets).unwrap_or(u32::MAX);
+
+    tracking.movement_balance = tracking
+        .movement_balance
+        .saturating_add(i16::try_from(packets).unwrap_or(i16::MAX));
+
+    if tracking.movement_balance >= MAX_MOVEMENT_BALANCE {
+        tracking.movement_balance = MAX_MOVEMENT_BALANCE;
+        tracking.balance_refilled = Some(now);
+    } else {
+        // the time towards the next packet counts for the next refill
+        tracking.balance_refilled = Some(refilled + MOVEMENT_PACKET_INTERVAL * packets);
+    }
+}
+
+/// Checks one position packet of a player. `tracking` is updated to include the movement if it
+/// is possible.
+pub fn check(
+    tracking: &mut MovementTracking,
+    abilities: Abilities,
+    movement: Movement,
+    surroundings: Surroundings,
+) -> Result<(), MovementViolationKind> {
+    if tracking.movement_balance <= 0 {
+        return Err(MovementViolationKind::Timer);
+    }
+
+    tracking.movement_balance -= 1;
+
+    let delta = movement.to - movement.from;
+
+    #[expect(
+        clippy::cast_possible_truncation,
+        reason = "knockback is far below the limits of f32"
+    )]
+    let knockback = tracking.server_velocity.as_vec3();
+
+    let horizontal_speed = delta.xz().length();
+    let max =
+        max_horizontal_speed(tracking, abilities, movement, surroundings) + knockback.xz().length();
+
+    if horizontal_speed > max {
+        return Err(MovementViolationKind::Speed {
+            speed: horizontal_speed,
+            max,
+        });
+    }
+
+    let flying = abilities.flight.allow && abilities.flight.is_flying;
+
+    if !flying && !surroundings.free_vertical {
+        let rise = if surroundings.supported_before {
+            JUMP_VELOCITY.max(STEP_HEIGHT)
+        } else if surroundings.supported_after {
+            // landing cuts the fall short, but the player does not rise
+            ((tracking.vertical_speed - GRAVITY) * VERTICAL_DRAG).max(0.0)
+        } else {
+            (tracking.vertical_speed - GRAVITY) * VERTICAL_DRAG
+        };
+
+        let max = rise + EPSILON + knockback.y.max(0.0);
+
+        if delta.y > max {
+            return Err(MovementViolationKind::Fly {
+                vertical_speed: delta.y,
+                max,
+            });
+        }
+
+        if movement.on_ground && !surroundings.supported_after && delta.y < 0.0 {
+            return Err(MovementViolationKind::NoFall {
+                fall_distance: tracking.fall_start_y - movement.to.y,
+            });
+        }
+    }
+
+    tracking.horizontal_speed = horizontal_speed;
+    tracking.vertical_speed = delta.y;
+
+    Ok(())
+}
+
+/// The furthest a player may move horizontally in this tick, given how fast they moved in the
+/// last one.
+fn max_horizontal_speed(
+    tracking: &MovementTracking,
+    abilities: Abilities,
+    movement: Movement,
+    surroundings: Surroundings,
+) -> f32 {
+    let sprinting = tracking.sprinting;
+
+    let (acceleration, drag) = if abilities.flight.allow && abilities.flight.is_flying {
+        let multiplier = if sprinting {
+            FLY_SPRINT_MULTIPLIER
+        } else {
+            1.0
+        };
+        (abilities.flying_speed * multiplier, DRAG)
+    } else if surroundings.supported_before {
+        let multiplier = if sprinting { SPRINT_MULTIPLIER } else { 1.0 };
+        let slipperiness = surroundings.slipperiness;
+
+        let acceleration =
+            abilities.running_speed * multiplier * GROUND_ACCELERATION / slipperiness.powi(3);
+
+        (acceleration, slipperiness * DRAG)
+    } else {
+        let multiplier = if sprinting { SPRINT_MULTIPLIER } else { 1.0 };
+        (AIR_ACCELERATION * multiplier, DRAG)
+    };
+
+    let mut max = tracking.horizontal_speed.mul_add(drag, acceleration);
+
+    if movement.jumped && sprinting {
+        max += SPRINT_JUMP_BOOST;
+    }
+
+    max.mul_add(SPEED_TOLERANCE, EPSILON)
+}
+
+/// Whether a player of `size` at `position` stands on a block.
+fn is_supported(position: Vec3, size: EntitySize, blocks: &Blocks) -> bool {
+    let probe = position - Vec3::new(0.0, GROUND_PROBE, 0.0);
+    handlers::has_block_collision(&probe, size, blocks)
+}
+
+/// Whether a player of `size` at `position` touches a block where gravity does not apply as
+/// usual.
+fn is_free_vertical(position: Vec3, size: EntitySize, blocks: &Blocks) -> bool {
+    let (min, max) = block_bounds(position, size);
+    let min = min - IVec3::Y;
+
+    let result = blocks.get_blocks(min, max, |_, state| {
+        if is_free_vertical_block(state) {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        }
+    });
+
+    result.is_break()
+}
+
+fn is_free_vertical_block(state: BlockState) -> bool {
+    if state.is_liquid() {
+        return true;
+    }
+
+    let kind = state.to_kind();
+
+    matches!(
+        kind,
+        BlockKind::Ladder
+            | BlockKind::Vine
+            | BlockKind::Scaffolding
+            | BlockKind::TwistingVines
+            | BlockKind::TwistingVinesPlant
+            | BlockKind::WeepingVines
+            | BlockKind::WeepingVinesPlant
+            | BlockKind::CaveVines
+            | BlockKind::CaveVinesPlant
+            | BlockKind::BubbleColumn
+            | BlockKind::PowderSnow
+            | BlockKind::SlimeBlock
+            | BlockKind::HoneyBlock
+    ) || kind.to_str().ends_with("_bed")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WALKING: Abilities = Abilities {
+        flight: Flight {
+            allow: false,
+            is_flying: false,
+        },
+        running_speed: 0.1,
+        flying_speed: 0.05,
+    };
+
+    const ON_GROUND: Surroundings = Surroundings {
+        supported_before: true,
+        supported_after: true,
+        slipperiness: 0.6,
+        free_vertical: false,
+    };
+
+    const IN_AIR: Surroundings = Surroundings {
+        supported_before: false,
+        supported_after: false,
+        slipperiness: 0.6,
+        free_vertical: false,
+    };
+
+    fn tracking() -> MovementTracking {
+        MovementTracking {
+            movement_balance: MAX_MOVEMENT_BALANCE,
+            ..MovementTracking::default()
+        }
+    }
+
+    fn movement(delta: Vec3, on_ground: bool) -> Movement {
+        Movement {
+            from: Vec3::ZERO,
+            to: delta,
+            on_ground,
+            jumped: false,
+        }
+    }
+
+    #[test]
+    fn test_walking_reaches_its_top_speed() {
+        let mut tracking = tracking();
+        let mut speed = 0.0_f32;
+
+        // each tick, the speed of the last tick is dragged and the acceleration added
+        for _ in 0..MAX_MOVEMENT_BALANCE {
+            speed = speed.mul_add(0.6 * DRAG, 0.1);
+
+            let result = check(
+                &mut tracking,
+                WALKING,
+                movement(Vec3::new(speed, 0.0, 0.0), true),
+                ON_GROUND,
+            );
+
+            assert_eq!(result, Ok(()));
+        }
+    }
+
+    #[test]
+    fn test_speed() {
+        let mut tracking = tracking();
+
+        let result = check(
+            &mut tracking,
+            WALKING,
+            movement(Vec3::new(1.0, 0.0, 0.0), true),
+            ON_GROUND,
+        );
+
+        assert!(matches!(result, Err(MovementViolationKind::Speed { .. })));
+    }
+
+    #[test]
+    fn test_flying_is_faster_than_walking() {
+        let mut tracking = tracking();
+
+        let flying = Abilities {
+            flight: Flight {
+                allow: true,
+                is_flying: true,
+            },
+            ..WALKING
+        };
+
+        tracking.horizontal_speed = 0.5;
+
+        let result = check(
+            &mut tracking,
+            flying,
+            movement(Vec3::new(0.5, 0.3, 0.0), false),
+            IN_AIR,
+        );
+
+        assert_eq!(result, Ok(()));
+    }
+
+    #[test]
+    fn test_hovering_without_flight() {
+        let mut tracking = tracking();
+
+        let result = check(&mut tracking, WALKING, movement(Vec3::ZERO, false), IN_AIR);
+
+        assert!(matches!(result, Err(MovementViolationKind::Fly { .. })));
+    }
+
+    #[test]
+    fn test_falling() {
+        let mut tracking = tracking();
+        let mut vertical_speed = 0.0_f32;
+
+        for _ in 0..MAX_MOVEMENT_BALANCE {
+            vertical_speed = (vertical_speed - GRAVITY) * VERTICAL_DRAG;
+
+            let result = check(
+                &mut tracking,
+                WALKING,
+                movement(Vec3::new(0.0, vertical_speed, 0.0), false),
+                IN_AIR,
+            );
+
+            assert_eq!(result, Ok(()));
+        }
+    }
+
+    #[test]
+    fn test_falling_then_landing() {
+        const LANDING: Surroundings = Surroundings {
+            supported_after: true,
+            ..IN_AIR
+        };
+
+        let mut tracking = tracking();
+        tracking.vertical_speed = -0.6;
+
+        // the ground is closer than the player would have fallen this tick
+        let result = check(
+            &mut tracking,
+            WALKING,
+            movement(Vec3::new(0.0, -0.1, 0.0), true),
+            LANDING,
+        );
+        assert_eq!(result, Ok(()));
+
+        tracking.vertical_speed = -0.6;
+
+        let result = check(
+            &mut tracking,
+            WALKING,
+            movement(Vec3::new(0.0, 0.2, 0.0), true),
+            LANDING,
+        );
+        assert!(matches!(result, Err(MovementViolationKind::Fly { .. })));
+    }
+
+    #[test]
+    fn test_no_fall() {
+        let mut tracking = tracking();
+        tracking.vertical_speed = -0.5;
+
+        let result = check(
+            &mut tracking,
+            WALKING,
+            movement(Vec3::new(0.0, -0.6, 0.0), true),
+            IN_AIR,
+        );
+
+        assert!(matches!(result, Err(MovementViolationKind::NoFall { .. })));
+    }
+
+    #[test]
+    fn test_timer() {
+        let mut tracking = tracking();
+
+        for _ in 0..MAX_MOVEMENT_BALANCE {
+            let result = check(
+                &mut tracking,
+                WALKING,
+                movement(Vec3::ZERO, true),
+                ON_GROUND,
+            );
+            assert_eq!(result, Ok(()));
+        }
+
+        let result = check(
+            &mut tracking,
+            WALKING,
+            movement(Vec3::ZERO, true),
+            ON_GROUND,
+        );
+        assert_eq!(result, Err(MovementViolationKind::Timer));
+    }
+
+    #[test]
+    fn test_balance_refills_by_wall_clock() {
+        let start = Instant::now();
+        let mut tracking = tracking();
+        refill_movement_balance(&mut tracking, start);
+
+        tracking.movement_balance = 0;
+
+        // a server at 10 TPS refills twice as much per tick
+        refill_movement_balance(&mut tracking, start + Duration::from_millis(120));
+        assert_eq!(tracking.movement_balance, 2);
+
+        // the 20 ms left over count towards the next packet
+        refill_movement_balance(&mut tracking, start + Duration::from_millis(150));
+        assert_eq!(tracking.movement_balance, 3);
+
+        refill_movement_balance(&mut tracking, start + Duration::from_secs(10));
+        assert_eq!(tracking.movement_balance, MAX_MOVEMENT_BALANCE);
+    }
+}
@@ -1,14 +1,18 @@
 //! Constructs for connecting and working with a `Heed` database.
 
-use std::path::Path;
+use std::{
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use byteorder::NativeEndian;
 use derive_more::Deref;
 use flecs_ecs::macros::Component;
 use heed::{Database, Env, EnvOpenOptions, types};
+use rkyv::{Archive, util::AlignedVec};
 use uuid::Uuid;
 
-use crate::simulation::skin::{ArchivedPlayerSkin, PlayerSkin};
+use crate::simulation::skin::PlayerSkin;
 
 /// A wrapper around a `Heed` database
 #[derive(Component, Debug, Clone, Deref)]
@@ -19,21 +23,32 @@ pub struct LocalDb {
 impl LocalDb {
     /// Creates a new [`LocalDb`]
     pub fn new() -> anyhow::Result<Self> {
-        let path = Path::new("db").join("heed.mdb");
+        Self::open(&Path::new("db").join("heed.mdb"))
+    }
 
-        std::fs::create_dir_all(&path)?;
+    /// Opens the database in the directory `path`, creating it if needed.
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        std::fs::create_dir_all(path)?;
 
         let env = unsafe {
             EnvOpenOptions::new()
                 .map_size(10 * 1024 * 1024) // 10MB
                 .max_dbs(8) // todo: why is this needed/configurable? ideally would be infinite...
-                .open(&path)?
+                .open(path)?
         };
 
         Ok(Self { env })
     }
 }
 
+/// Copies a value read from the database so it can be accessed as an archive. LMDB only aligns
+/// values to 2 bytes.
+fn aligned(bytes: &[u8]) -> AlignedVec {
+    let mut aligned = AlignedVec::with_capacity(bytes.len());
+    aligned.extend_from_slice(bytes);
+    aligned
+}
+
 /// A handler for player skin operations
 #[derive(Component, Debug, Clone)]
 pub struct SkinHandler {
@@ -71,8 +86,7 @@ impl SkinHandler {
             return Ok(None);
         };
 
-        let skin = unsafe { rkyv::access_unchecked::<ArchivedPlayerSkin>(skin) };
-        let skin = rkyv::deserialize::<_, rkyv::rancor::Error>(skin).unwrap();
+        let skin = rkyv::from_bytes::<PlayerSkin, rkyv::rancor::Error>(&aligned(skin))?;
         Ok(Some(skin))
     }
 
@@ -90,3 +104,179 @@ impl SkinHandler {
         Ok(())
     }
 }
+
+/// Why a player is banned and for how long.
+#[derive(
+    Debug,
+    Clone,
+    PartialEq,
+    Eq,
+    Archive,
+    rkyv::Deserialize,
+    rkyv::Serialize
+)]
+pub struct Ban {
+    pub reason: String,
+    /// When the ban ends as seconds since the Unix epoch, or `None` if it is permanent.
+    pub expires: Option<u64>,
+}
+
+impl Ban {
+    /// Creates a ban which never expires.
+    #[must_use]
+    pub fn permanent(reason: impl Into<String>) -> Self {
+        Self {
+            reason: reason.into(),
+            expires: None,
+        }
+    }
+
+    /// Creates a ban which ends at `until`.
+    #[must_use]
+    pub fn temporary(reason: impl Into<String>, until: SystemTime) -> Self {
+        let expires = until
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs());
+
+        Self {
+            reason: reason.into(),
+            expires: Some(expires),
+        }
+    }
+
+    /// When the ban ends, or `None` if it is permanent.
+    #[must_use]
+    pub fn expires_at(&self) -> Option<SystemTime> {
+        self.expires
+            .map(|secs| UNIX_EPOCH + std::time::Duration::from_secs(secs))
+    }
+
+    #[must_use]
+    pub fn is_expired(&self, now: SystemTime) -> bool {
+        self.expires_at().is_some_and(|expires| expires <= now)
+    }
+}
+
+/// The bans and whitelist checked before a player finishes logging in.
+#[derive(Component, Debug, Clone)]
+pub struct AccessList {
+    env: Env,
+    bans: Database<types::U128<NativeEndian>, types::Bytes>,
+    whitelist: Database<types::U128<NativeEndian>, types::Unit>,
+}
+
+impl AccessList {
+    /// Creates a new [`AccessList`] from a given [`LocalDb`].
+    pub fn new(db: &LocalDb) -> anyhow::Result<Self> {
+        let mut wtxn = db.write_txn()?;
+        let bans = db.create_database(&mut wtxn, Some("uuid-to-bans"))?;
+        let whitelist = db.create_database(&mut wtxn, Some("uuid-whitelist"))?;
+        wtxn.commit()?;
+
+        Ok(Self {
+            env: db.env.clone(),
+            bans,
+            whitelist,
+        })
+    }
+
+    /// Bans a player, replacing any existing ban.
+    pub fn ban(&self, uuid: Uuid, ban: &Ban) -> anyhow::Result<()> {
+        let uuid = uuid.as_u128();
+
+        let mut wtxn = self.env.write_txn()?;
+
+        let ban = rkyv::to_bytes::<rkyv::rancor::Error>(ban).unwrap();
+
+        self.bans.put(&mut wtxn, &uuid, &ban)?;
+        wtxn.commit()?;
+
+        Ok(())
+    }
+
+    /// Lifts a player's ban. Returns whether they were banned.
+    pub fn unban(&self, uuid: Uuid) -> anyhow::Result<bool> {
+        let uuid = uuid.as_u128();
+
+        let mut wtxn = self.env.write_txn()?;
+        let removed = self.bans.delete(&mut wtxn, &uuid)?;
+        wtxn.commit()?;
+
+        Ok(removed)
+    }
+
+    /// Finds the ban of a player which has not expired yet.
+    pub fn active_ban(&self, uuid: Uuid) -> anyhow::Result<Option<Ban>> {
+        let uuid = uuid.as_u128();
+
+        let rtxn = self.env.read_txn()?;
+
+        let Some(ban) = self.bans.get(&rtxn, &uuid)? else {
+            return Ok(None);
+        };
+
+        let ban = rkyv::from_bytes::<Ban, rkyv::rancor::Error>(&aligned(ban))?;
+
+        if ban.is_expired(SystemTime::now()) {
+            return Ok(None);
+        }
+
+        Ok(Some(ban))
+    }
+
+    /// Adds a player to the whitelist.
+    pub fn whitelist_add(&self, uuid: Uuid) -> anyhow::Result<()> {
+        let uuid = uuid.as_u128();
+
+        let mut wtxn = self.env.write_txn()?;
+        self.whitelist.put(&mut wtxn, &uuid, &())?;
+        wtxn.commit()?;
+
+        Ok(())
+    }
+
+    /// Removes a player from the whitelist. Returns whether they were on it.
+    pub fn whitelist_remove(&self, uuid: Uuid) -> anyhow::Result<bool> {
+        let uuid = uuid.as_u128();
+
+        let mut wtxn = self.env.write_txn()?;
+        let removed = self.whitelist.delete(&mut wtxn, &uuid)?;
+        wtxn.commit()?;
+
+        Ok(removed)
+    }
+
+    pub fn is_whitelisted(&self, uuid: Uuid) -> anyhow::Result<bool> {
+        let uuid = uuid.as_u128();
+
+        let rtxn = self.env.read_txn()?;
+        let entry = self.whitelist.get(&rtxn, &uuid)?;
+
+        Ok(entry.is_some())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::Ban;
+
+    #[test]
+    fn test_ban_is_expired() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_000);
+
+        assert!(!Ban::permanent("reason").is_expired(now));
+
+        let ban = Ban::temporary("reason", UNIX_EPOCH + Duration::from_secs(1_001));
+        assert!(!ban.is_expired(now));
+        assert!(ban.is_expired(now + Duration::from_secs(1)));
+        assert!(ban.is_expired(now + Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn test_ban_expiry_is_rounded_to_seconds() {
+        let ban = Ban::temporary("reason", UNIX_EPOCH + Duration::from_millis(1_500));
+        assert_eq!(ban.expires_at(), Some(UNIX_EPOCH + Duration::from_secs(1)));
+    }
+}
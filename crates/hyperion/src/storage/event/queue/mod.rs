@@ -65,4 +65,7 @@ define_events! {
     event::UpdateSelectedSlotEvent,
     event::StartDestroyBlock,
     event::HitGroundEvent,
+    event::ConfigChanged,
+    event::MovementViolation,
+    event::ResourcePackStatus,
 }
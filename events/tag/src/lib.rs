@@ -6,7 +6,7 @@
 use std::{collections::HashSet, net::SocketAddr};
 
 use flecs_ecs::prelude::*;
-use hyperion::{GameServerEndpoint, HyperionCore, simulation::Player};
+use hyperion::{GameServerEndpoint, HyperionCore, metrics::MetricsEndpoint, simulation::Player};
 use hyperion_clap::hyperion_command::CommandRegistry;
 use hyperion_gui::Gui;
 use hyperion_proxy_module::ProxyAddress;
@@ -133,7 +133,7 @@ impl Module for TagModule {
     }
 }
 
-pub fn init_game(address: SocketAddr) -> anyhow::Result<()> {
+pub fn init_game(address: SocketAddr, metrics: Option<SocketAddr>) -> anyhow::Result<()> {
     let world = World::new();
 
     world.import::<HyperionCore>();
@@ -147,6 +147,10 @@ pub fn init_game(address: SocketAddr) -> anyhow::Result<()> {
 
     world.set(GameServerEndpoint::from(address));
 
+    if let Some(metrics) = metrics {
+        world.set(MetricsEndpoint::from(metrics));
+    }
+
     let mut app = world.app();
 
     app.enable_rest(0)
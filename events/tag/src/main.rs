@@ -22,6 +22,11 @@ struct Args {
     #[clap(short, long, default_value = "35565")]
     #[serde(default = "default_port")]
     port: u16,
+
+    /// The port game server metrics are served on. Metrics are not served if unset
+    #[clap(long)]
+    #[serde(default)]
+    metrics_port: Option<u16>,
 }
 
 fn default_ip() -> String {
@@ -71,5 +76,9 @@ fn main() {
     let address = format!("{ip}:{port}", ip = args.ip, port = args.port);
     let address = address.parse::<SocketAddr>().unwrap();
 
-    init_game(address).unwrap();
+    let metrics = args
+        .metrics_port
+        .map(|port| SocketAddr::new(address.ip(), port));
+
+    init_game(address, metrics).unwrap();
 }
@@ -12,6 +12,7 @@ use flecs_ecs::{
 use glam::IVec3;
 use hyperion::{
     BlockKind, Prev,
+    config::Config,
     net::{
         Compose, ConnectionId, agnostic,
         packets::{BossBarAction, BossBarS2c},
@@ -546,9 +547,16 @@ impl Module for AttackModule {
                             get_respawn_pos(query.world, &random_mate).as_vec3()
                         } else {
                             // There are no other teammates, so spawn the player in a random location
-                            query.world.get::<&AsyncRuntime>(|runtime| {
-                                query.world.get::<&mut Blocks>(|blocks| {
-                                    find_spawn_position(blocks, runtime, &avoid_blocks())
+                            query.world.get::<&Config>(|config| {
+                                query.world.get::<&AsyncRuntime>(|runtime| {
+                                    query.world.get::<&mut Blocks>(|blocks| {
+                                        find_spawn_position(
+                                            blocks,
+                                            runtime,
+                                            &avoid_blocks(),
+                                            &config.spawn,
+                                        )
+                                    })
                                 })
                             })
                         };
@@ -1,14 +1,14 @@
 use flecs_ecs::{
     core::{
-        ComponentOrPairId, EntityViewGet, QueryBuilderImpl, SystemAPI, TableIter, TermBuilderImpl,
-        World, flecs,
+        Builder, ComponentOrPairId, EntityViewGet, QueryAPI, QueryBuilderImpl, SystemAPI,
+        TableIter, TermBuilderImpl, World, flecs,
     },
     macros::{Component, system},
     prelude::Module,
 };
 use hyperion::{
     net::ConnectionId,
-    simulation::{Name, Player, Position, event},
+    simulation::{HidesChat, Name, Player, Position, dimension::InWorld, event},
     storage::EventQueue,
     valence_protocol::{packets::play, text::IntoText},
 };
@@ -35,6 +35,9 @@ impl Module for ChatModule {
             .component::<Player>()
             .add_trait::<(flecs::With, ChatCooldown)>();
 
+        // excluded from chat, which goes to the players near the sender in the sender's world
+        let hides_chat = world.query::<&ConnectionId>().with::<HidesChat>().build();
+
         system!("handle_chat_messages", world, &mut EventQueue<event::ChatMessage>($), &hyperion::net::Compose($))
 
             .each_iter(move |it: TableIter<'_, false>, _: usize, (event_queue, compose): (&mut EventQueue<event::ChatMessage>, &hyperion::net::Compose)| {
@@ -57,7 +60,7 @@ impl Module for ChatModule {
 
                     // Check cooldown
                     // todo: try_get if entity is dead/not found what will happen?
-                    by.get::<(&Name, &Position, &mut ChatCooldown, &ConnectionId, &Team)>(|(name, position, cooldown, io, team)| {
+                    by.get::<(&Name, &Position, &mut ChatCooldown, &ConnectionId, &Team, Option<&InWorld>)>(|(name, position, cooldown, io, team, in_world)| {
                         // Check if player is still on cooldown
                         if cooldown.expires > current_tick {
                             let remaining_ticks = cooldown.expires - current_tick;
@@ -91,7 +94,12 @@ impl Module for ChatModule {
 
                         let center = position.to_chunk();
 
+                        let mut hidden = Vec::new();
+                        hides_chat.each(|io| hidden.push(*io));
+
                         compose.broadcast_local(&packet, center, system)
+                            .world(InWorld::proxy_id(in_world))
+                            .exclude_all(hidden)
                             .send()
                             .unwrap();
                     });
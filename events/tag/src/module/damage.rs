@@ -4,7 +4,7 @@ use flecs_ecs::{
     prelude::{Module, SystemAPI},
 };
 use hyperion::{
-    net::{Compose, ConnectionId, agnostic},
+    net::{Compose, ConnectionId, Priority, agnostic},
     simulation::{Position, event::HitGroundEvent, metadata::living_entity::Health},
     storage::EventQueue,
 };
@@ -65,6 +65,7 @@ impl Module for DamageModule {
                                 .unwrap();
                             compose
                                 .broadcast_local(&sound, position.to_chunk(), system)
+                                .priority(Priority::Cosmetic)
                                 .send()
                                 .unwrap();
                         },
@@ -6,6 +6,7 @@ use flecs_ecs::{
     prelude::Module,
 };
 use hyperion::{
+    config::{Config, Spawn},
     runtime::AsyncRuntime,
     simulation::{Position, Uuid, blocks::Blocks},
     valence_protocol::{
@@ -18,19 +19,11 @@ use rustc_hash::FxHashMap;
 #[derive(Component)]
 pub struct SpawnModule;
 
-const RADIUS: i32 = 0;
 const SPAWN_MIN_Y: i16 = 3;
 const SPAWN_MAX_Y: i16 = 100;
 
-fn position_in_radius() -> IVec2 {
-    let x = fastrand::i32(-RADIUS..=RADIUS);
-    let z = fastrand::i32(-RADIUS..=RADIUS);
-
-    IVec2::new(x, z)
-}
-
-fn random_chunk_in_radius() -> I16Vec2 {
-    let pos: IVec2 = position_in_radius() >> 4;
+fn random_chunk_in_radius(spawn: &Spawn) -> I16Vec2 {
+    let pos: IVec2 = spawn.random_column() >> 4;
     pos.as_i16vec2()
 }
 
@@ -58,16 +51,17 @@ impl Module for SpawnModule {
             flecs::OnSet,
             &Uuid,
             &mut Blocks($),
-            &AsyncRuntime($) ,
+            &AsyncRuntime($),
+            &Config($),
         )
         .without::<Position>()
         .each_entity({
             let positions = Rc::clone(&positions);
-            move |entity, (uuid, blocks, runtime)| {
+            move |entity, (uuid, blocks, runtime, config)| {
                 let mut positions = positions.borrow_mut();
-                let position = *positions
-                    .entry(uuid.0)
-                    .or_insert_with(|| find_spawn_position(blocks, runtime, &avoid_blocks));
+                let position = *positions.entry(uuid.0).or_insert_with(|| {
+                    find_spawn_position(blocks, runtime, &avoid_blocks, &config.spawn)
+                });
 
                 entity.set(Position::from(position));
             }
@@ -86,18 +80,19 @@ pub fn find_spawn_position(
     blocks: &mut Blocks,
     runtime: &AsyncRuntime,
     avoid_blocks: &RoaringBitmap,
+    spawn: &Spawn,
 ) -> Vec3 {
     const MAX_TRIES: usize = 3;
-    const FALLBACK_POSITION: Vec3 = Vec3::new(0.0, 120.0, 0.0);
 
     for _ in 0..MAX_TRIES {
-        let chunk = random_chunk_in_radius();
+        let chunk = random_chunk_in_radius(spawn);
         if let Some(pos) = try_chunk_for_spawn(chunk, blocks, runtime, avoid_blocks) {
             return pos;
         }
     }
 
-    FALLBACK_POSITION
+    // the center of the spawn block
+    spawn.center().as_vec3() + Vec3::new(0.5, 0.0, 0.5)
 }
 
 fn try_chunk_for_spawn(